@@ -0,0 +1,79 @@
+//! Run the hardware-in-the-loop check (see `hil`) from a Linux board, such as a Raspberry Pi
+//!
+//! ```text
+//! linux-hil <i2c bus> [<address>] [<gpio chip> <EN line>]
+//! linux-hil /dev/i2c-1 0x32 /dev/gpiochip0 17
+//! ```
+//!
+//! The bus is opened through i2cdev and the EN line, if given, through the GPIO character device;
+//! without one, the EN line is taken to be tied high. The address defaults to 0x32. Prints the
+//! report and exits with status 1 if a check failed.
+//!
+//! Build with `cargo run --example linux-hil --features linux -- ...`.
+
+extern crate linux_embedded_hal;
+extern crate lp55231;
+
+use std::env;
+use std::process;
+
+use linux_embedded_hal::gpio_cdev::{Chip, LineRequestFlags};
+use linux_embedded_hal::{CdevPin, Delay, I2cdev};
+use lp55231::eh1::{Eh1Bus, Eh1Delay, Eh1Pin};
+use lp55231::{hil, Addr, Lp55231};
+
+/// Print usage and exit
+fn usage() -> ! {
+    eprintln!("usage: linux-hil <i2c bus> [<address>] [<gpio chip> <EN line>]");
+    process::exit(2);
+}
+
+/// Print an error and exit
+fn fail<E: std::fmt::Display>(what: &str, e: E) -> ! {
+    eprintln!("linux-hil: {}: {}", what, e);
+    process::exit(2);
+}
+
+/// Parse an I2C address, in hex with a `0x` prefix or in decimal
+fn parse_addr(arg: &str) -> Option<u8> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+/// Request the EN line as an output, initially low
+fn en_pin(chip: &str, line: &str) -> CdevPin {
+    let line = line.parse().unwrap_or_else(|_| usage());
+    let mut chip = Chip::new(chip).unwrap_or_else(|e| fail(chip, e));
+    let handle = chip
+        .get_line(line)
+        .and_then(|l| l.request(LineRequestFlags::OUTPUT, 0, "lp55231"))
+        .unwrap_or_else(|e| fail("EN line", e));
+    CdevPin::new(handle).unwrap_or_else(|e| fail("EN line", e))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (bus, rest) = match args.split_first() {
+        Some((bus, rest)) => (bus, rest),
+        None => usage(),
+    };
+    let (addr, rest) = match rest.first().and_then(|a| parse_addr(a)) {
+        Some(addr) => (Addr::Custom(addr), &rest[1..]),
+        None => (Addr::_0x32, rest),
+    };
+    let pin = match rest {
+        [] => None,
+        [chip, line] => Some(Eh1Pin::new(en_pin(chip, line))),
+        _ => usage(),
+    };
+
+    let i2c = I2cdev::new(bus).unwrap_or_else(|e| fail(bus, e));
+    let mut lp = Lp55231::new(Eh1Bus::new(i2c), pin, addr);
+    let report = hil::run(&mut lp, &mut Eh1Delay::new(Delay)).unwrap_or_else(|e| fail("bus", e));
+    println!("{:#?}", report);
+    if !report.passed() {
+        process::exit(1);
+    }
+}
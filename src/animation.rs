@@ -0,0 +1,164 @@
+//! Host-driven frame animation
+//!
+//! The engines hold at most 96 instructions, which is not enough for long or data-driven
+//! sequences. An `Animator` instead plays frames held in host memory, writing one frame per
+//! period. Drive it with `step` from a timer, or let `play` block on a delay.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use color::RgbGroup;
+use {Error, Lp55231, Millis, D};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// What to do after the last frame
+pub enum Playback {
+    /// Stop on the last frame
+    Once,
+    /// Start again from the first frame
+    Loop,
+    /// Play the frames backwards, then forwards again, indefinitely
+    PingPong,
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Frames to be played
+pub enum Frames<'a> {
+    /// PWM values of D1 through D9
+    Pwm(&'a [[u8; 9]]),
+    /// Red, green and blue PWM values of an RGB LED
+    Rgb(RgbGroup, &'a [(u8, u8, u8)]),
+}
+
+impl<'a> Frames<'a> {
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        match *self {
+            Frames::Pwm(f) => f.len(),
+            Frames::Rgb(_, f) => f.len(),
+        }
+    }
+
+    /// Are there no frames
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Plays `Frames` at a fixed frame period
+pub struct Animator<'a> {
+    /// Frames being played
+    frames: Frames<'a>,
+    /// Time between frames, in ms
+    period: u16,
+    /// What to do after the last frame
+    playback: Playback,
+    /// Index of the next frame to be written
+    next: usize,
+    /// Is ping-pong playback currently running backwards
+    reverse: bool,
+    /// Has playback finished
+    done: bool,
+}
+
+impl<'a> Animator<'a> {
+    /// Create an animator playing the frames once, one every `period` ms
+    pub fn new<T: Into<Millis>>(frames: Frames<'a>, period: T) -> Self {
+        Animator {
+            frames,
+            period: period.into().0,
+            playback: Playback::Once,
+            next: 0,
+            reverse: false,
+            done: frames.is_empty(),
+        }
+    }
+
+    /// Set what to do after the last frame
+    pub fn with_playback(mut self, playback: Playback) -> Self {
+        self.playback = playback;
+        self
+    }
+
+    /// Time between frames, in ms
+    pub fn period(&self) -> u16 {
+        self.period
+    }
+
+    /// Has playback finished. Never true for looping or ping-pong playback.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Return to the first frame
+    pub fn rewind(&mut self) {
+        self.next = 0;
+        self.reverse = false;
+        self.done = self.frames.is_empty();
+    }
+
+    /// Advance `next` past the frame just written
+    fn advance(&mut self) {
+        let last = self.frames.len() - 1;
+        match self.playback {
+            Playback::Once if self.next == last => self.done = true,
+            Playback::Loop if self.next == last => self.next = 0,
+            Playback::PingPong if last == 0 => {}
+            Playback::PingPong if self.reverse && self.next == 0 => {
+                self.reverse = false;
+                self.next = 1;
+            }
+            Playback::PingPong if !self.reverse && self.next == last => {
+                self.reverse = true;
+                self.next = last - 1;
+            }
+            _ if self.reverse => self.next -= 1,
+            _ => self.next += 1,
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Write the animator's next frame and advance it. Returns `false`, writing nothing, once
+    /// playback has finished. Call once per frame period.
+    pub fn step_animation(&mut self, anim: &mut Animator) -> Result<bool, Error<E>> {
+        if anim.done {
+            return Ok(false);
+        }
+        match anim.frames {
+            Frames::Pwm(frames) => {
+                for (d, pwm) in D::iter().zip(frames[anim.next].iter()) {
+                    self.set_pwm(d, *pwm)?;
+                }
+            }
+            Frames::Rgb(led, frames) => {
+                let (r, g, b) = frames[anim.next];
+                self.set_rgb(led, r, g, b)?;
+            }
+        }
+        anim.advance();
+        Ok(true)
+    }
+
+    /// Play the animation to the end, waiting one frame period between frames. Looping and
+    /// ping-pong playback only return on error.
+    pub fn play_animation<DL: DelayMs<u16>>(
+        &mut self,
+        anim: &mut Animator,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>> {
+        while self.step_animation(anim)? {
+            delay.delay_ms(anim.period);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,834 @@
+//! Engine program assembler
+//!
+//! `Assembler` builds engine programs from typed instructions instead of hand-encoded words. Each
+//! instruction method appends one instruction word and returns the assembler, so instructions
+//! can be chained. Errors (an operand out of range, or running out of program memory) are kept
+//! and reported by `finish`, so a chain need not be checked after every instruction.
+//!
+//! ```ignore
+//! let mut asm = Assembler::new();
+//! asm.forever(|asm| {
+//!     asm.ramp(16, false, 255).ramp(16, false, -255);
+//! });
+//! let program = asm.finish([0, 0, 0])?;
+//! ```
+//!
+//! Addresses can be named with `Assembler::label`, so that timing analysis and diagnostics can
+//! refer to them by name: `Lp55231::engine_position` reports where an engine is relative to them.
+//! Operands can be left as holes with `Assembler::hole`, to be filled in at load time (see
+//! `template`).
+//!
+//! An engine can also pick the D lines it drives itself, with the `mux_*` instructions: either one
+//! D line directly (`mux_sel`), or a row of a mapping table held in the program memory, each row a
+//! set of D lines (`mux_map_start`, `mux_map_next`, and so on). Mapping an engine this way replaces
+//! the map set with `Lp55231::set_engine_map`. Table rows are appended with `Assembler::row`, and
+//! must lie where the engine never executes them.
+//!
+//! Branch and mapping table targets are program memory addresses, so programs are assembled to
+//! run from address 0. `relocate` moves assembled instructions to run from any other address.
+//!
+//! Each instruction also has a `const fn` encoder, so that small programs can be kept in flash as
+//! `const` data. Encoders never panic: those taking operands with a limited range return
+//! `ProgramError::InvalidOperand` (`Error::InvalidArgument` once converted) for operands out of
+//! range. In a `const`, `const_word` turns such an error into a compilation failure:
+//!
+//! ```ignore
+//! const BLINK: [u16; 4] = [
+//!     set_pwm(255),
+//!     const_word(wait(31, true)),
+//!     set_pwm(0),
+//!     const_word(branch(0, 0)),
+//! ];
+//! ```
+
+use core::fmt;
+
+use program::{Program, ProgramError, PROGRAM_MEMORY_SIZE};
+use template::{Hole, HoleKind, Holes};
+use {OutputMask, D};
+
+/// Largest loop count of a branch instruction
+pub const MAX_LOOP_COUNT: u8 = 63;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Options of the `end` instruction
+pub struct End {
+    /// Raise an interrupt, setting the engine's bit in STATUS_IRQ and asserting the INT pin, so
+    /// that the host learns the program has completed
+    pub send_interrupt: bool,
+    /// Reset the program counter to the engine's start address and the PWM value to zero, so
+    /// that the program restarts from the top when next run
+    pub reset_pc: bool,
+}
+
+impl End {
+    /// The `end` instruction word with these options
+    pub const fn encode(self) -> u16 {
+        0xc000 | (self.send_interrupt as u16) << 12 | (self.reset_pc as u16) << 11
+    }
+}
+
+impl From<End> for u16 {
+    fn from(e: End) -> Self {
+        e.encode()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Engine program variables
+pub enum Variable {
+    /// Variable A, local to each engine
+    A,
+    /// Variable B, local to each engine
+    B,
+    /// Variable C, shared by the engines and the host (see `registers::GLOBAL_VAR`)
+    C,
+}
+
+impl Variable {
+    /// The variable's operand code
+    pub const fn encode(self) -> u16 {
+        match self {
+            Variable::A => 0b00,
+            Variable::B => 0b01,
+            Variable::C => 0b10,
+        }
+    }
+}
+
+impl From<Variable> for u16 {
+    fn from(v: Variable) -> Self {
+        v.encode()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Conditions of the conditional jump instructions, comparing two variables
+pub enum Condition {
+    /// The first variable differs from the second
+    NotEqual,
+    /// The first variable is less than the second
+    Less,
+    /// The first variable is greater than or equal to the second
+    GreaterOrEqual,
+    /// The first variable equals the second
+    Equal,
+}
+
+impl Condition {
+    /// The opcode of the conditional jump testing this condition
+    pub const fn encode(self) -> u16 {
+        match self {
+            Condition::NotEqual => 0x8800,
+            Condition::Less => 0x8a00,
+            Condition::GreaterOrEqual => 0x8c00,
+            Condition::Equal => 0x8e00,
+        }
+    }
+}
+
+impl From<Condition> for u16 {
+    fn from(c: Condition) -> Self {
+        c.encode()
+    }
+}
+
+/// Largest number of instructions a conditional jump can skip
+pub const MAX_SKIP: u8 = 31;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Trigger sources or destinations of the `trigger` instruction
+pub struct Triggers {
+    /// Engine 1
+    pub e1: bool,
+    /// Engine 2
+    pub e2: bool,
+    /// Engine 3
+    pub e3: bool,
+    /// The TRIG pin, shared by every device wired to it
+    pub external: bool,
+}
+
+impl Triggers {
+    /// No trigger
+    pub const NONE: Triggers = Triggers {
+        e1: false,
+        e2: false,
+        e3: false,
+        external: false,
+    };
+
+    /// The TRIG pin only
+    pub const EXTERNAL: Triggers = Triggers {
+        e1: false,
+        e2: false,
+        e3: false,
+        external: true,
+    };
+
+    /// The 6-bit trigger field
+    pub const fn encode(self) -> u16 {
+        self.e1 as u16 | (self.e2 as u16) << 1 | (self.e3 as u16) << 2 | (self.external as u16) << 5
+    }
+}
+
+/// Encode a `ramp`: step the PWM value `increments` times, up if positive and down if negative,
+/// with `step_time` cycles between steps (see `Assembler::ramp`)
+///
+/// Fails with `ProgramError::InvalidOperand` unless `step_time` is between 1 and 31 and
+/// `increments` between -255 and 255.
+pub const fn ramp(step_time: u8, prescale: bool, increments: i16) -> Result<u16, ProgramError> {
+    if step_time < 1 || step_time > 31 || increments.unsigned_abs() > 255 {
+        return Err(ProgramError::InvalidOperand);
+    }
+    Ok((prescale as u16) << 14
+        | (step_time as u16) << 9
+        | ((increments < 0) as u16) << 8
+        | increments.unsigned_abs())
+}
+
+/// Encode a `wait` of `step_time` cycles, as for `ramp`
+pub const fn wait(step_time: u8, prescale: bool) -> Result<u16, ProgramError> {
+    ramp(step_time, prescale, 0)
+}
+
+/// Encode a `set_pwm` of the mapped D lines
+pub const fn set_pwm(pwm: u8) -> u16 {
+    0x4000 | pwm as u16
+}
+
+/// Encode a jump to the engine's start address
+pub const fn go_to_start() -> u16 {
+    0x0000
+}
+
+/// Encode a `branch` to `address`, `loop_count` times. A loop count of 0 jumps forever.
+///
+/// Fails with `ProgramError::InvalidOperand` unless `loop_count` is at most `MAX_LOOP_COUNT` and
+/// `address` lies in the program memory.
+pub const fn branch(loop_count: u8, address: u8) -> Result<u16, ProgramError> {
+    if loop_count > MAX_LOOP_COUNT || address as usize >= PROGRAM_MEMORY_SIZE {
+        return Err(ProgramError::InvalidOperand);
+    }
+    Ok(0xa000 | (loop_count as u16) << 7 | address as u16)
+}
+
+/// Encode an `end` with the options
+pub const fn end(end: End) -> u16 {
+    end.encode()
+}
+
+/// Encode a `load` of `value` into the variable
+pub const fn load(var: Variable, value: u8) -> u16 {
+    0x9000 | var.encode() << 10 | value as u16
+}
+
+/// Encode an `add` of `value` to the variable, wrapping around at 256
+pub const fn add(var: Variable, value: u8) -> u16 {
+    0x9100 | var.encode() << 10 | value as u16
+}
+
+/// Encode a `sub` of `value` from the variable, wrapping around at 0
+pub const fn sub(var: Variable, value: u8) -> u16 {
+    0x9200 | var.encode() << 10 | value as u16
+}
+
+/// Encode an `add` of the variables `a` and `b` into the variable `target`, wrapping around at
+/// 256
+pub const fn add_vars(target: Variable, a: Variable, b: Variable) -> u16 {
+    0x9300 | target.encode() << 10 | a.encode() << 2 | b.encode()
+}
+
+/// Encode a `sub` of the variable `b` from `a` into the variable `target`, wrapping around at 0
+pub const fn sub_vars(target: Variable, a: Variable, b: Variable) -> u16 {
+    0x9310 | target.encode() << 10 | a.encode() << 2 | b.encode()
+}
+
+/// Encode a conditional `jump`, skipping `skip` instructions if `condition` holds between `a`
+/// and `b`
+///
+/// Fails with `ProgramError::InvalidOperand` unless `skip` is at most `MAX_SKIP`.
+pub const fn jump(
+    condition: Condition,
+    skip: u8,
+    a: Variable,
+    b: Variable,
+) -> Result<u16, ProgramError> {
+    if skip > MAX_SKIP {
+        return Err(ProgramError::InvalidOperand);
+    }
+    Ok(condition.encode() | (skip as u16) << 4 | a.encode() << 2 | b.encode())
+}
+
+/// Encode a `trigger`: wait until every trigger in `wait` has been received, then send the
+/// triggers in `send`
+pub const fn trigger(wait: Triggers, send: Triggers) -> u16 {
+    0xe000 | wait.encode() << 7 | send.encode() << 1
+}
+
+/// Encode a `mux_map_start`: set the first row of the mapping table to the row at `address`,
+/// and map the engine to it
+///
+/// Fails with `ProgramError::InvalidOperand` unless `address` lies in the program memory.
+pub const fn mux_map_start(address: u8) -> Result<u16, ProgramError> {
+    mux_address(0x9c00, address)
+}
+
+/// Encode a `mux_ld_end`: set the last row of the mapping table to the row at `address`
+///
+/// Fails with `ProgramError::InvalidOperand` unless `address` lies in the program memory.
+pub const fn mux_ld_end(address: u8) -> Result<u16, ProgramError> {
+    mux_address(0x9c80, address)
+}
+
+/// Encode a `mux_map_addr`: map the engine to the row at `address`, which becomes the current row
+///
+/// Fails with `ProgramError::InvalidOperand` unless `address` lies in the program memory.
+pub const fn mux_map_addr(address: u8) -> Result<u16, ProgramError> {
+    mux_address(0x9f80, address)
+}
+
+/// Encode a `mux_map_next`: map the engine to the next row of the mapping table, wrapping from
+/// the last row to the first
+pub const fn mux_map_next() -> u16 {
+    0x9d80
+}
+
+/// Encode a `mux_map_prev`: map the engine to the previous row of the mapping table, wrapping
+/// from the first row to the last
+pub const fn mux_map_prev() -> u16 {
+    0x9dc0
+}
+
+/// Encode a `mux_sel`: map the engine to the D line alone, or to none
+pub const fn mux_sel(d: Option<D>) -> u16 {
+    match d {
+        Some(d) => 0x9d00 | (d as u16 + 1),
+        None => 0x9d00,
+    }
+}
+
+/// Encode a `mux_clr`: map the engine to no D line
+pub const fn mux_clr() -> u16 {
+    mux_sel(None)
+}
+
+/// Encode a `mux_*` instruction taking a program memory address
+const fn mux_address(opcode: u16, address: u8) -> Result<u16, ProgramError> {
+    if address as usize >= PROGRAM_MEMORY_SIZE {
+        return Err(ProgramError::InvalidOperand);
+    }
+    Ok(opcode | address as u16)
+}
+
+/// Take the instruction word out of an encoder's result in a `const` item, failing compilation if
+/// an operand is out of range
+///
+/// This panics when evaluated at run time on an error, so only use it where it is evaluated at
+/// compile time; everywhere else, handle the encoder's result.
+pub const fn const_word(encoded: Result<u16, ProgramError>) -> u16 {
+    match encoded {
+        Ok(word) => word,
+        Err(_) => panic!("instruction operand out of range"),
+    }
+}
+
+/// Clear the `send_interrupt` option of every `end` instruction, so that the program ends without
+/// raising an interrupt
+pub fn silence_interrupts(instructions: &mut [u16]) {
+    for word in instructions.iter_mut().filter(|w| **w & 0xe000 == 0xc000) {
+        *word &= !0x1000;
+    }
+}
+
+/// Whether an instruction word is a branch or `mux_*` instruction, whose operand is an absolute
+/// address
+fn is_addressed(word: u16) -> bool {
+    word & 0xe000 == 0xa000 || matches!(word & 0xff80, 0x9c00 | 0x9c80 | 0x9e00 | 0x9f00 | 0x9f80)
+}
+
+/// Offset the branch and mapping table targets of instructions assembled to run from address 0,
+/// so that they run from address `base`
+///
+/// Other instructions are position independent and are left as they are. Mapping table rows are
+/// data, and are left as they are as well, unless a row happens to encode an addressed
+/// instruction. Fails without changing the instructions if they would not fit in the program
+/// memory from `base`, or if a target would fall outside of it.
+pub fn relocate(instructions: &mut [u16], base: u8) -> Result<(), ProgramError> {
+    let base = u16::from(base);
+    if usize::from(base) + instructions.len() > PROGRAM_MEMORY_SIZE {
+        return Err(ProgramError::TooLarge);
+    }
+    let targets_fit = instructions
+        .iter()
+        .filter(|w| is_addressed(**w))
+        .all(|w| usize::from((w & 0x7f) + base) < PROGRAM_MEMORY_SIZE);
+    if !targets_fit {
+        return Err(ProgramError::InvalidOperand);
+    }
+    for word in instructions.iter_mut().filter(|w| is_addressed(**w)) {
+        *word = (*word & !0x7f) | ((*word & 0x7f) + base);
+    }
+    Ok(())
+}
+
+/// Largest number of labels an `Assembler` records
+pub const MAX_LABELS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Names given to program memory addresses while assembling (see `Assembler::label`)
+pub struct Labels {
+    /// Name and address of each label, in the order they were added
+    entries: [(&'static str, u8); MAX_LABELS],
+    /// Number of labels
+    len: usize,
+}
+
+impl Default for Labels {
+    fn default() -> Self {
+        Labels {
+            entries: [("", 0); MAX_LABELS],
+            len: 0,
+        }
+    }
+}
+
+impl Labels {
+    /// The labels' names and addresses, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u8)> + '_ {
+        self.entries[..self.len].iter().cloned()
+    }
+
+    /// The address of the label with the name, if any
+    pub fn address(&self, name: &str) -> Option<u8> {
+        self.iter().find(|(n, _)| *n == name).map(|(_, a)| a)
+    }
+
+    /// Number of labels
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Are there no labels
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Offset every label's address by `base`, as `relocate` does the instructions
+    pub fn relocate(&mut self, base: u8) {
+        for (_, address) in self.entries[..self.len].iter_mut() {
+            *address = address.saturating_add(base);
+        }
+    }
+
+    /// Locate a program counter relative to the nearest label at or before it
+    pub fn position(&self, pc: u8) -> Position {
+        let label = self
+            .iter()
+            .filter(|(_, address)| *address <= pc)
+            .max_by_key(|(_, address)| *address);
+        Position {
+            pc,
+            label: label.map(|(name, address)| (name, pc.saturating_sub(address))),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A program counter, located relative to the source labels (see `Labels::position`)
+///
+/// Displays as the label and the number of instructions past it, followed by the program counter,
+/// e.g. `'wait_for_trigger'+1 (PC=0x2c)`.
+pub struct Position {
+    /// The program counter
+    pub pc: u8,
+    /// The nearest label at or before the program counter, and how many instructions past it
+    /// the program counter is
+    pub label: Option<(&'static str, u8)>,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.label {
+            Some((name, 0)) => write!(f, "'{}' (PC={:#04x})", name, self.pc),
+            Some((name, offset)) => write!(f, "'{}'+{} (PC={:#04x})", name, offset, self.pc),
+            None => write!(f, "PC={:#04x}", self.pc),
+        }
+    }
+}
+
+/// Builder of engine programs
+pub struct Assembler {
+    /// Instruction words assembled so far
+    words: [u16; PROGRAM_MEMORY_SIZE],
+    /// Number of instruction words assembled
+    len: usize,
+    /// Labels added so far
+    labels: Labels,
+    /// Template holes marked so far
+    holes: Holes,
+    /// First error encountered, if any
+    error: Option<ProgramError>,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Assembler {
+    /// Create an empty assembler
+    pub fn new() -> Self {
+        Assembler {
+            words: [0; PROGRAM_MEMORY_SIZE],
+            len: 0,
+            labels: Labels::default(),
+            holes: Holes::default(),
+            error: None,
+        }
+    }
+
+    /// Address of the next instruction
+    pub fn address(&self) -> u8 {
+        self.len as u8
+    }
+
+    /// The instruction words assembled so far, or the first error encountered
+    pub fn instructions(&self) -> Result<&[u16], ProgramError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(&self.words[..self.len]),
+        }
+    }
+
+    /// The labels added so far, with their addresses
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    /// Name the address of the next instruction, for timing analysis (see `timing`) and
+    /// diagnostics. At most `MAX_LABELS` labels can be added.
+    pub fn label(&mut self, name: &'static str) -> &mut Self {
+        match self.labels.entries.get_mut(self.labels.len) {
+            Some(entry) => *entry = (name, self.len as u8),
+            None => return self.fail(ProgramError::TooManyLabels),
+        }
+        self.labels.len += 1;
+        self
+    }
+
+    /// The template holes marked so far
+    pub fn holes(&self) -> &Holes {
+        &self.holes
+    }
+
+    /// Mark an operand of the last instruction as a named hole, to be filled in at load time (see
+    /// `template`). At most `template::MAX_HOLES` holes can be marked.
+    pub fn hole(&mut self, name: &'static str, kind: HoleKind) -> &mut Self {
+        let address = match self.len.checked_sub(1) {
+            Some(address) => address as u8,
+            None => return self.fail(ProgramError::InvalidOperand),
+        };
+        match self.holes.push(Hole {
+            name,
+            address,
+            kind,
+        }) {
+            Ok(()) => self,
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Build the program, with each engine starting at the given address
+    pub fn finish(&self, start: [u8; 3]) -> Result<Program, ProgramError> {
+        Program::new(start, self.instructions()?)
+    }
+
+    /// Append an instruction word
+    pub fn word(&mut self, word: u16) -> &mut Self {
+        match self.words.get_mut(self.len) {
+            Some(slot) => *slot = word,
+            None => return self.fail(ProgramError::TooLarge),
+        }
+        self.len += 1;
+        self
+    }
+
+    /// Append an encoded instruction word, or record the encoder's error
+    fn encoded(&mut self, encoded: Result<u16, ProgramError>) -> &mut Self {
+        match encoded {
+            Ok(word) => self.word(word),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Record an error, keeping the first
+    fn fail(&mut self, e: ProgramError) -> &mut Self {
+        self.error.get_or_insert(e);
+        self
+    }
+
+    /// Step the PWM value `increments` times, up if positive and down if negative, with
+    /// `step_time` cycles between steps. A cycle is 0.49 ms, or 15.6 ms if `prescale` is set.
+    /// `step_time` must be between 1 and 31, and `increments` between -255 and 255.
+    pub fn ramp(&mut self, step_time: u8, prescale: bool, increments: i16) -> &mut Self {
+        self.encoded(ramp(step_time, prescale, increments))
+    }
+
+    /// Wait for `step_time` cycles, as for `ramp`
+    pub fn wait(&mut self, step_time: u8, prescale: bool) -> &mut Self {
+        self.ramp(step_time, prescale, 0)
+    }
+
+    /// Set the PWM value of the mapped D lines
+    pub fn set_pwm(&mut self, pwm: u8) -> &mut Self {
+        self.word(set_pwm(pwm))
+    }
+
+    /// Jump to the engine's start address
+    pub fn go_to_start(&mut self) -> &mut Self {
+        self.word(go_to_start())
+    }
+
+    /// Jump to `address`, `loop_count` times, then continue. A loop count of 0 jumps forever.
+    pub fn branch(&mut self, loop_count: u8, address: u8) -> &mut Self {
+        self.encoded(branch(loop_count, address))
+    }
+
+    /// Load `value` into the variable
+    pub fn load(&mut self, var: Variable, value: u8) -> &mut Self {
+        self.word(load(var, value))
+    }
+
+    /// Add `value` to the variable, wrapping around at 256
+    pub fn add(&mut self, var: Variable, value: u8) -> &mut Self {
+        self.word(add(var, value))
+    }
+
+    /// Subtract `value` from the variable, wrapping around at 0
+    pub fn sub(&mut self, var: Variable, value: u8) -> &mut Self {
+        self.word(sub(var, value))
+    }
+
+    /// Set the variable `target` to the sum of `a` and `b`, wrapping around at 256
+    pub fn add_vars(&mut self, target: Variable, a: Variable, b: Variable) -> &mut Self {
+        self.word(add_vars(target, a, b))
+    }
+
+    /// Set the variable `target` to `a` minus `b`, wrapping around at 0
+    pub fn sub_vars(&mut self, target: Variable, a: Variable, b: Variable) -> &mut Self {
+        self.word(sub_vars(target, a, b))
+    }
+
+    /// Skip the next `skip` instructions if `condition` holds between `a` and `b`. `skip` must be
+    /// at most `MAX_SKIP`.
+    pub fn jump(&mut self, condition: Condition, skip: u8, a: Variable, b: Variable) -> &mut Self {
+        self.encoded(jump(condition, skip, a, b))
+    }
+
+    /// Wait until every trigger in `wait` has been received, then send the triggers in `send`
+    pub fn trigger(&mut self, wait: Triggers, send: Triggers) -> &mut Self {
+        self.word(trigger(wait, send))
+    }
+
+    /// Map the engine to the mapping table row at `address`, making it the first row
+    pub fn mux_map_start(&mut self, address: u8) -> &mut Self {
+        self.encoded(mux_map_start(address))
+    }
+
+    /// Make the row at `address` the last row of the mapping table
+    pub fn mux_ld_end(&mut self, address: u8) -> &mut Self {
+        self.encoded(mux_ld_end(address))
+    }
+
+    /// Map the engine to the mapping table row at `address`
+    pub fn mux_map_addr(&mut self, address: u8) -> &mut Self {
+        self.encoded(mux_map_addr(address))
+    }
+
+    /// Map the engine to the next row of the mapping table, wrapping from the last to the first
+    pub fn mux_map_next(&mut self) -> &mut Self {
+        self.word(mux_map_next())
+    }
+
+    /// Map the engine to the previous row of the mapping table, wrapping from the first to the
+    /// last
+    pub fn mux_map_prev(&mut self) -> &mut Self {
+        self.word(mux_map_prev())
+    }
+
+    /// Map the engine to the D line alone, or to none
+    pub fn mux_sel(&mut self, d: Option<D>) -> &mut Self {
+        self.word(mux_sel(d))
+    }
+
+    /// Append a mapping table row, the set of D lines an engine mapped to it drives
+    pub fn row(&mut self, outputs: OutputMask) -> &mut Self {
+        self.word(u16::from(outputs.msb()) << 8 | u16::from(outputs.lsb()))
+    }
+
+    /// End the program, halting the engine
+    pub fn end(&mut self, end: End) -> &mut Self {
+        self.word(end.encode())
+    }
+
+    /// Run the instructions added by `body` `count` times, closing them with a branch. `count`
+    /// must be between 1 and `MAX_LOOP_COUNT + 1`.
+    pub fn repeat<F: FnOnce(&mut Self)>(&mut self, count: u8, body: F) -> &mut Self {
+        if !(1..=MAX_LOOP_COUNT + 1).contains(&count) {
+            return self.fail(ProgramError::InvalidOperand);
+        }
+        let start = self.address();
+        body(self);
+        if count > 1 {
+            self.branch(count - 1, start);
+        }
+        self
+    }
+
+    /// Run the instructions added by `body` forever
+    pub fn forever<F: FnOnce(&mut Self)>(&mut self, body: F) -> &mut Self {
+        let start = self.address();
+        body(self);
+        self.branch(0, start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The words assembled by `build`, or its error
+    fn assembled<F: FnOnce(&mut Assembler)>(build: F) -> Result<[u16; 4], ProgramError> {
+        let mut asm = Assembler::new();
+        build(&mut asm);
+        let mut words = [0; 4];
+        let instructions = asm.instructions()?;
+        words[..instructions.len()].copy_from_slice(instructions);
+        Ok(words)
+    }
+
+    #[test]
+    fn step_times_run_from_1_to_31() {
+        assert_eq!(ramp(0, false, 1), Err(ProgramError::InvalidOperand));
+        assert_eq!(ramp(1, false, 1), Ok(0x0201));
+        assert_eq!(ramp(31, false, 1), Ok(0x3e01));
+        assert_eq!(ramp(32, false, 1), Err(ProgramError::InvalidOperand));
+        assert_eq!(wait(0, true), Err(ProgramError::InvalidOperand));
+        assert_eq!(wait(1, true), Ok(0x4200));
+        assert_eq!(wait(31, true), Ok(0x7e00));
+        assert_eq!(wait(32, true), Err(ProgramError::InvalidOperand));
+    }
+
+    #[test]
+    fn ramp_increments_run_from_minus_255_to_255() {
+        assert_eq!(ramp(1, false, 255), Ok(0x02ff));
+        assert_eq!(ramp(1, false, 256), Err(ProgramError::InvalidOperand));
+        assert_eq!(ramp(1, false, -1), Ok(0x0301));
+        assert_eq!(ramp(1, false, -255), Ok(0x03ff));
+        assert_eq!(ramp(1, false, -256), Err(ProgramError::InvalidOperand));
+        assert_eq!(ramp(1, false, i16::MIN), Err(ProgramError::InvalidOperand));
+        assert_eq!(ramp(1, false, 0), wait(1, false));
+    }
+
+    #[test]
+    fn branch_loop_counts_and_addresses_are_bounded() {
+        assert_eq!(branch(0, 0), Ok(0xa000));
+        assert_eq!(branch(MAX_LOOP_COUNT, 95), Ok(0xbfdf));
+        assert_eq!(branch(64, 0), Err(ProgramError::InvalidOperand));
+        assert_eq!(branch(0, 96), Err(ProgramError::InvalidOperand));
+    }
+
+    #[test]
+    fn jumps_skip_at_most_31_instructions() {
+        let (a, b) = (Variable::A, Variable::C);
+        assert_eq!(jump(Condition::NotEqual, 0, a, b), Ok(0x8802));
+        assert_eq!(jump(Condition::Less, MAX_SKIP, a, b), Ok(0x8bf2));
+        assert_eq!(jump(Condition::GreaterOrEqual, 1, b, a), Ok(0x8c18));
+        assert_eq!(jump(Condition::Equal, 32, a, b), Err(ProgramError::InvalidOperand));
+    }
+
+    #[test]
+    fn mux_addresses_lie_in_the_program_memory() {
+        assert_eq!(mux_map_start(95), Ok(0x9c5f));
+        assert_eq!(mux_ld_end(95), Ok(0x9cdf));
+        assert_eq!(mux_map_addr(95), Ok(0x9fdf));
+        assert_eq!(mux_map_start(96), Err(ProgramError::InvalidOperand));
+        assert_eq!(mux_ld_end(96), Err(ProgramError::InvalidOperand));
+        assert_eq!(mux_map_addr(96), Err(ProgramError::InvalidOperand));
+        assert_eq!(mux_map_addr(u8::MAX), Err(ProgramError::InvalidOperand));
+        assert_eq!(mux_sel(Some(D::D9)), 0x9d09);
+        assert_eq!(mux_clr(), 0x9d00);
+    }
+
+    #[test]
+    fn byte_operands_take_the_whole_range() {
+        assert_eq!(set_pwm(0), 0x4000);
+        assert_eq!(set_pwm(255), 0x40ff);
+        assert_eq!(load(Variable::C, 255), 0x98ff);
+        assert_eq!(add(Variable::B, 255), 0x95ff);
+        assert_eq!(sub(Variable::A, 255), 0x92ff);
+    }
+
+    #[test]
+    fn repeat_counts_run_from_1_to_64() {
+        let body = |asm: &mut Assembler| {
+            asm.set_pwm(1);
+        };
+        assert_eq!(
+            assembled(|asm| {
+                asm.repeat(0, body);
+            }),
+            Err(ProgramError::InvalidOperand)
+        );
+        assert_eq!(
+            assembled(|asm| {
+                asm.repeat(1, body);
+            }),
+            Ok([0x4001, 0, 0, 0])
+        );
+        assert_eq!(
+            assembled(|asm| {
+                asm.repeat(64, body);
+            }),
+            Ok([0x4001, 0xbf80, 0, 0])
+        );
+        assert_eq!(
+            assembled(|asm| {
+                asm.repeat(65, body);
+            }),
+            Err(ProgramError::InvalidOperand)
+        );
+    }
+
+    #[test]
+    fn assembler_keeps_the_first_operand_error() {
+        let words = assembled(|asm| {
+            asm.set_pwm(1).ramp(32, false, 1).branch(64, 0);
+        });
+        assert_eq!(words, Err(ProgramError::InvalidOperand));
+        let mut asm = Assembler::new();
+        for _ in 0..PROGRAM_MEMORY_SIZE {
+            asm.set_pwm(0);
+        }
+        assert_eq!(asm.instructions().map(|w| w.len()), Ok(PROGRAM_MEMORY_SIZE));
+        asm.set_pwm(0);
+        assert_eq!(asm.instructions(), Err(ProgramError::TooLarge));
+    }
+
+    #[test]
+    fn relocation_keeps_targets_in_the_program_memory() {
+        let mut words = [branch(0, 0).unwrap(), mux_map_addr(1).unwrap()];
+        relocate(&mut words, 94).unwrap();
+        assert_eq!(words, [0xa05e, 0x9fdf]);
+        let mut words = [branch(0, 2).unwrap(), set_pwm(0)];
+        assert_eq!(relocate(&mut words, 94), Err(ProgramError::InvalidOperand));
+        assert_eq!(words, [0xa002, 0x4000]);
+        assert_eq!(relocate(&mut [0; 2], 95), Err(ProgramError::TooLarge));
+    }
+}
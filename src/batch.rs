@@ -0,0 +1,344 @@
+//! Deferred register writes
+//!
+//! A `Batch` stages register changes in RAM instead of sending them immediately. `flush` then
+//! writes every staged register, coalescing runs of consecutive registers into single
+//! auto-increment bursts where the device supports it (see `transaction::auto_increments`).
+//! Staging the same register twice keeps only the last value, so per-frame animation code can set
+//! everything it needs and pay for a handful of transactions rather than one per setter.
+//!
+//! `Lp55231::stage_rgb` and `Lp55231::stage_color` stage colors as `set_rgb` and `set_color` write
+//! them, after color correction, white point, curves and trims, whereas `Batch::set_rgb` stages the
+//! raw values.
+//!
+//! Registers under a slew limit (see `slew`), the direct PWM registers and the master faders, are
+//! written one at a time, so that the limit applies to them as to any other write.
+//!
+//! A batch holding a whole frame can be committed with `flush_blanked`, which gates the outputs
+//! off around the writes so that a partially written frame is never visible.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use color::{Rgb, RgbGroup};
+use registers as reg;
+use slew::slew_limited;
+use transaction::auto_increments;
+use {Brightness, ChannelConfig, Error, Fader, Lp55231, OutputMask, D};
+
+/// Number of registers a `Batch` can stage, covering every register up to `GAIN_CHANGE`
+pub const BATCH_REGISTERS: usize = reg::GAIN_CHANGE as usize + 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// How outputs are gated off while a frame is committed
+pub enum Blanking {
+    /// Turn every D line off with the on/off mask
+    Outputs,
+    /// Set the master fader to zero. Only blanks D lines assigned to the fader.
+    Fader(Fader),
+}
+
+/// Register writes staged for a later `flush`
+pub struct Batch {
+    /// Staged value of each register
+    values: [u8; BATCH_REGISTERS],
+    /// Registers with a staged value, one bit per register
+    dirty: u128,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Batch {
+    /// Create an empty batch
+    pub fn new() -> Self {
+        Batch {
+            values: [0; BATCH_REGISTERS],
+            dirty: 0,
+        }
+    }
+
+    /// Are no writes staged
+    pub fn is_empty(&self) -> bool {
+        self.dirty == 0
+    }
+
+    /// Discard every staged write
+    pub fn clear(&mut self) {
+        self.dirty = 0;
+    }
+
+    /// Stage a write of the register. Returns `false`, staging nothing, if the register is beyond
+    /// `GAIN_CHANGE`.
+    pub fn write(&mut self, register: u8, value: u8) -> bool {
+        if usize::from(register) >= BATCH_REGISTERS {
+            return false;
+        }
+        self.values[usize::from(register)] = value;
+        self.dirty |= 1 << register;
+        true
+    }
+
+    /// Stage a write of a typed register
+    pub fn write_register<R: reg::Register>(&mut self, value: R) {
+        self.write(R::ADDRESS, value.encode());
+    }
+
+    /// Stage setting the D line to the provided PWM value
+    pub fn set_pwm<B: Into<Brightness>>(&mut self, d: D, pwm: B) {
+        self.write(reg::D_PWM_BASE + u8::from(d), pwm.into().0);
+    }
+
+    /// Stage setting the red, green and blue PWM values of an RGB LED, as raw register values
+    pub fn set_rgb(&mut self, led: RgbGroup, r: u8, g: u8, b: u8) {
+        self.set_pwm(led.red, r);
+        self.set_pwm(led.green, g);
+        self.set_pwm(led.blue, b);
+    }
+
+    /// Stage configuring the D line's control register
+    pub fn configure_channel(&mut self, d: D, config: ChannelConfig) {
+        self.write(reg::D_CTRL_BASE + u8::from(d), u8::from(config));
+    }
+
+    /// Stage turning the D lines in the set on, and all others off
+    pub fn set_outputs(&mut self, outputs: OutputMask) {
+        self.write(reg::OUTPUT_ONOFF_MSB, outputs.msb());
+        self.write(reg::OUTPUT_ONOFF_LSB, outputs.lsb());
+    }
+
+    /// Is the register staged
+    fn is_dirty(&self, register: usize) -> bool {
+        self.dirty & (1 << register) != 0
+    }
+
+    /// Unstage the register, returning its staged value, if any
+    fn take(&mut self, register: u8) -> Option<u8> {
+        if self.is_dirty(usize::from(register)) {
+            self.dirty &= !(1 << register);
+            Some(self.values[usize::from(register)])
+        } else {
+            None
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Stage setting an RGB LED to the red, green and blue values as `set_rgb` would write them:
+    /// after its color correction and white point, and each D line's curve and trim
+    pub fn stage_rgb(&self, batch: &mut Batch, led: RgbGroup, r: u8, g: u8, b: u8) {
+        let c = self.correct_color(led, Rgb::new(r, g, b));
+        batch.set_pwm(led.red, self.output_pwm(led.red, c.r));
+        batch.set_pwm(led.green, self.output_pwm(led.green, c.g));
+        batch.set_pwm(led.blue, self.output_pwm(led.blue, c.b));
+    }
+
+    /// Stage setting an RGB LED to the color as `set_color` would write it, in the color space
+    /// selected with `set_color_space`
+    pub fn stage_color<C: Into<Rgb>>(&self, batch: &mut Batch, led: RgbGroup, color: C) {
+        let c = self.color_space.linearize(color.into());
+        self.stage_rgb(batch, led, c.r, c.g, c.b);
+    }
+
+    /// Write every register staged in the batch, returning the number of transactions used. The
+    /// batch is emptied as registers are written; if a write fails, the registers not yet written
+    /// remain staged.
+    pub fn flush(&mut self, batch: &mut Batch) -> Result<usize, Error<E>> {
+        let mut buf = [0u8; BATCH_REGISTERS + 1];
+        let mut transactions = 0;
+        let mut start = 0;
+        while start < BATCH_REGISTERS {
+            if !batch.is_dirty(start) {
+                start += 1;
+                continue;
+            }
+            // Bursts never touch the registers without auto-increment, 0x16-0x1E, nor the
+            // slew-limited master faders, which are written one at a time
+            let bursts = |r: usize| auto_increments(r as u8) && !slew_limited(r as u8);
+            let mut end = start + 1;
+            while end < BATCH_REGISTERS && batch.is_dirty(end) && bursts(start) && bursts(end) {
+                end += 1;
+            }
+            let len = end - start;
+            buf[0] = start as u8;
+            buf[1..=len].copy_from_slice(&batch.values[start..end]);
+            self.send(&buf[..=len])?;
+            for r in start..end {
+                batch.dirty &= !(1 << r);
+            }
+            transactions += 1;
+            start = end;
+        }
+        Ok(transactions)
+    }
+
+    /// Write every register staged in the batch with the outputs blanked, restoring them once
+    /// the whole batch has been written. Any staged change to the registers used for blanking is
+    /// applied on restore rather than mid-frame. Returns the number of transactions used for the
+    /// staged registers.
+    ///
+    /// With `Blanking::Fader`, the fader is zeroed and put back to its value regardless of any
+    /// slew limit on it (see `set_fader_slew_limit`), so that the blanking is instant and leaves
+    /// the fader as it was; a staged change to the fader is then written as usual, within the
+    /// limit.
+    pub fn flush_blanked(
+        &mut self,
+        batch: &mut Batch,
+        blanking: Blanking,
+    ) -> Result<usize, Error<E>> {
+        match blanking {
+            Blanking::Outputs => {
+                let saved = self.outputs()?;
+                let msb = batch
+                    .take(reg::OUTPUT_ONOFF_MSB)
+                    .unwrap_or_else(|| saved.msb());
+                let lsb = batch
+                    .take(reg::OUTPUT_ONOFF_LSB)
+                    .unwrap_or_else(|| saved.lsb());
+                self.set_outputs(OutputMask::none())?;
+                let transactions = self.flush(batch)?;
+                self.set_outputs(OutputMask::from_bytes(msb, lsb))?;
+                Ok(transactions)
+            }
+            Blanking::Fader(f) => {
+                let register = reg::MASTER_FADE_1 + u8::from(f);
+                let saved = self.read(register)?;
+                let staged = batch.take(register);
+                self.send_unlimited(&[register, 0])?;
+                let transactions = self.flush(batch)?;
+                self.send_unlimited(&[register, saved])?;
+                if let Some(value) = staged {
+                    self.send(&[register, value])?;
+                }
+                Ok(transactions)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock;
+
+    /// A batch staging a run of registers that auto-increment, two direct PWM registers, which
+    /// do not, and a run after them
+    fn staged() -> Batch {
+        let mut batch = Batch::new();
+        batch.set_outputs(OutputMask::all());
+        batch.write(reg::D1_CTRL, 0x20);
+        batch.write(reg::D2_CTRL, 0x21);
+        batch.write(reg::D1_CTRL, 0x22);
+        batch.write(reg::D8_PWM, 0x30);
+        batch.write(reg::D9_PWM, 0x31);
+        batch.write(reg::D1_I_CTL, 0x40);
+        batch.write(reg::D2_I_CTL, 0x41);
+        batch
+    }
+
+    #[test]
+    fn flush_never_bursts_across_the_pwm_registers() {
+        let mut lp = mock::enabled();
+        let mut batch = staged();
+        assert_eq!(lp.flush(&mut batch).unwrap(), 4);
+        assert!(batch.is_empty());
+        assert_eq!(
+            lp.i2c.writes,
+            [
+                &[reg::OUTPUT_ONOFF_MSB, 0x01, 0xff, 0x22, 0x21][..],
+                &[reg::D8_PWM, 0x30],
+                &[reg::D9_PWM, 0x31],
+                &[reg::D1_I_CTL, 0x40, 0x41],
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_flush_keeps_unwritten_registers_staged() {
+        let mut lp = mock::enabled();
+        let mut batch = staged();
+        lp.i2c.fail_after = Some(2);
+        assert!(lp.flush(&mut batch).is_err());
+        assert!(!batch.is_empty());
+
+        lp.i2c.fail_after = None;
+        lp.i2c.clear();
+        assert_eq!(lp.flush(&mut batch).unwrap(), 2);
+        assert_eq!(
+            lp.i2c.writes,
+            [&[reg::D9_PWM, 0x31][..], &[reg::D1_I_CTL, 0x40, 0x41]]
+        );
+    }
+
+    #[test]
+    fn flush_blanked_restores_the_outputs() {
+        let mut lp = mock::enabled();
+        lp.set_outputs(OutputMask::all()).unwrap();
+        let mut batch = Batch::new();
+        batch.set_pwm(D::D1, 0x80);
+        lp.flush_blanked(&mut batch, Blanking::Outputs).unwrap();
+        let writes: &[&[u8]] = &[
+            &[reg::OUTPUT_ONOFF_MSB, 0x01, 0xff],
+            &[reg::OUTPUT_ONOFF_MSB, 0x00, 0x00],
+            &[reg::D1_PWM, 0x80],
+            &[reg::OUTPUT_ONOFF_MSB, 0x01, 0xff],
+        ];
+        assert_eq!(lp.i2c.writes, writes);
+    }
+
+    #[test]
+    fn flush_writes_the_faders_through_the_slew_limit() {
+        let mut lp = mock::enabled();
+        lp.set_fader_slew_limit(Fader::F1, Some(10)).unwrap();
+        lp.i2c.clear();
+        let mut batch = Batch::new();
+        batch.write(reg::MASTER_FADE_1, 200);
+        batch.write(reg::MASTER_FADE_2, 50);
+        batch.write(reg::MASTER_FADE_3, 60);
+        assert_eq!(lp.flush(&mut batch).unwrap(), 3);
+        let writes: &[&[u8]] = &[
+            &[reg::MASTER_FADE_1, 10],
+            &[reg::MASTER_FADE_2, 50],
+            &[reg::MASTER_FADE_3, 60],
+        ];
+        assert_eq!(lp.i2c.writes, writes);
+    }
+
+    #[test]
+    fn fader_blanking_bypasses_the_slew_limit() {
+        let mut lp = mock::enabled();
+        lp.set_master_fader(Fader::F1, 200).unwrap();
+        lp.set_fader_slew_limit(Fader::F1, Some(10)).unwrap();
+        lp.i2c.clear();
+        let mut batch = Batch::new();
+        batch.set_pwm(D::D1, 0x80);
+        lp.flush_blanked(&mut batch, Blanking::Fader(Fader::F1)).unwrap();
+        let writes: &[&[u8]] = &[
+            &[reg::MASTER_FADE_1, 0],
+            &[reg::D1_PWM, 0x80],
+            &[reg::MASTER_FADE_1, 200],
+        ];
+        assert_eq!(lp.i2c.writes, writes);
+
+        // A staged fader change is applied after the restore, within the limit
+        lp.i2c.clear();
+        batch.write(reg::MASTER_FADE_1, 100);
+        lp.flush_blanked(&mut batch, Blanking::Fader(Fader::F1)).unwrap();
+        let writes: &[&[u8]] = &[
+            &[reg::MASTER_FADE_1, 0],
+            &[reg::MASTER_FADE_1, 200],
+            &[reg::MASTER_FADE_1, 190],
+        ];
+        assert_eq!(lp.i2c.writes, writes);
+    }
+}
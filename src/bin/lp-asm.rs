@@ -0,0 +1,97 @@
+//! Assemble engine programs into the binary blob format
+//!
+//! ```text
+//! lp-asm <source> <blob>
+//! lp-asm --hex [--start E1,E2,E3] <hex> <blob>
+//! ```
+//!
+//! `source` is TI LP5523/LP55231 assembly source, as edited in TI's evaluation software, and is
+//! assembled as by `source::assemble`; its `.segment` directives set the engine start addresses.
+//! With `--hex`, the input is instead the hexadecimal instruction words TI's tools export, four
+//! digits per instruction (see `Program::from_hex`), and the engine start addresses are given with
+//! `--start`, defaulting to 0.
+//!
+//! The resulting blob can be embedded with `include_bytes!` and loaded in place through a
+//! `ProgramImage`, or decoded with `Program::from_blob`. Programs built in Rust with
+//! `asm::Assembler` need no separate tool: `Program::to_blob` writes the same blob, for instance
+//! from a build script.
+
+extern crate lp55231;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use lp55231::program::{Program, BLOB_MAX_LEN};
+use lp55231::source;
+
+/// Parse a `--start` argument of three comma-separated start addresses
+fn parse_start(arg: &str) -> Option<[u8; 3]> {
+    let mut start = [0u8; 3];
+    let mut parts = arg.split(',');
+    for s in start.iter_mut() {
+        let part = parts.next()?.trim();
+        *s = match part.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).ok()?,
+            None => part.parse().ok()?,
+        };
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(start)
+}
+
+/// Print usage and exit
+fn usage() -> ! {
+    eprintln!("usage: lp-asm <source> <blob>");
+    eprintln!("       lp-asm --hex [--start E1,E2,E3] <hex> <blob>");
+    process::exit(2);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut hex = false;
+    let mut start = None;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--hex" {
+            hex = true;
+        } else if arg == "--start" || arg == "-s" {
+            start = Some(
+                args.next()
+                    .as_deref()
+                    .and_then(parse_start)
+                    .unwrap_or_else(|| usage()),
+            );
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.len() != 2 || (start.is_some() && !hex) {
+        usage();
+    }
+
+    let text = fs::read_to_string(&paths[0]).unwrap_or_else(|e| {
+        eprintln!("lp-asm: {}: {}", paths[0], e);
+        process::exit(1);
+    });
+    let program = if hex {
+        Program::from_hex(start.unwrap_or([0; 3]), &text).map_err(|e| e.to_string())
+    } else {
+        source::assemble(&text).map_err(|e| e.to_string())
+    };
+    let program = program.unwrap_or_else(|e| {
+        eprintln!("lp-asm: {}: {}", paths[0], e);
+        process::exit(1);
+    });
+    let mut blob = [0u8; BLOB_MAX_LEN];
+    let len = program.to_blob(&mut blob).unwrap_or_else(|e| {
+        eprintln!("lp-asm: {}", e);
+        process::exit(1);
+    });
+    if let Err(e) = fs::write(&paths[1], &blob[..len]) {
+        eprintln!("lp-asm: {}: {}", paths[1], e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,218 @@
+//! Blink codes
+//!
+//! A `BlinkCode` flashes a message on a single D line from E1, for diagnostics on devices without
+//! a display: either a short text in Morse code, or a count of blinks, as for numeric error codes.
+//! By default the code repeats forever, with a pause in between.
+//!
+//! ```ignore
+//! lp.start_blink_code(D::D7, &BlinkCode::count(3, 250))?;
+//! lp.start_blink_code(D::D7, &BlinkCode::morse("SOS", 120).with_pause(2000))?;
+//! ```
+//!
+//! Morse code follows the usual timing, in units of the dot length: a dash lasts three, the gap
+//! between the elements of a letter one, between letters three, and between words seven. A count
+//! blinks on and off for one unit each. Every blink takes a few instructions, so messages are
+//! limited to a few characters, and counts to a dozen or so blinks.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm::{Assembler, End};
+use patterns::{ticks, wait_ticks, CYCLE_TICKS};
+use program::{Program, ProgramError};
+use {Engine, Error, Lp55231, Millis, OutputMask, D};
+
+/// Longest Morse message, in characters
+const MAX_MESSAGE: usize = 16;
+
+/// Morse code of the letters A to Z, then the digits 0 to 9
+const MORSE: [&str; 36] = [
+    ".-", "-...", "-.-.", "-..", ".", "..-.", "--.", "....", "..", ".---", "-.-", ".-..", "--",
+    "-.", "---", ".--.", "--.-", ".-.", "...", "-", "..-", "...-", ".--", "-..-", "-.--", "--..",
+    "-----", ".----", "..---", "...--", "....-", ".....", "-....", "--...", "---..", "----.",
+];
+
+/// The Morse code of a character, ignoring case
+fn morse(c: u8) -> Option<&'static str> {
+    match c.to_ascii_uppercase() {
+        c @ b'A'..=b'Z' => Some(MORSE[usize::from(c - b'A')]),
+        c @ b'0'..=b'9' => Some(MORSE[26 + usize::from(c - b'0')]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// What a blink code flashes
+enum Code {
+    /// A text in Morse code, of letters, digits and spaces
+    Morse {
+        /// The text
+        text: [u8; MAX_MESSAGE],
+        /// Length of the text, which may exceed `MAX_MESSAGE`
+        len: usize,
+    },
+    /// A number of blinks
+    Count(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// A message flashed on a D line
+pub struct BlinkCode {
+    /// What is flashed
+    code: Code,
+    /// Length of a Morse dot, or of each blink and the gap after it
+    unit: Millis,
+    /// Time the D line stays dark before the code repeats
+    pause: Millis,
+    /// Repeat the code forever rather than flash it once
+    repeat: bool,
+    /// PWM value of the D line while lit
+    brightness: u8,
+}
+
+impl BlinkCode {
+    /// Flash a text in Morse code, with dots `unit` long, at full brightness, repeating after a
+    /// word gap
+    ///
+    /// Letters, digits and spaces are accepted; anything else fails when building the program.
+    pub fn morse<T: Into<Millis>>(message: &str, unit: T) -> Self {
+        let mut text = [0u8; MAX_MESSAGE];
+        for (slot, c) in text.iter_mut().zip(message.bytes()) {
+            *slot = c;
+        }
+        let unit = unit.into();
+        BlinkCode {
+            code: Code::Morse {
+                text,
+                len: message.len(),
+            },
+            unit,
+            pause: Millis(unit.0.saturating_mul(7)),
+            repeat: true,
+            brightness: 255,
+        }
+    }
+
+    /// Blink `count` times, each blink and the gap after it `unit` long, at full brightness,
+    /// repeating after a pause of seven units
+    pub fn count<T: Into<Millis>>(count: u8, unit: T) -> Self {
+        let unit = unit.into();
+        BlinkCode {
+            code: Code::Count(count),
+            unit,
+            pause: Millis(unit.0.saturating_mul(7)),
+            repeat: true,
+            brightness: 255,
+        }
+    }
+
+    /// Stay dark for `pause` between repetitions, after the code's last gap
+    pub fn with_pause<T: Into<Millis>>(mut self, pause: T) -> Self {
+        self.pause = pause.into();
+        self
+    }
+
+    /// Repeat the code forever, or flash it once and end
+    pub fn with_repeat(mut self, repeat: bool) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Light the D line with the PWM value rather than at full brightness
+    pub fn with_brightness(mut self, brightness: u8) -> Self {
+        self.brightness = brightness;
+        self
+    }
+
+    /// Build the code's program for E1, starting at address 0
+    ///
+    /// Fails with `ProgramError::InvalidOperand` for an empty message or count, or a character
+    /// with no Morse code, and with `ProgramError::TooLarge` if the code does not fit in the
+    /// program memory.
+    pub fn program(&self) -> Result<Program, ProgramError> {
+        let mut asm = Assembler::new();
+        let unit = ticks(self.unit);
+        let pause = ticks(self.pause);
+        if self.repeat {
+            let mut result = Ok(());
+            asm.forever(|asm| {
+                result = self.flash(asm, unit);
+                wait_ticks(asm, pause);
+            });
+            result?;
+        } else {
+            self.flash(&mut asm, unit)?;
+            asm.end(End::default());
+        }
+        asm.finish([0; 3])
+    }
+
+    /// Flash the code once, the last gap included
+    fn flash(&self, asm: &mut Assembler, unit: u32) -> Result<(), ProgramError> {
+        match self.code {
+            Code::Count(0) => Err(ProgramError::InvalidOperand),
+            Code::Count(count) => {
+                for _ in 0..count {
+                    self.blink(asm, unit, unit);
+                }
+                Ok(())
+            }
+            Code::Morse { len, .. } if len > MAX_MESSAGE => Err(ProgramError::TooLarge),
+            Code::Morse { text, len } => {
+                let words = text[..len].split(|c| *c == b' ').filter(|w| !w.is_empty());
+                let mut flashed = false;
+                for (w, word) in words.enumerate() {
+                    if w > 0 {
+                        // The word gap, of which the letter gap is already waited
+                        wait_ticks(asm, 4 * unit);
+                    }
+                    for (l, c) in word.iter().enumerate() {
+                        let code = morse(*c).ok_or(ProgramError::InvalidOperand)?;
+                        if l > 0 {
+                            wait_ticks(asm, 2 * unit);
+                        }
+                        for element in code.bytes() {
+                            let on = if element == b'-' { 3 * unit } else { unit };
+                            self.blink(asm, on, unit);
+                        }
+                    }
+                    flashed = true;
+                }
+                if flashed {
+                    Ok(())
+                } else {
+                    Err(ProgramError::InvalidOperand)
+                }
+            }
+        }
+    }
+
+    /// Light the D line for `on` clock ticks, then keep it dark for `off`
+    fn blink(&self, asm: &mut Assembler, on: u32, off: u32) {
+        // Each set_pwm takes a cycle
+        asm.set_pwm(self.brightness);
+        wait_ticks(asm, on.saturating_sub(CYCLE_TICKS));
+        asm.set_pwm(0);
+        wait_ticks(asm, off.saturating_sub(CYCLE_TICKS));
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Flash the blink code on the D line from E1 (see `BlinkCode::program`)
+    ///
+    /// E1 is mapped to the D line alone and the program replaces any running program (see
+    /// `start_program`). The D line must be on (see `set_outputs`).
+    pub fn start_blink_code(&mut self, d: D, code: &BlinkCode) -> Result<(), Error<E>> {
+        let program = code.program()?;
+        self.set_engine_map(Engine::E1, OutputMask::from(d))?;
+        self.start_program(&program)
+    }
+}
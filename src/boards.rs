@@ -0,0 +1,55 @@
+//! Board layout presets
+//!
+//! Channel to RGB LED mappings for common boards, so that e.g.
+//! `lp.set_rgb(boards::sparkfun().led(1).unwrap(), 0xff, 0, 0)` lights the first LED red without
+//! reverse-engineering which D line is which color.
+//!
+//! Both presets follow TI's reference layout, in which the red dies are driven by D7-D9 (the
+//! outputs powered from VDD, since red LEDs have the lowest forward voltage) and the green and
+//! blue dies by D1-D6 (powered from the charge pump).
+
+use color::RgbGroup;
+use D;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A board carrying an LP55231 and three RGB LEDs
+pub struct Board {
+    /// The RGB LEDs, in silkscreen order
+    leds: [RgbGroup; 3],
+}
+
+impl Board {
+    /// Describe a board by its RGB LEDs, in silkscreen order
+    pub fn new(leds: [RgbGroup; 3]) -> Self {
+        Board { leds }
+    }
+
+    /// The RGB LED with the given 1-based silkscreen number, if there is one
+    pub fn led(&self, n: usize) -> Option<RgbGroup> {
+        n.checked_sub(1).and_then(|i| self.leds.get(i)).cloned()
+    }
+
+    /// All RGB LEDs, in silkscreen order
+    pub fn leds(&self) -> &[RgbGroup; 3] {
+        &self.leds
+    }
+}
+
+/// The TI reference layout shared by the presets
+fn reference() -> Board {
+    Board::new([
+        RgbGroup::new(D::D7, D::D1, D::D2),
+        RgbGroup::new(D::D8, D::D3, D::D4),
+        RgbGroup::new(D::D9, D::D5, D::D6),
+    ])
+}
+
+/// SparkFun LP55231 breakout, with LEDs labelled 1-3
+pub fn sparkfun() -> Board {
+    reference()
+}
+
+/// TI LP55231 evaluation module
+pub fn ti_evm() -> Board {
+    reference()
+}
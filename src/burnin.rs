@@ -0,0 +1,155 @@
+//! Production burn-in
+//!
+//! `Lp55231::burn_in` stresses every D line in turn, then together, while sampling the die
+//! temperature and the LED test ADC, and reports pass or fail per D line. It is meant for
+//! bring-up jigs and end-of-line testing, and takes as long as its `BurnIn` configuration asks.
+//!
+//! The sequence is:
+//!
+//! 1. Set every D line to the burn-in current
+//! 2. Alternate the odd and even D lines at full PWM, `cycles` times
+//! 3. Soak all D lines at full PWM for `soak` ms, sampling every `sample_interval` ms
+//!
+//! Currents and PWM values are restored afterwards, even on failure.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use selftest::{ChannelReport, SHORT_CIRCUIT_LIMIT};
+use {DegreesC, Error, Lp55231, D};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Burn-in stress sequence configuration
+pub struct BurnIn {
+    /// Output current of every D line, in 100 µA steps
+    pub current: u8,
+    /// Number of odd/even alternation cycles
+    pub cycles: u16,
+    /// Time each half of an alternation cycle is held, in ms
+    pub step: u16,
+    /// Duration of the all-on thermal soak, in ms
+    pub soak: u16,
+    /// Time between samples during the soak, in ms
+    pub sample_interval: u16,
+    /// Die temperature above which the burn-in fails
+    pub max_temp: DegreesC,
+}
+
+impl Default for BurnIn {
+    /// Full current, 100 alternations of 50 ms, and a 10 s soak sampled every second
+    fn default() -> Self {
+        BurnIn {
+            current: 0xff,
+            cycles: 100,
+            step: 50,
+            soak: 10_000,
+            sample_interval: 1000,
+            max_temp: DegreesC(85),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Result of `Lp55231::burn_in`
+pub struct BurnInReport {
+    /// Highest die temperature sampled
+    pub max_temp: DegreesC,
+    /// Whether the die stayed at or below the configured temperature limit
+    pub temp_ok: bool,
+    /// Results of D1 through D9. Each holds the lowest voltage sampled, and passes only if every
+    /// sample was within the healthy range.
+    pub channels: [ChannelReport; 9],
+}
+
+impl BurnInReport {
+    /// Whether every check passed
+    pub fn passed(&self) -> bool {
+        self.temp_ok && self.channels.iter().all(|c| c.passed)
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Run the burn-in stress sequence. Must be called after `enable`, with the engines stopped.
+    pub fn burn_in<DL>(&mut self, config: &BurnIn, delay: &mut DL) -> Result<BurnInReport, Error<E>>
+    where
+        DL: DelayMs<u8> + DelayMs<u16>,
+    {
+        let mut currents = [0u8; 9];
+        let mut pwms = [0u8; 9];
+        for d in D::iter() {
+            let i = usize::from(u8::from(d));
+            currents[i] = self.read(reg::D1_I_CTL + u8::from(d))?;
+            pwms[i] = self.pwm(d)?;
+        }
+
+        let report = self.stress(config, delay);
+
+        for d in D::iter() {
+            let i = usize::from(u8::from(d));
+            self.send(&[reg::D1_I_CTL + u8::from(d), currents[i]])?;
+            self.set_pwm(d, pwms[i])?;
+        }
+        report
+    }
+
+    /// Run the stress sequence of `burn_in`, without restoring the D lines
+    fn stress<DL>(&mut self, config: &BurnIn, delay: &mut DL) -> Result<BurnInReport, Error<E>>
+    where
+        DL: DelayMs<u8> + DelayMs<u16>,
+    {
+        for d in D::iter() {
+            self.send(&[reg::D1_I_CTL + u8::from(d), config.current])?;
+        }
+
+        for _ in 0..config.cycles {
+            for odd in &[true, false] {
+                for d in D::iter() {
+                    let on = (u8::from(d) % 2 == 0) == *odd;
+                    self.set_pwm(d, if on { 0xff } else { 0 })?;
+                }
+                DelayMs::<u16>::delay_ms(delay, config.step);
+            }
+        }
+
+        for d in D::iter() {
+            self.set_pwm(d, 0xff)?;
+        }
+        let vdd = self.measure_led_test(reg::TestCtl::LEDTEST_VDD.bits(), delay)?;
+        let mut report = BurnInReport {
+            max_temp: DegreesC(i8::MIN),
+            temp_ok: true,
+            channels: [ChannelReport {
+                voltage: i16::MAX,
+                passed: true,
+            }; 9],
+        };
+        let mut elapsed = 0;
+        loop {
+            let temp = self.temperature(delay)?;
+            report.max_temp = report.max_temp.max(temp);
+            report.temp_ok &= temp <= config.max_temp;
+            for (d, channel) in D::iter().zip(report.channels.iter_mut()) {
+                let voltage = self.measure_led_test(u8::from(d), delay)?;
+                channel.voltage = channel.voltage.min(voltage);
+                channel.passed &= voltage >= SHORT_CIRCUIT_LIMIT && voltage < vdd;
+            }
+            if elapsed >= config.soak {
+                break;
+            }
+            let wait = config.sample_interval.max(1).min(config.soak - elapsed);
+            DelayMs::<u16>::delay_ms(delay, wait);
+            elapsed += wait;
+        }
+        Ok(report)
+    }
+}
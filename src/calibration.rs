@@ -0,0 +1,204 @@
+//! Production calibration
+//!
+//! `Lp55231::calibrate` measures the forward voltage of the LED on each D line at a defined
+//! current, for binning and for later comparison. The LED test ADC measures the voltage on the D
+//! pin, which sits below the charge pump output by the forward voltage of the LED, so each D line
+//! is driven at full PWM and its forward voltage taken as VOUT less the pin voltage.
+//!
+//! The report also carries a brightness trim for each D line, to compensate for differences
+//! between LED bins, which production tooling fills in from luminance measurements and
+//! `Lp55231::load_trims` applies.
+//!
+//! `Lp55231::calibrate_temperature` measures the offset of the die temperature sensor at a known
+//! ambient, to be stored per device alongside the report and restored with
+//! `Lp55231::set_temperature_offset`.
+//!
+//! The resulting `CalibrationReport` can be persisted (with the `serde` feature) and compared
+//! against later measurements with `Lp55231::check_degradation`. LEDs nearing the end of their
+//! life typically show a drifting forward voltage well before they visibly fail.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Result of `Lp55231::check_degradation`
+pub struct DegradationReport {
+    /// The new measurements
+    pub current: CalibrationReport,
+    /// Change in forward voltage of D1 through D9 since the baseline, in mV
+    pub drift: [i16; 9],
+    /// D lines whose forward voltage drifted by more than the threshold
+    pub degraded: OutputMask,
+}
+
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {DegreesC, Error, Lp55231, OutputMask, D};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Result of `Lp55231::calibrate`
+pub struct CalibrationReport {
+    /// Output current the D lines were measured at, in 100 µA steps
+    pub current: u8,
+    /// Charge pump output voltage, in mV
+    pub vout: i16,
+    /// Forward voltage of the LEDs on D1 through D9, in mV
+    pub forward_voltage: [i16; 9],
+    /// Brightness trim of D1 through D9, out of 255 (see `Lp55231::set_trim`)
+    ///
+    /// `calibrate` records the trims in effect. Production tooling that measures the LEDs'
+    /// luminance fills these in, and `Lp55231::load_trims` applies them.
+    pub trim: [u8; 9],
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Measure the forward voltage of every D line at `current`, in 100 µA steps. Must be called
+    /// after `enable`, with the engines stopped.
+    ///
+    /// Each D line is driven alone at full PWM while it is measured. Currents and PWM values are
+    /// restored afterwards.
+    pub fn calibrate<DL: DelayMs<u8>>(
+        &mut self,
+        current: u8,
+        delay: &mut DL,
+    ) -> Result<CalibrationReport, Error<E>> {
+        let mut currents = [0u8; 9];
+        let mut pwms = [0u8; 9];
+        for d in D::iter() {
+            let i = usize::from(u8::from(d));
+            currents[i] = self.read(reg::D1_I_CTL + u8::from(d))?;
+            pwms[i] = self.pwm(d)?;
+        }
+
+        let report = self.measure_forward_voltages(current, delay);
+
+        for d in D::iter() {
+            let i = usize::from(u8::from(d));
+            self.send(&[reg::D1_I_CTL + u8::from(d), currents[i]])?;
+            self.send(&[reg::D_PWM_BASE + u8::from(d), pwms[i]])?;
+        }
+        report
+    }
+
+    /// Take the measurements of `calibrate`, without restoring the D lines
+    fn measure_forward_voltages<DL: DelayMs<u8>>(
+        &mut self,
+        current: u8,
+        delay: &mut DL,
+    ) -> Result<CalibrationReport, Error<E>> {
+        for d in D::iter() {
+            self.send(&[reg::D1_I_CTL + u8::from(d), current])?;
+            self.send(&[reg::D_PWM_BASE + u8::from(d), 0])?;
+        }
+        let mut report = CalibrationReport {
+            current,
+            vout: self.measure_vout(delay)?,
+            forward_voltage: [0; 9],
+            trim: self.trims,
+        };
+        for (d, vf) in D::iter().zip(report.forward_voltage.iter_mut()) {
+            *vf = report.vout - self.measure_pin(d, 0, delay)?;
+        }
+        Ok(report)
+    }
+
+    /// Measure the forward voltage, in mV, of the LED on the D line at its current drive setting.
+    /// Must be called after `enable`, with the engines stopped.
+    ///
+    /// The D line is driven at full PWM while it is measured, and its PWM value restored
+    /// afterwards; the other D lines are left as they are, so switch them off first for a
+    /// measurement undisturbed by their load on the charge pump. Unlike `calibrate`, which
+    /// measures every D line at a set current, this takes one measurement of one D line, as a
+    /// building block for custom checks.
+    pub fn measure_led_vf<DL: DelayMs<u8>>(
+        &mut self,
+        d: D,
+        delay: &mut DL,
+    ) -> Result<i16, Error<E>> {
+        let pwm = self.pwm(d)?;
+        let vout = self.measure_vout(delay)?;
+        Ok(vout - self.measure_pin(d, pwm, delay)?)
+    }
+
+    /// Drive the D line at full PWM and measure its pin voltage, in mV, then set its PWM value to
+    /// `restore`
+    fn measure_pin<DL: DelayMs<u8>>(
+        &mut self,
+        d: D,
+        restore: u8,
+        delay: &mut DL,
+    ) -> Result<i16, Error<E>> {
+        self.send(&[reg::D_PWM_BASE + u8::from(d), 0xff])?;
+        delay.delay_ms(3);
+        let pin = self.measure_led_test(u8::from(d), delay);
+        self.send(&[reg::D_PWM_BASE + u8::from(d), restore])?;
+        pin
+    }
+
+    /// Measure the offset of the die temperature sensor, with the device at the known `ambient`
+    /// temperature, and apply it (see `set_temperature_offset`). Returns the offset, in °C, for
+    /// the application to store.
+    ///
+    /// The raw reading is averaged over `samples` conversions (see `average_temperature`). The
+    /// device should have settled at ambient, with its outputs off, so that the die is not
+    /// self-heated. On error, the previous offset is kept.
+    pub fn calibrate_temperature<DL: DelayMs<u8>>(
+        &mut self,
+        ambient: DegreesC,
+        samples: u8,
+        delay: &mut DL,
+    ) -> Result<i8, Error<E>> {
+        let previous = self.temperature_offset;
+        self.temperature_offset = 0;
+        let raw = match self.average_temperature(samples, delay) {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.temperature_offset = previous;
+                return Err(e);
+            }
+        };
+        let offset = (i16::from(ambient.0) - i16::from(raw.0)).clamp(-128, 127) as i8;
+        self.temperature_offset = offset;
+        Ok(offset)
+    }
+
+    /// Apply the brightness trims recorded in a calibration report (see `set_trims`)
+    pub fn load_trims(&mut self, report: &CalibrationReport) {
+        self.set_trims(report.trim);
+    }
+
+    /// Re-measure the forward voltages at the baseline's current, and flag every D line whose
+    /// forward voltage has drifted, in either direction, by more than `max_drift` mV. Must be
+    /// called after `enable`, with the engines stopped.
+    pub fn check_degradation<DL: DelayMs<u8>>(
+        &mut self,
+        baseline: &CalibrationReport,
+        max_drift: i16,
+        delay: &mut DL,
+    ) -> Result<DegradationReport, Error<E>> {
+        let current = self.calibrate(baseline.current, delay)?;
+        let mut drift = [0i16; 9];
+        let mut degraded = OutputMask::none();
+        for (d, dv) in D::iter().zip(drift.iter_mut()) {
+            let i = usize::from(u8::from(d));
+            *dv = current.forward_voltage[i] - baseline.forward_voltage[i];
+            if dv.unsigned_abs() > max_drift.unsigned_abs() {
+                degraded = degraded.with(d);
+            }
+        }
+        Ok(DegradationReport {
+            current,
+            drift,
+            degraded,
+        })
+    }
+}
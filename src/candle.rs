@@ -0,0 +1,114 @@
+//! Candle flicker
+//!
+//! A `Candle` flickers the D lines mapped to E1 like a flame, entirely on the device. The engine
+//! draws pseudo-random numbers with its variables: each step, B is raised by an odd constant and
+//! added to A, so that A runs through a scrambled sequence of all 256 values. The top bits of A
+//! then pick one of eight brightness levels, by comparing it against thresholds loaded into C.
+//! Most levels sit close to the base brightness, and a few dip towards the full depth, so that
+//! the flame mostly burns steadily and gutters now and then.
+//!
+//! ```ignore
+//! lp.start_candle(OutputMask::from(D::D3), &Candle::new(200, 120).with_seed(17))?;
+//! ```
+//!
+//! The program overwrites the global variable C, which the other engines and the host share (see
+//! `registers::GLOBAL_VAR`). Candles with different seeds flicker out of step, for several
+//! candles on several engines (see `Lp55231::load_engine_program`).
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm::{Assembler, Condition, Variable};
+use patterns::{ticks, PRESCALED_CYCLE_TICKS};
+use program::{Program, ProgramError};
+use {Engine, Error, Lp55231, Millis, OutputMask};
+
+/// Dip of each brightness level below the base, in eighths of the depth
+const LEVELS: [u8; 8] = [0, 0, 1, 1, 2, 3, 5, 8];
+
+/// Odd constant B is raised by on each step
+const STRIDE: u8 = 29;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// A flickering flame
+pub struct Candle {
+    /// PWM value of the steady flame
+    base: u8,
+    /// Largest dip below the base, in PWM steps
+    depth: u8,
+    /// Time each brightness level is held
+    interval: Millis,
+    /// Starting value of A
+    seed: u8,
+}
+
+impl Candle {
+    /// Flicker around `base`, dipping by up to `depth`, changing level every 60 ms
+    pub fn new(base: u8, depth: u8) -> Self {
+        Candle {
+            base,
+            depth,
+            interval: Millis(60),
+            seed: 0,
+        }
+    }
+
+    /// Hold each brightness level for `interval`, to the nearest 15.6 ms and at most 484 ms;
+    /// deeper dips are held for half as long
+    pub fn with_interval<T: Into<Millis>>(mut self, interval: T) -> Self {
+        self.interval = interval.into();
+        self
+    }
+
+    /// Start the pseudo-random sequence from `seed`, so that candles flicker out of step
+    pub fn with_seed(mut self, seed: u8) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Build the candle's program for E1, starting at address 0
+    pub fn program(&self) -> Result<Program, ProgramError> {
+        let cycles = (ticks(self.interval) + PRESCALED_CYCLE_TICKS / 2) / PRESCALED_CYCLE_TICKS;
+        let hold = cycles.clamp(2, 31) as u8;
+
+        let mut asm = Assembler::new();
+        asm.load(Variable::A, self.seed).load(Variable::B, 0);
+        let top = asm.address();
+        asm.add(Variable::B, STRIDE)
+            .add_vars(Variable::A, Variable::A, Variable::B);
+        for (i, dip) in LEVELS.iter().enumerate() {
+            let dip = (u16::from(self.depth) * u16::from(*dip) / 8) as u8;
+            let hold = if i >= 6 { hold / 2 } else { hold };
+            if i + 1 < LEVELS.len() {
+                // Levels run from the top values of A down; skip this one unless A has reached
+                // its threshold
+                asm.load(Variable::C, (256 - 32 * (i + 1)) as u8)
+                    .jump(Condition::Less, 3, Variable::A, Variable::C);
+            }
+            asm.set_pwm(self.base.saturating_sub(dip))
+                .wait(hold, true)
+                .branch(0, top);
+        }
+        asm.finish([0; 3])
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Flicker the D lines in the set from E1, forever (see `Candle::program`)
+    ///
+    /// E1 is mapped to the D lines and the program replaces any running program (see
+    /// `start_program`). The D lines must be on (see `set_outputs`).
+    pub fn start_candle(&mut self, outputs: OutputMask, candle: &Candle) -> Result<(), Error<E>> {
+        let program = candle.program()?;
+        self.set_engine_map(Engine::E1, outputs)?;
+        self.start_program(&program)
+    }
+}
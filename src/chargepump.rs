@@ -0,0 +1,137 @@
+//! Charge pump mode selection from the output wiring
+//!
+//! Each D line sinks current from an LED whose anode is powered either from the charge pump output
+//! (VOUT) or straight from the supply (VDD). A line drives its LED properly only while the anode
+//! voltage exceeds the LED's forward voltage by the current sink's headroom, so the charge pump
+//! mode follows from the forward voltages of the VOUT-powered LEDs and the supply range:
+//!
+//! - with no LED on VOUT, the charge pump is turned off,
+//! - when VDD always suffices, it is bypassed (1x),
+//! - when VDD never suffices, it is forced to 1.5x, and
+//! - otherwise it switches automatically as the supply droops, e.g. on a discharging battery.
+//!
+//! Declare the wiring in `Config::wiring` to have `Lp55231::apply_config` pick the mode. Wiring
+//! that cannot work (an LED that even VOUT at 1.5x, or VDD for a VDD-powered LED, cannot drive) is
+//! reported by `Wiring::charge_pump_mode`, and logged as a warning by `apply_config`, which then
+//! keeps the charge pump mode in `Config::misc`.
+
+use registers as reg;
+use D;
+
+/// Voltage, in mV, the current sinks need between the D pin and ground to regulate the full output
+/// current
+pub const HEADROOM: u16 = 100;
+
+/// Charge pump output voltage, in mV, in 1.5x mode
+pub const BOOSTED_VOUT: u16 = 4500;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Charge pump operating mode
+pub enum ChargePumpMode {
+    /// The charge pump is off
+    Off,
+    /// VOUT follows VDD (1x)
+    Bypass,
+    /// VOUT is boosted (1.5x)
+    Boost,
+    /// The device switches between 1x and 1.5x as the headroom requires (see
+    /// `registers::GainChange`)
+    Automatic,
+}
+
+impl From<ChargePumpMode> for reg::Misc {
+    fn from(m: ChargePumpMode) -> Self {
+        match m {
+            ChargePumpMode::Off => reg::Misc::empty(),
+            ChargePumpMode::Bypass => reg::Misc::CP_MODE_1x,
+            ChargePumpMode::Boost => reg::Misc::CP_MODE_1_5x,
+            ChargePumpMode::Automatic => reg::Misc::CP_MODE_AUTO,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Where an LED's anode is powered from
+pub enum Supply {
+    /// The charge pump output
+    Vout,
+    /// The supply, directly
+    Vdd,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The LED driven by a D line
+pub struct Output {
+    /// Where the LED's anode is powered from
+    pub supply: Supply,
+    /// Forward voltage of the LED at the output current, in mV
+    pub forward_voltage: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Wiring that cannot drive an LED
+pub enum WiringError {
+    /// The D line's LED is powered from VDD, and its forward voltage exceeds the minimum VDD
+    VddTooLow(D),
+    /// The D line's LED is powered from VOUT, and its forward voltage exceeds even the boosted
+    /// VOUT
+    VoutTooLow(D),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The LEDs wired to each D line, and the supply voltage range
+pub struct Wiring {
+    /// LED driven by D1 through D9, if any
+    pub outputs: [Option<Output>; 9],
+    /// Lowest supply voltage, in mV
+    pub vdd_min: u16,
+    /// Highest supply voltage, in mV
+    pub vdd_max: u16,
+}
+
+impl Wiring {
+    /// Create a wiring with no LEDs, for a supply between `vdd_min` and `vdd_max` mV
+    pub fn new(vdd_min: u16, vdd_max: u16) -> Self {
+        Wiring {
+            outputs: [None; 9],
+            vdd_min,
+            vdd_max,
+        }
+    }
+
+    /// Declare the LED driven by a D line
+    pub fn with_output(mut self, d: D, supply: Supply, forward_voltage: u16) -> Self {
+        self.outputs[usize::from(u8::from(d))] = Some(Output {
+            supply,
+            forward_voltage,
+        });
+        self
+    }
+
+    /// The charge pump mode that drives every LED most efficiently, or the first D line whose LED
+    /// cannot be driven
+    pub fn charge_pump_mode(&self) -> Result<ChargePumpMode, WiringError> {
+        let mut needed = None;
+        for (d, output) in D::iter().zip(self.outputs.iter()) {
+            let output = match output {
+                Some(output) => output,
+                None => continue,
+            };
+            let required = output.forward_voltage.saturating_add(HEADROOM);
+            match output.supply {
+                Supply::Vdd if required > self.vdd_min => return Err(WiringError::VddTooLow(d)),
+                Supply::Vdd => {}
+                Supply::Vout if required > BOOSTED_VOUT => {
+                    return Err(WiringError::VoutTooLow(d))
+                }
+                Supply::Vout => needed = needed.max(Some(required)),
+            }
+        }
+        Ok(match needed {
+            None => ChargePumpMode::Off,
+            Some(v) if v <= self.vdd_min => ChargePumpMode::Bypass,
+            Some(v) if v > self.vdd_max => ChargePumpMode::Boost,
+            Some(_) => ChargePumpMode::Automatic,
+        })
+    }
+}
@@ -0,0 +1,179 @@
+//! Scanner and chase effects
+//!
+//! A `Chase` walks a lit segment across a chosen sequence of D lines on a single engine, using the
+//! engine's mapping table (see `asm`): each row of the table is one position of the segment, and
+//! the engine steps through the rows with `mux_map_next`. The segment either wraps around from
+//! the last D line to the first, as a marquee, or bounces back and forth between the ends, as a
+//! scanner.
+//!
+//! An engine drives every D line it maps to with the same PWM value, so the segment is lit
+//! evenly. The tail is a fade in time rather than in space: with `with_tail`, the segment fades
+//! out over the end of each step before it moves on, along the curve set with `with_easing`.
+//!
+//! ```ignore
+//! let scanner = Chase::new(&[D::D1, D::D2, D::D3, D::D4, D::D5], 80)
+//!     .with_bounce(true)
+//!     .with_tail(60);
+//! lp.start_chase(&scanner)?;
+//! ```
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm::Assembler;
+use patterns::{eased_fade, fit_easing, ticks, wait_ticks, Easing, CYCLE_TICKS};
+use program::{Program, ProgramError};
+use {Error, Lp55231, Millis, OutputMask, D};
+
+/// Largest number of rows in a chase's mapping table: a scanner over 9 D lines
+const MAX_ROWS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A segment walking across a sequence of D lines
+pub struct Chase {
+    /// The D lines, in walking order
+    outputs: [D; 9],
+    /// Number of D lines given
+    len: usize,
+    /// Time spent at each position
+    step: Millis,
+    /// Time the segment fades out over at the end of each step
+    tail: Millis,
+    /// Shape of the tail's fade
+    easing: Easing,
+    /// Number of consecutive D lines lit
+    width: u8,
+    /// Bounce between the ends rather than wrapping around
+    bounce: bool,
+    /// PWM value of the lit segment
+    brightness: u8,
+}
+
+impl Chase {
+    /// Walk a single lit D line across `outputs`, in order, moving on every `step`, at full
+    /// brightness and without a tail, wrapping around from the last to the first
+    pub fn new<T: Into<Millis>>(outputs: &[D], step: T) -> Self {
+        let mut chase = Chase {
+            outputs: [D::D1; 9],
+            len: outputs.len(),
+            step: step.into(),
+            tail: Millis(0),
+            easing: Easing::Linear,
+            width: 1,
+            bounce: false,
+            brightness: 255,
+        };
+        for (slot, d) in chase.outputs.iter_mut().zip(outputs.iter()) {
+            *slot = *d;
+        }
+        chase
+    }
+
+    /// Light `width` consecutive D lines of the sequence rather than one
+    pub fn with_width(mut self, width: u8) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Fade the segment out over the last `tail` of each step. A tail longer than the step fades
+    /// over the whole step. A fade takes at least one cycle per PWM step of the brightness, so at
+    /// full brightness, tails under about 125 ms run long.
+    pub fn with_tail<T: Into<Millis>>(mut self, tail: T) -> Self {
+        self.tail = tail.into();
+        self
+    }
+
+    /// Fade the tail along the easing curve rather than linearly, approximated with as many
+    /// linear ramps as fit in the program memory, up to `patterns::EASING_SEGMENTS`
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    /// Bounce the segment back and forth between the ends of the sequence, or wrap it around
+    pub fn with_bounce(mut self, bounce: bool) -> Self {
+        self.bounce = bounce;
+        self
+    }
+
+    /// Light the segment with the PWM value rather than at full brightness
+    pub fn with_brightness(mut self, brightness: u8) -> Self {
+        self.brightness = brightness;
+        self
+    }
+
+    /// Build the chase's program for E1
+    ///
+    /// The mapping table comes first in the program memory, and E1 starts right after it. Each
+    /// step takes its time to the nearest cycle, mapping the next row included. To run the chase
+    /// on another engine, load the instructions with `Lp55231::load_engine_program` at the
+    /// engine's start address; the table moves with them.
+    ///
+    /// Fails with `ProgramError::InvalidOperand` unless between 1 and 9 D lines were given, and
+    /// the width is between 1 and their number.
+    pub fn program(&self) -> Result<Program, ProgramError> {
+        let n = self.len;
+        let width = usize::from(self.width);
+        if n == 0 || n > self.outputs.len() || width == 0 || width > n {
+            return Err(ProgramError::InvalidOperand);
+        }
+        let segment = |p: usize| {
+            (p..p + width).fold(OutputMask::none(), |m, i| m.with(self.outputs[i % n]))
+        };
+        let mut rows = [OutputMask::none(); MAX_ROWS];
+        let mut count = 0;
+        if self.bounce {
+            let positions = n - width + 1;
+            let back = (1..positions.saturating_sub(1)).rev();
+            for p in (0..positions).chain(back) {
+                rows[count] = segment(p);
+                count += 1;
+            }
+        } else {
+            for (p, row) in rows.iter_mut().take(n).enumerate() {
+                *row = segment(p);
+            }
+            count = n;
+        }
+
+        // set_pwm, mux_map_next and the branch take a cycle each
+        let step = ticks(self.step).saturating_sub(3 * CYCLE_TICKS);
+        let tail = ticks(self.tail).min(step);
+        fit_easing(|segments| {
+            let mut asm = Assembler::new();
+            for row in rows.iter().take(count) {
+                asm.row(*row);
+            }
+            let start = asm.address();
+            asm.mux_map_start(0).mux_ld_end(count as u8 - 1);
+            let top = asm.address();
+            asm.set_pwm(self.brightness);
+            wait_ticks(&mut asm, step - tail);
+            if tail > 0 {
+                eased_fade(&mut asm, self.brightness, 0, tail, self.easing, segments);
+            }
+            asm.mux_map_next().branch(0, top);
+            asm.finish([start; 3])
+        })
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Run the chase on E1, forever (see `Chase::program`)
+    ///
+    /// The program replaces any running program (see `start_program`). E1 maps itself to the
+    /// segment's D lines as it goes, so its engine map need not be set, but the D lines must be
+    /// on (see `set_outputs`).
+    pub fn start_chase(&mut self, chase: &Chase) -> Result<(), Error<E>> {
+        let program = chase.program()?;
+        self.start_program(&program)
+    }
+}
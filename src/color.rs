@@ -0,0 +1,387 @@
+//! Color output
+//!
+//! An `RgbGroup` names the three D lines driving the red, green and blue dies of one RGB LED, and
+//! an `RgbwGroup` the four D lines of an RGBW LED with an added white die. A `CctGroup` pairs a
+//! warm and a cool white string for tunable-white fixtures.
+//!
+//! Colors are passed as `Rgb` values, which convert from `(r, g, b)` tuples and `[r, g, b]`
+//! arrays of 8-bit components, and from 16-bit components with `Rgb::from_rgb16`. These take
+//! PWM values, which are linear in light output. Colors picked in sRGB or HSV, as from a color
+//! picker, are gamma-encoded instead, and are converted with `Rgb::from_srgb` and
+//! `Rgb::from_hsv`, which decode them to linear PWM values. Alternatively, select
+//! `ColorSpace::Srgb` with `Lp55231::set_color_space` to have `set_color` decode every color it is
+//! passed.
+//!
+//! With the `rgb` feature, `Rgb` also converts from and to `rgb::RGB8`, and from `rgb::RGB16` as
+//! with `Rgb::from_rgb16`. With the `palette` feature, it converts from `palette` colors: `Srgb`
+//! and `Hsv` colors are decoded to linear PWM values as by `Rgb::from_srgb` and `Rgb::from_hsv`,
+//! and `LinSrgb` colors are taken as they are. Colors from either crate thus pass straight to
+//! `set_color` and the other color setters.
+//!
+//! A `ColorMatrix` attached to an RGB group (see `Lp55231::set_color_correction`) corrects every
+//! color set on it, to match fixtures to a reference, and a white point (see
+//! `Lp55231::set_white_point`) then scales each channel so that full white renders as the intended
+//! chromaticity.
+
+use curve::Curve;
+use D;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// The D lines driving one RGB LED
+pub struct RgbGroup {
+    /// D line driving the red die
+    pub red: D,
+    /// D line driving the green die
+    pub green: D,
+    /// D line driving the blue die
+    pub blue: D,
+}
+
+impl RgbGroup {
+    /// Group three D lines into an RGB LED
+    pub fn new(red: D, green: D, blue: D) -> Self {
+        RgbGroup { red, green, blue }
+    }
+
+    /// The D lines, in red, green, blue order
+    pub fn lines(&self) -> [D; 3] {
+        [self.red, self.green, self.blue]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// The D lines driving one RGBW LED
+pub struct RgbwGroup {
+    /// D line driving the red die
+    pub red: D,
+    /// D line driving the green die
+    pub green: D,
+    /// D line driving the blue die
+    pub blue: D,
+    /// D line driving the white die
+    pub white: D,
+}
+
+impl RgbwGroup {
+    /// Group four D lines into an RGBW LED
+    pub fn new(red: D, green: D, blue: D, white: D) -> Self {
+        RgbwGroup {
+            red,
+            green,
+            blue,
+            white,
+        }
+    }
+
+    /// The D lines, in red, green, blue, white order
+    pub fn lines(&self) -> [D; 4] {
+        [self.red, self.green, self.blue, self.white]
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// An RGB color, as the PWM values of the red, green and blue dies
+pub struct Rgb {
+    /// Red PWM value
+    pub r: u8,
+    /// Green PWM value
+    pub g: u8,
+    /// Blue PWM value
+    pub b: u8,
+}
+
+impl Rgb {
+    /// Create a color from its red, green and blue PWM values
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    /// Create a color from 16-bit components, rounded to the nearest PWM value
+    pub fn from_rgb16(r: u16, g: u16, b: u16) -> Self {
+        let to8 = |c: u16| ((u32::from(c) * 255 + 32767) / 65535) as u8;
+        Rgb::new(to8(r), to8(g), to8(b))
+    }
+
+    /// Create a color from gamma-encoded sRGB components, decoding them to linear PWM values
+    ///
+    /// The sRGB transfer function is approximated by gamma 2.2 (see `curve::Curve::Gamma`).
+    pub fn from_srgb(r: u8, g: u8, b: u8) -> Self {
+        let decode = |c| Curve::Gamma.apply(c);
+        Rgb::new(decode(r), decode(g), decode(b))
+    }
+
+    /// Create a color from its sRGB hue, in degrees, saturation and value, decoding it to linear
+    /// PWM values as for `from_srgb`. Hues of 360 degrees and above wrap around.
+    pub fn from_hsv(hue: u16, saturation: u8, value: u8) -> Self {
+        let hue = u32::from(hue % 360);
+        let (s, v) = (u32::from(saturation), u32::from(value));
+        let rem = (hue % 60) * 255 / 60;
+        let p = (v * (255 - s) / 255) as u8;
+        let q = (v * (255 - s * rem / 255) / 255) as u8;
+        let t = (v * (255 - s * (255 - rem) / 255) / 255) as u8;
+        let v = value;
+        let (r, g, b) = match hue / 60 {
+            0 => (v, t, p),
+            1 => (q, v, p),
+            2 => (p, v, t),
+            3 => (p, q, v),
+            4 => (t, p, v),
+            _ => (v, p, q),
+        };
+        Rgb::from_srgb(r, g, b)
+    }
+}
+
+impl From<(u8, u8, u8)> for Rgb {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Rgb::new(r, g, b)
+    }
+}
+
+impl From<[u8; 3]> for Rgb {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Rgb::new(r, g, b)
+    }
+}
+
+impl From<Rgb> for (u8, u8, u8) {
+    fn from(c: Rgb) -> Self {
+        (c.r, c.g, c.b)
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<rgb::RGB8> for Rgb {
+    fn from(c: rgb::RGB8) -> Self {
+        Rgb::new(c.r, c.g, c.b)
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<rgb::RGB16> for Rgb {
+    fn from(c: rgb::RGB16) -> Self {
+        Rgb::from_rgb16(c.r, c.g, c.b)
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<Rgb> for rgb::RGB8 {
+    fn from(c: Rgb) -> Self {
+        rgb::RGB8::new(c.r, c.g, c.b)
+    }
+}
+
+/// A component between 0.0 and 1.0, rounded to the nearest 8-bit value
+#[cfg(feature = "palette")]
+fn unit_to_u8(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+#[cfg(feature = "palette")]
+impl From<palette::Srgb<u8>> for Rgb {
+    fn from(c: palette::Srgb<u8>) -> Self {
+        Rgb::from_srgb(c.red, c.green, c.blue)
+    }
+}
+
+#[cfg(feature = "palette")]
+impl From<palette::Srgb<f32>> for Rgb {
+    fn from(c: palette::Srgb<f32>) -> Self {
+        Rgb::from_srgb(unit_to_u8(c.red), unit_to_u8(c.green), unit_to_u8(c.blue))
+    }
+}
+
+#[cfg(feature = "palette")]
+impl From<palette::LinSrgb<u8>> for Rgb {
+    fn from(c: palette::LinSrgb<u8>) -> Self {
+        Rgb::new(c.red, c.green, c.blue)
+    }
+}
+
+#[cfg(feature = "palette")]
+impl From<palette::LinSrgb<f32>> for Rgb {
+    fn from(c: palette::LinSrgb<f32>) -> Self {
+        Rgb::new(unit_to_u8(c.red), unit_to_u8(c.green), unit_to_u8(c.blue))
+    }
+}
+
+#[cfg(feature = "palette")]
+impl From<palette::Hsv> for Rgb {
+    fn from(c: palette::Hsv) -> Self {
+        let hue = (c.hue.into_positive_degrees() + 0.5) as u16;
+        Rgb::from_hsv(hue, unit_to_u8(c.saturation), unit_to_u8(c.value))
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// How the components of colors passed to `Lp55231::set_color` are encoded
+pub enum ColorSpace {
+    /// Linear PWM values
+    #[default]
+    Linear,
+    /// Gamma-encoded sRGB, as produced by most user interfaces and palettes, linearized as by
+    /// `Rgb::from_srgb`
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Decode a color in this color space to linear PWM values
+    pub fn linearize(self, c: Rgb) -> Rgb {
+        match self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb => Rgb::from_srgb(c.r, c.g, c.b),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A 3×3 color correction matrix, in fixed point with 256 standing for 1.0
+///
+/// Row `i` gives the contributions of the incoming red, green and blue components to output
+/// component `i`, so that `[r', g', b'] = M × [r, g, b]`. Matrices are typically derived from a
+/// colorimeter measurement of each fixture's primaries, to match it to a reference.
+pub struct ColorMatrix(pub [[i16; 3]; 3]);
+
+impl ColorMatrix {
+    /// The matrix leaving colors unchanged
+    pub const IDENTITY: ColorMatrix = ColorMatrix([[256, 0, 0], [0, 256, 0], [0, 0, 256]]);
+
+    /// Apply the matrix to a color, rounding and clamping each component to a PWM value
+    pub fn apply(&self, c: Rgb) -> Rgb {
+        let input = [i32::from(c.r), i32::from(c.g), i32::from(c.b)];
+        let row = |r: &[i16; 3]| {
+            let sum: i32 = r.iter().zip(input.iter()).map(|(m, c)| i32::from(*m) * c).sum();
+            ((sum + 128) >> 8).clamp(0, 255) as u8
+        };
+        Rgb::new(row(&self.0[0]), row(&self.0[1]), row(&self.0[2]))
+    }
+}
+
+impl Default for ColorMatrix {
+    fn default() -> Self {
+        ColorMatrix::IDENTITY
+    }
+}
+
+/// Red, green and blue scales, out of 255, that balance a fixture whose full white measures
+/// `r`, `g` and `b` in its red, green and blue components
+///
+/// The measurements are relative intensities in any unit, e.g. from a colorimeter, taken with
+/// every die at full PWM. Each channel is scaled down to match the weakest, so that full white
+/// renders as equal parts of each.
+pub fn white_point_from_measured(r: u16, g: u16, b: u16) -> [u8; 3] {
+    let weakest = u32::from(r.min(g).min(b));
+    let scale = |c: u16| match c {
+        0 => 0,
+        c => ((weakest * 255 + u32::from(c) / 2) / u32::from(c)) as u8,
+    };
+    [scale(r), scale(g), scale(b)]
+}
+
+/// Set, replace or clear (with `None`) the value attached to an RGB group in a fixed table,
+/// failing if the table is full
+pub(crate) fn attach<T: Copy>(
+    table: &mut [Option<(RgbGroup, T)>],
+    led: RgbGroup,
+    value: Option<T>,
+) -> Result<(), ()> {
+    let slot = match table.iter().position(|e| e.is_some_and(|(g, _)| g == led)) {
+        Some(slot) => slot,
+        None if value.is_none() => return Ok(()),
+        None => table.iter().position(Option::is_none).ok_or(())?,
+    };
+    table[slot] = value.map(|v| (led, v));
+    Ok(())
+}
+
+/// The value attached to an RGB group in a fixed table, if any
+pub(crate) fn attached<T: Copy>(table: &[Option<(RgbGroup, T)>], led: RgbGroup) -> Option<T> {
+    table.iter().flatten().find(|(g, _)| *g == led).map(|(_, v)| *v)
+}
+
+/// Split an RGB color into RGBW, moving the component common to all three channels onto the
+/// white die
+///
+/// The white die renders the common component with better efficiency and color rendering than
+/// mixing red, green and blue. Returns `[red, green, blue, white]`.
+pub fn extract_white(r: u8, g: u8, b: u8) -> [u8; 4] {
+    let w = r.min(g).min(b);
+    [r - w, g - w, b - w, w]
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A warm and a cool white string, mixed to produce intermediate color temperatures
+pub struct CctGroup {
+    /// D line driving the warm white string
+    pub warm: D,
+    /// D line driving the cool white string
+    pub cool: D,
+    /// Color temperature of the warm string, in kelvin
+    pub warm_kelvin: u16,
+    /// Color temperature of the cool string, in kelvin
+    pub cool_kelvin: u16,
+}
+
+impl CctGroup {
+    /// Pair a warm and a cool string, calibrated with the color temperature of each
+    pub fn new(warm: D, warm_kelvin: u16, cool: D, cool_kelvin: u16) -> Self {
+        CctGroup {
+            warm,
+            cool,
+            warm_kelvin,
+            cool_kelvin,
+        }
+    }
+
+    /// Compute the `[warm, cool]` PWM values producing the color temperature at the brightness
+    ///
+    /// The strings are mixed linearly in mired (reciprocal color temperature), which tracks
+    /// perceived color much more closely than kelvin. Color temperatures outside of the
+    /// calibrated range are clamped to it. If the warm string is not warmer than the cool one,
+    /// all output goes to the warm string.
+    pub fn mix(&self, kelvin: u16, brightness: u8) -> [u8; 2] {
+        let mired = |k: u16| 1_000_000 / u32::from(k.max(1));
+        let warm = mired(self.warm_kelvin);
+        let cool = mired(self.cool_kelvin);
+        if warm <= cool {
+            return [brightness, 0];
+        }
+        let target = mired(kelvin).clamp(cool, warm);
+        let cool_share = (warm - target) * 255 / (warm - cool);
+        let cool_pwm = u32::from(brightness) * cool_share / 255;
+        [(u32::from(brightness) - cool_pwm) as u8, cool_pwm as u8]
+    }
+}
+
+#[cfg(all(test, any(feature = "rgb", feature = "palette")))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "rgb")]
+    #[test]
+    fn rgb_crate_colors_convert() {
+        assert_eq!(Rgb::from(rgb::RGB8::new(1, 2, 3)), Rgb::new(1, 2, 3));
+        assert_eq!(rgb::RGB8::from(Rgb::new(1, 2, 3)), rgb::RGB8::new(1, 2, 3));
+        let c = Rgb::from(rgb::RGB16::new(0, 0x8000, 0xffff));
+        assert_eq!(c, Rgb::new(0, 128, 255));
+    }
+
+    #[cfg(feature = "palette")]
+    #[test]
+    fn palette_colors_are_linearized() {
+        let srgb = Rgb::from_srgb(200, 100, 50);
+        assert_eq!(Rgb::from(palette::Srgb::new(200u8, 100, 50)), srgb);
+        let unit = palette::Srgb::new(200.0 / 255.0, 100.0 / 255.0, 50.0 / 255.0);
+        assert_eq!(Rgb::from(unit), srgb);
+        assert_eq!(Rgb::from(palette::LinSrgb::new(200u8, 100, 50)), Rgb::new(200, 100, 50));
+        let hsv = palette::Hsv::new(120.0, 1.0, 0.5);
+        assert_eq!(Rgb::from(hsv), Rgb::from_hsv(120, 255, 128));
+        assert_eq!(Rgb::from(palette::Hsv::new(-240.0, 1.0, 0.5)), Rgb::from(hsv));
+    }
+}
@@ -0,0 +1,68 @@
+//! Checksums shared by the stored formats
+//!
+//! Program blobs (see `program`) and persisted records (see `persist`) end in a CRC-16/CCITT-FALSE
+//! of their contents. These functions compute the same checksum, so that programs received from
+//! flash or a network can be checked before they are pushed into engine memory, and so that
+//! assembled programs can be fingerprinted. Multi-byte words are checksummed big-endian, as they
+//! are stored.
+
+/// Incremental CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff)
+///
+/// Feeding data in several pieces gives the same result as feeding it all at once.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Crc16 {
+    /// Running CRC
+    crc: u16,
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Crc16 { crc: 0xffff }
+    }
+}
+
+impl Crc16 {
+    /// Start a new CRC
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed bytes into the CRC
+    pub fn update(&mut self, bytes: &[u8]) -> &mut Self {
+        for byte in bytes {
+            self.crc ^= u16::from(*byte) << 8;
+            for _ in 0..8 {
+                self.crc = if self.crc & 0x8000 != 0 {
+                    (self.crc << 1) ^ 0x1021
+                } else {
+                    self.crc << 1
+                };
+            }
+        }
+        self
+    }
+
+    /// Feed instruction words into the CRC, big-endian
+    pub fn update_words(&mut self, words: &[u16]) -> &mut Self {
+        for word in words {
+            self.update(&word.to_be_bytes());
+        }
+        self
+    }
+
+    /// The CRC of everything fed in so far
+    pub fn finish(&self) -> u16 {
+        self.crc
+    }
+}
+
+/// CRC-16/CCITT-FALSE of `bytes`
+pub fn crc16(bytes: &[u8]) -> u16 {
+    Crc16::new().update(bytes).finish()
+}
+
+/// CRC-16/CCITT-FALSE of instruction words, big-endian, as from `asm::Assembler` or
+/// `Program::instructions`
+pub fn crc16_words(words: &[u16]) -> u16 {
+    Crc16::new().update_words(words).finish()
+}
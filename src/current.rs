@@ -0,0 +1,120 @@
+//! Drive current limits
+//!
+//! Each D line's drive current, set in 100 µA steps up to 25.5 mA, can exceed what the LED on it
+//! is rated for. `Lp55231::set_current_cap` declares the highest current a D line may be driven
+//! at; every write of its D*x*_I_CTL register is then checked against it, whichever method makes
+//! it, including `set_current`, calibration, burn-in, snapshots and batches.
+//!
+//! A single write over the cap is rejected with `Error::InvalidArgument`, or, with
+//! `set_current_clamping`, lowered to the cap. Burst writes over a cap are always rejected, as
+//! they do not come from `set_current`.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Error, Lp55231, D};
+
+/// Outcome of checking a write against the current caps
+pub(crate) enum Capped {
+    /// The write is within the caps
+    Unchanged,
+    /// The single value written is lowered to the cap
+    Clamped(u8),
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Set the D line's drive current, in 100 µA steps, within its cap (see `set_current_cap`)
+    pub fn set_current(&mut self, d: D, current: u8) -> Result<(), Error<E>> {
+        self.send(&[reg::D1_I_CTL + u8::from(d), current])
+    }
+
+    /// Read the D line's drive current, in 100 µA steps
+    pub fn current(&mut self, d: D) -> Result<u8, Error<E>> {
+        self.read(reg::D1_I_CTL + u8::from(d))
+    }
+
+    /// Set the highest drive current, in 100 µA steps, the D line may be set to, or `None` for
+    /// no cap
+    ///
+    /// The current already set is not checked; set it again, or read it back with `current`, to
+    /// bring it within the new cap.
+    pub fn set_current_cap(&mut self, d: D, cap: Option<u8>) {
+        self.current_caps[usize::from(u8::from(d))] = cap;
+    }
+
+    /// The D line's drive current cap, in 100 µA steps
+    pub fn current_cap(&self, d: D) -> Option<u8> {
+        self.current_caps[usize::from(u8::from(d))]
+    }
+
+    /// Lower single drive current writes over a D line's cap to the cap, rather than rejecting
+    /// them
+    pub fn set_current_clamping(&mut self, clamp: bool) {
+        self.clamp_currents = clamp;
+    }
+
+    /// Check a write about to be sent against the current caps
+    pub(crate) fn current_filter(&self, bytes: &[u8]) -> Result<Capped, Error<E>> {
+        let (start, values) = match bytes.split_first() {
+            Some((start, values)) => (*start, values),
+            None => return Ok(Capped::Unchanged),
+        };
+        for (i, value) in values.iter().enumerate() {
+            let r = usize::from(start) + i;
+            if !(usize::from(reg::D1_I_CTL)..=usize::from(reg::D9_I_CTL)).contains(&r) {
+                continue;
+            }
+            match self.current_caps[r - usize::from(reg::D1_I_CTL)] {
+                Some(cap) if *value > cap => {
+                    if self.clamp_currents && values.len() == 1 {
+                        return Ok(Capped::Clamped(cap));
+                    }
+                    return Err(Error::InvalidArgument);
+                }
+                _ => {}
+            }
+        }
+        Ok(Capped::Unchanged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use batch::Batch;
+    use mock;
+
+    #[test]
+    fn single_writes_over_the_cap_are_rejected_or_clamped() {
+        let mut lp = mock::enabled();
+        lp.set_current_cap(D::D1, Some(100));
+        assert!(matches!(lp.set_current(D::D1, 101), Err(Error::InvalidArgument)));
+        lp.set_current(D::D1, 100).unwrap();
+        lp.set_current_clamping(true);
+        lp.set_current(D::D1, 255).unwrap();
+        assert_eq!(lp.i2c.writes, [[reg::D1_I_CTL, 100], [reg::D1_I_CTL, 100]]);
+    }
+
+    #[test]
+    fn bursts_over_the_cap_are_always_rejected() {
+        let mut lp = mock::enabled();
+        lp.set_current_cap(D::D2, Some(100));
+        lp.set_current_clamping(true);
+        let mut batch = Batch::new();
+        batch.write(reg::D1_I_CTL, 200);
+        batch.write(reg::D2_I_CTL, 101);
+        batch.write(reg::D3_I_CTL, 200);
+        assert!(matches!(lp.flush(&mut batch), Err(Error::InvalidArgument)));
+        assert!(lp.i2c.writes.is_empty());
+        assert!(!batch.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! Perceptual brightness curves
+//!
+//! The eye's response to light is roughly logarithmic, so a linear ramp of PWM values appears to
+//! brighten quickly and then flatten out. The curves here map a perceived brightness onto the PWM
+//! value producing it.
+//!
+//! Any mapping implementing `BrightnessCurve` can stand in for the built-in curves, through
+//! `Curve::Custom`, on every path that consults them: `set_pwm` and the color setters built on it
+//! (see `Lp55231::set_pwm_curve`), and the master faders (see `Lp55231::set_fader_curve`). A
+//! lookup table measured for a particular LED is a `Lut`:
+//!
+//! ```ignore
+//! static MEASURED: Lut = Lut([0, 0, 1, /* ... */ 255]);
+//! lp.set_pwm_curve(OutputMask::all(), Curve::Custom(&MEASURED));
+//! ```
+//!
+//! With logarithmic dimming selected (see `Lp55231::set_logarithmic`), the device maps each 8-bit
+//! PWM value onto an exponential curve of 12-bit duty cycles, with far finer steps at the low end.
+//! `log_register` picks the PWM value whose duty cycle best matches a linear light level, so that
+//! `Lp55231::set_pwm_16bit` can take a 16-bit level and make full use of them.
+
+use core::fmt;
+
+/// Gamma 2.2 lookup table, indexed by perceived brightness
+const GAMMA_2_2: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6,
+    6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 11, 11, 11, 12,
+    12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19,
+    20, 20, 21, 22, 22, 23, 23, 24, 25, 25, 26, 26, 27, 28, 28, 29,
+    30, 30, 31, 32, 33, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41,
+    42, 43, 43, 44, 45, 46, 47, 48, 49, 49, 50, 51, 52, 53, 54, 55,
+    56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71,
+    73, 74, 75, 76, 77, 78, 79, 81, 82, 83, 84, 85, 87, 88, 89, 90,
+    91, 93, 94, 95, 97, 98, 99, 100, 102, 103, 105, 106, 107, 109, 110, 111,
+    113, 114, 116, 117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135,
+    137, 138, 140, 141, 143, 145, 146, 148, 149, 151, 153, 154, 156, 158, 159, 161,
+    163, 165, 166, 168, 170, 172, 173, 175, 177, 179, 181, 182, 184, 186, 188, 190,
+    192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213, 215, 217, 219, 221,
+    223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253, 255,
+];
+
+/// CIE 1931 lightness lookup table, indexed by perceived brightness
+const CIE_1931: [u8; 256] = [
+    0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 4,
+    4, 4, 4, 4, 4, 5, 5, 5, 5, 5, 6, 6, 6, 6, 6, 7,
+    7, 7, 7, 8, 8, 8, 8, 9, 9, 9, 10, 10, 10, 10, 11, 11,
+    11, 12, 12, 12, 13, 13, 13, 14, 14, 15, 15, 15, 16, 16, 17, 17,
+    17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 23, 24, 24, 25,
+    25, 26, 26, 27, 28, 28, 29, 29, 30, 31, 31, 32, 32, 33, 34, 34,
+    35, 36, 37, 37, 38, 39, 39, 40, 41, 42, 43, 43, 44, 45, 46, 47,
+    47, 48, 49, 50, 51, 52, 53, 54, 54, 55, 56, 57, 58, 59, 60, 61,
+    62, 63, 64, 65, 66, 67, 68, 70, 71, 72, 73, 74, 75, 76, 77, 79,
+    80, 81, 82, 83, 85, 86, 87, 88, 90, 91, 92, 94, 95, 96, 98, 99,
+    100, 102, 103, 105, 106, 108, 109, 110, 112, 113, 115, 116, 118, 120, 121, 123,
+    124, 126, 128, 129, 131, 132, 134, 136, 138, 139, 141, 143, 145, 146, 148, 150,
+    152, 154, 155, 157, 159, 161, 163, 165, 167, 169, 171, 173, 175, 177, 179, 181,
+    183, 185, 187, 189, 191, 193, 196, 198, 200, 202, 204, 207, 209, 211, 214, 216,
+    218, 220, 223, 225, 228, 230, 232, 235, 237, 240, 242, 245, 247, 250, 252, 255,
+];
+
+/// 2^(k/32) for k in 0..32, in Q15, the fractional steps of the logarithmic dimming curve
+const LOG_STEPS: [u32; 32] = [
+    32768, 33486, 34219, 34968, 35734, 36516, 37316, 38133,
+    38968, 39821, 40693, 41584, 42495, 43425, 44376, 45348,
+    46341, 47356, 48393, 49452, 50535, 51642, 52773, 53928,
+    55109, 56316, 57549, 58809, 60097, 61413, 62757, 64132,
+];
+
+/// Duty cycle of a PWM value in logarithmic dimming, as 2^(value/32) - 1 in Q15, rising from 0 at
+/// 0 to `log_duty(255)` at full duty
+fn log_duty(value: u8) -> u32 {
+    (LOG_STEPS[usize::from(value & 31)] << (value >> 5)) - LOG_STEPS[0]
+}
+
+/// The duty cycle, out of 4095, the device gives a PWM value in logarithmic dimming
+///
+/// The device's curve is modelled as exponential, doubling every 32 steps, from off at 0 to full
+/// duty at 255.
+pub fn log_duty_12bit(value: u8) -> u16 {
+    ((u64::from(log_duty(value)) * 4095 + u64::from(log_duty(255)) / 2)
+        / u64::from(log_duty(255))) as u16
+}
+
+/// The PWM value whose duty cycle in logarithmic dimming is nearest the linear light level, out
+/// of 65535
+pub fn log_register(level: u16) -> u8 {
+    let target = (u64::from(level) * u64::from(log_duty(255)) / 65535) as u32;
+    // The duty cycle rises with the PWM value, so the nearest is either side of the first value
+    // reaching the target
+    let (mut low, mut high) = (0u8, 255u8);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if log_duty(mid) < target {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low > 0 && target - log_duty(low - 1) < log_duty(low) - target {
+        low - 1
+    } else {
+        low
+    }
+}
+
+/// The PWM value whose duty cycle in linear dimming is nearest the linear light level, out of
+/// 65535
+pub fn linear_register(level: u16) -> u8 {
+    ((u32::from(level) * 255 + 32767) / 65535) as u8
+}
+
+/// A mapping of perceived brightness onto PWM values
+pub trait BrightnessCurve {
+    /// Map a perceived brightness onto a PWM value
+    fn apply(&self, value: u8) -> u8;
+}
+
+/// A curve given as a lookup table, indexed by perceived brightness
+pub struct Lut(pub [u8; 256]);
+
+impl BrightnessCurve for Lut {
+    fn apply(&self, value: u8) -> u8 {
+        self.0[usize::from(value)]
+    }
+}
+
+impl BrightnessCurve for fn(u8) -> u8 {
+    fn apply(&self, value: u8) -> u8 {
+        self(value)
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Mapping applied to a brightness value before it is written to the device
+pub enum Curve {
+    /// Values are written unchanged
+    #[default]
+    Linear,
+    /// Values are mapped through a gamma 2.2 curve, so that equal steps look equal to the eye
+    Gamma,
+    /// Values are mapped through the CIE 1931 lightness curve, as commonly specified for
+    /// architectural lighting. Steps at the low end are finer than with `Gamma`.
+    Cie1931,
+    /// Values are mapped through an application-provided curve. Curves compare equal only if they
+    /// are the same object. This variant cannot be serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(&'static (dyn BrightnessCurve + Sync)),
+}
+
+impl fmt::Debug for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Curve::Linear => f.write_str("Linear"),
+            Curve::Gamma => f.write_str("Gamma"),
+            Curve::Cie1931 => f.write_str("Cie1931"),
+            Curve::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl PartialEq for Curve {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Curve::Linear, Curve::Linear)
+            | (Curve::Gamma, Curve::Gamma)
+            | (Curve::Cie1931, Curve::Cie1931) => true,
+            (Curve::Custom(a), Curve::Custom(b)) => core::ptr::addr_eq(*a, *b),
+            _ => false,
+        }
+    }
+}
+
+impl Curve {
+    /// Map a perceived brightness onto a PWM value
+    pub fn apply(self, value: u8) -> u8 {
+        match self {
+            Curve::Linear => value,
+            Curve::Gamma => GAMMA_2_2[usize::from(value)],
+            Curve::Cie1931 => CIE_1931[usize::from(value)],
+            Curve::Custom(curve) => curve.apply(value),
+        }
+    }
+}
+
+impl BrightnessCurve for Curve {
+    fn apply(&self, value: u8) -> u8 {
+        Curve::apply(*self, value)
+    }
+}
@@ -0,0 +1,102 @@
+//! Global brightness scaling
+//!
+//! `Lp55231::set_global_brightness` scales every LED by one factor, for a product-wide night mode,
+//! without touching the code that sets colors and PWM values. The factor is applied once to each
+//! D line: D lines mapped to a master fader are dimmed through the fader, which also covers the
+//! engines driving them, and the other D lines through their direct PWM value.
+//!
+//! The driver follows the fader mapping from the D*x*_CTRL writes it makes, so it is lost on a
+//! write bypassing it (such as a raw transaction). D lines driven by an engine and not mapped to
+//! a fader, and `write_frame_fast`, are not dimmed.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Brightness, Error, Fader, Lp55231, D};
+
+/// Scale `value` by `scale`, out of 255
+pub(crate) fn dim(value: u8, scale: u8) -> u8 {
+    ((u16::from(value) * u16::from(scale) + 127) / 255) as u8
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Scale the brightness of every LED, either a raw `u8`/`Brightness` or a `Percent`; full
+    /// brightness by default
+    ///
+    /// Master faders set through the driver are rewritten at once. Direct PWM values are scaled
+    /// as they are written, so the D lines not mapped to a fader only change on their next write;
+    /// re-apply the current scene or colors to dim them at once.
+    pub fn set_global_brightness<B>(&mut self, brightness: B) -> Result<(), Error<E>>
+    where
+        B: Into<Brightness>,
+    {
+        self.global_brightness = brightness.into().0;
+        for (f, value) in [Fader::F1, Fader::F2, Fader::F3].iter().zip(self.fader_values) {
+            if let Some(value) = value {
+                let value = dim(value, self.global_brightness);
+                self.send(&[reg::MASTER_FADE_1 + u8::from(*f), value])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The global brightness scale
+    pub fn global_brightness(&self) -> Brightness {
+        Brightness(self.global_brightness)
+    }
+
+    /// The master fader value written for `value`, after the fader's curve: remembered, so that
+    /// a change of global brightness can rewrite it, and scaled by the global brightness
+    pub(crate) fn dimmed_fader(&mut self, f: Fader, value: u8) -> u8 {
+        self.fader_values[usize::from(u8::from(f))] = Some(value);
+        dim(value, self.global_brightness)
+    }
+
+    /// The direct PWM value written for `pwm`, after the D line's curve and trim: scaled by the
+    /// global brightness unless the D line is dimmed through a master fader
+    pub(crate) fn dimmed_pwm(&self, d: D, pwm: u8) -> u8 {
+        if self.fader_mapped & (1 << u8::from(d)) != 0 {
+            pwm
+        } else {
+            dim(pwm, self.global_brightness)
+        }
+    }
+
+    /// Follow the fader mapping of the D lines from a write about to be sent
+    pub(crate) fn dimming_observe(&mut self, bytes: &[u8]) {
+        let (start, values) = match bytes.split_first() {
+            Some((start, values)) => (*start, values),
+            None => return,
+        };
+        if start == reg::RESET {
+            self.forget_faders();
+            return;
+        }
+        for (i, value) in values.iter().enumerate() {
+            let r = usize::from(start) + i;
+            if (usize::from(reg::D1_CTRL)..=usize::from(reg::D9_CTRL)).contains(&r) {
+                let bit = 1 << (r - usize::from(reg::D1_CTRL));
+                if value & reg::DxCtrl::MAPPING_FADER3.bits() != 0 {
+                    self.fader_mapped |= bit;
+                } else {
+                    self.fader_mapped &= !bit;
+                }
+            }
+        }
+    }
+
+    /// Forget the fader mapping and values, as the device has been reset
+    pub(crate) fn forget_faders(&mut self) {
+        self.fader_mapped = 0;
+        self.fader_values = [None; 3];
+    }
+}
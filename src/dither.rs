@@ -0,0 +1,82 @@
+//! Software temporal dithering
+//!
+//! In linear PWM mode the lowest brightness steps are large relative to the light they produce,
+//! and fades through them visibly stair-step. `Dither` synthesizes intermediate levels by
+//! alternating between adjacent PWM codes on successive calls to `tick`, so that the average
+//! duty cycle matches a target with 8 extra bits of resolution.
+//!
+//! `tick` must be called at a steady, fast rate (100 Hz or more) for the alternation to be
+//! invisible. Only D lines given a target with `set` are driven.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use {Error, Lp55231, OutputMask, D};
+
+/// Temporal dithering state for the 9 D lines
+pub struct Dither {
+    /// Target of each D line, as a PWM value with 8 fractional bits
+    targets: [u16; 9],
+    /// Accumulated fractional error of each D line
+    error: [u8; 9],
+    /// PWM value last written to each D line
+    written: [Option<u8>; 9],
+    /// D lines being driven
+    active: OutputMask,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dither {
+    /// Create a dithering layer driving no D lines
+    pub fn new() -> Self {
+        Dither {
+            targets: [0; 9],
+            error: [0; 9],
+            written: [None; 9],
+            active: OutputMask::none(),
+        }
+    }
+
+    /// Set the target of the D line, as a PWM value in the upper byte with 8 fractional bits in
+    /// the lower byte. The D line is driven from the next `tick`.
+    pub fn set(&mut self, d: D, target: u16) {
+        self.targets[usize::from(u8::from(d))] = target;
+        self.active = self.active.with(d);
+    }
+
+    /// Stop driving the D line. Its PWM register is left at its last value.
+    pub fn release(&mut self, d: D) {
+        self.active = self.active.without(d);
+        self.written[usize::from(u8::from(d))] = None;
+    }
+
+    /// Write the next dithered PWM value of every driven D line. Values unchanged since the last
+    /// tick are not rewritten.
+    #[allow(deprecated)]
+    pub fn tick<E, I, P>(&mut self, lp: &mut Lp55231<I, P>) -> Result<(), Error<E>>
+    where
+        E: Debug,
+        I: Write<Error = E> + WriteRead<Error = E>,
+        P: OutputPin,
+    {
+        for d in self.active.iter() {
+            let i = usize::from(u8::from(d));
+            let whole = (self.targets[i] >> 8) as u8;
+            let (error, carry) = self.error[i].overflowing_add(self.targets[i] as u8);
+            self.error[i] = error;
+            let pwm = if carry { whole.saturating_add(1) } else { whole };
+            if self.written[i] != Some(pwm) {
+                lp.set_pwm(d, pwm)?;
+                self.written[i] = Some(pwm);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! embedded-hal 1.0 buses, PWM channels and output pins
+//!
+//! `Eh1Bus` adapts an embedded-hal 1.0 `I2c` bus to the embedded-hal 0.2 traits the driver is
+//! bound on. Register reads are issued as a single `transaction` of a write followed by a read,
+//! so the register address and the data are transferred with a repeated start rather than a
+//! stop in between, which matters on the status-poll-heavy paths such as program loading and LED
+//! tests. Wrap the bus before handing it to `Lp55231::new`.
+//!
+//! `PwmChannel` hands a single D line to code generic over the embedded-hal 1.0 `SetDutyCycle`
+//! trait, such as a backlight or buzzer driver. The channels of a device share it through a
+//! `RefCell`:
+//!
+//! ```ignore
+//! let lp = RefCell::new(lp);
+//! let [d1, d2, ..] = PwmChannel::split(&lp);
+//! let backlight = Backlight::new(d1);
+//! ```
+//!
+//! `pin::LedPin` also implements the embedded-hal 1.0 `OutputPin` and `StatefulOutputPin`.
+//!
+//! The other way round, `Eh1Pin` drives the EN line from an embedded-hal 1.0 `OutputPin`, and
+//! `Eh1Delay` hands an embedded-hal 1.0 `DelayNs` to the methods taking a delay, so that HALs
+//! implementing only embedded-hal 1.0, such as `linux-embedded-hal`, drive the device unchanged.
+
+use core::cell::RefCell;
+use core::fmt::Debug;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::digital;
+use embedded_hal_1::i2c::{I2c, Operation};
+use embedded_hal_1::pwm::{self, ErrorKind, ErrorType, SetDutyCycle};
+use hal::blocking::delay::{DelayMs, DelayUs};
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use pin::LedPin;
+use {Error, Lp55231, D};
+
+/// An embedded-hal 1.0 I2C bus, usable by the driver
+pub struct Eh1Bus<I> {
+    /// The wrapped I2C bus
+    i2c: I,
+}
+
+impl<I> Eh1Bus<I> {
+    /// Wrap `i2c`
+    pub fn new(i2c: I) -> Self {
+        Eh1Bus { i2c }
+    }
+
+    /// Release the wrapped bus
+    pub fn release(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I: I2c> Write for Eh1Bus<I> {
+    type Error = I::Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), I::Error> {
+        self.i2c.write(addr, bytes)
+    }
+}
+
+impl<I: I2c> WriteRead for Eh1Bus<I> {
+    type Error = I::Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I::Error> {
+        self.i2c.transaction(
+            addr,
+            &mut [Operation::Write(bytes), Operation::Read(buffer)],
+        )
+    }
+}
+
+/// An embedded-hal 1.0 output pin, usable as the driver's EN pin
+///
+/// The driver's pin trait cannot report errors, so errors setting the pin are dropped; a pin
+/// left low shows up as the device not answering.
+pub struct Eh1Pin<P> {
+    /// The wrapped pin
+    pin: P,
+}
+
+impl<P> Eh1Pin<P> {
+    /// Wrap `pin`
+    pub fn new(pin: P) -> Self {
+        Eh1Pin { pin }
+    }
+
+    /// Release the wrapped pin
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+#[allow(deprecated)]
+impl<P: digital::OutputPin> OutputPin for Eh1Pin<P> {
+    fn set_low(&mut self) {
+        let _ = self.pin.set_low();
+    }
+
+    fn set_high(&mut self) {
+        let _ = self.pin.set_high();
+    }
+}
+
+/// An embedded-hal 1.0 delay, usable wherever the driver takes a delay
+pub struct Eh1Delay<DL> {
+    /// The wrapped delay
+    delay: DL,
+}
+
+impl<DL> Eh1Delay<DL> {
+    /// Wrap `delay`
+    pub fn new(delay: DL) -> Self {
+        Eh1Delay { delay }
+    }
+
+    /// Release the wrapped delay
+    pub fn release(self) -> DL {
+        self.delay
+    }
+}
+
+impl<DL: DelayNs> DelayUs<u16> for Eh1Delay<DL> {
+    fn delay_us(&mut self, us: u16) {
+        self.delay.delay_us(u32::from(us));
+    }
+}
+
+impl<DL: DelayNs> DelayUs<u32> for Eh1Delay<DL> {
+    fn delay_us(&mut self, us: u32) {
+        self.delay.delay_us(us);
+    }
+}
+
+impl<DL: DelayNs> DelayMs<u8> for Eh1Delay<DL> {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay.delay_ms(u32::from(ms));
+    }
+}
+
+impl<DL: DelayNs> DelayMs<u16> for Eh1Delay<DL> {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay.delay_ms(u32::from(ms));
+    }
+}
+
+impl<DL: DelayNs> DelayMs<u32> for Eh1Delay<DL> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay.delay_ms(ms);
+    }
+}
+
+impl<E: Debug> pwm::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl<E: Debug> digital::Error for Error<E> {
+    fn kind(&self) -> digital::ErrorKind {
+        digital::ErrorKind::Other
+    }
+}
+
+/// A D line of a driver shared through a `RefCell`, usable as an embedded-hal 1.0 PWM channel
+///
+/// Duty cycles are linear light levels out of 65535, set through `Lp55231::set_pwm_16bit`, so
+/// that D lines in logarithmic dimming get its finer low-end steps. Each call borrows the driver
+/// mutably, and panics if it is already borrowed.
+pub struct PwmChannel<'a, I: 'a, P: 'a> {
+    /// The shared driver
+    lp: &'a RefCell<Lp55231<I, P>>,
+    /// The D line driven
+    d: D,
+}
+
+impl<'a, I, P> PwmChannel<'a, I, P> {
+    /// Drive the D line of the shared driver
+    pub fn new(lp: &'a RefCell<Lp55231<I, P>>, d: D) -> Self {
+        PwmChannel { lp, d }
+    }
+
+    /// A channel for each D line of the shared driver, in order
+    pub fn split(lp: &'a RefCell<Lp55231<I, P>>) -> [Self; 9] {
+        [D::D1, D::D2, D::D3, D::D4, D::D5, D::D6, D::D7, D::D8, D::D9]
+            .map(|d| PwmChannel::new(lp, d))
+    }
+
+    /// The D line driven
+    pub fn line(&self) -> D {
+        self.d
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> ErrorType for PwmChannel<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    type Error = Error<E>;
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> SetDutyCycle for PwmChannel<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Error<E>> {
+        self.lp.borrow_mut().set_pwm_16bit(self.d, duty)
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> digital::ErrorType for LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    type Error = Error<E>;
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> digital::OutputPin for LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    fn set_low(&mut self) -> Result<(), Error<E>> {
+        self.set(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Error<E>> {
+        self.set(true)
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> digital::StatefulOutputPin for LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    fn is_set_high(&mut self) -> Result<bool, Error<E>> {
+        self.is_lit()
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Error<E>> {
+        self.is_lit().map(|lit| !lit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// An embedded-hal 1.0 delay adding up the time it was asked to wait
+    #[derive(Default)]
+    struct Waited(u64);
+
+    impl DelayNs for Waited {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0 += u64::from(ns);
+        }
+    }
+
+    /// An embedded-hal 1.0 pin remembering its level
+    #[derive(Default)]
+    struct Level(bool);
+
+    impl digital::ErrorType for Level {
+        type Error = Infallible;
+    }
+
+    impl digital::OutputPin for Level {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0 = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0 = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn delays_forward_to_delay_ns() {
+        let mut delay = Eh1Delay::new(Waited::default());
+        DelayUs::<u16>::delay_us(&mut delay, 100);
+        DelayMs::<u8>::delay_ms(&mut delay, 2);
+        DelayMs::<u32>::delay_ms(&mut delay, 1);
+        assert_eq!(delay.release().0, 3_100_000);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn pins_follow_the_driver() {
+        let mut pin = Eh1Pin::new(Level::default());
+        pin.set_high();
+        assert!(pin.pin.0);
+        pin.set_low();
+        assert!(!pin.release().0);
+    }
+}
@@ -0,0 +1,147 @@
+//! Engine handles
+//!
+//! `Lp55231::engine` borrows the driver for one engine, returning an `EngineHandle` whose methods
+//! act on that engine only, so that code working with an engine names it once instead of passing
+//! it to every call:
+//!
+//! ```ignore
+//! let mut e2 = lp.engine(Engine::E2);
+//! e2.set_mode(Mode::Disabled)?;
+//! e2.set_mode(Mode::Load)?;
+//! e2.load(0x20, &program)?;
+//! e2.set_map(OutputMask::none().with(D::D4).with(D::D5))?;
+//! e2.set_mode(Mode::Run)?;
+//! e2.set_exec(Exec::FreeRun)?;
+//! ```
+//!
+//! Each method is the driver method of the same purpose with the engine filled in; see those for
+//! the details.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm;
+use registers as reg;
+use {Engine, Error, Exec, Lp55231, Mode, OutputMask, StepState};
+
+/// One engine of a borrowed driver
+pub struct EngineHandle<'a, I: 'a, P: 'a> {
+    /// The driver
+    lp: &'a mut Lp55231<I, P>,
+    /// The engine acted on
+    e: Engine,
+}
+
+impl<'a, I, P> EngineHandle<'a, I, P> {
+    /// The engine acted on
+    pub fn engine(&self) -> Engine {
+        self.e
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> EngineHandle<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Set the engine's operation mode (see `Lp55231::set_mode`)
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), Error<E>> {
+        self.lp.set_mode(self.e, mode)
+    }
+
+    /// Set how the engine executes its program (see `Lp55231::set_exec`)
+    pub fn set_exec(&mut self, exec: Exec) -> Result<(), Error<E>> {
+        self.lp.set_exec(self.e, exec)
+    }
+
+    /// Freeze the engine's program where it is (see `Lp55231::pause_engine`)
+    pub fn pause(&mut self) -> Result<(), Error<E>> {
+        self.lp.pause_engine(self.e)
+    }
+
+    /// Continue a paused program from where it left off (see `Lp55231::resume_engine`)
+    pub fn resume(&mut self) -> Result<(), Error<E>> {
+        self.lp.resume_engine(self.e)
+    }
+
+    /// Restart the engine's program from its start address, and set it free running
+    pub fn restart(&mut self) -> Result<(), Error<E>> {
+        self.lp.restart_engine(self.e)
+    }
+
+    /// Execute a single instruction of the engine's program (see `Lp55231::debug_step`)
+    pub fn step(&mut self) -> Result<StepState, Error<E>> {
+        self.lp.debug_step(self.e)
+    }
+
+    /// Load a program assembled to run from address 0 at address `base`, and point the engine's
+    /// start address at it (see `Lp55231::load_engine_program`)
+    pub fn load(&mut self, base: u8, instructions: &[u16]) -> Result<(), Error<E>> {
+        self.lp.load_engine_program(self.e, base, instructions)
+    }
+
+    /// Replace the engine's running program with one loaded at `base` (see
+    /// `Lp55231::swap_engine_program`)
+    pub fn swap(&mut self, base: u8, instructions: &[u16]) -> Result<(), Error<E>> {
+        self.lp.swap_engine_program(self.e, base, instructions)
+    }
+
+    /// Read the address of the engine's first instruction
+    pub fn start(&mut self) -> Result<u8, Error<E>> {
+        self.lp.read(reg::PROG1_START + u8::from(self.e))
+    }
+
+    /// Read the engine's program counter
+    pub fn program_counter(&mut self) -> Result<u8, Error<E>> {
+        self.lp.program_counter(self.e)
+    }
+
+    /// Set the engine's program counter, which only takes while the engine is held
+    pub fn set_program_counter(&mut self, pc: u8) -> Result<(), Error<E>> {
+        self.lp.send(&[reg::PC1 + u8::from(self.e), pc])
+    }
+
+    /// Locate the engine's program counter relative to the labels of its program (see
+    /// `Lp55231::engine_position`)
+    pub fn position(&mut self, labels: &asm::Labels) -> Result<asm::Position, Error<E>> {
+        self.lp.engine_position(self.e, labels)
+    }
+
+    /// Read the engine's local variable
+    pub fn variable(&mut self) -> Result<u8, Error<E>> {
+        self.lp.read(reg::ENGINE_A_VAR + u8::from(self.e))
+    }
+
+    /// Read the set of D lines the engine drives
+    pub fn map(&mut self) -> Result<OutputMask, Error<E>> {
+        self.lp.engine_map(self.e)
+    }
+
+    /// Set the D lines the engine drives (see `Lp55231::set_engine_map`)
+    pub fn set_map(&mut self, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.lp.set_engine_map(self.e, outputs)
+    }
+
+    /// Set the D lines the engine drives, after checking that none is claimed elsewhere (see
+    /// `Lp55231::set_engine_map_checked`)
+    pub fn set_map_checked(&mut self, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.lp.set_engine_map_checked(self.e, outputs)
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Borrow the driver for operations on a single engine
+    pub fn engine(&mut self, e: Engine) -> EngineHandle<'_, I, P> {
+        EngineHandle { lp: self, e }
+    }
+}
@@ -0,0 +1,148 @@
+//! Interrupt-fed frame queue
+//!
+//! A `FrameQueue` is a fixed-capacity queue of 9-channel frames for externally generated content:
+//! an interrupt handler or higher-priority task pushes frames as they are produced, and the main
+//! loop drains them into a `stream::FrameSink`, such as the driver, a bounded number per call.
+//! When the producer outruns the consumer, the queue's `Overflow` policy decides which frame is
+//! lost. Each push and pop runs inside a short critical section, so the queue can live in a
+//! `static` shared between priorities.
+//!
+//! ```ignore
+//! static FRAMES: FrameQueue = FrameQueue::new(Overflow::DropOldest);
+//!
+//! // in an interrupt handler
+//! FRAMES.push([0x80; 9]);
+//!
+//! // in the main loop, from a 20 ms timer
+//! FRAMES.drain(&mut lp, 1)?;
+//! ```
+
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+use stream::FrameSink;
+
+/// Number of frames a `FrameQueue` holds
+pub const FRAME_QUEUE_LEN: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Which frame is lost when a frame is pushed onto a full queue
+pub enum Overflow {
+    /// Discard the oldest queued frame to make room, keeping the output current
+    DropOldest,
+    /// Discard the frame being pushed, keeping the queued sequence intact
+    DropNewest,
+}
+
+/// The frames, as a ring buffer
+struct Ring {
+    /// Frame slots
+    frames: [[u8; 9]; FRAME_QUEUE_LEN],
+    /// Index of the oldest frame
+    head: usize,
+    /// Number of queued frames
+    len: usize,
+    /// Number of frames lost to overflow
+    dropped: u32,
+}
+
+/// A fixed-capacity queue of frames shared through a critical section
+pub struct FrameQueue {
+    /// The queued frames
+    ring: Mutex<RefCell<Ring>>,
+    /// What to do when full
+    overflow: Overflow,
+}
+
+impl FrameQueue {
+    /// Create an empty queue, suitable for a `static`
+    pub const fn new(overflow: Overflow) -> Self {
+        FrameQueue {
+            ring: Mutex::new(RefCell::new(Ring {
+                frames: [[0; 9]; FRAME_QUEUE_LEN],
+                head: 0,
+                len: 0,
+                dropped: 0,
+            })),
+            overflow,
+        }
+    }
+
+    /// Queue a frame of the PWM values of D1 through D9. Returns whether it was queued without
+    /// losing a frame.
+    pub fn push(&self, frame: [u8; 9]) -> bool {
+        critical_section::with(|cs| {
+            let mut ring = self.ring.borrow_ref_mut(cs);
+            let full = ring.len == FRAME_QUEUE_LEN;
+            if full {
+                ring.dropped = ring.dropped.wrapping_add(1);
+                match self.overflow {
+                    Overflow::DropNewest => return false,
+                    Overflow::DropOldest => {
+                        ring.head = (ring.head + 1) % FRAME_QUEUE_LEN;
+                        ring.len -= 1;
+                    }
+                }
+            }
+            let tail = (ring.head + ring.len) % FRAME_QUEUE_LEN;
+            ring.frames[tail] = frame;
+            ring.len += 1;
+            !full
+        })
+    }
+
+    /// Take the oldest queued frame, if any
+    pub fn pop(&self) -> Option<[u8; 9]> {
+        critical_section::with(|cs| {
+            let mut ring = self.ring.borrow_ref_mut(cs);
+            if ring.len == 0 {
+                return None;
+            }
+            let frame = ring.frames[ring.head];
+            ring.head = (ring.head + 1) % FRAME_QUEUE_LEN;
+            ring.len -= 1;
+            Some(frame)
+        })
+    }
+
+    /// Apply up to `max` queued frames to the sink, oldest first, returning how many were applied
+    ///
+    /// Calling this from a periodic timer bounds the rate of writes to the device. Stops at the
+    /// first error; the failed frame is not retried.
+    pub fn drain<S: FrameSink>(&self, sink: &mut S, max: usize) -> Result<usize, S::Error> {
+        let mut applied = 0;
+        while applied < max {
+            let frame = match self.pop() {
+                Some(frame) => frame,
+                None => break,
+            };
+            sink.apply_frame(&frame)?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Number of queued frames
+    pub fn len(&self) -> usize {
+        critical_section::with(|cs| self.ring.borrow_ref(cs).len)
+    }
+
+    /// Are there no queued frames
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames lost to overflow since the queue was created
+    pub fn dropped(&self) -> u32 {
+        critical_section::with(|cs| self.ring.borrow_ref(cs).dropped)
+    }
+
+    /// Discard every queued frame
+    pub fn clear(&self) {
+        critical_section::with(|cs| {
+            let mut ring = self.ring.borrow_ref_mut(cs);
+            ring.head = 0;
+            ring.len = 0;
+        })
+    }
+}
@@ -0,0 +1,148 @@
+//! Heap-backed builders
+//!
+//! The default types are fixed-capacity so that the driver runs without a heap. On targets that
+//! have one, and in host-side tooling, these builders grow as needed instead, and produce the
+//! fixed-capacity types (or borrow as them) once complete.
+
+use alloc::vec::Vec;
+
+use animation::Frames;
+#[cfg(feature = "engines")]
+use asm::relocate;
+use color::RgbGroup;
+#[cfg(feature = "engines")]
+use program::{Program, ProgramError, PROGRAM_MEMORY_SIZE};
+use scene::Scene;
+#[cfg(feature = "engines")]
+use Engine;
+
+#[cfg(feature = "engines")]
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Builder of a program memory image from one program per engine
+///
+/// Each engine's program is assembled to run from address 0 (see `asm::Assembler`). `build` lays
+/// the programs out one after another, in engine order, relocating each to its address.
+pub struct ProgramBuilder {
+    /// Instruction words of each engine's program
+    engines: [Vec<u16>; 3],
+}
+
+#[cfg(feature = "engines")]
+impl ProgramBuilder {
+    /// Create a builder with empty programs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The engine's program, to be appended to
+    pub fn engine(&mut self, e: Engine) -> &mut Vec<u16> {
+        &mut self.engines[usize::from(u8::from(e))]
+    }
+
+    /// Total number of instructions across the engines' programs
+    pub fn len(&self) -> usize {
+        self.engines.iter().map(Vec::len).sum()
+    }
+
+    /// Are all the engines' programs empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lay out and relocate the engines' programs into a program memory image
+    pub fn build(&self) -> Result<Program, ProgramError> {
+        if self.len() > PROGRAM_MEMORY_SIZE {
+            return Err(ProgramError::TooLarge);
+        }
+        let mut instructions = Vec::with_capacity(self.len());
+        let mut start = [0u8; 3];
+        for (s, program) in start.iter_mut().zip(self.engines.iter()) {
+            *s = instructions.len() as u8;
+            let at = instructions.len();
+            instructions.extend_from_slice(program);
+            relocate(&mut instructions[at..], *s)?;
+        }
+        // An empty program at the very end of memory still needs a valid start address
+        for s in start.iter_mut() {
+            *s = (*s).min(PROGRAM_MEMORY_SIZE as u8 - 1);
+        }
+        Program::new(start, &instructions)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A growable list of scenes, looked up by identifier
+pub struct SceneList {
+    /// The scenes, in insertion order
+    scenes: Vec<Scene>,
+}
+
+impl SceneList {
+    /// Create an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a scene, replacing any scene with the same identifier
+    pub fn insert(&mut self, scene: Scene) {
+        match self.scenes.iter_mut().find(|s| s.id == scene.id) {
+            Some(s) => *s = scene,
+            None => self.scenes.push(scene),
+        }
+    }
+
+    /// Remove and return the scene with the identifier, if any
+    pub fn remove(&mut self, id: u8) -> Option<Scene> {
+        let at = self.scenes.iter().position(|s| s.id == id)?;
+        Some(self.scenes.remove(at))
+    }
+
+    /// The scene with the identifier, if any
+    pub fn get(&self, id: u8) -> Option<&Scene> {
+        self.scenes.iter().find(|s| s.id == id)
+    }
+
+    /// The scenes, in insertion order
+    pub fn scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A growable list of PWM frames, to be played by an `animation::Animator`
+pub struct FrameList {
+    /// PWM values of D1 through D9, one entry per frame
+    frames: Vec<[u8; 9]>,
+}
+
+impl FrameList {
+    /// Create an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a frame of the PWM values of D1 through D9
+    pub fn push(&mut self, pwm: [u8; 9]) {
+        self.frames.push(pwm);
+    }
+
+    /// Append a frame setting the colors of three RGB LEDs, and every other D line to zero
+    pub fn push_rgb(&mut self, leds: &[RgbGroup; 3], colors: [(u8, u8, u8); 3]) {
+        self.push(Scene::from_rgb(0, leds, colors).pwm);
+    }
+
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Are there no frames
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The frames, to be passed to `Animator::new`
+    pub fn frames(&self) -> Frames<'_> {
+        Frames::Pwm(&self.frames)
+    }
+}
@@ -0,0 +1,91 @@
+//! Host to engine handshake through the global variable
+//!
+//! The global variable (variable C, see `registers::GLOBAL_VAR`) can be written by the host and
+//! read and written by every engine, which makes it the one channel for parameterizing running
+//! programs. This module uses it to carry command codes: the host posts a code with
+//! `Lp55231::post_command`, and engine programs dispatch on it with `Assembler::on_command` or
+//! wait for it with `Assembler::wait_for_command`. An engine may reply by overwriting the
+//! variable with `Assembler::reply`, which the host waits for with `Lp55231::wait_for_reply`.
+//!
+//! ```ignore
+//! let mut asm = Assembler::new();
+//! asm.forever(|asm| {
+//!     asm.on_command(FAST, fast).on_command(SLOW, slow);
+//! });
+//! ```
+//!
+//! The engine side snippets use variable A as scratch, so programs using them must not keep
+//! anything in it across them.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm::{Assembler, Condition, Variable};
+use registers as reg;
+use {Error, Lp55231, Millis};
+
+impl Assembler {
+    /// Jump to `address` if the host has posted `code`, otherwise continue
+    pub fn on_command(&mut self, code: u8, address: u8) -> &mut Self {
+        self.load(Variable::A, code)
+            .jump(Condition::NotEqual, 1, Variable::C, Variable::A)
+            .branch(0, address)
+    }
+
+    /// Wait until the host posts `code`
+    pub fn wait_for_command(&mut self, code: u8) -> &mut Self {
+        let start = self.address();
+        self.load(Variable::A, code)
+            .jump(Condition::Equal, 1, Variable::C, Variable::A)
+            .branch(0, start)
+    }
+
+    /// Reply to the host with `code`, replacing the posted command
+    pub fn reply(&mut self, code: u8) -> &mut Self {
+        self.load(Variable::C, code)
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Post a command code for the engines' programs
+    pub fn post_command(&mut self, code: u8) -> Result<(), Error<E>> {
+        self.send(&[reg::GLOBAL_VAR, code])
+    }
+
+    /// The code currently held in the global variable, as posted by the host or replied by an
+    /// engine
+    pub fn command(&mut self) -> Result<u8, Error<E>> {
+        self.read(reg::GLOBAL_VAR)
+    }
+
+    /// Wait for an engine to reply with `code`, polling the global variable every millisecond
+    ///
+    /// Returns `Error::Timeout` if no reply has arrived after `timeout` ms.
+    pub fn wait_for_reply<T, DL>(
+        &mut self,
+        code: u8,
+        timeout: T,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>>
+    where
+        T: Into<Millis>,
+        DL: DelayMs<u8>,
+    {
+        for _ in 0..=timeout.into().0 {
+            if self.command()? == code {
+                return Ok(());
+            }
+            delay.delay_ms(1);
+        }
+        Err(Error::Timeout)
+    }
+}
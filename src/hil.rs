@@ -0,0 +1,125 @@
+//! Hardware-in-the-loop checks
+//!
+//! `run` takes a device through a scripted sequence against real hardware, so that integrators
+//! can validate their wiring with one call:
+//!
+//! 1. enable the device and check that it responds,
+//! 2. reset it and check every register against its default (see `Lp55231::verify_defaults`),
+//! 3. sweep the direct PWM register of every D line, reading each value back,
+//! 4. run the self-test's LED test (see `Lp55231::self_test`), and
+//! 5. run a one-instruction engine program that writes the global variable.
+//!
+//! The device is left enabled, with every engine disabled and every D line at zero PWM. Any
+//! configuration applied before `run` is lost to the reset.
+//!
+//! On a bench, the sequence can be run from a Raspberry Pi or any other Linux board with
+//! `linux-embedded-hal`, whose bus, pins and delay implement embedded-hal 1.0 and are wrapped with
+//! the adapters in `eh1`. The `linux-hil` example does so, and builds with the `linux` feature:
+//!
+//! ```ignore
+//! let i2c = linux_embedded_hal::I2cdev::new("/dev/i2c-1")?;
+//! let mut lp = Lp55231::new_without_pin(Eh1Bus::new(i2c), Addr::_0x32);
+//! let report = hil::run(&mut lp, &mut Eh1Delay::new(linux_embedded_hal::Delay))?;
+//! assert!(report.passed(), "{:?}", report);
+//! ```
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm::{end, load, End, Variable};
+use registers as reg;
+use selftest::{DefaultMismatch, SelfTestReport};
+use {Engine, Error, Exec, Lp55231, Mode, D};
+
+/// Value written to the global variable by the engine program check
+const ENGINE_MARKER: u8 = 0x5a;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Results of `run`
+pub struct HilReport {
+    /// The device acknowledged and reads back as enabled
+    pub enabled: bool,
+    /// The first register found not to hold its default value after reset, if any
+    pub defaults: Option<DefaultMismatch>,
+    /// The D lines whose PWM register did not read back every value of the sweep
+    pub pwm_failures: u16,
+    /// Results of the LED test
+    pub self_test: SelfTestReport,
+    /// The engine program ran and wrote the global variable
+    pub engine_ran: bool,
+}
+
+impl HilReport {
+    /// Whether every check passed
+    pub fn passed(&self) -> bool {
+        self.enabled
+            && self.defaults.is_none()
+            && self.pwm_failures == 0
+            && self.self_test.passed()
+            && self.engine_ran
+    }
+}
+
+/// Run the scripted sequence against the device
+///
+/// Bus errors abort the sequence and are returned; failed checks are recorded in the report.
+#[allow(deprecated)]
+pub fn run<E, I, P, DL>(lp: &mut Lp55231<I, P>, delay: &mut DL) -> Result<HilReport, Error<E>>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+    DL: DelayMs<u8>,
+{
+    lp.enable()?;
+    delay.delay_ms(1);
+    let enabled = lp.ping().is_ok();
+
+    lp.reset()?;
+    delay.delay_ms(1);
+    let defaults = lp.verify_defaults()?;
+    lp.enable()?;
+    delay.delay_ms(1);
+
+    let mut pwm_failures = 0;
+    for d in D::iter() {
+        let register = reg::D_PWM_BASE + u8::from(d);
+        for pwm in (0..=255).step_by(51) {
+            lp.send(&[register, pwm])?;
+            if lp.read(register)? != pwm {
+                pwm_failures |= 1 << u8::from(d);
+            }
+        }
+        lp.send(&[register, 0])?;
+    }
+
+    let self_test = lp.self_test(delay)?;
+
+    lp.send(&[reg::GLOBAL_VAR, 0])?;
+    lp.set_mode(Engine::E1, Mode::Disabled)?;
+    lp.set_mode(Engine::E1, Mode::Load)?;
+    delay.delay_ms(1);
+    lp.load_engine_program(
+        Engine::E1,
+        0,
+        &[load(Variable::C, ENGINE_MARKER), end(End::default())],
+    )?;
+    lp.set_mode(Engine::E1, Mode::Run)?;
+    lp.set_exec(Engine::E1, Exec::FreeRun)?;
+    delay.delay_ms(10);
+    let engine_ran = lp.read(reg::GLOBAL_VAR)? == ENGINE_MARKER;
+    lp.set_exec(Engine::E1, Exec::Hold)?;
+    lp.set_mode(Engine::E1, Mode::Disabled)?;
+    lp.send(&[reg::GLOBAL_VAR, 0])?;
+
+    Ok(HilReport {
+        enabled,
+        defaults,
+        pwm_failures,
+        self_test,
+        engine_ran,
+    })
+}
@@ -0,0 +1,67 @@
+//! Transaction trace ring buffer
+//!
+//! With the `history` feature enabled, the driver records the last `HISTORY_LEN` register
+//! operations. After a failure, `Lp55231::history` can be dumped for post-mortem analysis on
+//! devices without live log output.
+
+/// Number of register operations recorded
+pub const HISTORY_LEN: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Kind of register operation
+pub enum Op {
+    /// A write, possibly of several consecutive registers
+    Write,
+    /// A read, possibly of several consecutive registers
+    Read,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A recorded register operation
+pub struct Record {
+    /// Kind of operation
+    pub op: Op,
+    /// First register addressed
+    pub register: u8,
+    /// Value written to or read from the first register
+    pub value: u8,
+    /// Number of registers transferred
+    pub len: u8,
+    /// Whether the transfer succeeded
+    pub ok: bool,
+}
+
+/// Ring buffer of the most recent register operations
+pub struct History {
+    /// Recorded operations; the oldest is at `next` once the buffer has wrapped
+    records: [Option<Record>; HISTORY_LEN],
+    /// Index the next record is written to
+    next: usize,
+}
+
+impl History {
+    /// Create an empty history
+    pub(crate) fn new() -> Self {
+        History {
+            records: [None; HISTORY_LEN],
+            next: 0,
+        }
+    }
+
+    /// Record an operation, evicting the oldest if the buffer is full
+    pub(crate) fn push(&mut self, record: Record) {
+        self.records[self.next] = Some(record);
+        self.next = (self.next + 1) % HISTORY_LEN;
+    }
+
+    /// Forget all recorded operations
+    pub fn clear(&mut self) {
+        *self = History::new();
+    }
+
+    /// Iterate over the recorded operations, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &Record> {
+        let (newer, older) = self.records.split_at(self.next);
+        older.iter().chain(newer.iter()).filter_map(|r| r.as_ref())
+    }
+}
@@ -0,0 +1,109 @@
+//! Register transports
+//!
+//! The driver is bound on the `embedded_hal` I2C traits, but all it ever does with the bus is
+//! write and read runs of consecutive registers. `Lp55231Interface` captures just that, so that
+//! transports other than a plain I2C bus, such as an I2C-over-USB bridge, an RPC link to a
+//! co-processor that owns the bus, or a simulator, need only implement two methods.
+//! `InterfaceBus` then adapts such a transport to the traits the driver is bound on. Wrap the
+//! transport before handing it to `Lp55231::new`:
+//!
+//! ```ignore
+//! let lp = Lp55231::new_without_pin(InterfaceBus::new(bridge), Addr::_0x32);
+//! ```
+//!
+//! Every plain I2C bus is itself an `Lp55231Interface`, so code written against the trait, such as
+//! a wrapper adding logging or fault injection, works over either.
+
+use hal::blocking::i2c::{Write, WriteRead};
+
+/// Largest number of consecutive registers in a single transfer
+const MAX_TRANSFER: usize = 256;
+
+/// Register-level access to LP55231 devices
+pub trait Lp55231Interface {
+    /// Error returned by the transport
+    type Error;
+
+    /// Write `values` to consecutive registers of the device at `device`, starting at `register`
+    fn write_registers(
+        &mut self,
+        device: u8,
+        register: u8,
+        values: &[u8],
+    ) -> Result<(), Self::Error>;
+
+    /// Read consecutive registers of the device at `device`, starting at `register`, into `buf`
+    fn read_registers(
+        &mut self,
+        device: u8,
+        register: u8,
+        buf: &mut [u8],
+    ) -> Result<(), Self::Error>;
+}
+
+impl<I, E> Lp55231Interface for I
+where
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    type Error = E;
+
+    /// Write the registers in one I2C transaction, the register address followed by the values
+    fn write_registers(&mut self, device: u8, register: u8, values: &[u8]) -> Result<(), E> {
+        let mut buf = [0u8; MAX_TRANSFER + 1];
+        for chunk in values.chunks(MAX_TRANSFER) {
+            buf[0] = register;
+            buf[1..=chunk.len()].copy_from_slice(chunk);
+            self.write(device, &buf[..=chunk.len()])?;
+        }
+        Ok(())
+    }
+
+    fn read_registers(&mut self, device: u8, register: u8, buf: &mut [u8]) -> Result<(), E> {
+        self.write_read(device, &[register], buf)
+    }
+}
+
+/// A register transport, usable by the driver
+pub struct InterfaceBus<T> {
+    /// The wrapped transport
+    transport: T,
+}
+
+impl<T> InterfaceBus<T> {
+    /// Wrap `transport`
+    pub fn new(transport: T) -> Self {
+        InterfaceBus { transport }
+    }
+
+    /// The wrapped transport
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Release the wrapped transport
+    pub fn release(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: Lp55231Interface> Write for InterfaceBus<T> {
+    type Error = T::Error;
+
+    /// Write the registers addressed by the first byte with the rest
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), T::Error> {
+        match bytes.split_first() {
+            Some((register, values)) => self.transport.write_registers(addr, *register, values),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Lp55231Interface> WriteRead for InterfaceBus<T> {
+    type Error = T::Error;
+
+    /// Read the registers starting at the last byte written
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), T::Error> {
+        let register = bytes.last().cloned().unwrap_or(0);
+        self.transport.read_registers(addr, register, buffer)
+    }
+}
@@ -0,0 +1,180 @@
+//! Per-engine interrupt masking
+//!
+//! An engine raises an interrupt, setting its bit in STATUS_IRQ and asserting the INT pin, when it
+//! executes an `end` instruction with `send_interrupt` set. The device has no register to mask
+//! these per engine, so the driver masks them in the programs it loads instead: with
+//! `Lp55231::set_interrupt_mask`, the `end` instructions of the engines left out of the mask are
+//! loaded without `send_interrupt`, and those engines end silently.
+//!
+//! The mask applies to programs loaded after it is set, through `load_program` (and so
+//! `start_program`) or `load_engine_program`. `engine_interrupts` and `wait_engine_interrupt`
+//! report only the engines in the mask.
+//!
+//! ```ignore
+//! lp.set_interrupt_mask(EngineSet::none().with(Engine::E2));
+//! lp.start_program(&program)?;
+//! let fired = lp.wait_engine_interrupt(EngineSet::all(), 1000, &mut delay)?;
+//! ```
+
+use core::fmt::Debug;
+use core::ops::{BitAnd, BitOr, Not};
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Engine, Error, Lp55231, Millis};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A set of engines, E1 in bit 0
+pub struct EngineSet(u8);
+
+impl EngineSet {
+    /// Mask of the 3 valid bits
+    const ALL: u8 = 0b111;
+
+    /// The empty set
+    pub fn none() -> Self {
+        EngineSet(0)
+    }
+
+    /// All 3 engines
+    pub fn all() -> Self {
+        EngineSet(Self::ALL)
+    }
+
+    /// This set, plus the engine
+    pub fn with(self, e: Engine) -> Self {
+        self | EngineSet::from(e)
+    }
+
+    /// This set, minus the engine
+    pub fn without(self, e: Engine) -> Self {
+        self & !EngineSet::from(e)
+    }
+
+    /// Whether the set contains the engine
+    pub fn contains(self, e: Engine) -> bool {
+        self & EngineSet::from(e) != EngineSet::none()
+    }
+
+    /// Whether the set is empty
+    pub fn is_empty(self) -> bool {
+        self == EngineSet::none()
+    }
+
+    /// Iterate over the engines in the set, in order
+    pub fn iter(self) -> impl Iterator<Item = Engine> {
+        IntoIterator::into_iter([Engine::E1, Engine::E2, Engine::E3])
+            .filter(move |e| self.contains(*e))
+    }
+}
+
+impl From<Engine> for EngineSet {
+    fn from(e: Engine) -> Self {
+        EngineSet(1 << u8::from(e))
+    }
+}
+
+impl From<reg::StatusIrq> for EngineSet {
+    /// The engines whose interrupt bits are set
+    fn from(status: reg::StatusIrq) -> Self {
+        let mut set = EngineSet::none();
+        if status.contains(reg::StatusIrq::ENG1_INT) {
+            set = set.with(Engine::E1);
+        }
+        if status.contains(reg::StatusIrq::ENG2_INT) {
+            set = set.with(Engine::E2);
+        }
+        if status.contains(reg::StatusIrq::ENG3_INT) {
+            set = set.with(Engine::E3);
+        }
+        set
+    }
+}
+
+impl BitOr for EngineSet {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        EngineSet(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for EngineSet {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        EngineSet(self.0 & rhs.0)
+    }
+}
+
+impl Not for EngineSet {
+    type Output = Self;
+    fn not(self) -> Self {
+        EngineSet(!self.0 & Self::ALL)
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Select the engines whose end-of-program interrupts may assert the INT pin, for programs
+    /// loaded from now on
+    pub fn set_interrupt_mask(&mut self, allowed: EngineSet) {
+        self.interrupt_mask = allowed;
+    }
+
+    /// The engines whose end-of-program interrupts may assert the INT pin
+    pub fn interrupt_mask(&self) -> EngineSet {
+        self.interrupt_mask
+    }
+
+    /// Read which engines in the interrupt mask have sent an interrupt. Note that this reads (and
+    /// so clears) the STATUS_IRQ register.
+    pub fn engine_interrupts(&mut self) -> Result<EngineSet, Error<E>> {
+        let status = self.read_register::<reg::StatusIrq>()?;
+        Ok(EngineSet::from(status) & self.interrupt_mask)
+    }
+
+    /// Check whether any of the engines in the interrupt mask has sent an interrupt, returning
+    /// those that have. Note that this reads (and so clears) the STATUS_IRQ register, so
+    /// interrupts from engines outside of `engines` are lost.
+    pub fn poll_engine_interrupt(&mut self, engines: EngineSet) -> nb::Result<EngineSet, Error<E>> {
+        let fired = self.engine_interrupts()? & engines;
+        if fired.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(fired)
+        }
+    }
+
+    /// Wait for any of the engines in the interrupt mask to send an interrupt, polling
+    /// STATUS_IRQ every millisecond, and return those that have
+    ///
+    /// Returns `Error::Timeout` if none has after `timeout` ms. Use this when the INT pin is not
+    /// wired to the host; otherwise, wait for the pin and call `engine_interrupts`.
+    pub fn wait_engine_interrupt<T, DL>(
+        &mut self,
+        engines: EngineSet,
+        timeout: T,
+        delay: &mut DL,
+    ) -> Result<EngineSet, Error<E>>
+    where
+        T: Into<Millis>,
+        DL: DelayMs<u8>,
+    {
+        for _ in 0..=timeout.into().0 {
+            match self.poll_engine_interrupt(engines) {
+                Ok(fired) => return Ok(fired),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => delay.delay_ms(1),
+            }
+        }
+        Err(Error::Timeout)
+    }
+}
@@ -0,0 +1,47 @@
+//! Iterator-based I2C buses
+//!
+//! Some HALs only implement the iterator-based `WriteIter` and `WriteIterRead` traits. `IterBus`
+//! adapts such a bus to the slice-based `Write` and `WriteRead` traits the driver is bound on.
+//! Wrap the bus before handing it to `Lp55231::new`.
+
+use hal::blocking::i2c::{Write, WriteIter, WriteIterRead, WriteRead};
+
+/// An iterator-based I2C bus, usable by the driver
+pub struct IterBus<I> {
+    /// The wrapped I2C bus
+    i2c: I,
+}
+
+impl<I> IterBus<I> {
+    /// Wrap `i2c`
+    pub fn new(i2c: I) -> Self {
+        IterBus { i2c }
+    }
+
+    /// Release the wrapped bus
+    pub fn release(self) -> I {
+        self.i2c
+    }
+}
+
+impl<I, E> Write for IterBus<I>
+where
+    I: WriteIter<Error = E>,
+{
+    type Error = E;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), E> {
+        self.i2c.write(addr, bytes.iter().cloned())
+    }
+}
+
+impl<I, E> WriteRead for IterBus<I>
+where
+    I: WriteIterRead<Error = E>,
+{
+    type Error = E;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), E> {
+        self.i2c.write_iter_read(addr, bytes.iter().cloned(), buffer)
+    }
+}
@@ -3,33 +3,378 @@
 //! This is a driver for the [TI LP55231](http://www.ti.com/product/LP55231) RGB LED controller IC
 //! using the [`embedded_hal`](https://github.com/rust-embedded/embedded-hal/) traits.
 //!
-//! NOTE that this driver is not yet platform agnostic, as it relies on `cortex_m` for a delay.
+//! The driver is platform agnostic: every delay is taken from an `embedded_hal` delay passed in by
+//! the caller, so it runs as well on embedded Linux (see `hil`) as on a microcontroller.
 //!
 //! This driver optionally takes a [digital output
 //! pin](https://docs.rs/embedded-hal/0.2.1/embedded_hal/digital/trait.OutputPin.html) to control
 //! power to the LP55231. It will drive the pin (digital) high on power-on, and (digital) low on
-//! power-off.
+//! power-off. Boards with the EN line tied high use `Lp55231::new_without_pin`.
+//!
+//! The LP5523 shares the LP55231's register map, engines and I2C addresses, so this driver
+//! supports both parts. The `Lp5523` alias is provided for readability on boards using it.
+//! Package variants such as the LP55231SQ are the same die, and need nothing more. As no part in
+//! the family differs in its registers or engines, there is deliberately no family trait or type
+//! parameter: it would have nothing to abstract over.
 #![no_std]
 #![deny(missing_docs)]
 
 extern crate embedded_hal as hal;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "embedded-hal-1")]
+extern crate embedded_hal_1;
+#[cfg(feature = "embedded-storage")]
+extern crate embedded_storage;
+extern crate nb;
+#[cfg(feature = "critical-section")]
+extern crate critical_section;
+#[cfg(feature = "defmt")]
+extern crate defmt;
+#[cfg(feature = "fugit")]
+extern crate fugit;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "palette")]
+extern crate palette;
+#[cfg(feature = "rgb")]
+extern crate rgb;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "smart-leds-trait")]
+extern crate smart_leds_trait;
+#[cfg(feature = "ufmt")]
+extern crate ufmt;
+#[cfg(test)]
+extern crate std;
 #[macro_use]
 extern crate bitflags;
 
-use core::fmt::Debug;
+use core::convert::TryFrom;
+use core::fmt::{self, Debug};
+use core::ops::{BitAnd, BitOr, Not};
+use hal::blocking::delay::{DelayMs, DelayUs};
 use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
 use hal::digital::OutputPin;
 
+pub mod animation;
+#[cfg(feature = "engines")]
+pub mod asm;
+pub mod batch;
+#[cfg(feature = "engines")]
+pub mod blink;
+pub mod boards;
+pub mod burnin;
+pub mod calibration;
+#[cfg(feature = "engines")]
+pub mod candle;
+pub mod chargepump;
+#[cfg(feature = "engines")]
+pub mod chase;
+pub mod color;
+pub mod crc;
+mod current;
+pub mod curve;
+mod dimming;
+pub mod dither;
+#[cfg(feature = "embedded-hal-1")]
+pub mod eh1;
+#[cfg(feature = "engines")]
+pub mod engine;
+#[cfg(feature = "critical-section")]
+pub mod framequeue;
+#[cfg(feature = "alloc")]
+pub mod growable;
+#[cfg(feature = "engines")]
+pub mod handshake;
+#[cfg(feature = "hil")]
+pub mod hil;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod interface;
+#[cfg(feature = "engines")]
+pub mod interrupt;
+pub mod iter_bus;
+#[cfg(feature = "engines")]
+pub mod listing;
+#[cfg(feature = "engines")]
+pub mod lockstep;
+#[cfg(test)]
+mod mock;
+pub mod ontime;
+#[cfg(feature = "engines")]
+pub mod ota;
+#[cfg(feature = "engines")]
+pub mod patterns;
+pub mod persist;
+pub mod pin;
+mod powersave;
+#[cfg(feature = "engines")]
+pub mod program;
+pub mod queue;
+mod recovery;
 pub mod registers;
 use registers as reg;
+pub mod retry;
+pub mod scene;
+pub mod selftest;
+#[cfg(feature = "engines")]
+pub mod sim;
+pub mod snapshot;
+#[cfg(feature = "engines")]
+pub mod source;
+#[cfg(feature = "critical-section")]
+pub mod shared;
+mod slew;
+pub mod stream;
+pub mod strip;
+pub mod telemetry;
+#[cfg(feature = "engines")]
+pub mod template;
+pub mod thermal;
+#[cfg(feature = "engines")]
+pub mod timing;
+mod trace;
+pub mod transaction;
+#[cfg(feature = "ufmt")]
+mod ufmt_impls;
+#[cfg(feature = "engines")]
+pub mod watchdog;
 
 #[derive(Debug)]
+#[non_exhaustive]
 /// Error conditions returned by the LP55231
 pub enum Error<I> {
     /// The LP is not currently enabled
     NotEnabled,
-    /// Generic I2c error
-    I2cError(I),
+    /// Generic I2c error, with the register operation that failed
+    I2cError(I, Access),
+    /// An argument was outside of the range accepted by the device
+    InvalidArgument,
+    /// An engine is busy and cannot accept the operation
+    EngineBusy,
+    /// A program does not fit in the program memory
+    ProgramTooLarge,
+    /// The device did not complete an operation in the allotted time
+    Timeout,
+    /// A value read back from the device did not match the value written
+    VerifyFailed,
+    /// The D line is already claimed by another engine or by ratiometric dimming
+    #[cfg(feature = "engines")]
+    MappingConflict(D, Conflict),
+    /// The device has reset or been disabled since it was enabled (see `reset_detected`)
+    DeviceReset,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Direction of a register operation
+pub enum Operation {
+    /// Registers were read
+    Read,
+    /// Registers were written
+    Write,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// The register operation during which a bus error occurred
+///
+/// Displays as e.g. `writing 9 registers from 0x16 at 0x32`, or `reading register 0x3a at 0x32`.
+pub struct Access {
+    /// I2C address of the device, as passed to the HAL
+    pub device: u8,
+    /// Whether registers were read or written
+    pub operation: Operation,
+    /// First register addressed
+    pub register: u8,
+    /// Number of consecutive registers transferred
+    pub len: u8,
+}
+
+impl Access {
+    /// Whether several consecutive registers were transferred in one burst
+    pub fn is_burst(&self) -> bool {
+        self.len > 1
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.operation {
+            Operation::Read => "reading",
+            Operation::Write => "writing",
+        };
+        if self.is_burst() {
+            write!(f, "{} {} registers from {:#04x}", op, self.len, self.register)?;
+        } else {
+            write!(f, "{} register {:#04x}", op, self.register)?;
+        }
+        write!(f, " at {:#04x}", self.device)
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<I: embedded_hal_1::i2c::Error> Error<I> {
+    /// Classify a bus error, for HALs whose error type also implements the embedded-hal 1.0
+    /// `i2c::Error` trait. A `NoAcknowledge` kind usually means the device is absent or
+    /// unpowered, while bus and arbitration errors call for resetting the bus. Returns `None`
+    /// for errors that did not come from the bus.
+    pub fn i2c_error_kind(&self) -> Option<embedded_hal_1::i2c::ErrorKind> {
+        match self {
+            Error::I2cError(e, _) => Some(e.kind()),
+            _ => None,
+        }
+    }
+}
+
+impl<I> Error<I> {
+    /// A fixed description of the error, without its details, for logging over links too small
+    /// for `core::fmt`
+    pub fn message(&self) -> &'static str {
+        match self {
+            Error::NotEnabled => "device not enabled",
+            Error::I2cError(_, _) => "I2C error",
+            Error::InvalidArgument => "argument out of range",
+            Error::EngineBusy => "engine busy",
+            Error::ProgramTooLarge => "program does not fit in program memory",
+            Error::Timeout => "timed out waiting for device",
+            Error::VerifyFailed => "read back value does not match value written",
+            #[cfg(feature = "engines")]
+            Error::MappingConflict(_, _) => "D line already claimed",
+            Error::DeviceReset => "device reset unexpectedly",
+        }
+    }
+
+    /// The register operation during which a bus error occurred, or `None` for errors that did not
+    /// come from the bus
+    pub fn access(&self) -> Option<Access> {
+        match self {
+            Error::I2cError(_, access) => Some(*access),
+            _ => None,
+        }
+    }
+}
+
+impl<I: Debug> fmt::Display for Error<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::I2cError(e, access) => write!(f, "I2C error {}: {:?}", access, e),
+            #[cfg(feature = "engines")]
+            Error::MappingConflict(d, Conflict::Engine(e)) => {
+                write!(f, "{:?} is already mapped to engine {:?}", d, e)
+            }
+            #[cfg(feature = "engines")]
+            Error::MappingConflict(d, Conflict::Ratiometric) => {
+                write!(f, "{:?} is already under ratiometric dimming", d)
+            }
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl<I: Debug> core::error::Error for Error<I> {}
+
+#[cfg(feature = "engines")]
+impl<I> From<program::ProgramError> for Error<I> {
+    fn from(e: program::ProgramError) -> Self {
+        match e {
+            program::ProgramError::TooLarge => Error::ProgramTooLarge,
+            _ => Error::InvalidArgument,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Clock source selection
+///
+/// Several LP55231s can be kept phase-locked by running them all from one 32 kHz clock. Drive the
+/// CLK pins of every chip from the shared clock and configure each with `External` (or
+/// `Automatic`, to fall back to the internal oscillator should the clock go away).
+///
+/// The CLK pin is an input only: the LP55231 cannot put its internal oscillator out on any pin,
+/// so no chip can act as the clock leader, and there is no output mode. The shared clock must
+/// come from elsewhere, such as a 32.768 kHz oscillator or a microcontroller timer output.
+pub enum ClockMode {
+    /// Always use the internal oscillator
+    Internal,
+    /// Always use the external clock on the CLK pin
+    External,
+    /// Use the external clock when one is detected, and the internal oscillator otherwise
+    Automatic,
+}
+
+impl From<ClockMode> for reg::Misc {
+    fn from(c: ClockMode) -> Self {
+        match c {
+            ClockMode::Internal => reg::Misc::INT_CLK_EN,
+            ClockMode::External => reg::Misc::empty(),
+            ClockMode::Automatic => reg::Misc::CLK_DET_EN,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Function of the INT pin
+pub enum IntMode {
+    /// The INT pin signals engine interrupts
+    Interrupt,
+    /// The INT pin is a general purpose output, driven high when `true`
+    Output(bool),
+}
+
+impl From<IntMode> for reg::IntGpio {
+    fn from(m: IntMode) -> Self {
+        match m {
+            IntMode::Interrupt => reg::IntGpio::empty(),
+            IntMode::Output(false) => reg::IntGpio::INT_CONF,
+            IntMode::Output(true) => reg::IntGpio::INT_CONF | reg::IntGpio::INT_GPO,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Fault conditions detected by `Lp55231::check_faults`
+pub enum Fault {
+    /// The die temperature is above the monitor's threshold
+    OverTemperature(DegreesC),
+    /// The charge pump output voltage, in mV, is below the monitor's threshold
+    ChargePump(i16),
+}
+
+#[derive(Copy, Clone)]
+/// Thresholds and notification hook used by `Lp55231::check_faults`
+pub struct Monitor {
+    /// Die temperature above which `Fault::OverTemperature` is reported
+    pub max_temp: DegreesC,
+    /// VOUT voltage, in mV, below which `Fault::ChargePump` is reported
+    pub min_vout: i16,
+    /// Called once for every fault detected
+    pub on_fault: Option<fn(Fault)>,
+}
+
+impl Default for Monitor {
+    /// 85°C maximum temperature, and a 4.2V minimum VOUT for the 1.5x charge pump mode set by
+    /// `enable`
+    fn default() -> Self {
+        Monitor {
+            max_temp: DegreesC(85),
+            min_vout: 4200,
+            on_fault: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// How the I2C address is passed to the bus implementation
+pub enum AddrMode {
+    /// The 7-bit address, as expected by `embedded_hal` I2C implementations
+    SevenBit,
+    /// The 7-bit address shifted left by one, as expected by some HALs that take the address byte
+    /// including the R/W bit
+    Shifted,
 }
 
 #[derive(Copy, Clone)]
@@ -43,6 +388,8 @@ pub enum Addr {
     _0x34,
     /// ASEL1=VEN, ASEL2=VEN
     _0x35,
+    /// Any other 7-bit address, e.g. when the part sits behind an address translator or mux
+    Custom(u8),
 }
 
 impl From<Addr> for u8 {
@@ -52,11 +399,13 @@ impl From<Addr> for u8 {
             Addr::_0x33 => 0x33_u8,
             Addr::_0x34 => 0x34_u8,
             Addr::_0x35 => 0x35_u8,
+            Addr::Custom(a) => a,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 /// Enumeration of the 9 LED lines from the chip
 pub enum D {
     /// LED line D1
@@ -95,6 +444,572 @@ impl From<D> for u8 {
     }
 }
 
+impl TryFrom<u8> for D {
+    /// The rejected index
+    type Error = u8;
+
+    /// Convert a 0-based index (0 for D1, 8 for D9) to a D line
+    fn try_from(i: u8) -> Result<Self, u8> {
+        D::ALL.get(usize::from(i)).cloned().ok_or(i)
+    }
+}
+
+impl D {
+    /// All 9 D lines, in order
+    pub const ALL: [D; 9] = [
+        D::D1,
+        D::D2,
+        D::D3,
+        D::D4,
+        D::D5,
+        D::D6,
+        D::D7,
+        D::D8,
+        D::D9,
+    ];
+
+    /// Iterate over all 9 D lines, in order
+    pub fn iter() -> impl Iterator<Item = D> {
+        D::ALL.iter().cloned()
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A set of D lines, as held by the MSB/LSB register pairs (output on/off, ratiometric dimming,
+/// engine mapping)
+pub struct OutputMask(u16);
+
+impl OutputMask {
+    /// Mask of the 9 valid bits
+    const ALL: u16 = 0x01ff;
+
+    /// The empty set
+    pub fn none() -> Self {
+        OutputMask(0)
+    }
+
+    /// All 9 D lines
+    pub fn all() -> Self {
+        OutputMask(Self::ALL)
+    }
+
+    /// This set, plus the D line
+    pub fn with(self, d: D) -> Self {
+        self | OutputMask::from(d)
+    }
+
+    /// This set, minus the D line
+    pub fn without(self, d: D) -> Self {
+        self & !OutputMask::from(d)
+    }
+
+    /// Whether the set contains the D line
+    pub fn contains(self, d: D) -> bool {
+        self & OutputMask::from(d) != OutputMask::none()
+    }
+
+    /// Iterate over the D lines in the set, in order
+    pub fn iter(self) -> impl Iterator<Item = D> {
+        D::iter().filter(move |d| self.contains(*d))
+    }
+
+    /// Build the set from its MSB (D9) and LSB (D8-D1) register values
+    pub fn from_bytes(msb: u8, lsb: u8) -> Self {
+        OutputMask((u16::from(msb) << 8 | u16::from(lsb)) & Self::ALL)
+    }
+
+    /// The MSB (D9) register value
+    pub fn msb(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    /// The LSB (D8-D1) register value
+    pub fn lsb(self) -> u8 {
+        self.0 as u8
+    }
+}
+
+impl From<D> for OutputMask {
+    fn from(d: D) -> Self {
+        OutputMask(1 << u8::from(d))
+    }
+}
+
+impl BitOr for OutputMask {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        OutputMask(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for OutputMask {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        OutputMask(self.0 & rhs.0)
+    }
+}
+
+impl Not for OutputMask {
+    type Output = Self;
+    fn not(self) -> Self {
+        OutputMask(!self.0 & Self::ALL)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A raw PWM duty cycle, out of 255
+pub struct Brightness(pub u8);
+
+impl From<u8> for Brightness {
+    fn from(b: u8) -> Self {
+        Brightness(b)
+    }
+}
+
+impl From<Brightness> for u8 {
+    fn from(b: Brightness) -> Self {
+        b.0
+    }
+}
+
+impl From<Percent> for Brightness {
+    fn from(p: Percent) -> Self {
+        Brightness(((u16::from(p.0) * 255 + 50) / 100) as u8)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A PWM duty cycle, in percent
+pub struct Percent(u8);
+
+impl Percent {
+    /// Create a new percentage. Values above 100 are clamped.
+    pub fn new(p: u8) -> Self {
+        Percent(p.min(100))
+    }
+
+    /// The percentage, from 0 to 100
+    pub fn value(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<Brightness> for Percent {
+    fn from(b: Brightness) -> Self {
+        Percent(((u16::from(b.0) * 100 + 127) / 255) as u8)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A duration, in ms
+///
+/// With the `fugit` feature, any `fugit::Duration` converts into `Millis`, saturating at
+/// `u16::MAX` ms.
+pub struct Millis(pub u16);
+
+impl From<u16> for Millis {
+    fn from(ms: u16) -> Self {
+        Millis(ms)
+    }
+}
+
+impl From<Millis> for u16 {
+    fn from(ms: Millis) -> Self {
+        ms.0
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> From<fugit::Duration<u32, NOM, DENOM>> for Millis {
+    fn from(d: fugit::Duration<u32, NOM, DENOM>) -> Self {
+        Millis(d.to_millis().min(u32::from(u16::MAX)) as u16)
+    }
+}
+
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> From<fugit::Duration<u64, NOM, DENOM>> for Millis {
+    fn from(d: fugit::Duration<u64, NOM, DENOM>) -> Self {
+        Millis(d.to_millis().min(u64::from(u16::MAX)) as u16)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A temperature, in °C
+///
+/// Displays as e.g. `42°C`.
+pub struct DegreesC(pub i8);
+
+impl From<i8> for DegreesC {
+    fn from(c: i8) -> Self {
+        DegreesC(c)
+    }
+}
+
+impl From<DegreesC> for i8 {
+    fn from(c: DegreesC) -> Self {
+        c.0
+    }
+}
+
+impl fmt::Display for DegreesC {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}°C", self.0)
+    }
+}
+#[cfg(feature = "engines")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// What a D line was already claimed by, when mapping it to an engine
+pub enum Conflict {
+    /// Another engine drives the D line
+    Engine(Engine),
+    /// The D line has ratiometric dimming enabled
+    Ratiometric,
+}
+
+#[cfg(feature = "engines")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Enumeration of the 3 program execution engines
+pub enum Engine {
+    /// Program execution engine 1
+    E1,
+    /// Program execution engine 2
+    E2,
+    /// Program execution engine 3
+    E3,
+}
+
+#[cfg(feature = "engines")]
+impl From<Engine> for u8 {
+    fn from(e: Engine) -> Self {
+        match e {
+            Engine::E1 => 0,
+            Engine::E2 => 1,
+            Engine::E3 => 2,
+        }
+    }
+}
+
+#[cfg(feature = "engines")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Engine operation mode
+pub enum Mode {
+    /// The engine is disabled
+    Disabled,
+    /// The program memory may be written. All engines hold while any is in this mode.
+    Load,
+    /// The engine executes its program, as set by its `Exec`
+    Run,
+    /// The engine's D lines are controlled directly, and the program counter is reset
+    Halt,
+}
+
+#[cfg(feature = "engines")]
+impl From<Mode> for u8 {
+    fn from(m: Mode) -> Self {
+        match m {
+            Mode::Disabled => 0b00,
+            Mode::Load => 0b01,
+            Mode::Run => 0b10,
+            Mode::Halt => 0b11,
+        }
+    }
+}
+
+#[cfg(feature = "engines")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// How a running engine executes its program
+pub enum Exec {
+    /// Do not execute; the program counter can be read or written
+    Hold,
+    /// Execute the instruction at the program counter, increment it, and hold
+    Step,
+    /// Execute the program continuously
+    FreeRun,
+    /// Execute the instruction at the program counter and hold, without incrementing it
+    ExecuteOnce,
+}
+
+#[cfg(feature = "engines")]
+impl From<Exec> for u8 {
+    fn from(x: Exec) -> Self {
+        match x {
+            Exec::Hold => 0b00,
+            Exec::Step => 0b01,
+            Exec::FreeRun => 0b10,
+            Exec::ExecuteOnce => 0b11,
+        }
+    }
+}
+
+#[cfg(feature = "engines")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Engine state after `Lp55231::debug_step`
+pub struct StepState {
+    /// The engine's program counter
+    pub pc: u8,
+    /// The engine's local variable
+    pub variable: u8,
+    /// The global variable shared by the engines
+    pub global_variable: u8,
+}
+
+#[cfg(feature = "engines")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Where a D line takes its PWM value from
+pub enum Source {
+    /// The D line follows its direct PWM register
+    Direct,
+    /// The D line is driven by a program execution engine
+    Engine(Engine),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Enumeration of the 3 master faders
+pub enum Fader {
+    /// MASTER FADER1
+    F1,
+    /// MASTER FADER2
+    F2,
+    /// MASTER FADER3
+    F3,
+}
+
+impl From<Fader> for u8 {
+    fn from(f: Fader) -> Self {
+        match f {
+            Fader::F1 => 0,
+            Fader::F2 => 1,
+            Fader::F3 => 2,
+        }
+    }
+}
+
+impl From<Fader> for reg::DxCtrl {
+    fn from(f: Fader) -> Self {
+        match f {
+            Fader::F1 => reg::DxCtrl::MAPPING_FADER1,
+            Fader::F2 => reg::DxCtrl::MAPPING_FADER2,
+            Fader::F3 => reg::DxCtrl::MAPPING_FADER3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Inputs of the LED test ADC
+pub enum LedTestInput {
+    /// The voltage on a D line's pin
+    Pin(D),
+    /// The charge pump output
+    Vout,
+    /// The supply
+    Vdd,
+    /// The INT pin
+    Int,
+}
+
+impl From<LedTestInput> for u8 {
+    fn from(input: LedTestInput) -> Self {
+        match input {
+            LedTestInput::Pin(d) => u8::from(d),
+            LedTestInput::Vout => reg::TestCtl::LEDTEST_VOUT.bits(),
+            LedTestInput::Vdd => reg::TestCtl::LEDTEST_VDD.bits(),
+            LedTestInput::Int => reg::TestCtl::LEDTEST_INT.bits(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Per-channel configuration, as held by the Dx_CTRL registers
+pub struct ChannelConfig {
+    /// Master fader this channel is mapped to, if any
+    pub fader: Option<Fader>,
+    /// Use logarithmic (rather than linear) PWM brightness adjustment
+    pub log_dimming: bool,
+    /// Temperature compensation factor, in 0.1 %/°C steps. Valid range is -15 to 15; values
+    /// outside of it are clamped.
+    pub temp_comp: i8,
+}
+
+impl From<ChannelConfig> for u8 {
+    fn from(c: ChannelConfig) -> Self {
+        let mut bits = reg::DxCtrl::empty();
+        if let Some(f) = c.fader {
+            bits |= reg::DxCtrl::from(f);
+        }
+        if c.log_dimming {
+            bits |= reg::DxCtrl::LOG_EN;
+        }
+        let comp = c.temp_comp.clamp(-15, 15);
+        if comp < 0 {
+            bits |= reg::DxCtrl::TEMP_COMP_NEG;
+        }
+        bits.bits() | (comp.unsigned_abs() & reg::DxCtrl::TEMP_COMP_MAG.bits())
+    }
+}
+
+impl From<u8> for ChannelConfig {
+    fn from(b: u8) -> Self {
+        let bits = reg::DxCtrl::from_bits_truncate(b);
+        let fader = match bits & reg::DxCtrl::MAPPING_FADER3 {
+            reg::DxCtrl::MAPPING_FADER1 => Some(Fader::F1),
+            reg::DxCtrl::MAPPING_FADER2 => Some(Fader::F2),
+            reg::DxCtrl::MAPPING_FADER3 => Some(Fader::F3),
+            _ => None,
+        };
+        let magnitude = (bits & reg::DxCtrl::TEMP_COMP_MAG).bits() as i8;
+        ChannelConfig {
+            fader,
+            log_dimming: bits.contains(reg::DxCtrl::LOG_EN),
+            temp_comp: if bits.contains(reg::DxCtrl::TEMP_COMP_NEG) {
+                -magnitude
+            } else {
+                magnitude
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The device's configuration registers, as applied by `Lp55231::apply_config` and rewritten by
+/// `Lp55231::refresh_config`
+pub struct Config {
+    /// Clock, charge pump and auto-increment configuration
+    pub misc: reg::Misc,
+    /// Charge pump gain change tuning
+    pub gain_change: reg::GainChange,
+    /// Function of the INT pin
+    pub int_mode: IntMode,
+    /// Configuration of D1 through D9
+    pub channels: [ChannelConfig; 9],
+    /// D lines turned on
+    pub outputs: OutputMask,
+    /// D lines with ratiometric dimming enabled
+    pub ratiometric: OutputMask,
+    /// The LEDs wired to the D lines, from which the charge pump mode is chosen, overriding the
+    /// mode in `misc` (see `chargepump`)
+    pub wiring: Option<chargepump::Wiring>,
+    /// State shown as soon as `enable` has configured the device, rather than every D line dark
+    pub startup: Option<Startup>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// What the device shows right after `Lp55231::enable`, as set in `Config::startup`
+pub enum Startup {
+    /// Set every D line to the scene's PWM values at once, ignoring its transition time
+    Scene(scene::Scene),
+    /// Start the program (see `Lp55231::start_program`)
+    #[cfg(feature = "engines")]
+    Program(program::Program),
+}
+
+impl Default for Config {
+    /// The configuration set by `Lp55231::enable`, with every D line turned on
+    fn default() -> Self {
+        Config {
+            misc: reg::Misc::INT_CLK_EN
+                | reg::Misc::CLK_DET_EN
+                | reg::Misc::CP_MODE_1_5x
+                | reg::Misc::EN_AUTO_INCR,
+            gain_change: reg::GainChange::empty(),
+            int_mode: IntMode::Interrupt,
+            channels: [ChannelConfig::default(); 9],
+            outputs: OutputMask::all(),
+            ratiometric: OutputMask::none(),
+            wiring: None,
+            startup: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Delays, in µs, between the steps of the power sequence performed by
+/// `Lp55231::enable_with_delay`
+pub struct PowerSequence {
+    /// From setting the enable line high to sending CHIP_EN
+    pub en_to_chip_en: u32,
+    /// From sending CHIP_EN to configuring the device
+    pub chip_en_to_config: u32,
+    /// From disabling the device to setting the enable line high again. While not zero,
+    /// `Lp55231::enable` refuses to power up a disabled device, as it cannot wait.
+    pub off_hold_off: u32,
+}
+
+impl Default for PowerSequence {
+    /// The 500 µs startup delays from the datasheet, and no hold-off
+    fn default() -> Self {
+        PowerSequence {
+            en_to_chip_en: 500,
+            chip_en_to_config: 500,
+            off_hold_off: 0,
+        }
+    }
+}
+
+/// Size of the buffer used to split long writes (see `Lp55231::set_max_write_len`)
+pub const CHUNK_BUF_LEN: usize = 32;
+
+/// Wait `us` µs, in steps `DelayUs<u16>` can express
+fn delay_us<DL: DelayUs<u16> + ?Sized>(delay: &mut DL, us: u32) {
+    let mut remaining = us;
+    while remaining > 0 {
+        let step = remaining.min(u32::from(u16::MAX));
+        delay.delay_us(step as u16);
+        remaining -= step;
+    }
+}
+
+/// The LP5523 device. Its register map and engines are identical to the LP55231's, so it is
+/// driven by the same type rather than through a family trait.
+pub type Lp5523<I, P> = Lp55231<I, P>;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+/// A stand-in for the enable pin, for boards whose EN line is tied high
+///
+/// Setting it does nothing. See `Lp55231::new_without_pin`.
+pub struct NoPin;
+
+#[allow(deprecated)]
+impl OutputPin for NoPin {
+    fn set_low(&mut self) {}
+
+    fn set_high(&mut self) {}
+}
+
+/// Whether the shadow cache may hold the register's value
+fn shadowed(register: u8) -> bool {
+    usize::from(register) < SHADOW_LEN && reg::verifiable(register)
+}
+
+/// Number of 1ms polls to wait for an ADC conversion to complete
+const CONVERSION_POLLS: u8 = 50;
+
+/// Number of STATUS_IRQ reads `enable` makes waiting for the internal startup sequence, each
+/// taking over 100µs at 400 kHz, against a sequence of about 500µs
+const STARTUP_POLLS: u8 = 20;
+
+/// Interval between STATUS_IRQ reads in `enable_with_delay`, in µs
+const STARTUP_POLL_INTERVAL: u32 = 100;
+
+/// Longest time `enable_with_delay` waits for the internal startup sequence, in µs
+const STARTUP_TIMEOUT_US: u32 = 10_000;
+
+/// Number of registers below the program memory, which the shadow cache may hold
+const SHADOW_LEN: usize = reg::PROG_MEM_BASE as usize;
+
 /// The LP55231 device
 pub struct Lp55231<I, P> {
     /// The owned I2C bus
@@ -105,8 +1020,90 @@ pub struct Lp55231<I, P> {
     addr: u8,
     /// Has the LP55231 been enabled
     en: bool,
+    /// Read back and compare every register write
+    verify_writes: bool,
+    /// Maximum number of bytes, including the register address, in a single I2C write
+    max_write_len: usize,
+    /// Configuration last applied with `apply_config`
+    config: Option<Config>,
+    /// Clock source last selected with `set_clock_mode`, written again by `enable`
+    clock_mode: Option<ClockMode>,
+    /// Curve applied to each master fader value
+    fader_curves: [curve::Curve; 3],
+    /// Curve applied to each D line's PWM value
+    pwm_curves: [curve::Curve; 9],
+    /// Brightness trim of each D line, out of 255
+    trims: [u8; 9],
+    /// Color correction matrix of each RGB group that has one
+    color_corrections: [Option<(color::RgbGroup, color::ColorMatrix)>; 3],
+    /// Red, green and blue scales of each RGB group with a white point
+    white_points: [Option<(color::RgbGroup, [u8; 3])>; 3],
+    /// Encoding of colors passed to `set_color`
+    color_space: color::ColorSpace,
+    /// Correction added to every temperature reading, in °C
+    temperature_offset: i8,
+    /// Delays used by `enable_with_delay`
+    power_sequence: PowerSequence,
+    /// Has the device been disabled since it was last enabled
+    powered_down: bool,
+    /// Last value written to each register, when the shadow cache is enabled
+    shadow: Option<[Option<u8>; SHADOW_LEN]>,
+    /// Skip unchanged channels in `write_frame_fast`
+    fast_skip: bool,
+    /// Last frame written in full by `write_frame_fast`, while skipping unchanged channels
+    fast_frame: Option<[u8; 9]>,
+    /// Re-initialize the device when `ping` or `telemetry` detect a reset
+    auto_recover: bool,
+    /// Number of resets repaired automatically
+    recovered_resets: u16,
+    /// Number of I2C transactions failed in a row
+    bus_errors: u16,
+    /// Program restarted by `reinit`
+    #[cfg(feature = "engines")]
+    recovery_program: Option<program::Program>,
+    /// Engines whose end-of-program interrupts may assert the INT pin
+    #[cfg(feature = "engines")]
+    interrupt_mask: interrupt::EngineSet,
+    /// Automatic power-save policy, if any
+    power_save: Option<powersave::PowerSave>,
+    /// Flicker-sensitive mode, if enabled
+    flicker_guard: Option<powersave::FlickerGuard>,
+    /// Slew limit and state of each direct PWM register and master fader
+    slew: [Option<slew::SlewChannel>; slew::SLEW_CHANNELS],
+    /// Write without applying the slew limits
+    slew_bypass: bool,
+    /// On-time accumulator, when tracking
+    on_time: Option<ontime::OnTime>,
+    /// D lines kept off after a fault
+    failed_outputs: OutputMask,
+    /// Highest drive current of each D line
+    current_caps: [Option<u8>; 9],
+    /// Lower drive currents over the caps rather than rejecting them
+    clamp_currents: bool,
+    /// Global brightness scale, out of 255
+    global_brightness: u8,
+    /// D lines mapped to a master fader, one bit per D line
+    fader_mapped: u16,
+    /// Value of each master fader set through the driver, before the global brightness
+    fader_values: [Option<u8>; 3],
+    /// The most recent register operations
+    #[cfg(feature = "history")]
+    history: history::History,
+}
+
+impl<E, I> Lp55231<I, NoPin>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Create a new instance of an LP55231, as with `new`, whose EN line is tied high rather than
+    /// driven by a pin
+    pub fn new_without_pin(i2c: I, addr: Addr) -> Self {
+        Self::new(i2c, None, addr)
+    }
 }
 
+#[allow(deprecated)]
 impl<E, I, P> Lp55231<I, P>
 where
     E: Debug,
@@ -117,65 +1114,1526 @@ where
     /// Create a new instance of an LP55231 that exclusively owns its I2C bus. Optionally takes a
     /// power control pin.
     pub fn new(i2c: I, en_pin: Option<P>, addr: Addr) -> Self {
+        Self::new_with_addr_mode(i2c, en_pin, addr, AddrMode::SevenBit)
+    }
+
+    /// Create a new instance of an LP55231, as with `new`, for a HAL using the given addressing
+    /// convention
+    pub fn new_with_addr_mode(i2c: I, en_pin: Option<P>, addr: Addr, mode: AddrMode) -> Self {
+        let addr = match mode {
+            AddrMode::SevenBit => u8::from(addr),
+            AddrMode::Shifted => u8::from(addr) << 1,
+        };
         Lp55231 {
             i2c,
             en_pin,
-            addr: u8::from(addr) << 1,
+            addr,
             en: false,
+            verify_writes: false,
+            max_write_len: usize::MAX,
+            config: None,
+            clock_mode: None,
+            fader_curves: [curve::Curve::Linear; 3],
+            pwm_curves: [curve::Curve::Linear; 9],
+            trims: [0xff; 9],
+            color_corrections: [None; 3],
+            white_points: [None; 3],
+            color_space: color::ColorSpace::Linear,
+            temperature_offset: 0,
+            power_sequence: PowerSequence::default(),
+            powered_down: false,
+            shadow: None,
+            fast_skip: false,
+            fast_frame: None,
+            auto_recover: false,
+            recovered_resets: 0,
+            bus_errors: 0,
+            #[cfg(feature = "engines")]
+            recovery_program: None,
+            #[cfg(feature = "engines")]
+            interrupt_mask: interrupt::EngineSet::all(),
+            power_save: None,
+            flicker_guard: None,
+            slew: [None; slew::SLEW_CHANNELS],
+            slew_bypass: false,
+            on_time: None,
+            failed_outputs: OutputMask::none(),
+            current_caps: [None; 9],
+            clamp_currents: false,
+            global_brightness: 255,
+            fader_mapped: 0,
+            fader_values: [None; 3],
+            #[cfg(feature = "history")]
+            history: history::History::new(),
         }
     }
 
-    /// Convenience method to call `self.i2c.write` with `self.addr`
+    /// The most recent register operations, for post-mortem analysis
+    #[cfg(feature = "history")]
+    pub fn history(&mut self) -> &mut history::History {
+        &mut self.history
+    }
+
+    /// The address passed to the I2C bus implementation for this device
+    pub fn address(&self) -> u8 {
+        self.addr
+    }
+
+    /// Convenience method to call `self.i2c.write` with `self.addr`, after checking drive currents
+    /// against their caps (see `set_current_cap`) and applying any slew limit (see
+    /// `set_slew_limit`), and skipping the write if the shadow cache shows it would change
+    /// nothing (see `set_shadow_cache`)
     fn send(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
-        if self.en {
-            self.i2c.write(self.addr, bytes).map_err(|e| Error::I2cError(e))
-        } else {
-            Err(Error::NotEnabled)
+        let clamped;
+        let bytes = match self.current_filter(bytes)? {
+            current::Capped::Unchanged => bytes,
+            current::Capped::Clamped(value) => {
+                clamped = [bytes[0], value];
+                &clamped[..]
+            }
+        };
+        let limited;
+        let (slewed, step) = self.slew_filter(bytes);
+        let bytes = match slewed {
+            slew::Slewed::Unchanged => bytes,
+            slew::Slewed::Limited(value) => {
+                limited = [bytes[0], value];
+                &limited[..]
+            }
+            slew::Slewed::Skipped => return Ok(()),
+        };
+        let res = self.send_observed(bytes);
+        if res.is_ok() {
+            self.slew_commit(step);
         }
+        res
     }
 
-    /// Enable the device for use
-    ///
-    /// Sets the enable line high, then sends an enable command, waits 500us, and then configures
-    /// to device to use its internal clock, enable the charge pump at 1.5x boost, and
-    /// auto-increment on writes.
-    pub fn enable(&mut self) -> Result<(), Error<E>> {
-        if let Some(p) = self.en_pin.as_mut() {
-            p.set_high();
+    /// Write `bytes`, once past the current caps and slew limits, letting the observers see the
+    /// write and skipping it if the shadow cache shows it would change nothing
+    fn send_observed(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        self.power_save_observe(bytes)?;
+        self.flicker_observe(bytes)?;
+        self.dimming_observe(bytes);
+        self.on_time_observe(bytes);
+        let (shadow, start, values) = match (self.shadow.as_ref(), bytes.split_first()) {
+            (Some(shadow), Some((start, values))) => (shadow, *start, values),
+            _ => return self.send_split(bytes),
+        };
+        let unchanged = values.iter().enumerate().all(|(i, v)| {
+            let r = start.wrapping_add(i as u8);
+            shadowed(r) && shadow[usize::from(r)] == Some(*v)
+        });
+        if unchanged {
+            return Ok(());
+        }
+        let res = self.send_split(bytes);
+        if let Some(shadow) = self.shadow.as_mut() {
+            for (i, v) in values.iter().enumerate() {
+                let r = start.wrapping_add(i as u8);
+                if r == reg::RESET || res.is_err() {
+                    *shadow = [None; SHADOW_LEN];
+                    break;
+                }
+                if shadowed(r) {
+                    shadow[usize::from(r)] = Some(*v);
+                }
+            }
+        }
+        res
+    }
+
+    /// Write `bytes`, splitting the write into several if it exceeds the maximum write length
+    /// (see `set_max_write_len`)
+    fn send_split(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        let (start, values) = match bytes.split_first() {
+            Some((start, values)) if bytes.len() > self.max_write_len => (*start, values),
+            _ => return self.send_chunk(bytes),
+        };
+        let mut buf = [0u8; CHUNK_BUF_LEN];
+        let chunk_len = self.max_write_len.min(CHUNK_BUF_LEN) - 1;
+        for (i, chunk) in values.chunks(chunk_len).enumerate() {
+            buf[0] = start.wrapping_add((i * chunk_len) as u8);
+            buf[1..=chunk.len()].copy_from_slice(chunk);
+            self.send_chunk(&buf[..=chunk.len()])?;
         }
-        // TODO there should be a 500us delay here, but the chip appears to mostly work without it,
-        // so I'm not going to worry about it now. Ideally, this function should take a
-        // `embedded_hal::delay::Delay` and wait.
-        self.en = true;
-        self.send(&[reg::CNTRL1, (reg::Cntrl1::CHIP_EN).bits()])?;
-        self.send(&[
-                  reg::MISC,
-                  (reg::Misc::INT_CLK_EN
-                   | reg::Misc::CLK_DET_EN
-                   | reg::Misc::CP_MODE_1_5x
-                   | reg::Misc::EN_AUTO_INCR)
-                  .bits(),
-        ])?;
         Ok(())
     }
 
-    /// Soft-reset the device NOW
+    /// Write `bytes` in a single I2C transaction
+    fn send_chunk(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        if self.en {
+            let res = self.i2c.write(self.addr, bytes);
+            trace::write(self.addr, bytes, res.is_ok());
+            #[cfg(feature = "history")]
+            self.history.push(history::Record {
+                op: history::Op::Write,
+                register: bytes.first().cloned().unwrap_or(0),
+                value: bytes.get(1).cloned().unwrap_or(0),
+                len: bytes.len().saturating_sub(1) as u8,
+                ok: res.is_ok(),
+            });
+            self.count_bus_error(res.is_ok());
+            res.map_err(|e| {
+                let access = Access {
+                    device: self.addr,
+                    operation: Operation::Write,
+                    register: bytes.first().cloned().unwrap_or(0),
+                    len: bytes.len().saturating_sub(1) as u8,
+                };
+                Error::I2cError(e, access)
+            })?;
+            if self.verify_writes {
+                self.verify(bytes)?;
+            }
+            Ok(())
+        } else {
+            Err(Error::NotEnabled)
+        }
+    }
+
+    /// Read back the registers written by `bytes` and compare them with the values written,
+    /// skipping registers whose value changes on its own
+    fn verify(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        let (start, values) = match bytes.split_first() {
+            Some((start, values)) => (*start, values),
+            None => return Ok(()),
+        };
+        let mut buf = [0u8; 16];
+        for (i, chunk) in values.chunks(buf.len()).enumerate() {
+            let first = start.wrapping_add((i * buf.len()) as u8);
+            let read = &mut buf[..chunk.len()];
+            self.read_into(first, read)?;
+            for (j, (written, read)) in chunk.iter().zip(read.iter()).enumerate() {
+                if reg::verifiable(first.wrapping_add(j as u8)) && written != read {
+                    return Err(Error::VerifyFailed);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Convenience method to call `self.i2c.write_read` with `self.addr`, reading consecutive
+    /// registers starting at `reg` into `buf`
+    fn read_into(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Error<E>> {
+        if self.en {
+            let res = self.i2c.write_read(self.addr, &[reg], buf);
+            trace::read(self.addr, reg, buf, res.is_ok());
+            #[cfg(feature = "history")]
+            self.history.push(history::Record {
+                op: history::Op::Read,
+                register: reg,
+                value: buf.first().cloned().unwrap_or(0),
+                len: buf.len() as u8,
+                ok: res.is_ok(),
+            });
+            self.count_bus_error(res.is_ok());
+            res.map_err(|e| {
+                let access = Access {
+                    device: self.addr,
+                    operation: Operation::Read,
+                    register: reg,
+                    len: buf.len() as u8,
+                };
+                Error::I2cError(e, access)
+            })
+        } else {
+            Err(Error::NotEnabled)
+        }
+    }
+
+    /// Read a single register
+    fn read(&mut self, reg: u8) -> Result<u8, Error<E>> {
+        let mut buf = [0u8];
+        self.read_into(reg, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Write a typed register
+    pub fn write_register<R: reg::Register>(&mut self, value: R) -> Result<(), Error<E>> {
+        self.send(&[R::ADDRESS, value.encode()])
+    }
+
+    /// Read a typed register
+    pub fn read_register<R: reg::Register>(&mut self) -> Result<R, Error<E>> {
+        Ok(R::decode(self.read(R::ADDRESS)?))
+    }
+
+    /// Read-modify-write a register, replacing its value with `f` of its current value
+    ///
+    /// The register is only read if the shadow cache does not hold it (see `set_shadow_cache`).
+    pub fn modify<F: FnOnce(u8) -> u8>(&mut self, register: u8, f: F) -> Result<(), Error<E>> {
+        let cached = match self.shadow.as_ref() {
+            Some(shadow) if shadowed(register) => shadow[usize::from(register)],
+            _ => None,
+        };
+        let value = match cached {
+            Some(value) => value,
+            None => self.read(register)?,
+        };
+        self.send(&[register, f(value)])
+    }
+
+    /// Read-modify-write a typed register, replacing its value with `f` of its current value
+    pub fn modify_register<R, F>(&mut self, f: F) -> Result<(), Error<E>>
+    where
+        R: reg::Register,
+        F: FnOnce(R) -> R,
+    {
+        self.modify(R::ADDRESS, |value| f(R::decode(value)).encode())
+    }
+
+    /// Enable the device for use
+    ///
+    /// Sets the enable line high, then sends an enable command, polls STATUS_IRQ until the
+    /// internal startup sequence completes (see `poll_startup`), and then configures to device to
+    /// use its internal clock, or the source last selected with `set_clock_mode`, enable the
+    /// charge pump at 1.5x boost, and auto-increment on writes.
+    /// If a configuration has been kept (see `set_config`), it is then written, and its startup
+    /// state shown. Returns `Error::Timeout` if the startup sequence does not complete within
+    /// 20 reads.
+    ///
+    /// The delays set by `set_power_sequence` are not waited, as there is nothing to wait with,
+    /// except that a device disabled since it was last enabled is not powered up again before the
+    /// sequence's hold-off: if the hold-off is not zero, `Error::InvalidArgument` is returned
+    /// without touching the device, and `enable_with_delay` must be used instead.
+    pub fn enable(&mut self) -> Result<(), Error<E>> {
+        self.power_up(None)
+    }
+
+    /// Enable the device for use, as with `enable`, waiting between each step of the power-up
+    /// sequence as set by `set_power_sequence`
+    ///
+    /// If the device was previously disabled, first waits out the sequence's hold-off before
+    /// setting the enable line high again. The startup sequence is polled every 100µs, for at
+    /// most 10ms, before `Error::Timeout` is returned.
+    pub fn enable_with_delay<DL: DelayUs<u16>>(&mut self, delay: &mut DL) -> Result<(), Error<E>> {
+        self.power_up(Some(delay))
+    }
+
+    /// Run the power-up sequence shared by `enable` and `enable_with_delay`, waiting the power
+    /// sequence's delays if there is a delay to wait them with
+    fn power_up(&mut self, mut delay: Option<&mut dyn DelayUs<u16>>) -> Result<(), Error<E>> {
+        let seq = self.power_sequence;
+        if self.powered_down && seq.off_hold_off > 0 {
+            match delay.as_mut() {
+                Some(delay) => delay_us(*delay, seq.off_hold_off),
+                None => return Err(Error::InvalidArgument),
+            }
+        }
+        if let Some(p) = self.en_pin.as_mut() {
+            p.set_high();
+        }
+        self.powered_down = false;
+        if let Some(delay) = delay.as_mut() {
+            delay_us(*delay, seq.en_to_chip_en);
+        }
+        self.en = true;
+        self.send(&[reg::CNTRL1, (reg::Cntrl1::CHIP_EN).bits()])?;
+        if let Some(delay) = delay.as_mut() {
+            delay_us(*delay, seq.chip_en_to_config);
+        }
+        let mut waited = 0;
+        loop {
+            match self.poll_startup() {
+                Ok(()) => break,
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => {}
+            }
+            match delay.as_mut() {
+                Some(delay) if waited < STARTUP_TIMEOUT_US => {
+                    delay_us(*delay, STARTUP_POLL_INTERVAL);
+                    waited += STARTUP_POLL_INTERVAL;
+                }
+                None if waited + 1 < u32::from(STARTUP_POLLS) => waited += 1,
+                _ => return Err(Error::Timeout),
+            }
+        }
+        self.configure()?;
+        self.start_up()
+    }
+
+    /// Check whether the device's internal startup sequence, begun by setting CHIP_EN, has
+    /// completed. Note that this reads (and so clears) the STATUS_IRQ register.
+    pub fn poll_startup(&mut self) -> nb::Result<(), Error<E>> {
+        let status = self.read_register::<reg::StatusIrq>()?;
+        if status.contains(reg::StatusIrq::STARTUP_BUSY) {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Enable or disable paranoid write verification
+    ///
+    /// When enabled, every register write is followed by a read-back of the registers written,
+    /// and `Error::VerifyFailed` is returned on mismatch. Registers that change on their own,
+    /// such as those that start conversions or reset the device, are not compared. This doubles
+    /// bus traffic, so it is best reserved for safety-minded products and for debugging marginal
+    /// signal integrity.
+    pub fn set_verify_writes(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+
+    /// Limit the number of bytes, including the register address, sent in a single I2C write,
+    /// for HALs that cannot send long writes
+    ///
+    /// Longer burst writes are split into several, each starting at the register where the
+    /// last left off. Bursts never span registers that do not support auto-increment, so the
+    /// split writes are equivalent. Lengths below 2 are raised to 2; limits above
+    /// `CHUNK_BUF_LEN` split writes into `CHUNK_BUF_LEN`-byte chunks.
+    pub fn set_max_write_len(&mut self, len: usize) {
+        self.max_write_len = len.max(2);
+    }
+
+    /// Enable or disable the shadow cache
+    ///
+    /// When enabled, the driver remembers the last value written to each register, and skips
+    /// writes that would not change any register, such as animation frames setting mostly static
+    /// D lines. Registers that change on their own are never skipped. The cache starts out empty,
+    /// and is cleared by `reset` and `disable`; call `clear_shadow_cache` if the device may have
+    /// been reset behind the driver's back, e.g. by a supply glitch.
+    pub fn set_shadow_cache(&mut self, enable: bool) {
+        self.shadow = if enable {
+            Some([None; SHADOW_LEN])
+        } else {
+            None
+        };
+    }
+
+    /// Forget every register value held by the shadow cache, so that the next write of each
+    /// register goes to the device
+    pub fn clear_shadow_cache(&mut self) {
+        if let Some(shadow) = self.shadow.as_mut() {
+            *shadow = [None; SHADOW_LEN];
+        }
+    }
+
+    /// Set the delays used by `enable_with_delay`
+    pub fn set_power_sequence(&mut self, seq: PowerSequence) {
+        self.power_sequence = seq;
+    }
+
+    /// Configure the device to use its internal clock, or the source last selected with
+    /// `set_clock_mode`, enable the charge pump at 1.5x boost, and auto-increment on writes
+    fn configure(&mut self) -> Result<(), Error<E>> {
+        let clock = match self.clock_mode {
+            Some(mode) => reg::Misc::from(mode),
+            None => reg::Misc::INT_CLK_EN | reg::Misc::CLK_DET_EN,
+        };
+        self.send(&[
+                  reg::MISC,
+                  (clock
+                   | reg::Misc::CP_MODE_1_5x
+                   | reg::Misc::EN_AUTO_INCR)
+                  .bits(),
+        ])?;
+        Ok(())
+    }
+
+    /// Write the kept configuration, if any, and show its startup state
+    fn start_up(&mut self) -> Result<(), Error<E>> {
+        self.refresh_config()?;
+        match self.config.and_then(|config| config.startup) {
+            Some(Startup::Scene(scene)) => self.apply_scene(&scene),
+            #[cfg(feature = "engines")]
+            Some(Startup::Program(program)) => self.start_program(&program),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep `config` for `enable`, `reinit` and `refresh_config` without writing it, so that the
+    /// device comes up configured, and showing the configuration's startup state, on the next
+    /// `enable`
+    pub fn set_config(&mut self, config: Config) {
+        self.config = Some(config);
+    }
+
+    /// Write every configuration register from `config`, and keep it for `refresh_config`
+    ///
+    /// The configuration's startup state is not shown until the next `enable`.
+    pub fn apply_config(&mut self, config: Config) -> Result<(), Error<E>> {
+        self.config = Some(config);
+        self.refresh_config()
+    }
+
+    /// Rewrite every configuration register from the configuration last passed to
+    /// `apply_config`, doing nothing if there is none
+    ///
+    /// ESD events and supply glitches can corrupt register contents without resetting the
+    /// device. Calling this periodically, e.g. from the same timer that feeds a watchdog, puts the
+    /// device back in its intended configuration. Note that changes made since with the
+    /// individual setters, such as `set_outputs`, are overwritten; route them through
+    /// `apply_config` instead.
+    pub fn refresh_config(&mut self) -> Result<(), Error<E>> {
+        let config = match self.config {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let misc = match config.wiring.map(|w| w.charge_pump_mode()) {
+            Some(Ok(mode)) => (config.misc - reg::Misc::CP_MODE_AUTO) | mode.into(),
+            Some(Err(e)) => {
+                trace::wiring(self.addr, e);
+                config.misc
+            }
+            None => config.misc,
+        };
+        // MISC goes first, as it enables the auto-increment the bursts below rely on
+        self.write_register(misc)?;
+        self.write_register(config.gain_change)?;
+        self.write_register(reg::IntGpio::from(config.int_mode))?;
+        self.configure_channels(&config.channels)?;
+        self.set_outputs(config.outputs)?;
+        self.set_ratiometric(config.ratiometric)
+    }
+
+    /// Check that the device is still present and enabled
+    ///
+    /// Reads CNTRL1 and MISC and confirms they are as `enable` left them (see `reset_detected`).
+    /// Returns `Error::I2cError` if the device does not respond, and `Error::DeviceReset` if it has
+    /// been disabled or reset, unless automatic recovery is enabled (see `set_auto_recover`).
+    pub fn ping(&mut self) -> Result<(), Error<E>> {
+        self.check_reset()
+    }
+
+    /// Check that a device answers at the address, whether or not it has been enabled
+    ///
+    /// Reads CNTRL1, returning `Error::I2cError` if nothing answers. The enable line, if any,
+    /// must be high, as the device ignores the bus while it is low.
+    pub fn probe(&mut self) -> Result<(), Error<E>> {
+        let en = self.en;
+        self.en = true;
+        let res = self.read(reg::CNTRL1);
+        self.en = en;
+        res.map(|_| ())
+    }
+
+    /// Soft-reset the device NOW
     pub fn reset(&mut self) -> Result<(), Error<E>> {
         self.send(&[reg::RESET, reg::Reset::RESET_NOW.bits()])?;
         Ok(())
     }
 
+    /// Fade every D line to black over `duration` ms, then turn off the device
+    ///
+    /// The direct PWM registers are stepped to zero in software (see `fade_to`), CHIP_EN is
+    /// cleared, and the device is then disabled as by `disable`. D lines driven by an engine are
+    /// not faded; stop the engines first for a smooth fade.
+    pub fn disable_with_fade<T, DL>(&mut self, duration: T, delay: &mut DL) -> Result<(), Error<E>>
+    where
+        T: Into<Millis>,
+        DL: DelayMs<u16>,
+    {
+        self.fade_many(&D::ALL, &[0; 9], duration.into().0, delay)?;
+        self.write_register(reg::Cntrl1::empty())?;
+        self.disable();
+        Ok(())
+    }
+
     /// Turn off the device NOW
     pub fn disable(&mut self) {
         if let Some(p) = self.en_pin.as_mut() {
             p.set_low();
+            self.on_time_reset();
         }
         self.en = false;
+        self.powered_down = true;
+        self.clear_shadow_cache();
+        self.fast_frame = None;
+        self.forget_faders();
     }
 
-    /// Set the D line to the provided PWM value
-    pub fn set_pwm(&mut self, d: D, pwm: u8) -> Result<(), Error<E>> {
+    /// Set the D line to the provided PWM value, either a raw `u8`/`Brightness` or a `Percent`,
+    /// after mapping through the D line's curve (see `set_pwm_curve`) and scaling by its trim (see
+    /// `set_trim`) and the global brightness (see `set_global_brightness`)
+    pub fn set_pwm<B: Into<Brightness>>(&mut self, d: D, pwm: B) -> Result<(), Error<E>> {
+        let pwm = self.output_pwm(d, pwm.into().0);
         self.send(&[reg::D_PWM_BASE + u8::from(d), pwm])?;
         Ok(())
     }
+
+    /// Set the D line to a linear light level out of 65535, scaled by its trim and the global
+    /// brightness
+    ///
+    /// In logarithmic dimming (see `set_logarithmic`), the level is mapped onto the PWM value whose
+    /// 12-bit duty cycle is nearest (see `curve::log_register`), giving smooth dimming down to the
+    /// lowest duty cycles; in linear dimming, it is rounded to 8 bits. The D line's curve is not
+    /// applied, as the level is already linear. Reads the D line's control register, unless the
+    /// shadow cache holds it.
+    pub fn set_pwm_16bit(&mut self, d: D, level: u16) -> Result<(), Error<E>> {
+        let i = usize::from(u8::from(d));
+        let ctrl = reg::D_CTRL_BASE + u8::from(d);
+        let cached = self.shadow.as_ref().and_then(|s| s[usize::from(ctrl)]);
+        let ctrl = match cached {
+            Some(ctrl) => ctrl,
+            None => self.read(ctrl)?,
+        };
+        let mut level = u32::from(level) * u32::from(self.trims[i]) / 255;
+        if self.fader_mapped & (1 << u8::from(d)) == 0 {
+            level = level * u32::from(self.global_brightness) / 255;
+        }
+        let pwm = if ctrl & reg::DxCtrl::LOG_EN.bits() != 0 {
+            curve::log_register(level as u16)
+        } else {
+            curve::linear_register(level as u16)
+        };
+        self.send(&[reg::D_PWM_BASE + u8::from(d), pwm])
+    }
+
+    /// The PWM register value `set_pwm` writes for `pwm`: mapped through the D line's curve, then
+    /// scaled by its trim and the global brightness
+    fn output_pwm(&self, d: D, pwm: u8) -> u8 {
+        let i = usize::from(u8::from(d));
+        let pwm = self.pwm_curves[i].apply(pwm);
+        self.dimmed_pwm(d, dimming::dim(pwm, self.trims[i]))
+    }
+
+    /// Read the D line's direct PWM value
+    ///
+    /// This is the value last set by `set_pwm` (or a fade). While an engine drives the D line
+    /// (see `set_pwm_source`), the device does not expose the engine's PWM value, and this is not
+    /// the line's effective brightness.
+    pub fn pwm(&mut self, d: D) -> Result<u8, Error<E>> {
+        self.read(reg::D_PWM_BASE + u8::from(d))
+    }
+
+    /// Set the red, green and blue PWM values of an RGB LED, after applying its color correction
+    /// and white point (see `set_color_correction` and `set_white_point`), and each D line's curve
+    /// and trim, as `set_pwm` does
+    ///
+    /// The three writes go through a `batch::Batch`; see `stage_rgb` to set several LEDs in one.
+    pub fn set_rgb(&mut self, led: color::RgbGroup, r: u8, g: u8, b: u8) -> Result<(), Error<E>> {
+        let mut batch = batch::Batch::new();
+        self.stage_rgb(&mut batch, led, r, g, b);
+        self.flush(&mut batch)?;
+        Ok(())
+    }
+
+    /// Attach a color correction matrix to an RGB LED, applied by `set_rgb` and `set_color` to
+    /// every color set on it, or detach it with `None`
+    ///
+    /// Up to three RGB LEDs can carry a matrix at once; attaching a fourth returns
+    /// `Error::InvalidArgument`. Fades work on raw PWM values, and are not corrected.
+    pub fn set_color_correction(
+        &mut self,
+        led: color::RgbGroup,
+        matrix: Option<color::ColorMatrix>,
+    ) -> Result<(), Error<E>> {
+        color::attach(&mut self.color_corrections, led, matrix)
+            .map_err(|_| Error::InvalidArgument)
+    }
+
+    /// Set the white point of an RGB LED: the scales, out of 255, applied by `set_rgb` and
+    /// `set_color` to the red, green and blue channels, after any color correction, so that full
+    /// white renders as the intended chromaticity (see `color::white_point_from_measured`)
+    ///
+    /// Up to three RGB LEDs can carry a white point at once; setting a fourth returns
+    /// `Error::InvalidArgument`. Fades work on raw PWM values, and are not scaled.
+    pub fn set_white_point(
+        &mut self,
+        led: color::RgbGroup,
+        r_scale: u8,
+        g_scale: u8,
+        b_scale: u8,
+    ) -> Result<(), Error<E>> {
+        color::attach(&mut self.white_points, led, Some([r_scale, g_scale, b_scale]))
+            .map_err(|_| Error::InvalidArgument)
+    }
+
+    /// Remove the white point of an RGB LED, leaving its channels unscaled
+    pub fn clear_white_point(&mut self, led: color::RgbGroup) {
+        let _ = color::attach::<[u8; 3]>(&mut self.white_points, led, None);
+    }
+
+    /// Apply the RGB LED's color correction and white point, if it has them
+    fn correct_color(&self, led: color::RgbGroup, c: color::Rgb) -> color::Rgb {
+        let c = color::attached(&self.color_corrections, led).map_or(c, |m| m.apply(c));
+        match color::attached(&self.white_points, led) {
+            Some([r, g, b]) => {
+                let scale = |c: u8, s: u8| ((u16::from(c) * u16::from(s) + 127) / 255) as u8;
+                color::Rgb::new(scale(c.r, r), scale(c.g, g), scale(c.b, b))
+            }
+            None => c,
+        }
+    }
+
+    /// The PWM register values `set_color` writes for the color on the RGB LED
+    fn rendered_color(&self, led: color::RgbGroup, color: color::Rgb) -> color::Rgb {
+        let c = self.correct_color(led, self.color_space.linearize(color));
+        color::Rgb::new(
+            self.output_pwm(led.red, c.r),
+            self.output_pwm(led.green, c.g),
+            self.output_pwm(led.blue, c.b),
+        )
+    }
+
+    /// Whether `set_pwm` and `set_color` write the same register values on `other` as on this
+    /// device, which holds when their curves, trims, color corrections, color spaces, global
+    /// brightness and fader mappings match
+    fn encodes_like(&self, other: &Self) -> bool {
+        self.pwm_curves == other.pwm_curves
+            && self.trims == other.trims
+            && self.color_corrections == other.color_corrections
+            && self.white_points == other.white_points
+            && self.color_space == other.color_space
+            && self.global_brightness == other.global_brightness
+            && self.fader_mapped == other.fader_mapped
+    }
+
+    /// Set an RGB LED to the color, given as an `Rgb` or anything converting to one, in the color
+    /// space selected with `set_color_space`
+    pub fn set_color<C: Into<color::Rgb>>(
+        &mut self,
+        led: color::RgbGroup,
+        color: C,
+    ) -> Result<(), Error<E>> {
+        let c = self.color_space.linearize(color.into());
+        self.set_rgb(led, c.r, c.g, c.b)
+    }
+
+    /// Select how the components of colors passed to `set_color` are encoded. Colors are taken as
+    /// linear PWM values by default.
+    pub fn set_color_space(&mut self, space: color::ColorSpace) {
+        self.color_space = space;
+    }
+
+    /// Set the red, green, blue and white PWM values of an RGBW LED
+    pub fn set_rgbw(
+        &mut self,
+        led: color::RgbwGroup,
+        r: u8,
+        g: u8,
+        b: u8,
+        w: u8,
+    ) -> Result<(), Error<E>> {
+        for (d, pwm) in led.lines().iter().zip([r, g, b, w].iter()) {
+            self.set_pwm(*d, *pwm)?;
+        }
+        Ok(())
+    }
+
+    /// Render an RGB color on an RGBW LED, driving the white die with the component common to all
+    /// three channels (see `color::extract_white`)
+    pub fn set_rgbw_from_rgb(
+        &mut self,
+        led: color::RgbwGroup,
+        r: u8,
+        g: u8,
+        b: u8,
+    ) -> Result<(), Error<E>> {
+        let [r, g, b, w] = color::extract_white(r, g, b);
+        self.set_rgbw(led, r, g, b, w)
+    }
+
+    /// Set a tunable-white pair to the color temperature, in kelvin, at the brightness (see
+    /// `color::CctGroup::mix`)
+    pub fn set_cct(
+        &mut self,
+        group: color::CctGroup,
+        kelvin: u16,
+        brightness: u8,
+    ) -> Result<(), Error<E>> {
+        let [warm, cool] = group.mix(kelvin, brightness);
+        self.set_pwm(group.warm, warm)?;
+        self.set_pwm(group.cool, cool)?;
+        Ok(())
+    }
+
+    /// Fade the D line from its current PWM value to `target` over `duration` ms
+    ///
+    /// The direct PWM register is stepped in software, blocking on `delay` in between steps.
+    pub fn fade_to<B, T, DL>(
+        &mut self,
+        d: D,
+        target: B,
+        duration: T,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>>
+    where
+        B: Into<Brightness>,
+        T: Into<Millis>,
+        DL: DelayMs<u16>,
+    {
+        self.fade_many(&[d], &[target.into().0], duration.into().0, delay)
+    }
+
+    /// Fade three D lines, e.g. the red, green and blue lines of an RGB LED, from their current
+    /// PWM values to `target` over `duration` ms
+    pub fn fade_rgb_to<T, DL>(
+        &mut self,
+        leds: [D; 3],
+        target: [u8; 3],
+        duration: T,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>>
+    where
+        T: Into<Millis>,
+        DL: DelayMs<u16>,
+    {
+        self.fade_many(&leds, &target, duration.into().0, delay)
+    }
+
+    /// Step each D line of `leds` linearly to the matching value of `target` over `duration` ms
+    fn fade_many<DL: DelayMs<u16>>(
+        &mut self,
+        leds: &[D],
+        target: &[u8],
+        duration: u16,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>> {
+        let mut start = [0u8; 9];
+        let mut span = 0;
+        for (i, (d, t)) in leds.iter().zip(target.iter()).enumerate() {
+            start[i] = self.read(reg::D_PWM_BASE + u8::from(*d))?;
+            span = span.max((i16::from(*t) - i16::from(start[i])).unsigned_abs());
+        }
+
+        // One step per PWM code of the largest change, but no more than one per ms
+        let steps = span.min(duration).max(1);
+        let mut elapsed = 0;
+        for step in 1..=steps {
+            let now = (u32::from(duration) * u32::from(step) / u32::from(steps)) as u16;
+            delay.delay_ms(now - elapsed);
+            elapsed = now;
+            for (i, (d, t)) in leds.iter().zip(target.iter()).enumerate() {
+                let delta = i32::from(*t) - i32::from(start[i]);
+                let pwm = i32::from(start[i]) + delta * i32::from(step) / i32::from(steps);
+                self.send(&[reg::D_PWM_BASE + u8::from(*d), pwm as u8])?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "engines")]
+    /// Set the engine's operation mode
+    ///
+    /// Load mode can only be entered from disabled mode, so that a running program is never
+    /// overwritten.
+    pub fn set_mode(&mut self, e: Engine, mode: Mode) -> Result<(), Error<E>> {
+        let (field, load, run, halt) = match e {
+            Engine::E1 => (
+                reg::Cntrl2::ENG1_MODE,
+                reg::Cntrl2::ENG1_MODE_LOAD,
+                reg::Cntrl2::ENG1_MODE_RUN,
+                reg::Cntrl2::ENG1_MODE_HALT,
+            ),
+            Engine::E2 => (
+                reg::Cntrl2::ENG2_MODE,
+                reg::Cntrl2::ENG2_MODE_LOAD,
+                reg::Cntrl2::ENG2_MODE_RUN,
+                reg::Cntrl2::ENG2_MODE_HALT,
+            ),
+            Engine::E3 => (
+                reg::Cntrl2::ENG3_MODE,
+                reg::Cntrl2::ENG3_MODE_LOAD,
+                reg::Cntrl2::ENG3_MODE_RUN,
+                reg::Cntrl2::ENG3_MODE_HALT,
+            ),
+        };
+        let value = match mode {
+            Mode::Disabled => reg::Cntrl2::empty(),
+            Mode::Load => load,
+            Mode::Run => run,
+            Mode::Halt => halt,
+        };
+        self.modify_register(|cntrl2: reg::Cntrl2| (cntrl2 - field) | value)
+    }
+
+    #[cfg(feature = "engines")]
+    /// Set how the engine executes its program. The engine must be in run mode for instructions
+    /// to be executed.
+    pub fn set_exec(&mut self, e: Engine, exec: Exec) -> Result<(), Error<E>> {
+        let (field, step, free_run, once) = match e {
+            Engine::E1 => (
+                reg::Cntrl1::ENG1_EXEC,
+                reg::Cntrl1::ENG1_EXEC_STEP,
+                reg::Cntrl1::ENG1_EXEC_FREE_RUN,
+                reg::Cntrl1::ENG1_EXEC_ONCE,
+            ),
+            Engine::E2 => (
+                reg::Cntrl1::ENG2_EXEC,
+                reg::Cntrl1::ENG2_EXEC_STEP,
+                reg::Cntrl1::ENG2_EXEC_FREE_RUN,
+                reg::Cntrl1::ENG2_EXEC_ONCE,
+            ),
+            Engine::E3 => (
+                reg::Cntrl1::ENG3_EXEC,
+                reg::Cntrl1::ENG3_EXEC_STEP,
+                reg::Cntrl1::ENG3_EXEC_FREE_RUN,
+                reg::Cntrl1::ENG3_EXEC_ONCE,
+            ),
+        };
+        let value = match exec {
+            Exec::Hold => reg::Cntrl1::empty(),
+            Exec::Step => step,
+            Exec::FreeRun => free_run,
+            Exec::ExecuteOnce => once,
+        };
+        self.modify_register(|cntrl1: reg::Cntrl1| (cntrl1 - field) | value)
+    }
+
+    #[cfg(feature = "engines")]
+    /// Freeze the engine's program where it is, holding its outputs at their current values
+    ///
+    /// Unlike halting or disabling the engine, this keeps the program counter, so
+    /// `resume_engine` continues with the next instruction.
+    pub fn pause_engine(&mut self, e: Engine) -> Result<(), Error<E>> {
+        self.set_exec(e, Exec::Hold)
+    }
+
+    #[cfg(feature = "engines")]
+    /// Continue a program frozen by `pause_engine` from where it left off
+    pub fn resume_engine(&mut self, e: Engine) -> Result<(), Error<E>> {
+        self.set_exec(e, Exec::FreeRun)
+    }
+
+    #[cfg(feature = "engines")]
+    /// Stop all three engines NOW
+    ///
+    /// Holds and disables every engine in a single write. When `zero_outputs` is set, every direct
+    /// PWM register is then set to zero as well, so no D line stays lit.
+    pub fn halt_all_engines(&mut self, zero_outputs: bool) -> Result<(), Error<E>> {
+        // CNTRL1 with every engine in hold, followed by CNTRL2 with every engine disabled
+        self.send(&[
+            reg::CNTRL1,
+            reg::Cntrl1::CHIP_EN.bits(),
+            reg::Cntrl2::empty().bits(),
+        ])?;
+        if zero_outputs {
+            for d in D::iter() {
+                self.set_pwm(d, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "engines")]
+    /// Execute a single instruction of the engine's program, returning the engine's state
+    /// afterwards
+    ///
+    /// The engine must be in run mode, and is left holding at its new program counter.
+    pub fn debug_step(&mut self, e: Engine) -> Result<StepState, Error<E>> {
+        self.set_exec(e, Exec::Step)?;
+        let pc = self.program_counter(e)?;
+        let variable = self.read(reg::ENGINE_A_VAR + u8::from(e))?;
+        let global_variable = self.read(reg::GLOBAL_VAR)?;
+        Ok(StepState {
+            pc,
+            variable,
+            global_variable,
+        })
+    }
+
+    #[cfg(feature = "engines")]
+    /// Read the engine's program counter
+    pub fn program_counter(&mut self, e: Engine) -> Result<u8, Error<E>> {
+        self.read(reg::PC1 + u8::from(e))
+    }
+
+    #[cfg(feature = "engines")]
+    /// Read the engine's program counter and locate it relative to the labels of the program it
+    /// runs, for diagnostics such as "E2 stuck at 'wait_for_trigger'"
+    ///
+    /// The labels must be at the addresses the program was loaded to (see `Labels::relocate`).
+    pub fn engine_position(
+        &mut self,
+        e: Engine,
+        labels: &asm::Labels,
+    ) -> Result<asm::Position, Error<E>> {
+        Ok(labels.position(self.program_counter(e)?))
+    }
+
+    /// Select the clock source of the device
+    ///
+    /// The mode is kept, and written again by `enable`, `reinit` and `recover`. A configuration
+    /// kept with `set_config` or `apply_config` has the clock bits of its `misc` updated to match;
+    /// one passed in later selects the clock source with its own `misc`.
+    pub fn set_clock_mode(&mut self, mode: ClockMode) -> Result<(), Error<E>> {
+        let clock = reg::Misc::INT_CLK_EN | reg::Misc::CLK_DET_EN;
+        self.clock_mode = Some(mode);
+        if let Some(config) = self.config.as_mut() {
+            config.misc = (config.misc - clock) | reg::Misc::from(mode);
+        }
+        self.modify_register(|misc: reg::Misc| (misc - clock) | reg::Misc::from(mode))
+    }
+
+    /// Tune the charge pump's automatic gain changes (see `registers::GainChange`)
+    pub fn set_gain_change(&mut self, gain_change: reg::GainChange) -> Result<(), Error<E>> {
+        self.write_register(gain_change)?;
+        Ok(())
+    }
+
+    /// Select the function of the INT pin
+    pub fn set_int_mode(&mut self, mode: IntMode) -> Result<(), Error<E>> {
+        self.write_register(reg::IntGpio::from(mode))?;
+        Ok(())
+    }
+
+    /// Check whether the device is running from the external clock. Note that this reads (and so
+    /// clears) the STATUS_IRQ register.
+    pub fn external_clock_used(&mut self) -> Result<bool, Error<E>> {
+        let status = self.read_register::<reg::StatusIrq>()?;
+        Ok(status.contains(reg::StatusIrq::EXT_CLK_USED))
+    }
+
+    #[cfg(feature = "engines")]
+    /// Wait for the engines to become idle, polling the engine busy bit every millisecond
+    ///
+    /// Returns `Error::Timeout` if the engines are still busy after `timeout` ms. Note that this
+    /// reads (and so clears) the STATUS_IRQ register.
+    pub fn wait_engine_idle<T, DL>(&mut self, timeout: T, delay: &mut DL) -> Result<(), Error<E>>
+    where
+        T: Into<Millis>,
+        DL: DelayMs<u8>,
+    {
+        for _ in 0..=timeout.into().0 {
+            match self.poll_engine_idle() {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => delay.delay_ms(1),
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Set the master fader to the provided value. All D lines mapped to the fader are scaled by
+    /// it, after mapping through the fader's curve (see `set_fader_curve`) and scaling by the
+    /// global brightness (see `set_global_brightness`).
+    pub fn set_master_fader(&mut self, f: Fader, value: u8) -> Result<(), Error<E>> {
+        let value = self.fader_curves[usize::from(u8::from(f))].apply(value);
+        let value = self.dimmed_fader(f, value);
+        self.send(&[reg::MASTER_FADE_1 + u8::from(f), value])?;
+        Ok(())
+    }
+
+    /// Read the master fader's value, as held by the device
+    ///
+    /// This is the value after mapping through the fader's curve and scaling by the global
+    /// brightness, so it only matches the value passed to `set_master_fader` for the linear curve
+    /// at full global brightness.
+    pub fn master_fader(&mut self, f: Fader) -> Result<u8, Error<E>> {
+        self.read(reg::MASTER_FADE_1 + u8::from(f))
+    }
+
+    /// Select the curve applied to values passed to `set_master_fader`
+    ///
+    /// A perceptual curve makes global dimming look linear to the eye even for channels using
+    /// linear PWM adjustment. The new curve takes effect on the next `set_master_fader`.
+    pub fn set_fader_curve(&mut self, f: Fader, curve: curve::Curve) {
+        self.fader_curves[usize::from(u8::from(f))] = curve;
+    }
+
+    /// Select the curve applied to values passed to `set_pwm` (and the color setters built on
+    /// it) for the D lines in the set, e.g. `OutputMask::all()` to select it globally
+    ///
+    /// Software fades and `pwm` work on raw PWM values, and are not affected.
+    pub fn set_pwm_curve(&mut self, outputs: OutputMask, curve: curve::Curve) {
+        for d in outputs.iter() {
+            self.pwm_curves[usize::from(u8::from(d))] = curve;
+        }
+    }
+
+    /// Set the D line's brightness trim, out of 255, by which values passed to `set_pwm` (and the
+    /// color setters and scenes built on it) are scaled, to compensate for differences between
+    /// LED bins. Trims default to 255, leaving values unscaled.
+    ///
+    /// The new trim takes effect on the next `set_pwm`.
+    pub fn set_trim(&mut self, d: D, trim: u8) {
+        self.trims[usize::from(u8::from(d))] = trim;
+    }
+
+    /// Set the brightness trims of D1 through D9 (see `set_trim`)
+    pub fn set_trims(&mut self, trims: [u8; 9]) {
+        self.trims = trims;
+    }
+
+    /// The D line's brightness trim, out of 255
+    pub fn trim(&self, d: D) -> u8 {
+        self.trims[usize::from(u8::from(d))]
+    }
+
+    /// Configure the fader mapping, dimming mode and temperature compensation of the D line in a
+    /// single register write
+    pub fn configure_channel(&mut self, d: D, config: ChannelConfig) -> Result<(), Error<E>> {
+        self.send(&[reg::D_CTRL_BASE + u8::from(d), u8::from(config)])?;
+        Ok(())
+    }
+
+    /// Configure all nine D lines, D1 first, in a single auto-increment burst
+    pub fn configure_channels(&mut self, configs: &[ChannelConfig; 9]) -> Result<(), Error<E>> {
+        let mut buf = [0u8; 10];
+        buf[0] = reg::D1_CTRL;
+        for (b, c) in buf[1..].iter_mut().zip(configs.iter()) {
+            *b = u8::from(*c);
+        }
+        self.send(&buf)
+    }
+
+    /// Map the D lines of each RGB LED to a master fader, the first LED's to `Fader::F1`, the
+    /// second's to `Fader::F2` and the third's to `Fader::F3`, so that `set_master_fader` dims
+    /// each LED as a whole
+    ///
+    /// Takes one burst read and one burst write of the control registers. Dimming mode and
+    /// temperature compensation are left as they are, as are D lines in none of the LEDs.
+    pub fn assign_faders(&mut self, leds: &[color::RgbGroup; 3]) -> Result<(), Error<E>> {
+        let mut buf = [0u8; 9];
+        self.read_into(reg::D1_CTRL, &mut buf)?;
+        let mut configs = [ChannelConfig::default(); 9];
+        for (config, b) in configs.iter_mut().zip(buf.iter()) {
+            *config = ChannelConfig::from(*b);
+        }
+        for (led, f) in leds.iter().zip([Fader::F1, Fader::F2, Fader::F3].iter()) {
+            for d in led.lines().iter() {
+                configs[usize::from(u8::from(*d))].fader = Some(*f);
+            }
+        }
+        self.configure_channels(&configs)
+    }
+
+    /// Select logarithmic PWM dimming for the D lines in the set, leaving the others as they are
+    pub fn set_logarithmic(&mut self, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.set_log_dimming(outputs, true)
+    }
+
+    /// Select linear PWM dimming for the D lines in the set, leaving the others as they are
+    pub fn set_linear(&mut self, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.set_log_dimming(outputs, false)
+    }
+
+    /// Set or clear LOG_EN in the control registers of the D lines in the set, with a single
+    /// burst read (skipped when the shadow cache holds every register) and a single burst write
+    /// spanning the first to the last D line in the set
+    fn set_log_dimming(&mut self, outputs: OutputMask, log: bool) -> Result<(), Error<E>> {
+        let (first, last) = match (outputs.iter().next(), outputs.iter().last()) {
+            (Some(first), Some(last)) => (u8::from(first), u8::from(last)),
+            _ => return Ok(()),
+        };
+        let mut buf = [0u8; 10];
+        buf[0] = reg::D_CTRL_BASE + first;
+        let len = usize::from(last - first) + 1;
+        let span = usize::from(buf[0])..usize::from(buf[0]) + len;
+        let cached = self.shadow.as_ref().map(|s| &s[span]);
+        match cached {
+            Some(cached) if cached.iter().all(Option::is_some) => {
+                for (b, c) in buf[1..=len].iter_mut().zip(cached.iter()) {
+                    *b = c.unwrap_or(0);
+                }
+            }
+            _ => self.read_into(buf[0], &mut buf[1..=len])?,
+        }
+        for d in outputs.iter() {
+            let ctrl = &mut buf[1 + usize::from(u8::from(d) - first)];
+            if log {
+                *ctrl |= reg::DxCtrl::LOG_EN.bits();
+            } else {
+                *ctrl &= !reg::DxCtrl::LOG_EN.bits();
+            }
+        }
+        self.send(&buf[..=len])
+    }
+
+    /// Read the fader mapping, dimming mode and temperature compensation of the D line
+    pub fn channel_config(&mut self, d: D) -> Result<ChannelConfig, Error<E>> {
+        Ok(ChannelConfig::from(self.read(reg::D_CTRL_BASE + u8::from(d))?))
+    }
+
+    #[cfg(feature = "engines")]
+    /// Select whether the D line follows its direct PWM register or is driven by an engine
+    ///
+    /// The D line is removed from the LED mapping of every engine other than the selected one, so
+    /// that a line is only ever claimed by a single source. Lines not driven by an engine fall back
+    /// to their direct PWM register.
+    pub fn set_pwm_source(&mut self, d: D, source: Source) -> Result<(), Error<E>> {
+        for e in &[Engine::E1, Engine::E2, Engine::E3] {
+            let map = self.engine_map(*e)?;
+            let new = if source == Source::Engine(*e) {
+                map.with(d)
+            } else {
+                map.without(d)
+            };
+            if new != map {
+                self.set_engine_map(*e, new)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "engines")]
+    /// Read the set of D lines an engine drives
+    pub fn engine_map(&mut self, e: Engine) -> Result<OutputMask, Error<E>> {
+        self.read_mask(reg::ENG1_MAP_MSB + 2 * u8::from(e))
+    }
+
+    #[cfg(feature = "engines")]
+    /// Set the D lines an engine drives
+    pub fn set_engine_map(&mut self, e: Engine, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.send_mask(reg::ENG1_MAP_MSB + 2 * u8::from(e), outputs)
+    }
+
+    #[cfg(feature = "engines")]
+    /// Set the D lines an engine drives, as with `set_engine_map`, after checking that none of
+    /// them is driven by another engine or has ratiometric dimming enabled
+    ///
+    /// Returns `Error::MappingConflict` naming the first conflicting D line, without changing the
+    /// map, if any is already claimed.
+    pub fn set_engine_map_checked(
+        &mut self,
+        e: Engine,
+        outputs: OutputMask,
+    ) -> Result<(), Error<E>> {
+        for other in &[Engine::E1, Engine::E2, Engine::E3] {
+            if *other == e {
+                continue;
+            }
+            let claimed = self.engine_map(*other)? & outputs;
+            if let Some(d) = claimed.iter().next() {
+                return Err(Error::MappingConflict(d, Conflict::Engine(*other)));
+            }
+        }
+        let claimed = self.ratiometric()? & outputs;
+        if let Some(d) = claimed.iter().next() {
+            return Err(Error::MappingConflict(d, Conflict::Ratiometric));
+        }
+        self.set_engine_map(e, outputs)
+    }
+
+    /// Turn the D lines in the set on, and all others off. D lines marked failed (see
+    /// `failed_outputs`) are kept off.
+    pub fn set_outputs(&mut self, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.send_mask(reg::OUTPUT_ONOFF_MSB, outputs & !self.failed_outputs)
+    }
+
+    /// Read the set of D lines that are turned on
+    pub fn outputs(&mut self) -> Result<OutputMask, Error<E>> {
+        self.read_mask(reg::OUTPUT_ONOFF_MSB)
+    }
+
+    /// Enable ratiometric up/down dimming for the D lines in the set, and disable it for all
+    /// others
+    pub fn set_ratiometric(&mut self, outputs: OutputMask) -> Result<(), Error<E>> {
+        self.send_mask(reg::RATIO_MSB, outputs)
+    }
+
+    /// Read the set of D lines using ratiometric dimming
+    pub fn ratiometric(&mut self) -> Result<OutputMask, Error<E>> {
+        self.read_mask(reg::RATIO_MSB)
+    }
+
+    /// Write a mask to an MSB/LSB register pair
+    fn send_mask(&mut self, msb_reg: u8, mask: OutputMask) -> Result<(), Error<E>> {
+        self.send(&[msb_reg, mask.msb(), mask.lsb()])
+    }
+
+    /// Read a mask from an MSB/LSB register pair
+    fn read_mask(&mut self, msb_reg: u8) -> Result<OutputMask, Error<E>> {
+        let mut buf = [0u8; 2];
+        self.read_into(msb_reg, &mut buf)?;
+        Ok(OutputMask::from_bytes(buf[0], buf[1]))
+    }
+
+    /// Measure the die temperature and the charge pump output, and report any faults
+    ///
+    /// Each detected fault is passed to the monitor's `on_fault` hook. Returns the most severe
+    /// fault, over-temperature first, or `None` if the device is healthy. Note that this reads
+    /// (and so clears) the STATUS_IRQ register.
+    pub fn check_faults<DL: DelayMs<u8>>(
+        &mut self,
+        monitor: &Monitor,
+        delay: &mut DL,
+    ) -> Result<Option<Fault>, Error<E>> {
+        let mut fault = None;
+
+        let vout = self.measure_vout(delay)?;
+        if vout < monitor.min_vout {
+            fault = Some(Fault::ChargePump(vout));
+            if let Some(f) = monitor.on_fault {
+                f(Fault::ChargePump(vout));
+            }
+        }
+
+        let temp = self.temperature(delay)?;
+        if temp > monitor.max_temp {
+            fault = Some(Fault::OverTemperature(temp));
+            if let Some(f) = monitor.on_fault {
+                f(Fault::OverTemperature(temp));
+            }
+        }
+
+        Ok(fault)
+    }
+
+    /// Set the correction, in °C, added to every reading of the internal temperature sensor
+    ///
+    /// The on-die sensor has significant part-to-part error. Measure each device's offset once,
+    /// at a known ambient, with `calibrate_temperature`, store it, and set it again at startup.
+    /// Readings saturate at the limits of `DegreesC`.
+    pub fn set_temperature_offset(&mut self, offset: i8) {
+        self.temperature_offset = offset;
+    }
+
+    /// The correction, in °C, added to every temperature reading
+    pub fn temperature_offset(&self) -> i8 {
+        self.temperature_offset
+    }
+
+    /// Run a one-shot conversion of the internal temperature sensor, corrected by the temperature
+    /// offset (see `set_temperature_offset`)
+    pub fn temperature<DL: DelayMs<u8>>(&mut self, delay: &mut DL) -> Result<DegreesC, Error<E>> {
+        self.start_temperature()?;
+        self.poll_conversion(delay, |lp| lp.poll_temperature())
+    }
+
+    /// Run `samples` consecutive conversions of the internal temperature sensor, returning their
+    /// average rounded to the nearest °C, to reduce the noise of a single conversion
+    ///
+    /// Returns `Error::InvalidArgument` if `samples` is 0.
+    pub fn average_temperature<DL: DelayMs<u8>>(
+        &mut self,
+        samples: u8,
+        delay: &mut DL,
+    ) -> Result<DegreesC, Error<E>> {
+        if samples == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let mut sum = 0i16;
+        for _ in 0..samples {
+            sum += i16::from(self.temperature(delay)?.0);
+        }
+        let n = i16::from(samples);
+        let half = if sum < 0 { -n / 2 } else { n / 2 };
+        Ok(DegreesC(((sum + half) / n) as i8))
+    }
+
+    /// Start a one-shot conversion of the internal temperature sensor. The result is available
+    /// from `poll_temperature`.
+    pub fn start_temperature(&mut self) -> Result<(), Error<E>> {
+        self.write_register(reg::TempCtl::EN_TEMP_SENSOR)?;
+        Ok(())
+    }
+
+    /// Get the result of the temperature conversion started by `start_temperature`, decoded from
+    /// the two's complement TEMP_READ register and corrected by the temperature offset
+    pub fn poll_temperature(&mut self) -> nb::Result<DegreesC, Error<E>> {
+        let ctl = self.read_register::<reg::TempCtl>()?;
+        if ctl.contains(reg::TempCtl::TEMP_MEAS_BUSY) {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(self.latest_temperature()?)
+        }
+    }
+
+    /// Run a one-shot LED test ADC conversion of VOUT, returning mV
+    fn measure_vout<DL: DelayMs<u8>>(&mut self, delay: &mut DL) -> Result<i16, Error<E>> {
+        self.measure_led_test(reg::TestCtl::LEDTEST_VOUT.bits(), delay)
+    }
+
+    /// Run a one-shot LED test ADC conversion of the given input, returning mV
+    fn measure_led_test<DL: DelayMs<u8>>(
+        &mut self,
+        input: u8,
+        delay: &mut DL,
+    ) -> Result<i16, Error<E>> {
+        self.start_led_test(input)?;
+        self.poll_conversion(delay, |lp| lp.ledtest_done())
+    }
+
+    /// Start a one-shot LED test ADC conversion of VOUT. The result is available from
+    /// `poll_vout`.
+    pub fn start_vout(&mut self) -> Result<(), Error<E>> {
+        self.start_led_test(reg::TestCtl::LEDTEST_VOUT.bits())
+    }
+
+    /// Get the result, in mV, of the VOUT conversion started by `start_vout`. Note that this
+    /// reads (and so clears) the STATUS_IRQ register.
+    pub fn poll_vout(&mut self) -> nb::Result<i16, Error<E>> {
+        self.ledtest_done()
+    }
+
+    /// Start a one-shot LED test ADC conversion of the input, without waiting for it. The result
+    /// is available from `ledtest_done`.
+    ///
+    /// With `interrupt`, the INT pin is asserted when the conversion completes, provided it
+    /// signals interrupts (see `IntMode`), so that the host can render as usual until then rather
+    /// than poll. A D line's pin is only meaningful while
+    /// the D line is driven; `measure_led_vf` drives it at full PWM around a blocking
+    /// measurement.
+    pub fn start_led_measurement(
+        &mut self,
+        input: LedTestInput,
+        interrupt: bool,
+    ) -> Result<(), Error<E>> {
+        let mut ctl = reg::TestCtl::EN_LEDTEST_ADC.bits() | u8::from(input);
+        if interrupt {
+            ctl |= reg::TestCtl::EN_LEDTEST_INT.bits();
+        }
+        self.send(&[reg::TEST_CTL, ctl])?;
+        Ok(())
+    }
+
+    /// Start a one-shot LED test ADC conversion of the given input
+    fn start_led_test(&mut self, input: u8) -> Result<(), Error<E>> {
+        self.send(&[reg::TEST_CTL, reg::TestCtl::EN_LEDTEST_ADC.bits() | input])?;
+        Ok(())
+    }
+
+    /// Get the result, in mV, of the LED test ADC conversion started by `start_led_measurement`
+    /// (or `start_vout`) once the LEDTEST_MEAS_DONE flag is set, turning the ADC off. Note that
+    /// this reads (and so clears) the STATUS_IRQ register.
+    pub fn ledtest_done(&mut self) -> nb::Result<i16, Error<E>> {
+        let status = self.read_register::<reg::StatusIrq>()?;
+        if !status.contains(reg::StatusIrq::LEDTEST_MEAS_DONE) {
+            return Err(nb::Error::WouldBlock);
+        }
+        let adc = self.read(reg::TEST_ADC)?;
+        self.send(&[reg::TEST_CTL, 0])?;
+        // VOUT = ADC * 30mV - 1478mV
+        Ok(i16::from(adc) * 30 - 1478)
+    }
+
+    #[cfg(feature = "engines")]
+    /// Check whether the engines are idle, i.e. the engine busy bit is clear. Note that this
+    /// reads (and so clears) the STATUS_IRQ register.
+    pub fn poll_engine_idle(&mut self) -> nb::Result<(), Error<E>> {
+        let status = self.read_register::<reg::StatusIrq>()?;
+        if status.contains(reg::StatusIrq::ENGINE_BUSY) {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Call `poll` every millisecond until it completes, for at most `CONVERSION_POLLS` ms
+    fn poll_conversion<T, DL, F>(&mut self, delay: &mut DL, mut poll: F) -> Result<T, Error<E>>
+    where
+        DL: DelayMs<u8>,
+        F: FnMut(&mut Self) -> nb::Result<T, Error<E>>,
+    {
+        for _ in 0..CONVERSION_POLLS {
+            delay.delay_ms(1);
+            match poll(self) {
+                Ok(t) => return Ok(t),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => {}
+            }
+        }
+        Err(Error::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::{self, MockDelay};
+
+    #[test]
+    fn enable_refuses_to_cut_the_hold_off_short() {
+        let mut lp = mock::enabled();
+        lp.set_power_sequence(PowerSequence {
+            off_hold_off: 2_000,
+            ..PowerSequence::default()
+        });
+        lp.disable();
+        assert!(matches!(lp.enable(), Err(Error::InvalidArgument)));
+        assert!(lp.i2c.writes.is_empty());
+
+        let mut delay = MockDelay::default();
+        lp.enable_with_delay(&mut delay).unwrap();
+        assert!(delay.waited_us >= 2_000 + 500 + 500);
+    }
+
+    #[test]
+    fn enable_clears_the_power_down() {
+        let mut lp = mock::enabled();
+        lp.disable();
+        lp.enable().unwrap();
+        lp.set_power_sequence(PowerSequence {
+            en_to_chip_en: 0,
+            chip_en_to_config: 0,
+            off_hold_off: 2_000,
+        });
+        let mut delay = MockDelay::default();
+        lp.enable_with_delay(&mut delay).unwrap();
+        assert_eq!(delay.waited_us, 0);
+    }
+
+    #[test]
+    fn enable_gives_up_after_20_startup_reads() {
+        let mut i2c = mock::MockI2c::new();
+        i2c.startup_busy_reads = STARTUP_POLLS - 1;
+        let mut lp = Lp55231::new_without_pin(i2c, Addr::_0x32);
+        lp.enable().unwrap();
+
+        let mut i2c = mock::MockI2c::new();
+        i2c.startup_busy_reads = STARTUP_POLLS;
+        let mut lp = Lp55231::new_without_pin(i2c, Addr::_0x32);
+        assert!(matches!(lp.enable(), Err(Error::Timeout)));
+        let polls = lp.i2c.reads.iter().filter(|r| r.0 == reg::STATUS_IRQ).count();
+        assert_eq!(polls, usize::from(STARTUP_POLLS));
+    }
+
+    #[test]
+    fn clock_mode_is_kept_across_enables() {
+        let mut lp = mock::enabled();
+        lp.set_clock_mode(ClockMode::External).unwrap();
+        lp.disable();
+        lp.enable().unwrap();
+        let misc = reg::Misc::from_bits_truncate(lp.i2c.registers[usize::from(reg::MISC)]);
+        assert!(!misc.intersects(reg::Misc::INT_CLK_EN | reg::Misc::CLK_DET_EN));
+
+        lp.set_config(Config::default());
+        lp.set_clock_mode(ClockMode::Automatic).unwrap();
+        lp.enable().unwrap();
+        let misc = reg::Misc::from_bits_truncate(lp.i2c.registers[usize::from(reg::MISC)]);
+        assert_eq!(misc & (reg::Misc::INT_CLK_EN | reg::Misc::CLK_DET_EN), reg::Misc::CLK_DET_EN);
+    }
+
+    #[test]
+    fn shadow_cache_skips_unchanged_writes() {
+        let mut lp = mock::enabled();
+        lp.set_shadow_cache(true);
+        lp.set_pwm(D::D1, 5).unwrap();
+        lp.set_pwm(D::D1, 5).unwrap();
+        lp.set_pwm(D::D1, 6).unwrap();
+        assert_eq!(lp.i2c.writes, [[reg::D1_PWM, 5], [reg::D1_PWM, 6]]);
+    }
+
+    #[test]
+    fn shadow_cache_is_cleared_by_a_failed_write() {
+        let mut lp = mock::enabled();
+        lp.set_shadow_cache(true);
+        lp.set_pwm(D::D1, 5).unwrap();
+        lp.i2c.fail_after = Some(0);
+        assert!(lp.set_pwm(D::D2, 7).is_err());
+        lp.i2c.fail_after = None;
+        lp.i2c.clear();
+        lp.set_pwm(D::D1, 5).unwrap();
+        assert_eq!(lp.i2c.writes, [[reg::D1_PWM, 5]]);
+    }
+
+    #[test]
+    fn modify_reads_through_the_shadow_cache() {
+        let mut lp = mock::enabled();
+        lp.set_shadow_cache(true);
+        lp.modify(reg::D1_CTRL, |v| v | 0x20).unwrap();
+        lp.modify(reg::D1_CTRL, |v| v | 0x01).unwrap();
+        assert_eq!(lp.i2c.reads, [(reg::D1_CTRL, 1)]);
+        assert_eq!(lp.i2c.writes, [[reg::D1_CTRL, 0x20], [reg::D1_CTRL, 0x21]]);
+
+        // Registers the cache never holds are always read
+        lp.modify(reg::CNTRL1, |v| v).unwrap();
+        lp.modify(reg::CNTRL1, |v| v).unwrap();
+        assert_eq!(lp.i2c.reads[1..], [(reg::CNTRL1, 1), (reg::CNTRL1, 1)]);
+    }
+
+    #[test]
+    fn shadow_cache_is_cleared_by_reset() {
+        let mut lp = mock::enabled();
+        lp.set_shadow_cache(true);
+        lp.set_pwm(D::D1, 5).unwrap();
+        lp.reset().unwrap();
+        assert_eq!(lp.i2c.registers[usize::from(reg::D1_PWM)], 0);
+        lp.i2c.clear();
+        lp.set_pwm(D::D1, 5).unwrap();
+        assert_eq!(lp.i2c.writes, [[reg::D1_PWM, 5]]);
+    }
 }
@@ -0,0 +1,203 @@
+//! Program memory listings
+//!
+//! `Lp55231::program_listing` reads the whole program memory back and returns it as a `Listing`,
+//! which displays one line per instruction: its address, its word, the engine whose program it
+//! falls in (see `Program::memory_map`), and its disassembly. The cleared memory after the last
+//! instruction is shown as a single line. This is meant for bring-up and for pasting into support
+//! tickets:
+//!
+//! ```text
+//! 00  9c00  E1  mux_map_start(0x00)
+//! 01  4080  E1  set_pwm(128)
+//! 02  a001  E1  branch(0, 0x01)
+//! 03-5f  cleared
+//! ```
+//!
+//! Instructions are disassembled in the form of calls to the `asm` encoders, under their datasheet
+//! names for the few that have no encoder. Words that encode no known instruction, such as mapping
+//! table rows, are shown as `word(0x....)`.
+
+use core::fmt::{self, Debug};
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use program::{Program, PROGRAM_MEMORY_SIZE};
+use registers as reg;
+use {Error, Lp55231};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// An instruction word, displaying as its disassembly
+pub struct Disassembly(pub u16);
+
+/// The name of a variable operand
+fn variable(code: u16) -> Option<&'static str> {
+    match code & 0b11 {
+        0b00 => Some("Variable::A"),
+        0b01 => Some("Variable::B"),
+        0b10 => Some("Variable::C"),
+        _ => None,
+    }
+}
+
+/// Write the triggers set in a 6-bit trigger field, as a `Triggers` value
+fn triggers(f: &mut fmt::Formatter, field: u16) -> fmt::Result {
+    if field == 0 {
+        return f.write_str("Triggers::NONE");
+    }
+    f.write_str("Triggers {")?;
+    for (bit, name) in [(0x01, "e1"), (0x02, "e2"), (0x04, "e3"), (0x20, "external")].iter() {
+        if field & bit != 0 {
+            write!(f, " {}: true,", name)?;
+        }
+    }
+    f.write_str(" ..Triggers::NONE }")
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let w = self.0;
+        let address = w & 0x7f;
+        match w & 0xe000 {
+            _ if w == 0 => f.write_str("go_to_start()"),
+            0x0000 | 0x2000 | 0x4000 | 0x6000 => {
+                let step = (w >> 9) & 0x1f;
+                let prescale = w & 0x4000 != 0;
+                let increments = w & 0xff;
+                let sign = if w & 0x100 != 0 { "-" } else { "" };
+                match (step, increments) {
+                    (0, _) if w & 0xff00 == 0x4000 => write!(f, "set_pwm({})", increments),
+                    (0, _) => write!(f, "word({:#06x})", w),
+                    (_, 0) if w & 0x100 == 0 => write!(f, "wait({}, {})", step, prescale),
+                    _ => write!(f, "ramp({}, {}, {}{})", step, prescale, sign, increments),
+                }
+            }
+            0xa000 => write!(f, "branch({}, {:#04x})", (w >> 7) & 0x3f, address),
+            0xc000 if w & 0x07ff == 0 => write!(
+                f,
+                "end(End {{ send_interrupt: {}, reset_pc: {} }})",
+                w & 0x1000 != 0,
+                w & 0x0800 != 0
+            ),
+            0xe000 if w & 0x0001 == 0 => {
+                f.write_str("trigger(")?;
+                triggers(f, (w >> 7) & 0x3f)?;
+                f.write_str(", ")?;
+                triggers(f, (w >> 1) & 0x3f)?;
+                f.write_str(")")
+            }
+            0x8000 if w & 0xf000 == 0x8000 && w & 0x0800 != 0 => {
+                let condition = match w & 0xfe00 {
+                    0x8800 => "Condition::NotEqual",
+                    0x8a00 => "Condition::Less",
+                    0x8c00 => "Condition::GreaterOrEqual",
+                    _ => "Condition::Equal",
+                };
+                match (variable(w >> 2), variable(w)) {
+                    (Some(a), Some(b)) => {
+                        write!(f, "jump({}, {}, {}, {})", condition, (w >> 4) & 0x1f, a, b)
+                    }
+                    _ => write!(f, "word({:#06x})", w),
+                }
+            }
+            0x8000 if w & 0xf000 == 0x9000 && w & 0x0c00 != 0x0c00 => {
+                let target = variable(w >> 10).unwrap_or("?");
+                match (w >> 8) & 0x3 {
+                    0 => write!(f, "load({}, {})", target, w & 0xff),
+                    1 => write!(f, "add({}, {})", target, w & 0xff),
+                    2 => write!(f, "sub({}, {})", target, w & 0xff),
+                    _ => match (w & 0x00e0, variable(w >> 2), variable(w)) {
+                        (0, Some(a), Some(b)) => {
+                            let op = if w & 0x10 != 0 { "sub_vars" } else { "add_vars" };
+                            write!(f, "{}({}, {}, {})", op, target, a, b)
+                        }
+                        _ => write!(f, "word({:#06x})", w),
+                    },
+                }
+            }
+            _ => match w & 0xff80 {
+                0x9c00 => write!(f, "mux_map_start({:#04x})", address),
+                0x9c80 => write!(f, "mux_ld_end({:#04x})", address),
+                0x9e00 => write!(f, "mux_ld_start({:#04x})", address),
+                0x9f00 => write!(f, "mux_ld_addr({:#04x})", address),
+                0x9f80 => write!(f, "mux_map_addr({:#04x})", address),
+                _ => match w {
+                    0x9d00 => f.write_str("mux_clr()"),
+                    0x9d01..=0x9d09 => write!(f, "mux_sel(Some(D::D{}))", w & 0x0f),
+                    0x9d80 => f.write_str("mux_map_next()"),
+                    0x9d81 => f.write_str("mux_ld_next()"),
+                    0x9dc0 => f.write_str("mux_map_prev()"),
+                    0x9dc1 => f.write_str("mux_ld_prev()"),
+                    _ => write!(f, "word({:#06x})", w),
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The contents of the program memory, displaying as an annotated listing
+pub struct Listing {
+    /// The program memory, with the engines' start addresses
+    program: Program,
+}
+
+impl Listing {
+    /// List a program
+    pub fn new(program: Program) -> Self {
+        Listing { program }
+    }
+
+    /// The listed program
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let map = self.program.memory_map();
+        let instructions = self.program.instructions();
+        let len = instructions.iter().rposition(|w| *w != 0).map_or(0, |last| last + 1);
+        for (addr, word) in instructions[..len].iter().enumerate() {
+            write!(f, "{:02x}  {:04x}  ", addr, word)?;
+            match map.owner(addr as u8) {
+                Some(e) => write!(f, "{:?}", e)?,
+                None => f.write_str("--")?,
+            }
+            writeln!(f, "  {}", Disassembly(*word))?;
+        }
+        if len < PROGRAM_MEMORY_SIZE {
+            writeln!(f, "{:02x}-{:02x}  cleared", len, PROGRAM_MEMORY_SIZE - 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Listing {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Display2Format(self))
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Read back the whole program memory and the engines' start addresses as a `Listing`
+    ///
+    /// Reading the program memory requires an engine in load mode, so every engine is held and
+    /// disabled afterwards, as with `snapshot_with_program`.
+    pub fn program_listing(&mut self) -> Result<Listing, Error<E>> {
+        let start = [
+            self.read(reg::PROG1_START)?,
+            self.read(reg::PROG2_START)?,
+            self.read(reg::PROG3_START)?,
+        ];
+        Ok(Listing::new(self.capture_program(start)?))
+    }
+}
@@ -0,0 +1,69 @@
+//! Multi-chip lockstep through the TRIG pin
+//!
+//! Devices started together still drift apart over long runtimes, each running from its own
+//! oscillator. To hold them in lockstep, wire their TRIG pins together (the pin is open drain, so
+//! one pull-up serves them all) and run one engine on each device in a loop that starts with a
+//! sync point: the leader sends an external trigger there, and the followers wait for it. The
+//! followers are pulled back to the leader at the start of every repetition, so drift never
+//! builds up beyond one.
+//!
+//! ```ignore
+//! let mut leader = Assembler::new();
+//! leader.lockstep(Role::Leader, |asm| {
+//!     asm.ramp(16, false, 255).ramp(16, false, -255);
+//! });
+//! let mut follower = Assembler::new();
+//! follower.lockstep(Role::Follower, |asm| {
+//!     asm.wait(31, true).ramp(16, false, 255).ramp(16, false, -255);
+//! });
+//! // load each program on its devices, then
+//! strip.start_lockstep(0, Engine::E1)?;
+//! ```
+//!
+//! `Strip::start_lockstep` starts the followers before the leader, so that they are waiting for
+//! the first trigger. A follower's loop must be no longer than the leader's, or it is still busy
+//! when the next trigger arrives; `fits_lockstep` checks this from the programs' timing.
+
+use asm::{Assembler, Triggers};
+use timing::Timing;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// The part a device plays in lockstep
+pub enum Role {
+    /// Sends the external trigger that paces the other devices
+    Leader,
+    /// Waits for the leader's external trigger
+    Follower,
+}
+
+impl Assembler {
+    /// Synchronize with the other devices: send the external trigger as the leader, or wait for
+    /// it as a follower
+    pub fn sync_point(&mut self, role: Role) -> &mut Self {
+        match role {
+            Role::Leader => self.trigger(Triggers::NONE, Triggers::EXTERNAL),
+            Role::Follower => self.trigger(Triggers::EXTERNAL, Triggers::NONE),
+        }
+    }
+
+    /// Run the instructions added by `body` forever, after a sync point at the start of every
+    /// repetition
+    pub fn lockstep<F: FnOnce(&mut Self)>(&mut self, role: Role, body: F) -> &mut Self {
+        self.forever(|asm| {
+            asm.sync_point(role);
+            body(asm);
+        })
+    }
+}
+
+/// Whether a follower's loop is no longer than the leader's, so that the follower is waiting
+/// when each trigger arrives
+///
+/// Both programs must repeat forever, as `Assembler::lockstep` programs do.
+pub fn fits_lockstep(leader: &Timing, follower: &Timing) -> bool {
+    match (leader.period_us(), follower.period_us()) {
+        (Some(leader), Some(follower)) => follower <= leader,
+        _ => false,
+    }
+}
@@ -0,0 +1,215 @@
+//! Bus, pin and delay stand-ins for the unit tests
+//!
+//! `MockI2c` holds the register file of a single device and records every transaction, so that
+//! tests can check both what the driver wrote and how it split the writes into transactions. Like
+//! the device, it only takes program memory writes while an engine is in load mode and
+//! STATUS_IRQ no longer shows ENGINE_BUSY.
+
+use hal::blocking::delay::{DelayMs, DelayUs};
+use hal::blocking::i2c::{Write, WriteRead};
+use std::vec::Vec;
+
+use registers as reg;
+use {Addr, Lp55231, NoPin};
+
+/// Bytes of program memory in a page, the size of the program memory window
+const PAGE_BYTES: usize = (reg::PROG_MEM_END - reg::PROG_MEM_BASE) as usize + 1;
+
+/// Bytes of program memory, in 6 pages
+const PROGRAM_BYTES: usize = 6 * PAGE_BYTES;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The error returned by a failing `MockI2c` transaction
+pub struct MockError;
+
+/// An I2C bus with a single device on it
+pub struct MockI2c {
+    /// Register contents of the device, apart from the program memory
+    pub registers: [u8; 256],
+    /// Program memory, two bytes per instruction
+    pub program: [u8; PROGRAM_BYTES],
+    /// Number of STATUS_IRQ reads that show ENGINE_BUSY once an engine enters load mode
+    pub load_busy_reads: u8,
+    /// STATUS_IRQ reads still to show ENGINE_BUSY
+    busy_reads: u8,
+    /// Number of STATUS_IRQ reads still to show STARTUP_BUSY
+    pub startup_busy_reads: u8,
+    /// Drop every program memory write, as a device with a faulty bus would
+    pub drop_program_writes: bool,
+    /// Every write, as its bytes including the register address
+    pub writes: Vec<Vec<u8>>,
+    /// Every read, as its first register and length
+    pub reads: Vec<(u8, usize)>,
+    /// Number of transactions to let through before failing every one, if any
+    pub fail_after: Option<usize>,
+}
+
+impl MockI2c {
+    /// A device with every register at its power-on default
+    pub fn new() -> Self {
+        let mut i2c = MockI2c {
+            registers: [0; 256],
+            program: [0; PROGRAM_BYTES],
+            load_busy_reads: 0,
+            busy_reads: 0,
+            startup_busy_reads: 0,
+            drop_program_writes: false,
+            writes: Vec::new(),
+            reads: Vec::new(),
+            fail_after: None,
+        };
+        i2c.power_on();
+        i2c
+    }
+
+    /// Put every register back at its power-on default
+    pub fn power_on(&mut self) {
+        self.registers = [0; 256];
+        self.program = [0; PROGRAM_BYTES];
+        self.busy_reads = 0;
+        for (register, value) in reg::DEFAULTS.iter() {
+            self.registers[usize::from(*register)] = *value;
+        }
+    }
+
+    /// Forget the transactions recorded so far
+    pub fn clear(&mut self) {
+        self.writes.clear();
+        self.reads.clear();
+    }
+
+    /// Whether any engine is in load mode
+    fn loading(&self) -> bool {
+        let cntrl2 = reg::Cntrl2::from_bits_truncate(self.registers[usize::from(reg::CNTRL2)]);
+        [
+            (reg::Cntrl2::ENG1_MODE, reg::Cntrl2::ENG1_MODE_LOAD),
+            (reg::Cntrl2::ENG2_MODE, reg::Cntrl2::ENG2_MODE_LOAD),
+            (reg::Cntrl2::ENG3_MODE, reg::Cntrl2::ENG3_MODE_LOAD),
+        ]
+        .iter()
+        .any(|(field, load)| cntrl2 & *field == *load)
+    }
+
+    /// The program memory byte a register of the program memory window maps to, on the selected
+    /// page
+    fn program_byte(&mut self, register: u8) -> Option<&mut u8> {
+        if !(reg::PROG_MEM_BASE..=reg::PROG_MEM_END).contains(&register) {
+            return None;
+        }
+        let page = usize::from(self.registers[usize::from(reg::PROG_PAGE_SEL)]);
+        let offset = usize::from(register - reg::PROG_MEM_BASE);
+        self.program.get_mut(page * PAGE_BYTES + offset)
+    }
+
+    /// Write a register, as the device does
+    fn write_register(&mut self, register: u8, value: u8) {
+        if register == reg::RESET && value == reg::Reset::RESET_NOW.bits() {
+            self.power_on();
+        } else if (reg::PROG_MEM_BASE..=reg::PROG_MEM_END).contains(&register) {
+            // Program memory writes are dropped unless loading is possible
+            if self.loading() && self.busy_reads == 0 && !self.drop_program_writes {
+                if let Some(byte) = self.program_byte(register) {
+                    *byte = value;
+                }
+            }
+        } else {
+            let was_loading = self.loading();
+            self.registers[usize::from(register)] = value;
+            if register == reg::CNTRL2 && !was_loading && self.loading() {
+                self.busy_reads = self.load_busy_reads;
+            }
+        }
+    }
+
+    /// Read a register, as the device does
+    fn read_register(&mut self, register: u8) -> u8 {
+        if register == reg::STATUS_IRQ {
+            let mut status = self.registers[usize::from(register)];
+            if self.busy_reads > 0 {
+                self.busy_reads -= 1;
+                status |= reg::StatusIrq::ENGINE_BUSY.bits();
+            }
+            if self.startup_busy_reads > 0 {
+                self.startup_busy_reads -= 1;
+                status |= reg::StatusIrq::STARTUP_BUSY.bits();
+            }
+            return status;
+        }
+        match self.program_byte(register) {
+            Some(byte) => *byte,
+            None => self.registers[usize::from(register)],
+        }
+    }
+
+    /// Count a transaction, failing it if the failure point has been reached
+    fn transact(&mut self) -> Result<(), MockError> {
+        match self.fail_after {
+            Some(0) => Err(MockError),
+            Some(ref mut n) => {
+                *n -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for MockI2c {
+    type Error = MockError;
+
+    fn write(&mut self, _addr: u8, bytes: &[u8]) -> Result<(), MockError> {
+        self.transact()?;
+        self.writes.push(bytes.to_vec());
+        let (start, values) = bytes.split_first().expect("empty write");
+        for (i, value) in values.iter().enumerate() {
+            self.write_register(start.wrapping_add(i as u8), *value);
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead for MockI2c {
+    type Error = MockError;
+
+    fn write_read(&mut self, _addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), MockError> {
+        self.transact()?;
+        self.reads.push((bytes[0], buf.len()));
+        for (i, value) in buf.iter_mut().enumerate() {
+            *value = self.read_register(bytes[0].wrapping_add(i as u8));
+        }
+        Ok(())
+    }
+}
+
+/// A delay that only adds up the time it was asked to wait
+#[derive(Debug, Default)]
+pub struct MockDelay {
+    /// Total time waited, in µs
+    pub waited_us: u32,
+}
+
+impl DelayUs<u16> for MockDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.waited_us += u32::from(us);
+    }
+}
+
+impl DelayMs<u16> for MockDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.waited_us += u32::from(ms) * 1000;
+    }
+}
+
+impl DelayMs<u8> for MockDelay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.waited_us += u32::from(ms) * 1000;
+    }
+}
+
+/// An enabled driver on a mock bus, with no transactions recorded yet
+pub fn enabled() -> Lp55231<MockI2c, NoPin> {
+    let mut lp = Lp55231::new_without_pin(MockI2c::new(), Addr::_0x32);
+    lp.enable().unwrap();
+    lp.i2c.clear();
+    lp
+}
@@ -0,0 +1,204 @@
+//! Cumulative on-time tracking
+//!
+//! With an `OnTime` accumulator set by `Lp55231::set_on_time`, the driver follows the direct PWM
+//! value and drive current of each D line as it writes them, and `Lp55231::on_time_tick`, called
+//! periodically with the time elapsed since the previous call, integrates them: the time each D
+//! line was lit, its full-duty equivalent time, and the charge it drew. These feed lifetime and
+//! warranty analytics; the accumulator can be saved and restored across power cycles with
+//! `persist`, as whole seconds and µAh.
+//!
+//! Only what the driver writes is seen: master faders, engines, logarithmic dimming and the
+//! output on/off mask are not taken into account, and a D line's values are only known from its
+//! first write after tracking starts.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use persist::Persist;
+use registers as reg;
+use {Lp55231, Millis, D};
+
+/// Drive current of each D line after a reset, in 100 µA steps
+const DEFAULT_CURRENT: u8 = 0xaf;
+
+/// Charge units, of 1/255 of 100 µA for 1 ms, in a µAh
+const CHARGE_PER_UAH: u64 = 255 * 3_600_000 / 100;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Accumulated on-time of each D line
+pub struct OnTime {
+    /// PWM value last written to each D line
+    pwm: [u8; 9],
+    /// Drive current last written to each D line, in 100 µA steps
+    current: [u8; 9],
+    /// Time each D line was lit, in ms
+    lit: [u64; 9],
+    /// Integral of each D line's PWM value over time, in 1/255 of a ms
+    duty: [u64; 9],
+    /// Integral of each D line's PWM value times its current over time, in 1/255 of 100 µA for
+    /// 1 ms
+    charge: [u64; 9],
+}
+
+impl Default for OnTime {
+    fn default() -> Self {
+        OnTime::new()
+    }
+}
+
+impl OnTime {
+    /// Create an accumulator with nothing accumulated, for D lines dark and at their default
+    /// current
+    pub fn new() -> Self {
+        OnTime {
+            pwm: [0; 9],
+            current: [DEFAULT_CURRENT; 9],
+            lit: [0; 9],
+            duty: [0; 9],
+            charge: [0; 9],
+        }
+    }
+
+    /// Time the D line has been lit, at any PWM value, in ms
+    pub fn lit_ms(&self, d: D) -> u64 {
+        self.lit[usize::from(u8::from(d))]
+    }
+
+    /// Time the D line would have been lit to give the same light at full PWM, in ms
+    pub fn full_duty_ms(&self, d: D) -> u64 {
+        self.duty[usize::from(u8::from(d))] / 255
+    }
+
+    /// Charge drawn by the D line, in µAh; multiply by the LED's forward voltage for an energy
+    /// estimate
+    pub fn charge_uah(&self, d: D) -> u64 {
+        self.charge[usize::from(u8::from(d))] / CHARGE_PER_UAH
+    }
+
+    /// Forget everything accumulated, keeping the values last written
+    pub fn clear(&mut self) {
+        self.lit = [0; 9];
+        self.duty = [0; 9];
+        self.charge = [0; 9];
+    }
+
+    /// Add `elapsed` ms at the values last written
+    fn tick(&mut self, elapsed: u64) {
+        for i in 0..9 {
+            let pwm = u64::from(self.pwm[i]);
+            if pwm > 0 {
+                self.lit[i] += elapsed;
+            }
+            self.duty[i] += pwm * elapsed;
+            self.charge[i] += pwm * u64::from(self.current[i]) * elapsed;
+        }
+    }
+
+    /// Take the D lines as dark and at their default current, as after a reset
+    fn reset(&mut self) {
+        self.pwm = [0; 9];
+        self.current = [DEFAULT_CURRENT; 9];
+    }
+
+    /// Follow the PWM values and currents of a write about to be sent
+    fn observe(&mut self, bytes: &[u8]) {
+        let (start, values) = match bytes.split_first() {
+            Some((start, values)) => (*start, values),
+            None => return,
+        };
+        if start == reg::RESET {
+            self.reset();
+            return;
+        }
+        for (i, value) in values.iter().enumerate() {
+            let r = usize::from(start) + i;
+            let pwm = usize::from(reg::D_PWM_BASE);
+            let current = usize::from(reg::D1_I_CTL);
+            if (pwm..pwm + 9).contains(&r) {
+                self.pwm[r - pwm] = *value;
+            } else if (current..current + 9).contains(&r) {
+                self.current[r - current] = *value;
+            }
+        }
+    }
+}
+
+impl Persist for OnTime {
+    const KIND: u8 = 3;
+    const LEN: usize = 108;
+
+    /// Stored as the lit and full-duty times of each D line in seconds, then the charge in µAh,
+    /// each a big-endian `u32`, saturating; the values last written are not stored
+    fn encode(&self, buf: &mut [u8]) {
+        for i in 0..9 {
+            let fields = [
+                self.lit[i] / 1000,
+                self.duty[i] / (255 * 1000),
+                self.charge[i] / CHARGE_PER_UAH,
+            ];
+            for (j, field) in fields.iter().enumerate() {
+                let at = 4 * (9 * j + i);
+                let field = (*field).min(u64::from(u32::MAX)) as u32;
+                buf[at..at + 4].copy_from_slice(&field.to_be_bytes());
+            }
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let mut on_time = OnTime::new();
+        let field = |j: usize, i: usize| {
+            let at = 4 * (9 * j + i);
+            u64::from(u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]))
+        };
+        for i in 0..9 {
+            on_time.lit[i] = field(0, i) * 1000;
+            on_time.duty[i] = field(1, i) * 255 * 1000;
+            on_time.charge[i] = field(2, i) * CHARGE_PER_UAH;
+        }
+        on_time
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Start tracking on-time from the accumulator, either `OnTime::new()` or one restored from
+    /// storage, or stop tracking with `None`
+    ///
+    /// Start tracking right after `enable`, so that the driver has seen every value written.
+    pub fn set_on_time(&mut self, on_time: Option<OnTime>) {
+        self.on_time = on_time;
+    }
+
+    /// The on-time accumulated so far, if tracking
+    pub fn on_time(&self) -> Option<&OnTime> {
+        self.on_time.as_ref()
+    }
+
+    /// Accumulate `elapsed` ms at the PWM values and currents last written
+    pub fn on_time_tick<T: Into<Millis>>(&mut self, elapsed: T) {
+        if let Some(on_time) = self.on_time.as_mut() {
+            on_time.tick(u64::from(elapsed.into().0));
+        }
+    }
+
+    /// Take the D lines as dark, as the device has been reset
+    pub(crate) fn on_time_reset(&mut self) {
+        if let Some(on_time) = self.on_time.as_mut() {
+            on_time.reset();
+        }
+    }
+
+    /// Follow the PWM values and currents of a write about to be sent
+    pub(crate) fn on_time_observe(&mut self, bytes: &[u8]) {
+        if let Some(on_time) = self.on_time.as_mut() {
+            on_time.observe(bytes);
+        }
+    }
+}
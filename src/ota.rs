@@ -0,0 +1,208 @@
+//! Over-the-air pattern updates
+//!
+//! An `Updater` takes a pattern received over the air, as a program blob (see `program`) or a
+//! scene record (see `persist`), and brings it into service in steps:
+//!
+//! 1. `stage` validates the blob's header, version and CRC, and holds on to the decoded pattern,
+//! 2. `activate` writes the staged pattern to the device, reads it back to verify it, and only
+//!    then sets it running, and
+//! 3. if any step of activation fails, the previously active pattern of the same kind is written
+//!    back in its place.
+//!
+//! Programs and scenes are tracked separately, so that updating one leaves the other in place.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use persist::{self, PersistError};
+use program::{Program, ProgramError};
+use scene::Scene;
+use {Error, Lp55231, D};
+
+#[derive(Copy, Clone)]
+/// A pattern received over the air
+pub enum Payload {
+    /// A program for the engines
+    Program(Program),
+    /// A scene of direct PWM values
+    Scene(Scene),
+}
+
+impl Payload {
+    /// Decode and validate a program blob or a scene record
+    pub fn from_blob(blob: &[u8]) -> Result<Self, ProgramError> {
+        match Program::from_blob(blob) {
+            Ok(program) => return Ok(Payload::Program(program)),
+            Err(ProgramError::BadMagic) => {}
+            Err(e) => return Err(e),
+        }
+        match persist::from_record::<Scene, ()>(blob) {
+            Ok(scene) => Ok(Payload::Scene(scene)),
+            Err(PersistError::BadCrc) => Err(ProgramError::BadCrc),
+            Err(_) => Err(ProgramError::BadMagic),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Error conditions returned when staging or activating an update
+pub enum OtaError<E> {
+    /// The blob is not a valid program or scene
+    Invalid(ProgramError),
+    /// No update is staged
+    NothingStaged,
+    /// Activation failed, and the previous pattern was restored
+    Activation(Error<E>),
+    /// Activation failed, and so did restoring the previous pattern; the device's state is unknown
+    RollbackFailed(Error<E>),
+}
+
+impl<E> From<ProgramError> for OtaError<E> {
+    fn from(e: ProgramError) -> Self {
+        OtaError::Invalid(e)
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+/// Staging and activation of over-the-air updates, with rollback
+pub struct Updater {
+    /// The program currently running, if any
+    program: Option<Program>,
+    /// The scene currently shown, if any
+    scene: Option<Scene>,
+    /// The update waiting to be activated, if any
+    staged: Option<Payload>,
+}
+
+impl Updater {
+    /// Create an updater with no active pattern to roll back to
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an updater that rolls back to the program and scene already on the device
+    pub fn with_active(program: Option<Program>, scene: Option<Scene>) -> Self {
+        Updater {
+            program,
+            scene,
+            staged: None,
+        }
+    }
+
+    /// Validate a received blob and stage it for activation, replacing any update already staged
+    pub fn stage(&mut self, blob: &[u8]) -> Result<(), ProgramError> {
+        self.staged = Some(Payload::from_blob(blob)?);
+        Ok(())
+    }
+
+    /// The update waiting to be activated, if any
+    pub fn staged(&self) -> Option<&Payload> {
+        self.staged.as_ref()
+    }
+
+    /// Drop the staged update without activating it
+    pub fn discard(&mut self) {
+        self.staged = None;
+    }
+
+    /// The program last activated, if any
+    pub fn active_program(&self) -> Option<&Program> {
+        self.program.as_ref()
+    }
+
+    /// The scene last activated, if any
+    pub fn active_scene(&self) -> Option<&Scene> {
+        self.scene.as_ref()
+    }
+
+    /// Write the staged update to the device, verify it and set it running
+    ///
+    /// A program is started with `Lp55231::start_program`. A scene is written to the direct PWM
+    /// registers at once, bypassing any slew limit, and read back.
+    ///
+    /// On failure the previous pattern of the same kind is restored, or, with none, the engines
+    /// are left stopped. The staged update is consumed either way.
+    #[allow(deprecated)]
+    pub fn activate<E, I, P>(&mut self, lp: &mut Lp55231<I, P>) -> Result<(), OtaError<E>>
+    where
+        E: Debug,
+        I: Write<Error = E> + WriteRead<Error = E>,
+        P: OutputPin,
+    {
+        match self.staged.take().ok_or(OtaError::NothingStaged)? {
+            Payload::Program(program) => match lp.start_program(&program) {
+                Ok(()) => self.program = Some(program),
+                Err(e) => {
+                    let rollback = match self.program {
+                        Some(previous) => lp.start_program(&previous),
+                        None => lp.halt_all_engines(false),
+                    };
+                    return Err(match rollback {
+                        Ok(()) => OtaError::Activation(e),
+                        Err(_) => OtaError::RollbackFailed(e),
+                    });
+                }
+            },
+            Payload::Scene(scene) => match activate_scene(lp, &scene) {
+                Ok(()) => self.scene = Some(scene),
+                Err(e) => {
+                    let rollback = match self.scene {
+                        Some(previous) => activate_scene(lp, &previous),
+                        None => Ok(()),
+                    };
+                    return Err(match rollback {
+                        Ok(()) => OtaError::Activation(e),
+                        Err(_) => OtaError::RollbackFailed(e),
+                    });
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Apply and verify a scene
+///
+/// The scene is written at once, regardless of any slew limit, so that the read-back sees its
+/// values rather than the first step towards them.
+#[allow(deprecated)]
+fn activate_scene<E, I, P>(lp: &mut Lp55231<I, P>, scene: &Scene) -> Result<(), Error<E>>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    for d in D::iter() {
+        lp.set_pwm_immediate(d, scene.pwm(d))?;
+    }
+    for d in D::iter() {
+        let expected = lp.output_pwm(d, scene.pwm(d));
+        if lp.pwm(d)? != expected {
+            return Err(Error::VerifyFailed);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock;
+    use OutputMask;
+
+    #[test]
+    fn scenes_activate_under_a_slew_limit() {
+        let mut lp = mock::enabled();
+        lp.set_slew_limit(OutputMask::all(), Some(10)).unwrap();
+        let mut updater = Updater::new();
+        updater.staged = Some(Payload::Scene(Scene::new(1, [200; 9])));
+        updater.activate(&mut lp).unwrap();
+        assert!(updater.active_scene().is_some());
+        for d in D::iter() {
+            assert_eq!(lp.pwm(d).unwrap(), 200);
+        }
+        assert!(!lp.slew_tick().unwrap());
+    }
+}
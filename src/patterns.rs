@@ -0,0 +1,369 @@
+//! Engine program generators
+//!
+//! An engine sets every D line mapped to it to the same PWM value, so an RGB LED whose channels
+//! change independently takes three engines, one per channel. The generators here build such
+//! three-engine programs, with E1 driving red, E2 green and E3 blue, so that color effects run
+//! entirely on the device while the host sleeps.
+//!
+//! - `crossfade` ramps each channel from one color to another over a duration, then holds it;
+//!   `crossfade_eased` follows an `Easing` curve instead of a straight line.
+//! - `hue_rotation` sweeps the hue around the color wheel, at full saturation, forever.
+//!
+//! `Lp55231::start_crossfade` and `Lp55231::start_hue_rotation` map the engines to an RGB LED and
+//! start the program.
+//!
+//! The engines only ramp linearly, so an eased fade is approximated by a chain of linear ramps
+//! through points of the curve. More ramps follow the curve more closely but take more of the
+//! program memory: the generators use as many as fit, up to `EASING_SEGMENTS`.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm::{Assembler, End, MAX_LOOP_COUNT};
+use color::{Rgb, RgbGroup};
+use program::{Program, ProgramError};
+use timing::CLOCK_HZ;
+use {Engine, Error, Lp55231, Millis, OutputMask};
+
+/// Clock ticks in a cycle
+pub(crate) const CYCLE_TICKS: u32 = 16;
+
+/// Clock ticks in a prescaled cycle
+pub(crate) const PRESCALED_CYCLE_TICKS: u32 = 512;
+
+/// Longest step time of a `ramp` or `wait`, in cycles
+const MAX_STEP: u32 = 31;
+
+/// Convert ms to clock ticks
+pub(crate) fn ticks(duration: Millis) -> u32 {
+    u32::from(duration.0) * CLOCK_HZ / 1000
+}
+
+/// Wait for `ticks` clock ticks, to the nearest cycle
+pub(crate) fn wait_ticks(asm: &mut Assembler, ticks: u32) {
+    let mut prescaled = ticks / PRESCALED_CYCLE_TICKS;
+    let longest = MAX_STEP * (u32::from(MAX_LOOP_COUNT) + 1);
+    while prescaled >= MAX_STEP {
+        let count = (prescaled / MAX_STEP).min(longest / MAX_STEP);
+        asm.repeat(count as u8, |asm| {
+            asm.wait(MAX_STEP as u8, true);
+        });
+        prescaled -= count * MAX_STEP;
+    }
+    if prescaled > 0 {
+        asm.wait(prescaled as u8, true);
+    }
+    let cycles = (ticks % PRESCALED_CYCLE_TICKS + CYCLE_TICKS / 2) / CYCLE_TICKS;
+    if cycles > 0 {
+        asm.wait(cycles.min(MAX_STEP) as u8, false);
+    }
+}
+
+/// Ramp the PWM value from `from` to `to` over `ticks` clock ticks, as closely as the step times
+/// allow
+///
+/// The increments are split between two step times a cycle apart, so that the whole ramp takes
+/// the requested time to the nearest cycle. A ramp too slow for the longest step time is padded
+/// with a wait before it; one too fast for the shortest runs at one increment per cycle.
+pub(crate) fn fade(asm: &mut Assembler, from: u8, to: u8, ticks: u32) {
+    let increments = i16::from(to) - i16::from(from);
+    let steps = u32::from(increments.unsigned_abs());
+    if steps == 0 {
+        wait_ticks(asm, ticks);
+        return;
+    }
+    let (prescale, unit) = if ticks / CYCLE_TICKS / steps < MAX_STEP {
+        (false, CYCLE_TICKS)
+    } else {
+        (true, PRESCALED_CYCLE_TICKS)
+    };
+    let cycles = (ticks + unit / 2) / unit;
+    let (step, longer) = match cycles / steps {
+        0 => (1, 0),
+        step if step >= MAX_STEP => {
+            wait_ticks(asm, ticks.saturating_sub(MAX_STEP * steps * unit));
+            (MAX_STEP, 0)
+        }
+        step => (step, cycles % steps),
+    };
+    let sign = increments.signum();
+    let shorter = steps - longer;
+    if shorter > 0 {
+        asm.ramp(step as u8, prescale, sign * shorter as i16);
+    }
+    if longer > 0 {
+        asm.ramp(step as u8 + 1, prescale, sign * longer as i16);
+    }
+}
+
+/// Most linear ramps an eased fade is approximated with
+pub const EASING_SEGMENTS: u32 = 8;
+
+/// sin(kπ/32) for k in 0..=16, out of 1024
+const QUARTER_SINE: [u32; 17] = [
+    0, 100, 200, 297, 392, 483, 569, 650, 724, 792, 851, 903, 946, 980, 1004, 1019, 1024,
+];
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Shape of a fade's progress over time
+pub enum Easing {
+    /// Constant speed
+    #[default]
+    Linear,
+    /// Starting slowly and speeding up, quadratically
+    EaseIn,
+    /// Starting quickly and slowing down, quadratically
+    EaseOut,
+    /// Starting and ending slowly, along half a sine wave
+    Sine,
+    /// Starting and ending slowly, cubically, with a steeper middle than `Sine`
+    Cubic,
+}
+
+impl Easing {
+    /// The progress of a fade, out of 1024, at time `t` into it, out of 1024
+    pub fn apply(self, t: u32) -> u32 {
+        let t = t.min(1024);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t / 1024,
+            Easing::EaseOut => 1024 - (1024 - t) * (1024 - t) / 1024,
+            Easing::Sine => {
+                // (1 - cos πt) / 2 = sin²(πt / 2), interpolating the quarter sine wave
+                let (i, frac) = ((t / 64) as usize, t % 64);
+                let next = QUARTER_SINE[(i + 1).min(16)];
+                let sine = QUARTER_SINE[i] + (next - QUARTER_SINE[i]) * frac / 64;
+                sine * sine / 1024
+            }
+            Easing::Cubic if t < 512 => 4 * t * t * t / (1024 * 1024),
+            Easing::Cubic => {
+                let rest = 1024 - t;
+                1024 - 4 * rest * rest * rest / (1024 * 1024)
+            }
+        }
+    }
+}
+
+/// Ramp the PWM value from `from` to `to` over `ticks` clock ticks along the easing curve, as a
+/// chain of `segments` linear ramps (see `fade`)
+pub(crate) fn eased_fade(
+    asm: &mut Assembler,
+    from: u8,
+    to: u8,
+    ticks: u32,
+    easing: Easing,
+    segments: u32,
+) {
+    let segments = if easing == Easing::Linear { 1 } else { segments.max(1) };
+    let point = |k: u32| {
+        let progress = easing.apply(k * 1024 / segments) as i32;
+        let span = i32::from(to) - i32::from(from);
+        (i32::from(from) + (span * progress + span.signum() * 512) / 1024) as u8
+    };
+    for k in 0..segments {
+        let elapsed = |k: u32| u64::from(ticks) * u64::from(k) / u64::from(segments);
+        let ticks = (elapsed(k + 1) - elapsed(k)) as u32;
+        fade(asm, point(k), point(k + 1), ticks);
+    }
+}
+
+/// Build a program with `build`, given the most linear ramps per eased fade that fit in the
+/// program memory
+pub(crate) fn fit_easing<F>(build: F) -> Result<Program, ProgramError>
+where
+    F: Fn(u32) -> Result<Program, ProgramError>,
+{
+    let mut segments = EASING_SEGMENTS;
+    loop {
+        match build(segments) {
+            Err(ProgramError::TooLarge) if segments > 1 => segments /= 2,
+            result => return result,
+        }
+    }
+}
+
+/// Build a program fading an RGB LED from `from` to `to` over `duration`, with E1 driving red,
+/// E2 green and E3 blue
+///
+/// Each engine sets its channel to the starting value, ramps it to the final value, and ends,
+/// holding it there. The ramps take `duration` to the nearest cycle, or, over a few PWM steps
+/// in less than a cycle each, as long as one cycle per step.
+pub fn crossfade(from: Rgb, to: Rgb, duration: Millis) -> Result<Program, ProgramError> {
+    crossfade_eased(from, to, duration, Easing::Linear)
+}
+
+/// Build a program fading an RGB LED from `from` to `to` over `duration` along the easing curve,
+/// with E1 driving red, E2 green and E3 blue
+///
+/// As `crossfade`, with each channel's ramp split into linear ramps through points of the curve:
+/// as many as fit in the program memory, up to `EASING_SEGMENTS`.
+pub fn crossfade_eased(
+    from: Rgb,
+    to: Rgb,
+    duration: Millis,
+    easing: Easing,
+) -> Result<Program, ProgramError> {
+    let ticks = ticks(duration);
+    fit_easing(|segments| {
+        let mut asm = Assembler::new();
+        let mut start = [0u8; 3];
+        let channels = [(from.r, to.r), (from.g, to.g), (from.b, to.b)];
+        for (start, (from, to)) in start.iter_mut().zip(channels.iter()) {
+            *start = asm.address();
+            asm.set_pwm(*from);
+            eased_fade(&mut asm, *from, *to, ticks, easing, segments);
+            asm.end(End::default());
+        }
+        asm.finish(start)
+    })
+}
+
+/// Build a program rotating the hue of an RGB LED around the color wheel once every `period`,
+/// at full saturation and with `brightness` as the value, forever, with E1 driving red, E2 green
+/// and E3 blue
+///
+/// The sweep starts at red. It follows the HSV color wheel piecewise: over each sixth of the
+/// period, one channel ramps linearly while the other two hold. The engines run the same loop, a
+/// third of a period apart, so they stay in step. A ramp takes at least one cycle per PWM step of
+/// `brightness`, so with periods under about 0.75 s at full brightness the ramps run long.
+pub fn hue_rotation(period: Millis, brightness: u8) -> Result<Program, ProgramError> {
+    let sixth = ticks(period) / 6;
+    let mut asm = Assembler::new();
+    let mut start = [0u8; 3];
+    // Starting value and delay into the loop of each channel: red starts a sixth into its high
+    // hold, green rising, and blue a third into its low hold
+    let channels = [(brightness, sixth), (0, 0), (0, 2 * sixth)];
+    for (start, (initial, offset)) in start.iter_mut().zip(channels.iter()) {
+        *start = asm.address();
+        asm.set_pwm(*initial);
+        wait_ticks(&mut asm, *offset);
+        let (first, second) = if *initial == 0 {
+            ((0, brightness), (brightness, 0))
+        } else {
+            ((brightness, 0), (0, brightness))
+        };
+        asm.forever(|asm| {
+            fade(asm, first.0, first.1, sixth);
+            wait_ticks(asm, 2 * sixth);
+            fade(asm, second.0, second.1, sixth);
+            wait_ticks(asm, 2 * sixth);
+        });
+    }
+    asm.finish(start)
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Fade an RGB LED from one color to another over `duration` on the engines, leaving the host
+    /// free (see `crossfade`)
+    ///
+    /// The colors are rendered as `set_color` renders them, in its color space and with the LED's
+    /// color correction, white point, curves and trims, so the fade starts and ends on the colors
+    /// `set_color` would show; in between, the channels ramp linearly. Every engine is mapped to
+    /// one of the LED's D lines and started (see `start_program`), replacing any running program.
+    pub fn start_crossfade<C, T>(
+        &mut self,
+        led: RgbGroup,
+        from: C,
+        to: C,
+        duration: T,
+    ) -> Result<(), Error<E>>
+    where
+        C: Into<Rgb>,
+        T: Into<Millis>,
+    {
+        self.start_crossfade_eased(led, from, to, duration, Easing::Linear)
+    }
+
+    /// Fade an RGB LED from one color to another over `duration` along the easing curve on the
+    /// engines, as `start_crossfade` does (see `crossfade_eased`)
+    pub fn start_crossfade_eased<C, T>(
+        &mut self,
+        led: RgbGroup,
+        from: C,
+        to: C,
+        duration: T,
+        easing: Easing,
+    ) -> Result<(), Error<E>>
+    where
+        C: Into<Rgb>,
+        T: Into<Millis>,
+    {
+        let from = self.rendered_color(led, from.into());
+        let to = self.rendered_color(led, to.into());
+        let program = crossfade_eased(from, to, duration.into(), easing)?;
+        self.map_rgb_engines(led)?;
+        self.start_program(&program)
+    }
+
+    /// Rotate the hue of an RGB LED once every `period` on the engines, forever (see
+    /// `hue_rotation`)
+    ///
+    /// The channels are driven with raw PWM values: the LED's color correction, white point,
+    /// curves and trims are not applied. Every engine is mapped to one of the LED's D lines and
+    /// started (see `start_program`), replacing any running program.
+    pub fn start_hue_rotation<T: Into<Millis>>(
+        &mut self,
+        led: RgbGroup,
+        period: T,
+        brightness: u8,
+    ) -> Result<(), Error<E>> {
+        let program = hue_rotation(period.into(), brightness)?;
+        self.map_rgb_engines(led)?;
+        self.start_program(&program)
+    }
+
+    /// Map E1, E2 and E3 to the red, green and blue D lines of the LED
+    fn map_rgb_engines(&mut self, led: RgbGroup) -> Result<(), Error<E>> {
+        self.set_engine_map(Engine::E1, OutputMask::from(led.red))?;
+        self.set_engine_map(Engine::E2, OutputMask::from(led.green))?;
+        self.set_engine_map(Engine::E3, OutputMask::from(led.blue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The words `build` assembles
+    fn words<F: FnOnce(&mut Assembler)>(build: F) -> [u16; 4] {
+        let mut asm = Assembler::new();
+        build(&mut asm);
+        let mut words = [0; 4];
+        let instructions = asm.instructions().unwrap();
+        words[..instructions.len()].copy_from_slice(instructions);
+        words
+    }
+
+    #[test]
+    fn waits_switch_to_the_prescaler_at_a_prescaled_cycle() {
+        assert_eq!(words(|asm| wait_ticks(asm, CYCLE_TICKS / 2 - 1)), [0; 4]);
+        assert_eq!(words(|asm| wait_ticks(asm, CYCLE_TICKS)), [0x0200, 0, 0, 0]);
+        let longest = PRESCALED_CYCLE_TICKS - 1;
+        assert_eq!(words(|asm| wait_ticks(asm, longest)), [0x3e00, 0, 0, 0]);
+        let shortest = PRESCALED_CYCLE_TICKS;
+        assert_eq!(words(|asm| wait_ticks(asm, shortest)), [0x4200, 0, 0, 0]);
+        let prescaled = MAX_STEP * PRESCALED_CYCLE_TICKS;
+        assert_eq!(words(|asm| wait_ticks(asm, prescaled)), [0x7e00, 0, 0, 0]);
+        let repeated = 2 * MAX_STEP * PRESCALED_CYCLE_TICKS;
+        assert_eq!(words(|asm| wait_ticks(asm, repeated)), [0x7e00, 0xa080, 0, 0]);
+    }
+
+    #[test]
+    fn fades_switch_to_the_prescaler_past_the_longest_step() {
+        let fastest = (MAX_STEP - 1) * CYCLE_TICKS;
+        assert_eq!(words(|asm| fade(asm, 0, 1, fastest)), [0x3c01, 0, 0, 0]);
+        let slowest = MAX_STEP * CYCLE_TICKS;
+        assert_eq!(words(|asm| fade(asm, 0, 1, slowest)), [0x4201, 0, 0, 0]);
+        assert_eq!(words(|asm| fade(asm, 255, 0, 0)), [0x03ff, 0, 0, 0]);
+        assert_eq!(words(|asm| fade(asm, 0, 2, 3 * CYCLE_TICKS)), [0x0201, 0x0401, 0, 0]);
+    }
+}
@@ -0,0 +1,298 @@
+//! Persistence to external flash or EEPROM
+//!
+//! Calibration reports, scenes, on-time accumulators (see `ontime`) and programs can be saved to
+//! non-volatile storage and restored at boot. Each is stored as a self-checking record:
+//!
+//! | Offset | Size | Contents                                       |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 2    | Magic, `LR`                                    |
+//! | 2      | 1    | Kind of record                                 |
+//! | 3      | 1    | Payload length, `n`                            |
+//! | 4      | n    | Payload                                        |
+//! | 4 + n  | 2    | CRC-16/CCITT-FALSE of all preceding bytes, BE  |
+//!
+//! Programs are stored as their blob (see `program`), which carries its own header and CRC.
+//!
+//! Storage is reached through the `Storage` trait, whose methods match those of the
+//! `embedded-storage` `ReadStorage` and `Storage` traits, so implementations forward to them
+//! directly. With the `embedded-storage` feature, `EmbeddedStorage` does so for any
+//! `embedded_storage::Storage`:
+//!
+//! ```ignore
+//! let mut flash = EmbeddedStorage::new(flash);
+//! persist::save(&mut flash, 0, &scene)?;
+//! ```
+//!
+//! Erasing flash before writing, where needed, is left to the implementation.
+
+use calibration::CalibrationReport;
+use crc::crc16;
+#[cfg(feature = "engines")]
+use program::{Program, ProgramError, BLOB_MAX_LEN};
+use scene::Scene;
+
+/// Record magic number
+const MAGIC: [u8; 2] = *b"LR";
+/// Size of the record header, up to the payload
+const HEADER_LEN: usize = 4;
+/// Size of the record CRC
+const CRC_LEN: usize = 2;
+/// Largest record payload
+const MAX_PAYLOAD: usize = 108;
+
+/// Byte-addressed non-volatile storage
+pub trait Storage {
+    /// Error returned by the storage
+    type Error;
+
+    /// Read `bytes.len()` bytes starting at `offset`
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write `bytes` starting at `offset`
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Any `embedded-storage` storage, used as a `Storage`
+#[cfg(feature = "embedded-storage")]
+pub struct EmbeddedStorage<S> {
+    /// The wrapped storage
+    storage: S,
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<S: embedded_storage::Storage> EmbeddedStorage<S> {
+    /// Wrap `storage`
+    pub fn new(storage: S) -> Self {
+        EmbeddedStorage { storage }
+    }
+
+    /// Release the wrapped storage
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<S: embedded_storage::Storage> Storage for EmbeddedStorage<S> {
+    type Error = S::Error;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), S::Error> {
+        embedded_storage::ReadStorage::read(&mut self.storage, offset, bytes)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), S::Error> {
+        embedded_storage::Storage::write(&mut self.storage, offset, bytes)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Error conditions returned when saving or loading records
+pub enum PersistError<S> {
+    /// The storage failed
+    Storage(S),
+    /// No record of the expected kind is stored at the offset
+    NotFound,
+    /// The record's CRC does not match its contents
+    BadCrc,
+}
+
+/// A value that can be stored as a record
+pub trait Persist: Sized {
+    /// Kind of record, distinguishing the types stored
+    const KIND: u8;
+    /// Length of the encoded payload, at most 108 bytes
+    const LEN: usize;
+
+    /// Encode the value into `buf`, which is `LEN` bytes long
+    fn encode(&self, buf: &mut [u8]);
+
+    /// Decode a value from `buf`, which is `LEN` bytes long
+    fn decode(buf: &[u8]) -> Self;
+}
+
+impl Persist for CalibrationReport {
+    const KIND: u8 = 1;
+    const LEN: usize = 30;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.current;
+        buf[1..3].copy_from_slice(&self.vout.to_be_bytes());
+        for (i, v) in self.forward_voltage.iter().enumerate() {
+            buf[3 + 2 * i..5 + 2 * i].copy_from_slice(&v.to_be_bytes());
+        }
+        buf[21..30].copy_from_slice(&self.trim);
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let mut forward_voltage = [0; 9];
+        for (i, v) in forward_voltage.iter_mut().enumerate() {
+            *v = i16::from_be_bytes([buf[3 + 2 * i], buf[4 + 2 * i]]);
+        }
+        let mut trim = [0; 9];
+        trim.copy_from_slice(&buf[21..30]);
+        CalibrationReport {
+            current: buf[0],
+            vout: i16::from_be_bytes([buf[1], buf[2]]),
+            forward_voltage,
+            trim,
+        }
+    }
+}
+
+impl Persist for Scene {
+    const KIND: u8 = 2;
+    const LEN: usize = 12;
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.id;
+        buf[1..10].copy_from_slice(&self.pwm);
+        buf[10..12].copy_from_slice(&self.transition.to_be_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let mut pwm = [0; 9];
+        pwm.copy_from_slice(&buf[1..10]);
+        Scene {
+            id: buf[0],
+            pwm,
+            transition: u16::from_be_bytes([buf[10], buf[11]]),
+        }
+    }
+}
+
+/// Length of the record storing a `T`
+pub fn record_len<T: Persist>() -> usize {
+    HEADER_LEN + T::LEN + CRC_LEN
+}
+
+/// Save the value as a record at `offset`
+pub fn save<S: Storage, T: Persist>(
+    storage: &mut S,
+    offset: u32,
+    value: &T,
+) -> Result<(), PersistError<S::Error>> {
+    let mut buf = [0u8; HEADER_LEN + MAX_PAYLOAD + CRC_LEN];
+    let len = record_len::<T>();
+    buf[..2].copy_from_slice(&MAGIC);
+    buf[2] = T::KIND;
+    buf[3] = T::LEN as u8;
+    value.encode(&mut buf[HEADER_LEN..HEADER_LEN + T::LEN]);
+    let crc = crc16(&buf[..len - CRC_LEN]);
+    buf[len - CRC_LEN..len].copy_from_slice(&crc.to_be_bytes());
+    storage
+        .write(offset, &buf[..len])
+        .map_err(PersistError::Storage)
+}
+
+/// Load the value saved as a record at `offset`, validating its header and CRC
+pub fn load<S: Storage, T: Persist>(
+    storage: &mut S,
+    offset: u32,
+) -> Result<T, PersistError<S::Error>> {
+    let mut buf = [0u8; HEADER_LEN + MAX_PAYLOAD + CRC_LEN];
+    let len = record_len::<T>();
+    storage
+        .read(offset, &mut buf[..len])
+        .map_err(PersistError::Storage)?;
+    from_record(&buf[..len])
+}
+
+/// Decode a value from a record held in memory, validating its header and CRC
+pub fn from_record<T: Persist, S>(record: &[u8]) -> Result<T, PersistError<S>> {
+    let len = record_len::<T>();
+    if record.len() < len
+        || record[..2] != MAGIC
+        || record[2] != T::KIND
+        || usize::from(record[3]) != T::LEN
+    {
+        return Err(PersistError::NotFound);
+    }
+    let crc = u16::from_be_bytes([record[len - 2], record[len - 1]]);
+    if crc16(&record[..len - CRC_LEN]) != crc {
+        return Err(PersistError::BadCrc);
+    }
+    Ok(T::decode(&record[HEADER_LEN..len - CRC_LEN]))
+}
+
+/// Save the program's blob at `offset`
+#[cfg(feature = "engines")]
+pub fn save_program<S: Storage>(
+    storage: &mut S,
+    offset: u32,
+    program: &Program,
+) -> Result<(), PersistError<S::Error>> {
+    let mut buf = [0u8; BLOB_MAX_LEN];
+    // Every program fits in a buffer of the largest blob length
+    let _ = program.to_blob(&mut buf);
+    let len = program.blob_len();
+    storage
+        .write(offset, &buf[..len])
+        .map_err(PersistError::Storage)
+}
+
+/// Load a program saved at `offset`, validating its blob header and CRC
+///
+/// Reads `program::BLOB_MAX_LEN` bytes, whatever the length of the stored program.
+#[cfg(feature = "engines")]
+pub fn load_program<S: Storage>(
+    storage: &mut S,
+    offset: u32,
+) -> Result<Program, PersistError<S::Error>> {
+    let mut buf = [0u8; BLOB_MAX_LEN];
+    storage.read(offset, &mut buf).map_err(PersistError::Storage)?;
+    Program::from_blob(&buf).map_err(|e| match e {
+        ProgramError::BadCrc => PersistError::BadCrc,
+        _ => PersistError::NotFound,
+    })
+}
+
+#[cfg(all(test, feature = "embedded-storage"))]
+mod tests {
+    use super::*;
+
+    /// A RAM-backed `embedded-storage` storage
+    struct Ram([u8; 256]);
+
+    impl embedded_storage::ReadStorage for Ram {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            let stored = self.0.get(offset..offset + bytes.len()).ok_or(())?;
+            bytes.copy_from_slice(stored);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl embedded_storage::Storage for Ram {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            let stored = self.0.get_mut(offset..offset + bytes.len()).ok_or(())?;
+            stored.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_round_trip_through_embedded_storage() {
+        let scene = Scene {
+            id: 3,
+            pwm: [1, 2, 3, 4, 5, 6, 7, 8, 9],
+            transition: 500,
+        };
+        let mut storage = EmbeddedStorage::new(Ram([0xff; 256]));
+        save(&mut storage, 16, &scene).unwrap();
+        assert_eq!(load::<_, Scene>(&mut storage, 16).unwrap(), scene);
+        assert_eq!(load::<_, Scene>(&mut storage, 0), Err(PersistError::NotFound));
+        assert_eq!(load::<_, Scene>(&mut storage, 250), Err(PersistError::Storage(())));
+
+        let mut ram = storage.into_inner();
+        ram.0[16 + HEADER_LEN] ^= 1;
+        let mut storage = EmbeddedStorage::new(ram);
+        assert_eq!(load::<_, Scene>(&mut storage, 16), Err(PersistError::BadCrc));
+    }
+}
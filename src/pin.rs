@@ -0,0 +1,125 @@
+//! D lines as output pins
+//!
+//! `LedPin` drives a single D line fully on or off through the embedded-hal `OutputPin` trait, so
+//! that the device can stand in for a GPIO expander wherever code only knows how to drive an
+//! indicator LED from a pin. The pins of a device share it through a `RefCell`:
+//!
+//! ```ignore
+//! let lp = RefCell::new(lp);
+//! let [power, fault, ..] = LedPin::split(&lp);
+//! let indicator = Indicator::new(fault);
+//! ```
+//!
+//! With the `switch` feature, pins also implement the embedded-hal `StatefulOutputPin` and
+//! `ToggleableOutputPin`, reading the D line's PWM value back, so that LED and switch abstractions
+//! built on them, such as `switch-hal`'s `OutputSwitch`, `StatefulOutputSwitch` and
+//! `ToggleableOutputSwitch`, work with D lines as they are:
+//!
+//! ```ignore
+//! let mut status = LedPin::new(&lp, D::D7).into_active_high_switch();
+//! status.toggle()?;
+//! ```
+//!
+//! With the `embedded-hal-1` feature, pins also implement the embedded-hal 1.0 `OutputPin` and
+//! `StatefulOutputPin`.
+
+use core::cell::RefCell;
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+use hal::digital::v2;
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use {Error, Lp55231, D};
+
+/// A D line of a driver shared through a `RefCell`, usable as an output pin
+///
+/// Setting the pin high sets the D line to full brightness with `Lp55231::set_pwm`, after its
+/// curve, trim and the global brightness, and setting it low turns it off. Each call borrows the
+/// driver mutably, and panics if it is already borrowed.
+pub struct LedPin<'a, I: 'a, P: 'a> {
+    /// The shared driver
+    lp: &'a RefCell<Lp55231<I, P>>,
+    /// The D line driven
+    d: D,
+}
+
+impl<'a, I, P> LedPin<'a, I, P> {
+    /// Drive the D line of the shared driver
+    pub fn new(lp: &'a RefCell<Lp55231<I, P>>, d: D) -> Self {
+        LedPin { lp, d }
+    }
+
+    /// A pin for each D line of the shared driver, in order
+    pub fn split(lp: &'a RefCell<Lp55231<I, P>>) -> [Self; 9] {
+        [D::D1, D::D2, D::D3, D::D4, D::D5, D::D6, D::D7, D::D8, D::D9].map(|d| LedPin::new(lp, d))
+    }
+
+    /// The D line driven
+    pub fn line(&self) -> D {
+        self.d
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Turn the D line fully on or off
+    pub(crate) fn set(&mut self, on: bool) -> Result<(), Error<E>> {
+        self.lp.borrow_mut().set_pwm(self.d, if on { 255 } else { 0 })
+    }
+
+    /// Whether the D line is lit, from its PWM value
+    pub fn is_lit(&self) -> Result<bool, Error<E>> {
+        Ok(self.lp.borrow_mut().pwm(self.d)? != 0)
+    }
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> v2::OutputPin for LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    type Error = Error<E>;
+
+    fn set_low(&mut self) -> Result<(), Error<E>> {
+        self.set(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Error<E>> {
+        self.set(true)
+    }
+}
+
+#[cfg(feature = "switch")]
+#[allow(deprecated)]
+impl<'a, E, I, P> v2::StatefulOutputPin for LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    fn is_set_high(&self) -> Result<bool, Error<E>> {
+        self.is_lit()
+    }
+
+    fn is_set_low(&self) -> Result<bool, Error<E>> {
+        self.is_lit().map(|lit| !lit)
+    }
+}
+
+#[cfg(feature = "switch")]
+#[allow(deprecated)]
+impl<'a, E, I, P> v2::toggleable::Default for LedPin<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+}
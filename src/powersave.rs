@@ -0,0 +1,262 @@
+//! Automatic power-save orchestration
+//!
+//! With a policy set by `Lp55231::set_power_save`, the driver puts the device into power-save
+//! (MISC `POWERSAVE_EN`, with every engine stopped) once every D line has been dark for the
+//! policy's idle time, and takes it out again on the next write that lights a D line or sets an
+//! engine going. The driver has no clock of its own: call `Lp55231::power_save_tick` periodically
+//! with the current time in ms, e.g. from the same timer that feeds a `stream::Throttle`.
+//!
+//! A D line counts as dark when its direct PWM value is zero and no engine that is not disabled is
+//! mapped to it.
+//!
+//! PWM power-save (MISC `PWM_PS_EN`) saves power during ramps, but can introduce visible flicker at
+//! low duty cycles, which matters for video capture and flicker-sensitive viewers. With a
+//! threshold set by `Lp55231::set_flicker_threshold`, the driver clears `PWM_PS_EN` while any D
+//! line is lit below the threshold, and sets it again once none is.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Error, Lp55231, Millis, OutputMask, D};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// State of the power-save policy
+pub(crate) struct PowerSave {
+    /// Time every D line must stay dark before the device is put into power-save, in ms
+    idle: u16,
+    /// D lines whose direct PWM value is non-zero, D1 in bit 0, once known
+    lit: Option<u16>,
+    /// Time at which every D line was first seen dark
+    dark_since: Option<u32>,
+    /// The device is in power-save
+    asleep: bool,
+}
+
+impl PowerSave {
+    /// Create a policy with the given idle time
+    pub(crate) fn new(idle: Millis) -> Self {
+        PowerSave {
+            idle: idle.0,
+            lit: None,
+            dark_since: None,
+            asleep: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// State of the flicker-sensitive mode
+pub(crate) struct FlickerGuard {
+    /// PWM value below which a lit D line disables PWM power-save
+    threshold: u8,
+    /// Direct PWM value of D1 through D9
+    pwm: [u8; 9],
+    /// PWM_PS_EN was cleared by the guard
+    suppressed: bool,
+    /// PWM_PS_EN was set before the guard cleared it
+    restore: bool,
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Set the power-save policy: enter power-save once every D line has been dark for `idle`,
+    /// or `None` to leave power-save to the application
+    ///
+    /// Clearing the policy while the device is in power-save does not wake it; see `wake`.
+    pub fn set_power_save<T: Into<Millis>>(&mut self, idle: Option<T>) {
+        self.power_save = idle.map(|idle| PowerSave::new(idle.into()));
+    }
+
+    /// Whether the device was put into power-save by the policy
+    pub fn is_power_saving(&self) -> bool {
+        self.power_save.is_some_and(|ps| ps.asleep)
+    }
+
+    /// Advance the power-save policy to `now`, in ms, putting the device into power-save if every
+    /// D line has been dark for the idle time. Returns whether the device is in power-save.
+    ///
+    /// The first call reads every D line's PWM value; later calls only touch the bus when the
+    /// idle time runs out.
+    pub fn power_save_tick(&mut self, now: u32) -> Result<bool, Error<E>> {
+        let mut ps = match self.power_save {
+            Some(ps) => ps,
+            None => return Ok(false),
+        };
+        if ps.asleep {
+            return Ok(true);
+        }
+        let lit = match ps.lit {
+            Some(lit) => lit,
+            None => {
+                let mut lit = 0;
+                for d in D::iter() {
+                    if self.pwm(d)? != 0 {
+                        lit |= 1 << u8::from(d);
+                    }
+                }
+                lit
+            }
+        };
+        ps.lit = Some(lit);
+        if lit != 0 {
+            ps.dark_since = None;
+        } else {
+            let since = *ps.dark_since.get_or_insert(now);
+            if now.wrapping_sub(since) >= u32::from(ps.idle) && !self.engines_mapped()? {
+                self.enter_power_save()?;
+                ps.asleep = true;
+            }
+        }
+        self.power_save = Some(ps);
+        Ok(ps.asleep)
+    }
+
+    /// Take the device out of power-save, if the policy put it there
+    ///
+    /// Happens on its own on the next write that lights a D line or sets an engine going.
+    pub fn wake(&mut self) -> Result<(), Error<E>> {
+        match self.power_save.as_mut() {
+            Some(ps) if ps.asleep => {
+                ps.asleep = false;
+                ps.dark_since = None;
+            }
+            _ => return Ok(()),
+        }
+        self.modify_register(|misc: reg::Misc| misc - reg::Misc::POWERSAVE_EN)
+    }
+
+    /// Follow a register write through the power-save policy, waking the device first if the
+    /// write lights a D line or sets an engine going
+    pub(crate) fn power_save_observe(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        let ps = match self.power_save.as_mut() {
+            Some(ps) => ps,
+            None => return Ok(()),
+        };
+        let (register, value) = match bytes {
+            [register, value, ..] => (*register, *value),
+            _ => return Ok(()),
+        };
+        let wakes = if (reg::D_PWM_BASE..reg::D_PWM_BASE + 9).contains(&register) {
+            let bit = 1 << (register - reg::D_PWM_BASE);
+            if let Some(lit) = ps.lit.as_mut() {
+                if value == 0 {
+                    *lit &= !bit;
+                } else {
+                    *lit |= bit;
+                }
+            }
+            value != 0
+        } else {
+            register == reg::CNTRL2 && value != 0
+        };
+        if wakes {
+            self.wake()?;
+        }
+        Ok(())
+    }
+
+    /// Keep PWM power-save off while any D line is lit with a direct PWM value below `threshold`,
+    /// or `None` to leave PWM power-save to the application
+    ///
+    /// Every D line's PWM value is read when the threshold is set, and later followed through the
+    /// driver's writes. `PWM_PS_EN` is only set again if it was set when the guard cleared it; an
+    /// application changing it in the meantime should set the threshold again. PWM values set by
+    /// running engines are not seen by the driver, and do not count.
+    pub fn set_flicker_threshold(&mut self, threshold: Option<u8>) -> Result<(), Error<E>> {
+        if let Some(guard) = self.flicker_guard.take() {
+            if guard.suppressed && guard.restore {
+                self.modify_register(|misc: reg::Misc| misc | reg::Misc::PWM_PS_EN)?;
+            }
+        }
+        let threshold = match threshold {
+            Some(threshold) => threshold,
+            None => return Ok(()),
+        };
+        let mut pwm = [0u8; 9];
+        for (d, p) in D::iter().zip(pwm.iter_mut()) {
+            *p = self.pwm(d)?;
+        }
+        self.flicker_update(FlickerGuard {
+            threshold,
+            pwm,
+            suppressed: false,
+            restore: false,
+        })
+    }
+
+    /// The PWM value below which a lit D line keeps PWM power-save off, if any
+    pub fn flicker_threshold(&self) -> Option<u8> {
+        self.flicker_guard.map(|guard| guard.threshold)
+    }
+
+    /// Follow a register write through the flicker-sensitive mode
+    pub(crate) fn flicker_observe(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        let mut guard = match self.flicker_guard {
+            Some(guard) => guard,
+            None => return Ok(()),
+        };
+        match bytes {
+            [register, value] if (reg::D_PWM_BASE..reg::D_PWM_BASE + 9).contains(register) => {
+                guard.pwm[usize::from(register - reg::D_PWM_BASE)] = *value;
+                self.flicker_update(guard)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Clear or restore PWM_PS_EN as the PWM values require, and keep the guard's new state
+    fn flicker_update(&mut self, mut guard: FlickerGuard) -> Result<(), Error<E>> {
+        let dim = guard.pwm.iter().any(|p| (1..guard.threshold).contains(p));
+        if dim && !guard.suppressed {
+            let misc = self.read_register::<reg::Misc>()?;
+            guard.restore = misc.contains(reg::Misc::PWM_PS_EN);
+            if guard.restore {
+                self.write_register(misc - reg::Misc::PWM_PS_EN)?;
+            }
+            guard.suppressed = true;
+        } else if !dim && guard.suppressed {
+            if guard.restore {
+                self.modify_register(|misc: reg::Misc| misc | reg::Misc::PWM_PS_EN)?;
+            }
+            guard.suppressed = false;
+        }
+        self.flicker_guard = Some(guard);
+        Ok(())
+    }
+
+    /// Whether any engine that is not disabled is mapped to a D line
+    fn engines_mapped(&mut self) -> Result<bool, Error<E>> {
+        let cntrl2 = self.read_register::<reg::Cntrl2>()?;
+        // Engines are checked whether or not the `engines` feature drives them
+        let engines = [
+            (reg::ENG1_MAP_MSB, reg::Cntrl2::ENG1_MODE),
+            (reg::ENG1_MAP_MSB + 2, reg::Cntrl2::ENG2_MODE),
+            (reg::ENG1_MAP_MSB + 4, reg::Cntrl2::ENG3_MODE),
+        ];
+        for (map, mode) in engines.iter() {
+            if cntrl2.intersects(*mode) && self.read_mask(*map)? != OutputMask::none() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Stop every engine and set POWERSAVE_EN
+    fn enter_power_save(&mut self) -> Result<(), Error<E>> {
+        // CNTRL1 with every engine in hold, followed by CNTRL2 with every engine disabled
+        self.send(&[
+            reg::CNTRL1,
+            reg::Cntrl1::CHIP_EN.bits(),
+            reg::Cntrl2::empty().bits(),
+        ])?;
+        self.modify_register(|misc: reg::Misc| misc | reg::Misc::POWERSAVE_EN)
+    }
+}
@@ -0,0 +1,911 @@
+//! Engine programs
+//!
+//! A `Program` holds the contents of the 96-instruction program memory shared by the three
+//! engines, along with the address at which each engine's program starts.
+//!
+//! Programs can be stored and exchanged as a compact, versioned binary blob:
+//!
+//! | Offset | Size      | Contents                                       |
+//! |--------|-----------|------------------------------------------------|
+//! | 0      | 4         | Magic, `LP55`                                  |
+//! | 4      | 1         | Format version, currently 1                    |
+//! | 5      | 3         | Engine 1, 2 and 3 start addresses              |
+//! | 8      | 1         | Instruction count, `n`                         |
+//! | 9      | 2n        | Instruction words, big-endian                  |
+//! | 9 + 2n | 2         | CRC-16/CCITT-FALSE of all preceding bytes, BE  |
+//!
+//! `check_blob` validates a blob in place, and `Lp55231::load_program_blob` only loads a blob that
+//! passes. See `crc` for computing the checksum of assembled programs.
+//!
+//! Precompiled patterns can ship inside the firmware image as blobs built by `lp-asm`: a
+//! `ProgramImage` validates a blob embedded with `include_bytes!` once, then reads it in place,
+//! and `Lp55231::load_program_image` streams it into the program memory a page at a time, without
+//! copying the whole program to RAM:
+//!
+//! ```ignore
+//! static PULSE: &[u8] = include_bytes!("pulse.lp55");
+//!
+//! let image = ProgramImage::new(PULSE)?;
+//! lp.load_program_image(&image)?;
+//! ```
+
+use core::fmt::{self, Debug};
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use asm;
+use crc::{crc16, Crc16};
+use nb;
+use interrupt::EngineSet;
+use registers as reg;
+use {Engine, Error, Exec, Lp55231, Mode};
+
+/// Number of instructions in the program memory
+pub const PROGRAM_MEMORY_SIZE: usize = 96;
+
+/// Number of instructions in a program memory page
+pub const PAGE_SIZE: usize = 16;
+
+/// Number of program memory pages
+pub const PAGES: u8 = (PROGRAM_MEMORY_SIZE / PAGE_SIZE) as u8;
+
+/// Number of STATUS_IRQ reads made waiting for the engines to become idle once an engine enters
+/// load mode, each taking over 100µs at 400 kHz, against the 1ms the device may take
+const LOAD_POLLS: u8 = 20;
+
+/// Blob magic number
+const MAGIC: [u8; 4] = *b"LP55";
+/// Blob format version
+const VERSION: u8 = 1;
+/// Size of the blob header, up to the instruction words
+const HEADER_LEN: usize = 9;
+/// Size of the blob CRC
+const CRC_LEN: usize = 2;
+
+/// Largest possible blob, holding a full program memory
+pub const BLOB_MAX_LEN: usize = HEADER_LEN + 2 * PROGRAM_MEMORY_SIZE + CRC_LEN;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Error conditions returned when building, encoding or decoding programs
+pub enum ProgramError {
+    /// The program does not fit in the program memory
+    TooLarge,
+    /// An engine's start address lies outside of the program memory
+    InvalidStart,
+    /// The output buffer is too small to hold the blob
+    BufferTooSmall,
+    /// The blob is shorter than its header claims
+    Truncated,
+    /// The blob does not start with the expected magic number
+    BadMagic,
+    /// The blob's format version is not supported
+    UnsupportedVersion(u8),
+    /// The blob's CRC does not match its contents
+    BadCrc,
+    /// A text program contains something other than hexadecimal instruction words
+    InvalidText,
+    /// An instruction operand is outside of the range the instruction can encode
+    InvalidOperand,
+    /// Program memory is already claimed by another engine's program
+    Overlap,
+    /// More labels were added than an assembler can record
+    TooManyLabels,
+    /// The program runs for too many instructions to be timed
+    TooLong,
+    /// More holes were marked than an assembler can record
+    TooManyHoles,
+    /// No template hole has the name
+    UnknownHole,
+    /// No label has the name
+    UnknownLabel,
+}
+
+impl ProgramError {
+    /// A fixed description of the error, without its details, for logging over links too small
+    /// for `core::fmt`
+    pub fn message(&self) -> &'static str {
+        match self {
+            ProgramError::TooLarge => "program does not fit in program memory",
+            ProgramError::InvalidStart => "start address outside of program memory",
+            ProgramError::BufferTooSmall => "buffer too small for blob",
+            ProgramError::Truncated => "blob truncated",
+            ProgramError::BadMagic => "not a program blob",
+            ProgramError::UnsupportedVersion(_) => "unsupported blob version",
+            ProgramError::BadCrc => "blob CRC mismatch",
+            ProgramError::InvalidText => "invalid program text",
+            ProgramError::InvalidOperand => "instruction operand out of range",
+            ProgramError::Overlap => "program memory already in use",
+            ProgramError::TooManyLabels => "too many labels",
+            ProgramError::TooLong => "program runs too long to time",
+            ProgramError::TooManyHoles => "too many holes",
+            ProgramError::UnknownHole => "no such hole",
+            ProgramError::UnknownLabel => "no such label",
+        }
+    }
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::UnsupportedVersion(v) => write!(f, "unsupported blob version {}", v),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl core::error::Error for ProgramError {}
+
+#[derive(Copy, Clone, PartialEq)]
+/// The contents of the program memory and the start address of each engine
+pub struct Program {
+    /// Start address of each engine's program
+    start: [u8; 3],
+    /// Number of valid instructions
+    len: u8,
+    /// Instruction words
+    instructions: [u16; PROGRAM_MEMORY_SIZE],
+}
+
+impl Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Program")
+            .field("start", &self.start)
+            .field("instructions", &self.instructions())
+            .finish()
+    }
+}
+
+impl Program {
+    /// Create a program from its instruction words, loaded from address 0, and the address of each
+    /// engine's first instruction
+    pub fn new(start: [u8; 3], instructions: &[u16]) -> Result<Self, ProgramError> {
+        if instructions.len() > PROGRAM_MEMORY_SIZE {
+            return Err(ProgramError::TooLarge);
+        }
+        if start.iter().any(|s| usize::from(*s) >= PROGRAM_MEMORY_SIZE) {
+            return Err(ProgramError::InvalidStart);
+        }
+        let mut program = Program {
+            start,
+            len: instructions.len() as u8,
+            instructions: [0; PROGRAM_MEMORY_SIZE],
+        };
+        program.instructions[..instructions.len()].copy_from_slice(instructions);
+        Ok(program)
+    }
+
+    /// Parse a program from the ASCII hex format produced by TI's LP55231 assembler, and accepted
+    /// by the Linux `lp55xx` firmware loader
+    ///
+    /// The text is a sequence of hexadecimal digits, four per instruction word, most significant
+    /// first. Whitespace is ignored anywhere, and a `;` starts a comment running to the end of the
+    /// line. Assembly source is assembled with `source::assemble` instead.
+    pub fn from_hex(start: [u8; 3], text: &str) -> Result<Self, ProgramError> {
+        let mut instructions = [0u16; PROGRAM_MEMORY_SIZE];
+        let mut digits = 0;
+        for line in text.lines() {
+            let code = line.split(';').next().unwrap_or("");
+            for c in code.chars().filter(|c| !c.is_whitespace()) {
+                let nibble = c.to_digit(16).ok_or(ProgramError::InvalidText)? as u16;
+                let word = instructions.get_mut(digits / 4).ok_or(ProgramError::TooLarge)?;
+                *word = *word << 4 | nibble;
+                digits += 1;
+            }
+        }
+        if digits % 4 != 0 {
+            return Err(ProgramError::InvalidText);
+        }
+        Program::new(start, &instructions[..digits / 4])
+    }
+
+    /// The instruction words
+    pub fn instructions(&self) -> &[u16] {
+        &self.instructions[..usize::from(self.len)]
+    }
+
+    /// CRC-16/CCITT-FALSE of the start addresses and instruction words, identifying the program
+    /// independently of how it was stored
+    pub fn crc(&self) -> u16 {
+        Crc16::new()
+            .update(&self.start)
+            .update_words(self.instructions())
+            .finish()
+    }
+
+    /// The address of the engine's first instruction
+    pub fn start(&self, e: Engine) -> u8 {
+        self.start[usize::from(u8::from(e))]
+    }
+
+    /// Which engine's program occupies each instruction of the program memory
+    ///
+    /// Each engine's program is taken to run from its start address up to the next engine's, or
+    /// to the end of the instructions. Engines sharing a start address share a program, which is
+    /// attributed to the lowest numbered of them.
+    pub fn memory_map(&self) -> MemoryMap {
+        memory_map(self.start, self.len)
+    }
+
+    /// This program, with the `end` instructions of the engines outside of `allowed` no longer
+    /// raising interrupts (see `interrupt`)
+    pub fn with_interrupt_mask(&self, allowed: EngineSet) -> Program {
+        let map = self.memory_map();
+        let mut program = *self;
+        for (addr, word) in program.instructions[..usize::from(self.len)]
+            .iter_mut()
+            .enumerate()
+        {
+            if map.owner(addr as u8).is_some_and(|e| !allowed.contains(e)) {
+                asm::silence_interrupts(core::slice::from_mut(word));
+            }
+        }
+        program
+    }
+
+    /// Length of the blob encoding of this program
+    pub fn blob_len(&self) -> usize {
+        HEADER_LEN + 2 * usize::from(self.len) + CRC_LEN
+    }
+
+    /// Encode the program as a blob into `buf`, returning the number of bytes written
+    pub fn to_blob(&self, buf: &mut [u8]) -> Result<usize, ProgramError> {
+        let len = self.blob_len();
+        if buf.len() < len {
+            return Err(ProgramError::BufferTooSmall);
+        }
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4] = VERSION;
+        buf[5..8].copy_from_slice(&self.start);
+        buf[8] = self.len;
+        for (i, word) in self.instructions().iter().enumerate() {
+            buf[HEADER_LEN + 2 * i] = (word >> 8) as u8;
+            buf[HEADER_LEN + 2 * i + 1] = *word as u8;
+        }
+        let crc = crc16(&buf[..len - CRC_LEN]);
+        buf[len - 2] = (crc >> 8) as u8;
+        buf[len - 1] = crc as u8;
+        Ok(len)
+    }
+
+    /// Decode a program from a blob, validating its header and CRC
+    pub fn from_blob(blob: &[u8]) -> Result<Self, ProgramError> {
+        let len = check_blob(blob)?;
+        let count = (len - HEADER_LEN - CRC_LEN) / 2;
+
+        let mut instructions = [0u16; PROGRAM_MEMORY_SIZE];
+        for (i, word) in instructions.iter_mut().take(count).enumerate() {
+            let at = HEADER_LEN + 2 * i;
+            *word = u16::from(blob[at]) << 8 | u16::from(blob[at + 1]);
+        }
+        Program::new([blob[5], blob[6], blob[7]], &instructions[..count])
+    }
+}
+
+/// The three engines, in order
+const ENGINES: [Engine; 3] = [Engine::E1, Engine::E2, Engine::E3];
+
+/// Which engine's program occupies each of the first `len` instructions, given the engines' start
+/// addresses (see `Program::memory_map`)
+fn memory_map(start: [u8; 3], len: u8) -> MemoryMap {
+    let mut map = MemoryMap::new();
+    for e in &ENGINES {
+        let base = start[usize::from(u8::from(*e))];
+        if start[..usize::from(u8::from(*e))].contains(&base) {
+            continue;
+        }
+        let end = start
+            .iter()
+            .filter(|other| **other > base)
+            .min()
+            .map_or(len, |next| (*next).min(len));
+        let _ = map.claim(*e, base, usize::from(end.saturating_sub(base)));
+    }
+    map
+}
+
+/// Validate a blob's header and CRC without decoding it, returning the blob's length
+///
+/// Bytes past the blob's length are ignored, so a blob can be checked in place in a larger buffer
+/// read from flash or a network.
+pub fn check_blob(blob: &[u8]) -> Result<usize, ProgramError> {
+    if blob.len() < HEADER_LEN + CRC_LEN {
+        return Err(ProgramError::Truncated);
+    }
+    if blob[..4] != MAGIC {
+        return Err(ProgramError::BadMagic);
+    }
+    if blob[4] != VERSION {
+        return Err(ProgramError::UnsupportedVersion(blob[4]));
+    }
+    let count = usize::from(blob[8]);
+    if count > PROGRAM_MEMORY_SIZE {
+        return Err(ProgramError::TooLarge);
+    }
+    let len = HEADER_LEN + 2 * count + CRC_LEN;
+    if blob.len() < len {
+        return Err(ProgramError::Truncated);
+    }
+    let crc = u16::from(blob[len - 2]) << 8 | u16::from(blob[len - 1]);
+    if crc16(&blob[..len - CRC_LEN]) != crc {
+        return Err(ProgramError::BadCrc);
+    }
+    Ok(len)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// A validated program blob, read in place rather than decoded, so that a program embedded in
+/// flash with `include_bytes!` is never copied to RAM as a whole
+pub struct ProgramImage<'a> {
+    /// The blob, trimmed to its length
+    blob: &'a [u8],
+}
+
+impl<'a> ProgramImage<'a> {
+    /// Validate a blob's header and CRC (see `check_blob`), and its engines' start addresses
+    ///
+    /// Bytes past the blob's length are ignored, so an image can sit at the start of a larger
+    /// flash region.
+    pub fn new(blob: &'a [u8]) -> Result<Self, ProgramError> {
+        let len = check_blob(blob)?;
+        if blob[5..8].iter().any(|s| usize::from(*s) >= PROGRAM_MEMORY_SIZE) {
+            return Err(ProgramError::InvalidStart);
+        }
+        Ok(ProgramImage { blob: &blob[..len] })
+    }
+
+    /// The address of the engine's first instruction
+    pub fn start(&self, e: Engine) -> u8 {
+        self.blob[5 + usize::from(u8::from(e))]
+    }
+
+    /// Number of instructions
+    pub fn len(&self) -> usize {
+        usize::from(self.blob[8])
+    }
+
+    /// Whether the image holds no instructions
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The instruction words, decoded as they are read
+    pub fn instructions(&self) -> impl Iterator<Item = u16> + 'a {
+        self.blob[HEADER_LEN..self.blob.len() - CRC_LEN]
+            .chunks(2)
+            .map(|pair| u16::from(pair[0]) << 8 | u16::from(pair[1]))
+    }
+
+    /// The blob, up to and including its CRC
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.blob
+    }
+
+    /// CRC-16/CCITT-FALSE of the start addresses and instruction words, the same as the decoded
+    /// program's `Program::crc`
+    pub fn crc(&self) -> u16 {
+        Crc16::new()
+            .update(&self.blob[5..8])
+            .update(&self.blob[HEADER_LEN..self.blob.len() - CRC_LEN])
+            .finish()
+    }
+
+    /// Which engine's program occupies each instruction of the program memory (see
+    /// `Program::memory_map`)
+    pub fn memory_map(&self) -> MemoryMap {
+        memory_map([self.blob[5], self.blob[6], self.blob[7]], self.blob[8])
+    }
+
+    /// Decode the image into a `Program`
+    pub fn to_program(&self) -> Program {
+        let mut instructions = [0u16; PROGRAM_MEMORY_SIZE];
+        for (word, decoded) in instructions.iter_mut().zip(self.instructions()) {
+            *word = decoded;
+        }
+        Program {
+            start: [self.blob[5], self.blob[6], self.blob[7]],
+            len: self.blob[8],
+            instructions,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Allocation of the program memory to the engines' programs
+///
+/// A map records which engine's program occupies each instruction of the program memory, so that
+/// free space can be found for a new program (for example, to `swap_engine_program` into).
+pub struct MemoryMap {
+    /// The engine whose program occupies each instruction, if any
+    owners: [Option<Engine>; PROGRAM_MEMORY_SIZE],
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryMap {
+    /// Create a map of an empty program memory
+    pub fn new() -> Self {
+        MemoryMap {
+            owners: [None; PROGRAM_MEMORY_SIZE],
+        }
+    }
+
+    /// Mark `len` instructions from address `base` as occupied by the engine's program
+    ///
+    /// Fails without changing the map if the instructions lie outside of the program memory, or
+    /// if any is occupied by another engine's program.
+    pub fn claim(&mut self, e: Engine, base: u8, len: usize) -> Result<(), ProgramError> {
+        let owners = self
+            .owners
+            .get_mut(usize::from(base)..usize::from(base) + len)
+            .ok_or(ProgramError::TooLarge)?;
+        if owners.iter().any(|o| o.is_some() && *o != Some(e)) {
+            return Err(ProgramError::Overlap);
+        }
+        for o in owners {
+            *o = Some(e);
+        }
+        Ok(())
+    }
+
+    /// Mark every instruction occupied by the engine's program as free
+    pub fn release(&mut self, e: Engine) {
+        for o in self.owners.iter_mut().filter(|o| **o == Some(e)) {
+            *o = None;
+        }
+    }
+
+    /// The engine whose program occupies the instruction at `addr`, if any
+    pub fn owner(&self, addr: u8) -> Option<Engine> {
+        self.owners.get(usize::from(addr)).cloned().flatten()
+    }
+
+    /// Number of instructions occupied by the engine's program
+    pub fn used(&self, e: Engine) -> usize {
+        self.owners.iter().filter(|o| **o == Some(e)).count()
+    }
+
+    /// Number of free instructions
+    pub fn free(&self) -> usize {
+        self.owners.iter().filter(|o| o.is_none()).count()
+    }
+
+    /// The address of the first run of `len` free instructions, if there is one
+    pub fn find_free(&self, len: usize) -> Option<u8> {
+        let mut run = 0;
+        for (addr, owner) in self.owners.iter().enumerate() {
+            run = if owner.is_none() { run + 1 } else { 0 };
+            if run >= len {
+                return Some((addr + 1 - len) as u8);
+            }
+        }
+        if len == 0 {
+            Some(0)
+        } else {
+            None
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Write up to 16 instructions to a program memory page, starting at its first address
+    ///
+    /// The page is written in a single auto-increment burst. At least one engine must be in load
+    /// mode (see `set_mode`) and the engines must be idle (see `wait_engine_idle`).
+    pub fn write_program_page(&mut self, page: u8, instructions: &[u16]) -> Result<(), Error<E>> {
+        if page >= PAGES || instructions.len() > PAGE_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        self.write_page_at(page, 0, instructions)
+    }
+
+    /// Write instructions to the program memory, starting at address `addr`
+    ///
+    /// The instructions may span several pages; each page's share is written in a single burst.
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn write_program(&mut self, addr: u8, instructions: &[u16]) -> Result<(), Error<E>> {
+        if usize::from(addr) + instructions.len() > PROGRAM_MEMORY_SIZE {
+            return Err(Error::ProgramTooLarge);
+        }
+        let mut addr = usize::from(addr);
+        let mut rest = instructions;
+        while !rest.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let (chunk, tail) = rest.split_at(rest.len().min(PAGE_SIZE - offset));
+            self.write_page_at((addr / PAGE_SIZE) as u8, offset as u8, chunk)?;
+            addr += chunk.len();
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// Zero `len` instructions of the program memory, starting at address `addr`
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn clear_program_range(&mut self, addr: u8, len: usize) -> Result<(), Error<E>> {
+        let zeros = [0u16; PROGRAM_MEMORY_SIZE];
+        let zeros = zeros.get(..len).ok_or(Error::ProgramTooLarge)?;
+        self.write_program(addr, zeros)
+    }
+
+    /// Zero a program memory page
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn clear_program_page(&mut self, page: u8) -> Result<(), Error<E>> {
+        if page >= PAGES {
+            return Err(Error::InvalidArgument);
+        }
+        self.clear_program_range(page * PAGE_SIZE as u8, PAGE_SIZE)
+    }
+
+    /// Zero the whole program memory, so that stale instructions can never execute after a
+    /// partial load
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn clear_program_memory(&mut self) -> Result<(), Error<E>> {
+        self.clear_program_range(0, PROGRAM_MEMORY_SIZE)
+    }
+
+    /// Load a program into the program memory and set each engine's start address
+    ///
+    /// The interrupts of engines outside of the interrupt mask are silenced (see `interrupt`). At
+    /// least one engine must be in load mode and the engines must be idle.
+    pub fn load_program(&mut self, program: &Program) -> Result<(), Error<E>> {
+        let program = program.with_interrupt_mask(self.interrupt_mask);
+        self.write_program(0, program.instructions())?;
+        self.send(&[
+            reg::PROG1_START,
+            program.start(Engine::E1),
+            program.start(Engine::E2),
+            program.start(Engine::E3),
+        ])
+    }
+
+    /// Read instructions back from the program memory, starting at address `addr`, into `buf`
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn read_program(&mut self, addr: u8, buf: &mut [u16]) -> Result<(), Error<E>> {
+        if usize::from(addr) + buf.len() > PROGRAM_MEMORY_SIZE {
+            return Err(Error::ProgramTooLarge);
+        }
+        let mut addr = usize::from(addr);
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let offset = addr % PAGE_SIZE;
+            let n = rest.len().min(PAGE_SIZE - offset);
+            let (chunk, tail) = rest.split_at_mut(n);
+            self.send(&[reg::PROG_PAGE_SEL, (addr / PAGE_SIZE) as u8])?;
+            let mut bytes = [0u8; 2 * PAGE_SIZE];
+            self.read_into(reg::PROG_MEM_BASE + 2 * offset as u8, &mut bytes[..2 * n])?;
+            for (word, pair) in chunk.iter_mut().zip(bytes.chunks(2)) {
+                *word = u16::from(pair[0]) << 8 | u16::from(pair[1]);
+            }
+            addr += n;
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// Check that the program memory and each engine's start address hold the program, as
+    /// `load_program` loads it, returning `Error::VerifyFailed` if not
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn verify_program(&mut self, program: &Program) -> Result<(), Error<E>> {
+        let program = program.with_interrupt_mask(self.interrupt_mask);
+        let mut read = [0u16; PROGRAM_MEMORY_SIZE];
+        let read = &mut read[..program.instructions().len()];
+        self.read_program(0, read)?;
+        let mut start = [0u8; 3];
+        self.read_into(reg::PROG1_START, &mut start)?;
+        if read != program.instructions() || start != program.start {
+            return Err(Error::VerifyFailed);
+        }
+        Ok(())
+    }
+
+    /// Validate a program blob (see `check_blob`) and load the program it holds, so that a corrupt
+    /// blob never reaches the program memory
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn load_program_blob(&mut self, blob: &[u8]) -> Result<(), Error<E>> {
+        let program = Program::from_blob(blob)?;
+        self.load_program(&program)
+    }
+
+    /// Stream a program image (see `ProgramImage`) into the program memory a page at a time, and
+    /// set each engine's start address
+    ///
+    /// As with `load_program`, the interrupts of engines outside of the interrupt mask are
+    /// silenced. At least one engine must be in load mode and the engines must be idle.
+    pub fn load_program_image(&mut self, image: &ProgramImage) -> Result<(), Error<E>> {
+        let map = image.memory_map();
+        let mut words = image.instructions();
+        let mut addr = 0;
+        while addr < image.len() {
+            let mut page = [0u16; PAGE_SIZE];
+            let n = (image.len() - addr).min(PAGE_SIZE);
+            for (i, word) in page[..n].iter_mut().enumerate() {
+                *word = words.next().unwrap_or(0);
+                if map
+                    .owner((addr + i) as u8)
+                    .is_some_and(|e| !self.interrupt_mask.contains(e))
+                {
+                    asm::silence_interrupts(core::slice::from_mut(word));
+                }
+            }
+            self.write_page_at((addr / PAGE_SIZE) as u8, 0, &page[..n])?;
+            addr += n;
+        }
+        self.send(&[
+            reg::PROG1_START,
+            image.start(Engine::E1),
+            image.start(Engine::E2),
+            image.start(Engine::E3),
+        ])
+    }
+
+    /// Load a program with every engine stopped, zero the rest of the program memory, verify
+    /// both, and set free running each engine that owns a share of the program memory (see
+    /// `Program::memory_map`)
+    ///
+    /// Engines without a share are left disabled. Once in load mode, the program memory is only
+    /// written after the engines become idle, returning `Error::EngineBusy` if they do not; note
+    /// that waiting reads (and so clears) the STATUS_IRQ register.
+    pub fn start_program(&mut self, program: &Program) -> Result<(), Error<E>> {
+        self.halt_all_engines(false)?;
+        self.enter_load_mode(Engine::E1)?;
+        let len = program.instructions().len();
+        self.load_program(program)?;
+        self.clear_program_range(len as u8, PROGRAM_MEMORY_SIZE - len)?;
+        self.verify_program(program)?;
+        self.set_mode(Engine::E1, Mode::Disabled)?;
+        let map = program.memory_map();
+        for e in ENGINES.iter().filter(|e| map.used(**e) > 0) {
+            self.set_mode(*e, Mode::Run)?;
+            self.set_exec(*e, Exec::FreeRun)?;
+        }
+        Ok(())
+    }
+
+    /// Load an engine's program, assembled to run from address 0, at address `base` and point the
+    /// engine's start address at it
+    ///
+    /// Branch targets are fixed up for the new address (see `asm::relocate`), and the program's
+    /// interrupts are silenced if the engine is outside of the interrupt mask. The rest of the
+    /// program memory, and the other engines' start addresses, are left as they are.
+    ///
+    /// At least one engine must be in load mode. The program memory is only written after the
+    /// engines become idle, returning `Error::EngineBusy` if they do not; note that waiting reads
+    /// (and so clears) the STATUS_IRQ register.
+    pub fn load_engine_program(
+        &mut self,
+        e: Engine,
+        base: u8,
+        instructions: &[u16],
+    ) -> Result<(), Error<E>> {
+        let mut buf = [0u16; PROGRAM_MEMORY_SIZE];
+        let relocated = self.engine_program(e, base, instructions, &mut buf)?;
+        self.await_engines_idle()?;
+        self.write_program(base, relocated)?;
+        self.send(&[reg::PROG1_START + u8::from(e), base])
+    }
+
+    /// Check that the program memory at `base` and the engine's start address hold the engine's
+    /// program, as `load_engine_program` loads it, returning `Error::VerifyFailed` if not
+    ///
+    /// At least one engine must be in load mode and the engines must be idle.
+    pub fn verify_engine_program(
+        &mut self,
+        e: Engine,
+        base: u8,
+        instructions: &[u16],
+    ) -> Result<(), Error<E>> {
+        let mut buf = [0u16; PROGRAM_MEMORY_SIZE];
+        let relocated = self.engine_program(e, base, instructions, &mut buf)?;
+        let mut read = [0u16; PROGRAM_MEMORY_SIZE];
+        let read = &mut read[..relocated.len()];
+        self.read_program(base, read)?;
+        if read != relocated || self.read(reg::PROG1_START + u8::from(e))? != base {
+            return Err(Error::VerifyFailed);
+        }
+        Ok(())
+    }
+
+    /// Replace a running engine's program with one loaded into unused program memory
+    ///
+    /// The new program is loaded at `base`, which must lie clear of every program still in use,
+    /// and read back. Only once it has been verified are the engine's start address and program
+    /// counter pointed at it and the engine set running it, so the engine never executes a
+    /// partially loaded program, and the old program is never overwritten while the engine may
+    /// execute it. If verification fails, `Error::VerifyFailed` is returned and the engine is
+    /// left disabled.
+    ///
+    /// The swap is not seamless: the device holds every engine while any is in load mode, so the
+    /// engines, including the one being swapped, stand still with their outputs unchanged for the
+    /// duration of the load, and the others then continue from where they were. The program
+    /// memory is only written after the engines become idle, returning `Error::EngineBusy` if
+    /// they do not; note that waiting reads (and so clears) the STATUS_IRQ register.
+    pub fn swap_engine_program(
+        &mut self,
+        e: Engine,
+        base: u8,
+        instructions: &[u16],
+    ) -> Result<(), Error<E>> {
+        let mut buf = [0u16; PROGRAM_MEMORY_SIZE];
+        let relocated = self.engine_program(e, base, instructions, &mut buf)?;
+        self.set_exec(e, Exec::Hold)?;
+        self.set_mode(e, Mode::Disabled)?;
+        if let Err(err) = self.enter_load_mode(e) {
+            self.set_mode(e, Mode::Disabled)?;
+            return Err(err);
+        }
+        self.write_program(base, relocated)?;
+        let mut read = [0u16; PROGRAM_MEMORY_SIZE];
+        let read = &mut read[..relocated.len()];
+        self.read_program(base, read)?;
+        if read != relocated {
+            self.set_mode(e, Mode::Disabled)?;
+            return Err(Error::VerifyFailed);
+        }
+        self.send(&[reg::PROG1_START + u8::from(e), base])?;
+        self.set_mode(e, Mode::Run)?;
+        self.send(&[reg::PC1 + u8::from(e), base])?;
+        self.set_exec(e, Exec::FreeRun)
+    }
+
+    /// Put the engine in load mode and wait for the engines to become idle, so that the program
+    /// memory takes writes
+    pub(crate) fn enter_load_mode(&mut self, e: Engine) -> Result<(), Error<E>> {
+        self.set_mode(e, Mode::Load)?;
+        self.await_engines_idle()
+    }
+
+    /// Read STATUS_IRQ until the engine busy bit is clear, at most `LOAD_POLLS` times, returning
+    /// `Error::EngineBusy` if it stays set
+    fn await_engines_idle(&mut self) -> Result<(), Error<E>> {
+        for _ in 0..LOAD_POLLS {
+            match self.poll_engine_idle() {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => {}
+            }
+        }
+        Err(Error::EngineBusy)
+    }
+
+    /// Relocate an engine's program to run from `base` into `buf`, silencing its interrupts if
+    /// the engine is outside of the interrupt mask
+    fn engine_program<'b>(
+        &self,
+        e: Engine,
+        base: u8,
+        instructions: &[u16],
+        buf: &'b mut [u16; PROGRAM_MEMORY_SIZE],
+    ) -> Result<&'b [u16], Error<E>> {
+        let relocated = buf
+            .get_mut(..instructions.len())
+            .ok_or(Error::ProgramTooLarge)?;
+        relocated.copy_from_slice(instructions);
+        asm::relocate(relocated, base)?;
+        if !self.interrupt_mask.contains(e) {
+            asm::silence_interrupts(relocated);
+        }
+        Ok(relocated)
+    }
+
+    /// Write instructions to a page, starting `offset` instructions into it, in a single burst
+    fn write_page_at(
+        &mut self,
+        page: u8,
+        offset: u8,
+        instructions: &[u16],
+    ) -> Result<(), Error<E>> {
+        self.send(&[reg::PROG_PAGE_SEL, page])?;
+
+        let mut buf = [0u8; 1 + 2 * PAGE_SIZE];
+        buf[0] = reg::PROG_MEM_BASE + 2 * offset;
+        for (i, word) in instructions.iter().enumerate() {
+            buf[1 + 2 * i] = (word >> 8) as u8;
+            buf[2 + 2 * i] = *word as u8;
+        }
+        self.send(&buf[..1 + 2 * instructions.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asm::{Assembler, End};
+    use mock;
+
+    /// A two-instruction program ending in a branch, whose target `swap_engine_program` relocates
+    fn blink() -> [u16; 2] {
+        let mut asm = Assembler::new();
+        asm.set_pwm(255).branch(0, 0);
+        let mut words = [0; 2];
+        words.copy_from_slice(asm.instructions().unwrap());
+        words
+    }
+
+    #[test]
+    fn swap_points_the_engine_at_the_verified_program() {
+        let mut lp = mock::enabled();
+        lp.swap_engine_program(Engine::E2, 0x20, &blink()).unwrap();
+        let loaded = &lp.i2c.program[2 * 0x20..2 * 0x22];
+        assert_eq!(loaded, &[0x40, 0xff, 0xa0, 0x20]);
+        assert_eq!(lp.i2c.registers[usize::from(reg::PROG2_START)], 0x20);
+        assert_eq!(lp.i2c.registers[usize::from(reg::PC2)], 0x20);
+
+        // The start address is only written after the read-back, which selects the page last
+        let position = |register| lp.i2c.writes.iter().rposition(|w| w[0] == register);
+        assert!(position(reg::PROG2_START) > position(reg::PROG_PAGE_SEL));
+        assert!(lp.i2c.reads.contains(&(reg::PROG_MEM_BASE, 4)));
+    }
+
+    #[test]
+    fn swap_leaves_the_engine_disabled_when_the_load_fails() {
+        let mut lp = mock::enabled();
+        lp.i2c.drop_program_writes = true;
+        assert!(matches!(
+            lp.swap_engine_program(Engine::E2, 0x20, &blink()),
+            Err(Error::VerifyFailed)
+        ));
+        assert_eq!(lp.i2c.registers[usize::from(reg::PROG2_START)], 0x10);
+        assert!(lp.i2c.writes.iter().all(|w| w[0] != reg::PC2));
+        let cntrl2 = reg::Cntrl2::from_bits_truncate(lp.i2c.registers[usize::from(reg::CNTRL2)]);
+        assert!((cntrl2 & reg::Cntrl2::ENG2_MODE).is_empty());
+    }
+
+    #[test]
+    fn verify_engine_program_checks_the_relocated_words() {
+        let mut lp = mock::enabled();
+        lp.set_mode(Engine::E1, Mode::Load).unwrap();
+        let program = [asm::end(End::default()), asm::branch(0, 0).unwrap()];
+        lp.load_engine_program(Engine::E3, 0x30, &program).unwrap();
+        lp.verify_engine_program(Engine::E3, 0x30, &program).unwrap();
+        assert!(matches!(
+            lp.verify_engine_program(Engine::E3, 0x31, &program),
+            Err(Error::VerifyFailed)
+        ));
+    }
+
+    #[test]
+    fn start_program_waits_for_the_engines_to_become_idle() {
+        let mut lp = mock::enabled();
+        lp.i2c.load_busy_reads = 3;
+        let mut asm = Assembler::new();
+        asm.set_pwm(255).branch(0, 0);
+        let program = Program::new([0, 0, 0], asm.instructions().unwrap()).unwrap();
+        lp.start_program(&program).unwrap();
+        assert_eq!(&lp.i2c.program[..4], &[0x40, 0xff, 0xa0, 0x00]);
+        let polls = lp.i2c.reads.iter().filter(|r| **r == (reg::STATUS_IRQ, 1)).count();
+        assert_eq!(polls, 4);
+    }
+
+    #[test]
+    fn swap_gives_up_on_engines_that_stay_busy() {
+        let mut lp = mock::enabled();
+        lp.i2c.load_busy_reads = LOAD_POLLS;
+        assert!(matches!(
+            lp.swap_engine_program(Engine::E2, 0x20, &blink()),
+            Err(Error::EngineBusy)
+        ));
+        assert!(lp.i2c.writes.iter().all(|w| w[0] < reg::PROG_MEM_BASE));
+        let cntrl2 = reg::Cntrl2::from_bits_truncate(lp.i2c.registers[usize::from(reg::CNTRL2)]);
+        assert!((cntrl2 & reg::Cntrl2::ENG2_MODE).is_empty());
+
+        lp.i2c.load_busy_reads = LOAD_POLLS - 1;
+        lp.swap_engine_program(Engine::E2, 0x20, &blink()).unwrap();
+        assert_eq!(&lp.i2c.program[2 * 0x20..2 * 0x22], &[0x40, 0xff, 0xa0, 0x20]);
+    }
+}
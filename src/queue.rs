@@ -0,0 +1,179 @@
+//! Splitting the driver into an interrupt-side producer and a task-side consumer
+//!
+//! A `CommandQueue` is a fixed-size, lock-free, single-producer single-consumer queue of PWM,
+//! color and fader updates. `split` divides it into a `Producer`, which can enqueue from an
+//! interrupt handler without touching the bus, and a `Consumer`, which is given the driver and
+//! drains the queue from a task. Neither end ever blocks or takes a critical section, so a timer
+//! or audio ISR can feed the chip without the bus being shared across priorities.
+//!
+//! ```ignore
+//! static mut QUEUE: CommandQueue = CommandQueue::new();
+//!
+//! // at startup
+//! let (producer, consumer) = unsafe { QUEUE.split() };
+//!
+//! // in an interrupt handler
+//! producer.enqueue(Command::Pwm(D::D1, 0x80)).ok();
+//!
+//! // in a task
+//! consumer.drain(&mut lp)?;
+//! ```
+
+use core::cell::UnsafeCell;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use color::RgbGroup;
+use {Error, Fader, Lp55231, D};
+
+/// Number of slots in a `CommandQueue`. One slot is always kept free, so at most
+/// `QUEUE_LEN - 1` commands can be pending.
+pub const QUEUE_LEN: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// An update to be applied by the `Consumer`
+pub enum Command {
+    /// Set the D line to the PWM value
+    Pwm(D, u8),
+    /// Set the RGB LED to the red, green and blue PWM values
+    Rgb(RgbGroup, u8, u8, u8),
+    /// Set the master fader to the value
+    MasterFader(Fader, u8),
+}
+
+/// A lock-free single-producer single-consumer queue of `Command`s
+pub struct CommandQueue {
+    /// Command slots
+    buf: UnsafeCell<[Command; QUEUE_LEN]>,
+    /// Index of the next slot to be read, only advanced by the consumer
+    head: AtomicUsize,
+    /// Index of the next slot to be written, only advanced by the producer
+    tail: AtomicUsize,
+}
+
+// Only the producer writes to slots between `tail` and `head`, and only the consumer reads from
+// slots between `head` and `tail`, so the two ends never touch the same slot at once.
+unsafe impl Sync for CommandQueue {}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandQueue {
+    /// Create an empty queue, suitable for a `static`
+    pub const fn new() -> Self {
+        CommandQueue {
+            buf: UnsafeCell::new([Command::Pwm(D::D1, 0); QUEUE_LEN]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split the queue into its producer and consumer ends
+    pub fn split(&mut self) -> (Producer<'_>, Consumer<'_>) {
+        (
+            Producer {
+                queue: self,
+                _not_sync: PhantomData,
+            },
+            Consumer {
+                queue: self,
+                _not_sync: PhantomData,
+            },
+        )
+    }
+
+    /// Number of pending commands
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + QUEUE_LEN - head) % QUEUE_LEN
+    }
+
+    /// Are there no pending commands
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The enqueueing end of a `CommandQueue`
+pub struct Producer<'a> {
+    /// The queue being fed
+    queue: &'a CommandQueue,
+    /// Keep the producer to one context at a time
+    _not_sync: PhantomData<*const ()>,
+}
+
+unsafe impl<'a> Send for Producer<'a> {}
+
+impl<'a> Producer<'a> {
+    /// Enqueue the command, handing it back if the queue is full
+    pub fn enqueue(&mut self, cmd: Command) -> Result<(), Command> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % QUEUE_LEN;
+        if next == self.queue.head.load(Ordering::Acquire) {
+            return Err(cmd);
+        }
+        unsafe {
+            (*self.queue.buf.get())[tail] = cmd;
+        }
+        self.queue.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Is there no room for another command
+    pub fn is_full(&self) -> bool {
+        let next = (self.queue.tail.load(Ordering::Relaxed) + 1) % QUEUE_LEN;
+        next == self.queue.head.load(Ordering::Acquire)
+    }
+}
+
+/// The draining end of a `CommandQueue`
+pub struct Consumer<'a> {
+    /// The queue being drained
+    queue: &'a CommandQueue,
+    /// Keep the consumer to one context at a time
+    _not_sync: PhantomData<*const ()>,
+}
+
+unsafe impl<'a> Send for Consumer<'a> {}
+
+impl<'a> Consumer<'a> {
+    /// Take the oldest pending command, if any
+    pub fn dequeue(&mut self) -> Option<Command> {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if head == self.queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let cmd = unsafe { (*self.queue.buf.get())[head] };
+        self.queue.head.store((head + 1) % QUEUE_LEN, Ordering::Release);
+        Some(cmd)
+    }
+
+    /// Apply every pending command to the driver, returning how many were applied. Stops at the
+    /// first bus error; the failed command is not retried.
+    #[allow(deprecated)]
+    pub fn drain<E, I, P>(&mut self, lp: &mut Lp55231<I, P>) -> Result<usize, Error<E>>
+    where
+        E: Debug,
+        I: Write<Error = E> + WriteRead<Error = E>,
+        P: OutputPin,
+    {
+        let mut applied = 0;
+        while let Some(cmd) = self.dequeue() {
+            match cmd {
+                Command::Pwm(d, pwm) => lp.set_pwm(d, pwm)?,
+                Command::Rgb(led, r, g, b) => lp.set_rgb(led, r, g, b)?,
+                Command::MasterFader(f, value) => lp.set_master_fader(f, value)?,
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
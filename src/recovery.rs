@@ -0,0 +1,149 @@
+//! Unexpected reset detection and re-initialization
+//!
+//! A supply brown-out or ESD event can reset the device without the host noticing: CHIP_EN is
+//! cleared, every register returns to its default, and the engines' programs are lost. `ping` and
+//! `telemetry` check for this by reading back CNTRL1 and MISC, which `enable` always sets.
+//!
+//! `Lp55231::reinit` brings the device back: it enables it, which reapplies the configuration
+//! last passed to `apply_config` or `set_config` and shows its startup state, and restarts the
+//! program set with `set_recovery_program`. With `set_auto_recover`, `ping` and `telemetry` do so
+//! themselves on detecting a reset.
+//!
+//! A device that stops answering altogether, for example one latched up by an ESD event, calls
+//! for more: `Lp55231::recover` power-cycles it through the enable line, enables it again and
+//! restores a captured state. Unattended fixtures can watch `consecutive_bus_errors` and recover
+//! once it passes a threshold of their choosing.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+#[cfg(feature = "engines")]
+use program::Program;
+use registers as reg;
+use snapshot::DeviceState;
+use {Error, Lp55231};
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Whether the device has reset since it was enabled: CHIP_EN is clear, or MISC is back at
+    /// its power-on default of zero, which no configuration written by `enable` uses
+    pub fn reset_detected(&mut self) -> Result<bool, Error<E>> {
+        let cntrl1 = self.read_register::<reg::Cntrl1>()?;
+        if !cntrl1.contains(reg::Cntrl1::CHIP_EN) {
+            return Ok(true);
+        }
+        let misc = self.read_register::<reg::Misc>()?;
+        Ok(misc.is_empty())
+    }
+
+    /// Enable or disable automatic re-initialization by `ping` and `telemetry` when they detect a
+    /// reset
+    ///
+    /// When enabled, a detected reset is repaired with `reinit` and counted (see
+    /// `recovered_resets`) instead of being returned as `Error::DeviceReset`.
+    pub fn set_auto_recover(&mut self, enable: bool) {
+        self.auto_recover = enable;
+    }
+
+    /// Number of resets repaired automatically since the driver was created
+    pub fn recovered_resets(&self) -> u16 {
+        self.recovered_resets
+    }
+
+    /// Set the program `reinit` restarts (see `Lp55231::start_program`), or `None` to leave the
+    /// engines disabled
+    #[cfg(feature = "engines")]
+    pub fn set_recovery_program(&mut self, program: Option<Program>) {
+        self.recovery_program = program;
+    }
+
+    /// Re-initialize the device after a reset
+    ///
+    /// Enables the device, which reapplies the configuration last passed to `apply_config` or
+    /// `set_config` and shows its startup state (see `Config::startup`), and restarts the program
+    /// set with `set_recovery_program`. Direct PWM values and master faders are not otherwise
+    /// restored.
+    pub fn reinit(&mut self) -> Result<(), Error<E>> {
+        // Every register is back at its default, whatever the cache says was written
+        self.clear_shadow_cache();
+        self.enable()?;
+        #[cfg(feature = "engines")]
+        {
+            if let Some(program) = self.recovery_program {
+                self.start_program(&program)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check for a reset, repairing it if automatic recovery is enabled and returning
+    /// `Error::DeviceReset` otherwise
+    pub(crate) fn check_reset(&mut self) -> Result<(), Error<E>> {
+        if !self.reset_detected()? {
+            return Ok(());
+        }
+        if !self.auto_recover {
+            return Err(Error::DeviceReset);
+        }
+        self.reinit()?;
+        self.recovered_resets = self.recovered_resets.saturating_add(1);
+        Ok(())
+    }
+
+    /// Number of I2C transactions failed in a row, reset by any successful transaction
+    pub fn consecutive_bus_errors(&self) -> u16 {
+        self.bus_errors
+    }
+
+    /// Count a successful or failed I2C transaction
+    pub(crate) fn count_bus_error(&mut self, ok: bool) {
+        self.bus_errors = if ok { 0 } else { self.bus_errors.saturating_add(1) };
+    }
+
+    /// Bring the device back from persistent bus errors
+    ///
+    /// The device is power-cycled through the enable line, or, without one, sent a software reset
+    /// whose failure is ignored. It is then enabled as by `enable_with_delay`, which reapplies the
+    /// kept configuration and shows its startup state, and `state` is restored if given (see
+    /// `Lp55231::snapshot_with_program`); otherwise the program set with `set_recovery_program`
+    /// is restarted. Recovery succeeded if this returns `Ok`: the device answers and shows no
+    /// sign of a reset. It may be retried on error.
+    pub fn recover<DL>(
+        &mut self,
+        state: Option<&DeviceState>,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>>
+    where
+        DL: DelayUs<u16>,
+    {
+        if self.en_pin.is_none() && self.en {
+            // A device that still answers on the bus is brought back to its defaults
+            let _ = self.reset();
+        }
+        self.disable();
+        self.enable_with_delay(delay)?;
+        match state {
+            Some(state) => self.restore(state)?,
+            #[cfg(feature = "engines")]
+            None => {
+                if let Some(program) = self.recovery_program {
+                    self.start_program(&program)?;
+                }
+            }
+            #[cfg(not(feature = "engines"))]
+            None => (),
+        }
+        if self.reset_detected()? {
+            return Err(Error::DeviceReset);
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,73 @@
 //! I2C registers of the LP55231
+//!
+//! Every register address is available as a flat `u8` constant. Registers with typed fields
+//! additionally implement `Register`, which ties the field type to its address so that it can be
+//! read and written with `Lp55231::read_register` and `Lp55231::write_register`.
+#![allow(non_upper_case_globals)]
+
+/// A register with a typed value
+pub trait Register: Sized {
+    /// Address of the register
+    const ADDRESS: u8;
+
+    /// The raw register value
+    fn encode(&self) -> u8;
+
+    /// Decode a raw register value. Unknown bits are dropped.
+    fn decode(value: u8) -> Self;
+}
+
+/// Implement `Register` for a bitflags type
+macro_rules! register {
+    ($ty:ident, $addr:ident) => {
+        impl Register for $ty {
+            const ADDRESS: u8 = $addr;
+
+            fn encode(&self) -> u8 {
+                self.bits()
+            }
+
+            fn decode(value: u8) -> Self {
+                $ty::from_bits_truncate(value)
+            }
+        }
+    };
+}
 
 /// ENABLE/ ENGINE CONTROL1
 pub const CNTRL1: u8 = 0x00;
-// TODO engine control modes
 bitflags! {
     /// CNTRL1 register fields
     pub struct Cntrl1: u8 {
         /// Enables the chip
         const CHIP_EN = 1 << 6;
+        /// Engine 1 execution mode field. All clear is hold: the engine waits, and its PC can be
+        /// read or written.
+        const ENG1_EXEC = 0b11 << 4;
+        /// Engine 1 step: execute the instruction at PC, increment PC, then hold
+        const ENG1_EXEC_STEP = 0b01 << 4;
+        /// Engine 1 free run: execute the program continuously
+        const ENG1_EXEC_FREE_RUN = 0b10 << 4;
+        /// Engine 1 execute once: execute the instruction at PC, then hold without incrementing
+        const ENG1_EXEC_ONCE = 0b11 << 4;
+        /// Engine 2 execution mode field. All clear is hold: the engine waits, and its PC can be
+        /// read or written.
+        const ENG2_EXEC = 0b11 << 2;
+        /// Engine 2 step: execute the instruction at PC, increment PC, then hold
+        const ENG2_EXEC_STEP = 0b01 << 2;
+        /// Engine 2 free run: execute the program continuously
+        const ENG2_EXEC_FREE_RUN = 0b10 << 2;
+        /// Engine 2 execute once: execute the instruction at PC, then hold without incrementing
+        const ENG2_EXEC_ONCE = 0b11 << 2;
+        /// Engine 3 execution mode field. All clear is hold: the engine waits, and its PC can be
+        /// read or written.
+        const ENG3_EXEC = 0b11;
+        /// Engine 3 step: execute the instruction at PC, increment PC, then hold
+        const ENG3_EXEC_STEP = 0b01;
+        /// Engine 3 free run: execute the program continuously
+        const ENG3_EXEC_FREE_RUN = 0b10;
+        /// Engine 3 execute once: execute the instruction at PC, then hold without incrementing
+        const ENG3_EXEC_ONCE = 0b11;
     }
 }
 
@@ -17,20 +77,49 @@ bitflags! {
 ///
 /// * Disabled: Engines can be configured to disabled mode each one separately.
 /// * Load program: Writing to program memory is allowed only when the engine is in load program
-/// operation mode and engine busy bit (reg 3A) is not set. Serial bus master should check the busy
-/// bit before writing to program memory or allow at least 1ms delay after entering to load mode
-/// before memory write, to ensure initalization. All the three engines are in hold while one or
-/// more engines are in load program mode. PWM values are frozen, also. Program execution continues
-/// when all the engines are out of load program mode. Load program mode resets the program counter
-/// of the respective engine. Load program mode can be entered from the disabled mode only.
-/// Entering load program mode from the run program mode is not allowed.
+///   operation mode and engine busy bit (reg 3A) is not set. Serial bus master should check the busy
+///   bit before writing to program memory or allow at least 1ms delay after entering to load mode
+///   before memory write, to ensure initalization. All the three engines are in hold while one or
+///   more engines are in load program mode. PWM values are frozen, also. Program execution continues
+///   when all the engines are out of load program mode. Load program mode resets the program counter
+///   of the respective engine. Load program mode can be entered from the disabled mode only.
+///   Entering load program mode from the run program mode is not allowed.
 /// * Run Program: Run program mode executes the instructions stored in the program memory.
-/// Execution register (ENG1_EXEC etc.) bits define how the program is executed (hold, step, free
-/// run or execute once). Program start address can be programmed to the Program Counter (PC)
-/// register. The Program Counter is reset to zero when the PC’s upper limit value is reached.
+///   Execution register (ENG1_EXEC etc.) bits define how the program is executed (hold, step, free
+///   run or execute once). Program start address can be programmed to the Program Counter (PC)
+///   register. The Program Counter is reset to zero when the PC’s upper limit value is reached.
 // TODO: "Entering load program mode from the run program mode is not allowed" constrain this using
 // a type parameter to the Lp55231 struct
 pub const CNTRL2: u8 = 0x01;
+bitflags! {
+    /// CNTRL2 register fields
+    pub struct Cntrl2: u8 {
+        /// Engine 1 operation mode field. All clear is disabled.
+        const ENG1_MODE = 0b11 << 4;
+        /// Engine 1 load program: the program memory may be written
+        const ENG1_MODE_LOAD = 0b01 << 4;
+        /// Engine 1 run program: execute as set by ENG1_EXEC
+        const ENG1_MODE_RUN = 0b10 << 4;
+        /// Engine 1 halt: direct control of the engine's outputs, PC reset
+        const ENG1_MODE_HALT = 0b11 << 4;
+        /// Engine 2 operation mode field. All clear is disabled.
+        const ENG2_MODE = 0b11 << 2;
+        /// Engine 2 load program: the program memory may be written
+        const ENG2_MODE_LOAD = 0b01 << 2;
+        /// Engine 2 run program: execute as set by ENG2_EXEC
+        const ENG2_MODE_RUN = 0b10 << 2;
+        /// Engine 2 halt: direct control of the engine's outputs, PC reset
+        const ENG2_MODE_HALT = 0b11 << 2;
+        /// Engine 3 operation mode field. All clear is disabled.
+        const ENG3_MODE = 0b11;
+        /// Engine 3 load program: the program memory may be written
+        const ENG3_MODE_LOAD = 0b01;
+        /// Engine 3 run program: execute as set by ENG3_EXEC
+        const ENG3_MODE_RUN = 0b10;
+        /// Engine 3 halt: direct control of the engine's outputs, PC reset
+        const ENG3_MODE_HALT = 0b11;
+    }
+}
 
 /// OUTPUT DIRECT/RATIOMETRIC MSB
 ///
@@ -43,10 +132,18 @@ pub const RATIO_MSB: u8 = 0x02;
 /// OUTPUT DIRECT/RATIOMETRIC LSB
 pub const RATIO_LSB: u8 = 0x03;
 /// OUTPUT ON/OFF CONTROL MSB
+///
+/// Bit 0 turns D9 on. Every D line is on after power-on or reset, so this register defaults to
+/// 0x01 and `OUTPUT_ONOFF_LSB` to 0xff.
 pub const OUTPUT_ONOFF_MSB: u8 = 0x04;
-/// OUTPUT ON/OFF CONTROL LSB
+/// OUTPUT ON/OFF CONTROL LSB, one bit per D line from D1 in bit 0 to D8 in bit 7
 pub const OUTPUT_ONOFF_LSB: u8 = 0x05;
 
+/// Per LED control channel base address
+///
+/// D1_CTRL through D9_CTRL are contiguous, indexed by `D`.
+pub const D_CTRL_BASE: u8 = 0x06;
+
 /// Per LED control channel for D1 - fader channel assig, log dimming enable, temperature compensation
 ///
 /// This is the register used to assign the D1 output to the MASTER FADER group 1, 2, or 3, or none
@@ -72,6 +169,23 @@ pub const D7_CTRL: u8 = 0x0c;
 pub const D8_CTRL: u8 = 0x0d;
 /// Per LED control channel for D9 - fader channel assig, log dimming enable, temperature compensation
 pub const D9_CTRL: u8 = 0x0e;
+bitflags! {
+    /// Dx_CTRL register fields
+    pub struct DxCtrl: u8 {
+        /// Output is mapped to MASTER FADER1
+        const MAPPING_FADER1 = 0b01 << 6;
+        /// Output is mapped to MASTER FADER2
+        const MAPPING_FADER2 = 0b10 << 6;
+        /// Output is mapped to MASTER FADER3
+        const MAPPING_FADER3 = 0b11 << 6;
+        /// Logarithmic PWM brightness adjustment enable
+        const LOG_EN = 1 << 5;
+        /// Temperature compensation sign bit; set for a negative correction factor
+        const TEMP_COMP_NEG = 1 << 4;
+        /// Temperature compensation magnitude, in 0.1 %/°C steps
+        const TEMP_COMP_MAG = 0b1111;
+    }
+}
 
 /// PWM control registers
 ///
@@ -152,8 +266,38 @@ pub const PC2: u8 = 0x38;
 pub const PC3: u8 = 0x39;
 /// STATUS/INTERRUPT
 pub const STATUS_IRQ: u8 = 0x3A;
+bitflags! {
+    /// STATUS_IRQ register fields. Reading the register clears the engine interrupt bits.
+    pub struct StatusIrq: u8 {
+        /// An LED test measurement has completed
+        const LEDTEST_MEAS_DONE = 1 << 7;
+        /// The INT pin is masked, so interrupts are not signalled
+        const MASK_BUSY = 1 << 6;
+        /// The internal startup sequence is running
+        const STARTUP_BUSY = 1 << 5;
+        /// An engine is executing a command and the program memory may not be written
+        const ENGINE_BUSY = 1 << 4;
+        /// The external clock on the CLK pin is in use
+        const EXT_CLK_USED = 1 << 3;
+        /// Engine 1 has sent an interrupt
+        const ENG1_INT = 1 << 2;
+        /// Engine 2 has sent an interrupt
+        const ENG2_INT = 1 << 1;
+        /// Engine 3 has sent an interrupt
+        const ENG3_INT = 1 << 0;
+    }
+}
 /// INT/GPO
 pub const INT_GPIO: u8 = 0x3B;
+bitflags! {
+    /// INT_GPIO register fields
+    pub struct IntGpio: u8 {
+        /// The INT pin is a general purpose output rather than the interrupt output
+        const INT_CONF = 1 << 2;
+        /// Level driven on the INT pin when it is a general purpose output
+        const INT_GPO = 1 << 0;
+    }
+}
 /// These bits are used for storing a global 8-bit variable. Variable can be used to control program flow.
 pub const GLOBAL_VAR: u8 = 0x3C;
 
@@ -169,12 +313,46 @@ bitflags! {
 
 /// TEMP ADC CONTROL
 pub const TEMP_CTL: u8 = 0x3E;
+bitflags! {
+    /// TEMP_CTL register fields
+    pub struct TempCtl: u8 {
+        /// A temperature measurement is in progress. Read-only.
+        const TEMP_MEAS_BUSY = 1 << 7;
+        /// Enables the internal temperature sensor. Without CONTINUOUS_CONV, setting this
+        /// triggers a single conversion.
+        const EN_TEMP_SENSOR = 1 << 2;
+        /// Convert continuously rather than once
+        const CONTINUOUS_CONV = 1 << 1;
+        /// Use the value written to TEMP_WRITE, from an external sensor, for temperature
+        /// compensation instead of the internal sensor's reading
+        const SEL_EXTERNAL_TEMP = 1 << 0;
+    }
+}
 /// These bits are used for storing an 8-bit temperature reading acquired from the internal temperature sensor.
 pub const TEMP_READ: u8 = 0x3F;
 /// These bits are used for storing an 8-bit temperature reading acquired from an external sensor, if such a sensor is used.
 pub const TEMP_WRITE: u8 = 0x40;
 /// LED TEST CONTROL
 pub const TEST_CTL: u8 = 0x41;
+bitflags! {
+    /// TEST_CTL register fields
+    pub struct TestCtl: u8 {
+        /// Enables the LED test ADC, starting a conversion
+        const EN_LEDTEST_ADC = 1 << 7;
+        /// Signal completion of a conversion on the INT pin
+        const EN_LEDTEST_INT = 1 << 6;
+        /// Convert continuously rather than once
+        const LEDTEST_CONTINUOUS_CONV = 1 << 5;
+        /// LED test ADC input selection field. D1 through D9 are selected with 0 through 8.
+        const LEDTEST_CTRL = 0b11111;
+        /// Select VOUT as the LED test ADC input
+        const LEDTEST_VOUT = 0b01111;
+        /// Select VDD as the LED test ADC input
+        const LEDTEST_VDD = 0b10000;
+        /// Select the INT pin as the LED test ADC input
+        const LEDTEST_INT = 0b10001;
+    }
+}
 /// LED TEST ADC
 pub const TEST_ADC: u8 = 0x42;
 
@@ -225,3 +403,229 @@ pub const ENG3_MAP_LSB: u8 = 0x75;
 
 /// With hysteresis and timer bits the user can optimize the charge pump performance to better meet the requirements of the application at hand. Some applications need to be optimized for efficiency and others need to be optimized for minimum EMI, for example.
 pub const GAIN_CHANGE: u8 = 0x76;
+bitflags! {
+    /// GAIN_CHANGE register fields
+    pub struct GainChange: u8 {
+        /// Threshold voltage field, the headroom at which the charge pump changes gain. All
+        /// clear is 400 mV.
+        const THRESHOLD = 0b11 << 6;
+        /// 300 mV gain change threshold
+        const THRESHOLD_300MV = 0b01 << 6;
+        /// 200 mV gain change threshold
+        const THRESHOLD_200MV = 0b10 << 6;
+        /// 100 mV gain change threshold
+        const THRESHOLD_100MV = 0b11 << 6;
+        /// Adapt the threshold to the LED forward voltages
+        const ADAPTIVE_THRESH_EN = 1 << 5;
+        /// Gain change timer field, the minimum time between gain changes. All clear is 5 ms.
+        const TIMER = 0b11 << 3;
+        /// 10 ms gain change timer
+        const TIMER_10MS = 0b01 << 3;
+        /// 50 ms gain change timer
+        const TIMER_50MS = 0b10 << 3;
+        /// Infinite gain change timer: the gain only steps up
+        const TIMER_INFINITE = 0b11 << 3;
+        /// Return to 1x mode whenever the outputs allow, rather than only after the timer expires
+        const FORCE_1x = 1 << 2;
+    }
+}
+
+register!(Cntrl1, CNTRL1);
+register!(Cntrl2, CNTRL2);
+register!(Misc, MISC);
+register!(StatusIrq, STATUS_IRQ);
+register!(IntGpio, INT_GPIO);
+register!(Reset, RESET);
+register!(TempCtl, TEMP_CTL);
+register!(TestCtl, TEST_CTL);
+register!(GainChange, GAIN_CHANGE);
+
+/// Whether the register holds the value last written to it, so that a write may be verified by
+/// reading it back. Registers holding self-clearing command bits, read-only registers, and
+/// registers updated by running engines do not.
+pub fn verifiable(register: u8) -> bool {
+    !matches!(
+        register,
+        CNTRL1
+            | PC1
+            | PC2
+            | PC3
+            | STATUS_IRQ
+            | GLOBAL_VAR
+            | RESET
+            | TEMP_CTL
+            | TEMP_READ
+            | TEST_CTL
+            | TEST_ADC
+            | ENGINE_A_VAR
+            | ENGINE_B_VAR
+            | ENGINE_C_VAR
+    )
+}
+
+/// Documented power-on default value of every register that has one, in address order
+///
+/// Read-only registers whose value depends on the device's state (STATUS_IRQ, TEMP_READ and
+/// TEST_ADC) and the program memory are left out.
+pub const DEFAULTS: [(u8, u8); 60] = [
+    (CNTRL1, 0x00),
+    (CNTRL2, 0x00),
+    (RATIO_MSB, 0x00),
+    (RATIO_LSB, 0x00),
+    (OUTPUT_ONOFF_MSB, 0x01),
+    (OUTPUT_ONOFF_LSB, 0xff),
+    (D1_CTRL, 0x00),
+    (D2_CTRL, 0x00),
+    (D3_CTRL, 0x00),
+    (D4_CTRL, 0x00),
+    (D5_CTRL, 0x00),
+    (D6_CTRL, 0x00),
+    (D7_CTRL, 0x00),
+    (D8_CTRL, 0x00),
+    (D9_CTRL, 0x00),
+    (D1_PWM, 0x00),
+    (D2_PWM, 0x00),
+    (D3_PWM, 0x00),
+    (D4_PWM, 0x00),
+    (D5_PWM, 0x00),
+    (D6_PWM, 0x00),
+    (D7_PWM, 0x00),
+    (D8_PWM, 0x00),
+    (D9_PWM, 0x00),
+    (D1_I_CTL, 0xaf),
+    (D2_I_CTL, 0xaf),
+    (D3_I_CTL, 0xaf),
+    (D4_I_CTL, 0xaf),
+    (D5_I_CTL, 0xaf),
+    (D6_I_CTL, 0xaf),
+    (D7_I_CTL, 0xaf),
+    (D8_I_CTL, 0xaf),
+    (D9_I_CTL, 0xaf),
+    (MISC, 0x00),
+    (PC1, 0x00),
+    (PC2, 0x00),
+    (PC3, 0x00),
+    (INT_GPIO, 0x00),
+    (GLOBAL_VAR, 0x00),
+    (RESET, 0x00),
+    (TEMP_CTL, 0x00),
+    (TEMP_WRITE, 0x00),
+    (TEST_CTL, 0x00),
+    (ENGINE_A_VAR, 0x00),
+    (ENGINE_B_VAR, 0x00),
+    (ENGINE_C_VAR, 0x00),
+    (MASTER_FADE_1, 0x00),
+    (MASTER_FADE_2, 0x00),
+    (MASTER_FADE_3, 0x00),
+    (PROG1_START, 0x00),
+    (PROG2_START, 0x10),
+    (PROG3_START, 0x20),
+    (PROG_PAGE_SEL, 0x00),
+    (ENG1_MAP_MSB, 0x00),
+    (ENG1_MAP_LSB, 0x00),
+    (ENG2_MAP_MSB, 0x00),
+    (ENG2_MAP_LSB, 0x00),
+    (ENG3_MAP_MSB, 0x00),
+    (ENG3_MAP_LSB, 0x00),
+    (GAIN_CHANGE, 0x00),
+];
+
+// The table is checked at compile time: addresses strictly increasing, none in the program memory
+// window or among the state-dependent read-only registers
+const _: () = {
+    let mut i = 0;
+    while i < DEFAULTS.len() {
+        let (register, _) = DEFAULTS[i];
+        assert!(register < PROG_MEM_BASE || register > PROG_MEM_END);
+        assert!(register != STATUS_IRQ && register != TEMP_READ && register != TEST_ADC);
+        assert!(i == 0 || DEFAULTS[i - 1].0 < register);
+        i += 1;
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every register in the datasheet's register map, by address, with its power-on default, or
+    /// `None` for the read-only registers whose value depends on the device's state
+    const DATASHEET: [(u8, Option<u8>); 63] = [
+        (0x00, Some(0x00)),
+        (0x01, Some(0x00)),
+        (0x02, Some(0x00)),
+        (0x03, Some(0x00)),
+        // Every D line is on at power-on
+        (0x04, Some(0x01)),
+        (0x05, Some(0xff)),
+        (0x06, Some(0x00)),
+        (0x07, Some(0x00)),
+        (0x08, Some(0x00)),
+        (0x09, Some(0x00)),
+        (0x0a, Some(0x00)),
+        (0x0b, Some(0x00)),
+        (0x0c, Some(0x00)),
+        (0x0d, Some(0x00)),
+        (0x0e, Some(0x00)),
+        (0x16, Some(0x00)),
+        (0x17, Some(0x00)),
+        (0x18, Some(0x00)),
+        (0x19, Some(0x00)),
+        (0x1a, Some(0x00)),
+        (0x1b, Some(0x00)),
+        (0x1c, Some(0x00)),
+        (0x1d, Some(0x00)),
+        (0x1e, Some(0x00)),
+        // 17.5 mA
+        (0x26, Some(0xaf)),
+        (0x27, Some(0xaf)),
+        (0x28, Some(0xaf)),
+        (0x29, Some(0xaf)),
+        (0x2a, Some(0xaf)),
+        (0x2b, Some(0xaf)),
+        (0x2c, Some(0xaf)),
+        (0x2d, Some(0xaf)),
+        (0x2e, Some(0xaf)),
+        (0x36, Some(0x00)),
+        (0x37, Some(0x00)),
+        (0x38, Some(0x00)),
+        (0x39, Some(0x00)),
+        (0x3a, None),
+        (0x3b, Some(0x00)),
+        (0x3c, Some(0x00)),
+        (0x3d, Some(0x00)),
+        (0x3e, Some(0x00)),
+        (0x3f, None),
+        (0x40, Some(0x00)),
+        (0x41, Some(0x00)),
+        (0x42, None),
+        (0x45, Some(0x00)),
+        (0x46, Some(0x00)),
+        (0x47, Some(0x00)),
+        (0x48, Some(0x00)),
+        (0x49, Some(0x00)),
+        (0x4a, Some(0x00)),
+        // Engines 2 and 3 start at program addresses 16 and 32
+        (0x4c, Some(0x00)),
+        (0x4d, Some(0x10)),
+        (0x4e, Some(0x20)),
+        (0x4f, Some(0x00)),
+        (0x70, Some(0x00)),
+        (0x71, Some(0x00)),
+        (0x72, Some(0x00)),
+        (0x73, Some(0x00)),
+        (0x74, Some(0x00)),
+        (0x75, Some(0x00)),
+        (0x76, Some(0x00)),
+    ];
+
+    #[test]
+    fn defaults_match_the_datasheet() {
+        let mut defaults = DEFAULTS.iter();
+        for (register, default) in DATASHEET.iter() {
+            if let Some(default) = default {
+                assert_eq!(defaults.next(), Some(&(*register, *default)), "{:#04x}", register);
+            }
+        }
+        assert_eq!(defaults.next(), None);
+    }
+}
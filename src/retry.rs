@@ -0,0 +1,91 @@
+//! I2C retry policy
+//!
+//! `Retry` wraps an I2C bus and retries failed transfers, so that transient NACKs or arbitration
+//! losses on noisy buses don't immediately bubble up as hard failures. The caller decides which
+//! errors are worth retrying, since `embedded_hal` bus errors are opaque to the driver. Wrap the
+//! bus before handing it to `Lp55231::new`.
+
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+
+/// An I2C bus that retries failed transfers
+pub struct Retry<I, DL, E> {
+    /// The wrapped I2C bus
+    i2c: I,
+    /// Delay used to back off between attempts
+    delay: DL,
+    /// Total number of attempts per transfer, including the first
+    attempts: u8,
+    /// Delay before the first retry, in ms. Doubles on every subsequent retry.
+    backoff: u8,
+    /// Whether an error may succeed on retry
+    retriable: fn(&E) -> bool,
+}
+
+impl<I, DL, E> Retry<I, DL, E>
+where
+    DL: DelayMs<u8>,
+{
+    /// Wrap `i2c`, making up to `attempts` attempts per transfer while `retriable` returns true
+    /// for the error. Retries back off starting at `backoff` ms, doubling every time.
+    pub fn new(i2c: I, delay: DL, attempts: u8, backoff: u8, retriable: fn(&E) -> bool) -> Self {
+        Retry {
+            i2c,
+            delay,
+            attempts,
+            backoff,
+            retriable,
+        }
+    }
+
+    /// Release the wrapped bus and delay
+    pub fn release(self) -> (I, DL) {
+        (self.i2c, self.delay)
+    }
+
+    /// Run `op` until it succeeds, fails with a permanent error, or runs out of attempts
+    fn retry<F>(&mut self, mut op: F) -> Result<(), E>
+    where
+        F: FnMut(&mut I) -> Result<(), E>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match op(&mut self.i2c) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if attempt >= self.attempts || !(self.retriable)(&e) {
+                        return Err(e);
+                    }
+                }
+            }
+            self.delay.delay_ms(backoff);
+            backoff = backoff.saturating_mul(2);
+            attempt += 1;
+        }
+    }
+}
+
+impl<I, DL, E> Write for Retry<I, DL, E>
+where
+    I: Write<Error = E>,
+    DL: DelayMs<u8>,
+{
+    type Error = E;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), E> {
+        self.retry(|i2c| i2c.write(addr, bytes))
+    }
+}
+
+impl<I, DL, E> WriteRead for Retry<I, DL, E>
+where
+    I: WriteRead<Error = E>,
+    DL: DelayMs<u8>,
+{
+    type Error = E;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), E> {
+        self.retry(|i2c| i2c.write_read(addr, bytes, buffer))
+    }
+}
@@ -0,0 +1,99 @@
+//! Lighting scenes
+//!
+//! A `Scene` is a complete lighting state: the PWM value of every D line, along with an
+//! identifier and a default transition time. Scenes are set at once with
+//! `Lp55231::apply_scene`, or blended into from whatever is showing with
+//! `Lp55231::crossfade_to`.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use color::RgbGroup;
+use {Error, Lp55231, Millis, D};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// The PWM value of every D line, with metadata
+pub struct Scene {
+    /// Application-defined identifier
+    pub id: u8,
+    /// PWM values of D1 through D9, before mapping through the D lines' curves
+    pub pwm: [u8; 9],
+    /// Default transition time into the scene, in ms
+    pub transition: u16,
+}
+
+impl Scene {
+    /// Create a scene from the PWM values of D1 through D9, with no transition time
+    pub fn new(id: u8, pwm: [u8; 9]) -> Self {
+        Scene {
+            id,
+            pwm,
+            transition: 0,
+        }
+    }
+
+    /// Create a scene from the colors of three RGB LEDs, with no transition time
+    pub fn from_rgb(id: u8, leds: &[RgbGroup; 3], colors: [(u8, u8, u8); 3]) -> Self {
+        let mut pwm = [0; 9];
+        for (led, (r, g, b)) in leds.iter().zip(colors.iter()) {
+            for (d, value) in led.lines().iter().zip([*r, *g, *b].iter()) {
+                pwm[usize::from(u8::from(*d))] = *value;
+            }
+        }
+        Scene::new(id, pwm)
+    }
+
+    /// Set the default transition time into the scene
+    pub fn with_transition<T: Into<Millis>>(mut self, transition: T) -> Self {
+        self.transition = transition.into().0;
+        self
+    }
+
+    /// The PWM value of the D line
+    pub fn pwm(&self, d: D) -> u8 {
+        self.pwm[usize::from(u8::from(d))]
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Set every D line to the scene's PWM value
+    pub fn apply_scene(&mut self, scene: &Scene) -> Result<(), Error<E>> {
+        for d in D::iter() {
+            self.set_pwm(d, scene.pwm(d))?;
+        }
+        Ok(())
+    }
+
+    /// Fade every D line from its current PWM value to the scene's over `duration` ms
+    ///
+    /// The direct PWM registers are stepped in software, blocking on `delay` in between steps.
+    /// Pass the scene's `transition` to use its default transition time.
+    pub fn crossfade_to<T, DL>(
+        &mut self,
+        scene: &Scene,
+        duration: T,
+        delay: &mut DL,
+    ) -> Result<(), Error<E>>
+    where
+        T: Into<Millis>,
+        DL: DelayMs<u16>,
+    {
+        let mut leds = [D::D1; 9];
+        let mut target = [0; 9];
+        for (i, d) in D::iter().enumerate() {
+            leds[i] = d;
+            target[i] = self.output_pwm(d, scene.pwm(d));
+        }
+        self.fade_many(&leds, &target, duration.into().0, delay)
+    }
+}
@@ -0,0 +1,299 @@
+//! Power-up self-test
+//!
+//! `Lp55231::self_test` checks that the device responds and is enabled, that the charge pump
+//! output is up, and runs the LED test ADC across all 9 outputs. A D line passes when the voltage
+//! on its pin lies between the short-circuit limit and VDD while it is driven at full PWM; an open
+//! LED pulls the pin to ground, and a shorted one pulls it up to the supply.
+//!
+//! `Lp55231::respond_to_faults` acts on a self-test's findings according to a `FaultPolicy`: a
+//! shorted D line is turned off, so that it cannot overheat the device, and an open one may be
+//! measured again, in case it was only loose, before being taken as failed. D lines turned off
+//! are marked failed, and stay off through `set_outputs` and `apply_config` until
+//! `clear_failed_outputs`.
+//!
+//! `Lp55231::verify_defaults` checks every register against its documented power-on default (see
+//! `registers::DEFAULTS`), which catches a miswired address pin answering for another device, or
+//! a part that did not fully reset.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Error, Lp55231, Monitor, OutputMask, D};
+
+/// D pin voltage, in mV, below which an output is considered shorted or open (ADC code 80)
+pub(crate) const SHORT_CIRCUIT_LIMIT: i16 = 922;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Result of the LED test of a single D line
+pub struct ChannelReport {
+    /// Voltage on the D pin, in mV, while driven at full PWM
+    pub voltage: i16,
+    /// Whether the voltage is within the healthy range
+    pub passed: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Result of `Lp55231::self_test`
+pub struct SelfTestReport {
+    /// CHIP_EN reads back as set
+    pub chip_enabled: bool,
+    /// Charge pump output voltage, in mV
+    pub vout: i16,
+    /// VOUT is above the default `Monitor` threshold
+    pub charge_pump_ok: bool,
+    /// Supply voltage, in mV
+    pub vdd: i16,
+    /// LED test results for D1 through D9
+    pub channels: [ChannelReport; 9],
+}
+
+impl SelfTestReport {
+    /// Whether every check passed
+    pub fn passed(&self) -> bool {
+        self.chip_enabled && self.charge_pump_ok && self.channels.iter().all(|c| c.passed)
+    }
+
+    /// The fault found on the D line, if any: open if its pin voltage is below the short-circuit
+    /// limit, and shorted if it reaches VDD
+    pub fn fault(&self, d: D) -> Option<ChannelFault> {
+        fault(&self.channels[usize::from(u8::from(d))], self.vdd)
+    }
+}
+
+/// The fault of a D line from its LED test result
+fn fault(channel: &ChannelReport, vdd: i16) -> Option<ChannelFault> {
+    if channel.voltage < SHORT_CIRCUIT_LIMIT {
+        Some(ChannelFault::Open)
+    } else if channel.voltage >= vdd {
+        Some(ChannelFault::Shorted)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// A fault found on a D line by the LED test
+pub enum ChannelFault {
+    /// No current flows: the LED is missing, open, or wired to the wrong pin
+    Open,
+    /// The LED is bypassed, so that the pin sits at the supply
+    Shorted,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// How `Lp55231::respond_to_faults` handles faulty D lines
+pub struct FaultPolicy {
+    /// Turn shorted D lines off, and mark them failed
+    pub disable_shorted: bool,
+    /// Number of times an open D line is measured again before it is taken as open
+    pub open_retries: u8,
+    /// Time between measurements of an open D line, in ms
+    pub retry_interval: u8,
+    /// Turn D lines still open after the retries off, and mark them failed
+    pub disable_open: bool,
+}
+
+impl Default for FaultPolicy {
+    /// Turn shorted D lines off, and report open ones without retrying
+    fn default() -> Self {
+        FaultPolicy {
+            disable_shorted: true,
+            open_retries: 0,
+            retry_interval: 10,
+            disable_open: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Result of `Lp55231::respond_to_faults`
+pub struct FaultResponse {
+    /// D lines found shorted
+    pub shorted: OutputMask,
+    /// D lines found open, after any retries
+    pub open: OutputMask,
+    /// D lines turned off and marked failed
+    pub disabled: OutputMask,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A register found by `Lp55231::verify_defaults` not to hold its default value
+pub struct DefaultMismatch {
+    /// Address of the register
+    pub register: u8,
+    /// Documented default value
+    pub expected: u8,
+    /// Value read back
+    pub actual: u8,
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Run the power-up self-test. Must be called after `enable`.
+    ///
+    /// Each D line is driven at full PWM while it is measured, and restored to its previous PWM
+    /// value afterwards.
+    pub fn self_test<DL: DelayMs<u8>>(
+        &mut self,
+        delay: &mut DL,
+    ) -> Result<SelfTestReport, Error<E>> {
+        let cntrl1 = self.read_register::<reg::Cntrl1>()?;
+        let vout = self.measure_vout(delay)?;
+        let vdd = self.measure_led_test(reg::TestCtl::LEDTEST_VDD.bits(), delay)?;
+
+        let mut channels = [ChannelReport {
+            voltage: 0,
+            passed: false,
+        }; 9];
+        for (d, report) in D::ALL.iter().zip(channels.iter_mut()) {
+            *report = self.test_channel(*d, vdd, delay)?;
+        }
+
+        Ok(SelfTestReport {
+            chip_enabled: cntrl1.contains(reg::Cntrl1::CHIP_EN),
+            vout,
+            charge_pump_ok: vout >= Monitor::default().min_vout,
+            vdd,
+            channels,
+        })
+    }
+
+    /// Run the LED test of a single D line, driving it at full PWM and then restoring its PWM
+    /// value
+    fn test_channel<DL: DelayMs<u8>>(
+        &mut self,
+        d: D,
+        vdd: i16,
+        delay: &mut DL,
+    ) -> Result<ChannelReport, Error<E>> {
+        let pwm_reg = reg::D_PWM_BASE + u8::from(d);
+        let pwm = self.read(pwm_reg)?;
+        self.send(&[pwm_reg, 0xff])?;
+        delay.delay_ms(3);
+        let voltage = self.measure_led_test(u8::from(d), delay);
+        self.send(&[pwm_reg, pwm])?;
+        let voltage = voltage?;
+        Ok(ChannelReport {
+            voltage,
+            passed: voltage >= SHORT_CIRCUIT_LIMIT && voltage < vdd,
+        })
+    }
+
+    /// Act on the faulty D lines of a self-test report according to the policy
+    ///
+    /// Open D lines are measured again as the policy asks, and any that pass are no longer
+    /// reported. The D lines to disable are marked failed (see `failed_outputs`) and turned off.
+    pub fn respond_to_faults<DL: DelayMs<u8>>(
+        &mut self,
+        report: &SelfTestReport,
+        policy: &FaultPolicy,
+        delay: &mut DL,
+    ) -> Result<FaultResponse, Error<E>> {
+        let mut response = FaultResponse {
+            shorted: OutputMask::none(),
+            open: OutputMask::none(),
+            disabled: OutputMask::none(),
+        };
+        for d in D::iter() {
+            let mut found = report.fault(d);
+            for _ in 0..policy.open_retries {
+                if found != Some(ChannelFault::Open) {
+                    break;
+                }
+                delay.delay_ms(policy.retry_interval);
+                found = fault(&self.test_channel(d, report.vdd, delay)?, report.vdd);
+            }
+            match found {
+                Some(ChannelFault::Shorted) => {
+                    response.shorted = response.shorted.with(d);
+                    if policy.disable_shorted {
+                        response.disabled = response.disabled.with(d);
+                    }
+                }
+                Some(ChannelFault::Open) => {
+                    response.open = response.open.with(d);
+                    if policy.disable_open {
+                        response.disabled = response.disabled.with(d);
+                    }
+                }
+                None => {}
+            }
+        }
+        if response.disabled != OutputMask::none() {
+            self.failed_outputs = self.failed_outputs | response.disabled;
+            let on = self.outputs()?;
+            self.set_outputs(on)?;
+        }
+        Ok(response)
+    }
+
+    /// The D lines marked failed by `respond_to_faults`, which `set_outputs` keeps off
+    pub fn failed_outputs(&self) -> OutputMask {
+        self.failed_outputs
+    }
+
+    /// Forget the D lines marked failed, once repaired, so that they can be turned on again
+    pub fn clear_failed_outputs(&mut self) {
+        self.failed_outputs = OutputMask::none();
+    }
+
+    /// Check that every register holds its documented default value, returning the first that
+    /// does not
+    ///
+    /// Call right after `enable` followed by `reset`, before anything else is written: `enable`
+    /// itself changes CNTRL1 and MISC, which `reset` puts back.
+    pub fn verify_defaults(&mut self) -> Result<Option<DefaultMismatch>, Error<E>> {
+        for (register, expected) in reg::DEFAULTS.iter() {
+            let actual = self.read(*register)?;
+            if actual != *expected {
+                return Ok(Some(DefaultMismatch {
+                    register: *register,
+                    expected: *expected,
+                    actual,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock;
+
+    #[test]
+    fn verify_defaults_needs_a_reset_after_enable() {
+        let mut lp = mock::enabled();
+        let mismatch = lp.verify_defaults().unwrap().unwrap();
+        assert_eq!((mismatch.register, mismatch.expected), (reg::CNTRL1, 0x00));
+        lp.reset().unwrap();
+        assert_eq!(lp.verify_defaults().unwrap(), None);
+    }
+
+    #[test]
+    fn verify_defaults_reports_the_first_changed_register() {
+        let mut lp = mock::enabled();
+        lp.reset().unwrap();
+        assert_eq!(lp.verify_defaults().unwrap(), None);
+        lp.i2c.registers[usize::from(reg::D3_I_CTL)] = 0x20;
+        lp.i2c.registers[usize::from(reg::PROG2_START)] = 0x00;
+        assert_eq!(
+            lp.verify_defaults().unwrap(),
+            Some(DefaultMismatch {
+                register: reg::D3_I_CTL,
+                expected: 0xaf,
+                actual: 0x20,
+            })
+        );
+    }
+}
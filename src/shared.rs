@@ -0,0 +1,101 @@
+//! Sharing the driver between interrupt handlers and tasks
+//!
+//! `Shared` protects a driver with a `critical_section::Mutex`, so that it can live in a `static`
+//! and be used from both interrupt handlers and background tasks without hand-rolled unsafe
+//! sharing. Every access runs inside a critical section, so keep them short: prefer PWM and fader
+//! updates over operations that wait on ADC conversions.
+//!
+//! ```ignore
+//! static LP: Shared<I2c, Pin> = Shared::new();
+//!
+//! // at startup
+//! LP.init(Lp55231::new(i2c, Some(pin), Addr::_0x32));
+//!
+//! // in an interrupt handler
+//! LP.handle().set_pwm(D::D1, 0x80);
+//! ```
+
+use core::cell::RefCell;
+use core::fmt::Debug;
+use critical_section::Mutex;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use {Brightness, Error, Lp55231, D};
+
+/// A driver shared through a critical section
+pub struct Shared<I, P> {
+    /// The driver, once initialized
+    lp: Mutex<RefCell<Option<Lp55231<I, P>>>>,
+}
+
+impl<I, P> Shared<I, P> {
+    /// Create an empty container, suitable for a `static`
+    pub const fn new() -> Self {
+        Shared {
+            lp: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Move the driver into the container, returning the one previously held, if any
+    pub fn init(&self, lp: Lp55231<I, P>) -> Option<Lp55231<I, P>> {
+        critical_section::with(|cs| self.lp.borrow(cs).replace(Some(lp)))
+    }
+
+    /// Run `f` on the driver inside a critical section. Returns `None` if the container has not
+    /// been initialized.
+    pub fn lock<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Lp55231<I, P>) -> R,
+    {
+        critical_section::with(|cs| self.lp.borrow(cs).borrow_mut().as_mut().map(f))
+    }
+
+    /// Get a lightweight handle to the shared driver
+    pub fn handle(&self) -> Handle<'_, I, P> {
+        Handle { shared: self }
+    }
+}
+
+impl<I, P> Default for Shared<I, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A copyable reference to a `Shared` driver
+pub struct Handle<'a, I: 'a, P: 'a> {
+    /// The shared driver
+    shared: &'a Shared<I, P>,
+}
+
+impl<'a, I, P> Clone for Handle<'a, I, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, I, P> Copy for Handle<'a, I, P> {}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> Handle<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Run `f` on the driver inside a critical section. Returns `Error::NotEnabled` if the
+    /// container has not been initialized.
+    pub fn lock<R, F>(self, f: F) -> Result<R, Error<E>>
+    where
+        F: FnOnce(&mut Lp55231<I, P>) -> Result<R, Error<E>>,
+    {
+        self.shared.lock(f).unwrap_or(Err(Error::NotEnabled))
+    }
+
+    /// Set the D line to the provided PWM value
+    pub fn set_pwm<B: Into<Brightness>>(self, d: D, pwm: B) -> Result<(), Error<E>> {
+        self.lock(|lp| lp.set_pwm(d, pwm))
+    }
+}
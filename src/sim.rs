@@ -0,0 +1,354 @@
+//! Host-side engine simulation
+//!
+//! A `Simulator` runs a program on three simulated engines, cycle by cycle, and reports the PWM
+//! value of every D line after each cycle. A trace of the outputs can be compared against one
+//! recorded earlier, so that patterns and the generators building them can be snapshot-tested on
+//! the host, and checked across crate versions, without hardware:
+//!
+//! ```ignore
+//! let program = patterns::crossfade(Rgb::new(255, 0, 0), Rgb::new(0, 0, 255), Millis(500))?;
+//! let mut sim = Simulator::new(&program)
+//!     .with_map(Engine::E1, OutputMask::from(D::D1))
+//!     .with_map(Engine::E2, OutputMask::from(D::D2))
+//!     .with_map(Engine::E3, OutputMask::from(D::D3));
+//! let trace: Vec<[u8; 9]> = sim.trace().take(1100).collect();
+//! assert_eq!(trace, SNAPSHOT);
+//! ```
+//!
+//! A cycle is 16 ticks of the 32768 Hz clock, 0.49 ms; a prescaled ramp or wait step lasts 32
+//! cycles. The engines execute `ramp`, `wait`, `set_pwm`, `branch`, `end`, the variable
+//! instructions and the `mux_*` instructions; each other instruction takes one cycle, and so does
+//! every `trigger`, as if its triggers fired at once. Loop counts are kept per `branch`, as in
+//! `timing::analyze`. Variables start at zero, and C can be preset as the host would write it.
+
+use interrupt::EngineSet;
+use program::{Program, PROGRAM_MEMORY_SIZE};
+use {Engine, OutputMask, D};
+
+/// Cycles in a prescaled cycle
+const PRESCALE: u32 = 32;
+
+/// The three engines, in order
+const ENGINES: [Engine; 3] = [Engine::E1, Engine::E2, Engine::E3];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// A `ramp` or `wait` in progress
+struct Ramp {
+    /// Increments left, or 1 for a wait
+    steps: u16,
+    /// Step direction: 1 up, -1 down, 0 for a wait
+    direction: i8,
+    /// Cycles per step
+    step_cycles: u32,
+    /// Cycles left until the next step
+    countdown: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// State of a simulated engine
+struct EngineState {
+    /// Executing instructions; cleared by `end`
+    running: bool,
+    /// Program counter
+    pc: u8,
+    /// PWM value of the mapped D lines
+    pwm: u8,
+    /// Local variables A and B
+    vars: [u8; 2],
+    /// D lines driven
+    map: OutputMask,
+    /// First, last and current rows of the mapping table
+    table: [u8; 3],
+    /// Ramp or wait in progress
+    ramp: Option<Ramp>,
+}
+
+impl EngineState {
+    /// The value of the variable with the operand code, given the global variable
+    fn variable(&self, code: u16, global: u8) -> u8 {
+        match code & 0x3 {
+            0 => self.vars[0],
+            1 => self.vars[1],
+            _ => global,
+        }
+    }
+}
+
+/// Simulated engines running a program
+pub struct Simulator {
+    /// The program run
+    program: Program,
+    /// State of each engine
+    engines: [EngineState; 3],
+    /// Global variable C
+    global: u8,
+    /// Loops left of each branch instruction, while looping
+    loops: [Option<u8>; PROGRAM_MEMORY_SIZE],
+    /// Direct PWM value of each D line, for D lines no engine drives
+    direct: [u8; 9],
+    /// Cycles run
+    cycles: u32,
+}
+
+impl Simulator {
+    /// Simulate the program, with every engine owning a share of the program memory (see
+    /// `Program::memory_map`) running from its start address, as `Lp55231::start_program` sets
+    /// them, and driving no D line
+    pub fn new(program: &Program) -> Self {
+        let map = program.memory_map();
+        let mut running = EngineSet::none();
+        for e in ENGINES.iter() {
+            if map.used(*e) > 0 {
+                running = running.with(*e);
+            }
+        }
+        Simulator::with_engines(program, running)
+    }
+
+    /// Simulate the program with the engines in the set running from their start addresses, and
+    /// driving no D line
+    pub fn with_engines(program: &Program, running: EngineSet) -> Self {
+        let engine = |e: Engine| EngineState {
+            running: running.contains(e),
+            pc: program.start(e),
+            pwm: 0,
+            vars: [0; 2],
+            map: OutputMask::none(),
+            table: [0; 3],
+            ramp: None,
+        };
+        Simulator {
+            program: *program,
+            engines: [engine(Engine::E1), engine(Engine::E2), engine(Engine::E3)],
+            global: 0,
+            loops: [None; PROGRAM_MEMORY_SIZE],
+            direct: [0; 9],
+            cycles: 0,
+        }
+    }
+
+    /// Drive the D lines in the set from the engine, as `Lp55231::set_engine_map` does
+    pub fn with_map(mut self, e: Engine, outputs: OutputMask) -> Self {
+        self.engines[usize::from(u8::from(e))].map = outputs;
+        self
+    }
+
+    /// Preset the global variable C, as the host would write it
+    pub fn with_global(mut self, value: u8) -> Self {
+        self.global = value;
+        self
+    }
+
+    /// Set the PWM value of D lines no engine drives
+    pub fn with_direct(mut self, d: D, pwm: u8) -> Self {
+        self.direct[usize::from(u8::from(d))] = pwm;
+        self
+    }
+
+    /// Cycles run so far
+    pub fn cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    /// Whether every engine has ended
+    pub fn is_halted(&self) -> bool {
+        self.engines.iter().all(|e| !e.running)
+    }
+
+    /// The PWM value of each D line, D1 first: that of the lowest numbered engine driving it, or
+    /// its direct PWM value
+    pub fn outputs(&self) -> [u8; 9] {
+        let mut outputs = self.direct;
+        for d in D::iter() {
+            if let Some(engine) = self.engines.iter().find(|e| e.map.contains(d)) {
+                outputs[usize::from(u8::from(d))] = engine.pwm;
+            }
+        }
+        outputs
+    }
+
+    /// Run every engine for one cycle, returning the outputs after it
+    pub fn step(&mut self) -> [u8; 9] {
+        for i in 0..self.engines.len() {
+            self.cycle(i);
+        }
+        self.cycles = self.cycles.wrapping_add(1);
+        self.outputs()
+    }
+
+    /// Fill `buf` with the outputs after each of the next cycles
+    pub fn record(&mut self, buf: &mut [[u8; 9]]) {
+        for outputs in buf.iter_mut() {
+            *outputs = self.step();
+        }
+    }
+
+    /// An endless iterator over the outputs after each of the next cycles
+    pub fn trace(&mut self) -> Trace<'_> {
+        Trace { sim: self }
+    }
+
+    /// Run the engine for one cycle
+    fn cycle(&mut self, i: usize) {
+        if !self.engines[i].running {
+            return;
+        }
+        if self.engines[i].ramp.is_none() {
+            self.execute(i);
+        }
+        let engine = &mut self.engines[i];
+        if let Some(ramp) = engine.ramp.as_mut() {
+            ramp.countdown -= 1;
+            if ramp.countdown == 0 {
+                engine.pwm = match ramp.direction {
+                    1 => engine.pwm.saturating_add(1),
+                    -1 => engine.pwm.saturating_sub(1),
+                    _ => engine.pwm,
+                };
+                ramp.steps -= 1;
+                ramp.countdown = ramp.step_cycles;
+                if ramp.steps == 0 {
+                    engine.ramp = None;
+                    engine.pc = engine.pc.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Fetch and execute the engine's next instruction; instructions other than ramps and waits
+    /// complete within the cycle
+    fn execute(&mut self, i: usize) {
+        let instructions = self.program.instructions();
+        let start = self.program.start(ENGINES[i]);
+        let pc = usize::from(self.engines[i].pc);
+        let w = match instructions.get(pc) {
+            Some(w) => *w,
+            // Cleared memory reads as go_to_start
+            None => 0,
+        };
+        let mut next = self.engines[i].pc.wrapping_add(1);
+        let engine = &mut self.engines[i];
+        match w & 0xe000 {
+            _ if w == 0 => next = start,
+            0x0000 | 0x2000 | 0x4000 | 0x6000 => {
+                let step = u32::from((w >> 9) & 0x1f);
+                if step == 0 {
+                    engine.pwm = w as u8;
+                } else {
+                    let prescale = if w & 0x4000 != 0 { PRESCALE } else { 1 };
+                    let increments = w & 0xff;
+                    engine.ramp = Some(Ramp {
+                        steps: increments.max(1),
+                        direction: match (increments, w & 0x100 != 0) {
+                            (0, _) => 0,
+                            (_, false) => 1,
+                            (_, true) => -1,
+                        },
+                        step_cycles: step * prescale,
+                        countdown: step * prescale,
+                    });
+                    return;
+                }
+            }
+            0xa000 => {
+                let target = (w & 0x7f) as u8;
+                match ((w >> 7) & 0x3f) as u8 {
+                    0 => next = target,
+                    count => match self.loops[pc].unwrap_or(count) {
+                        0 => self.loops[pc] = None,
+                        left => {
+                            self.loops[pc] = Some(left - 1);
+                            next = target;
+                        }
+                    },
+                }
+            }
+            0xc000 => {
+                engine.running = false;
+                if w & 0x0800 != 0 {
+                    engine.pwm = 0;
+                    next = start;
+                } else {
+                    next = engine.pc;
+                }
+            }
+            0x8000 if w & 0xf000 == 0x9000 && w & 0x0c00 != 0x0c00 => {
+                let global = self.global;
+                let read = |code: u16| engine.variable(code, global);
+                let current = read(w >> 10);
+                let value = match (w >> 8) & 0x3 {
+                    0 => w as u8,
+                    1 => current.wrapping_add(w as u8),
+                    2 => current.wrapping_sub(w as u8),
+                    _ if w & 0x10 != 0 => read(w >> 2).wrapping_sub(read(w)),
+                    _ => read(w >> 2).wrapping_add(read(w)),
+                };
+                match (w >> 10) & 0x3 {
+                    0 => engine.vars[0] = value,
+                    1 => engine.vars[1] = value,
+                    _ => self.global = value,
+                }
+            }
+            0x8000 if w & 0xf800 == 0x8800 => {
+                let a = engine.variable(w >> 2, self.global);
+                let b = engine.variable(w, self.global);
+                let holds = match w & 0xfe00 {
+                    0x8800 => a != b,
+                    0x8a00 => a < b,
+                    0x8c00 => a >= b,
+                    _ => a == b,
+                };
+                if holds {
+                    next = next.wrapping_add(((w >> 4) & 0x1f) as u8);
+                }
+            }
+            0x8000 if w & 0xfc00 == 0x9c00 => {
+                let row = |addr: u8| {
+                    let w = instructions.get(usize::from(addr)).cloned().unwrap_or(0);
+                    OutputMask::from_bytes((w >> 8) as u8, w as u8)
+                };
+                let address = (w & 0x7f) as u8;
+                let [first, last, current] = engine.table;
+                let following = if current >= last { first } else { current + 1 };
+                let preceding = if current <= first { last } else { current - 1 };
+                match w & 0xff80 {
+                    0x9c00 => engine.table = [address, last, address],
+                    0x9c80 => engine.table[1] = address,
+                    0x9e00 => engine.table[0] = address,
+                    0x9f00 | 0x9f80 => engine.table[2] = address,
+                    _ => match w {
+                        0x9d80 | 0x9d81 => engine.table[2] = following,
+                        0x9dc0 | 0x9dc1 => engine.table[2] = preceding,
+                        _ => {}
+                    },
+                }
+                match w {
+                    0x9d00 => engine.map = OutputMask::none(),
+                    0x9d01..=0x9d09 => {
+                        engine.map = OutputMask::from(D::ALL[usize::from(w as u8 - 1)]);
+                    }
+                    0x9d80 | 0x9dc0 => engine.map = row(engine.table[2]),
+                    _ if matches!(w & 0xff80, 0x9c00 | 0x9f80) => engine.map = row(address),
+                    _ => {}
+                }
+            }
+            // Triggers fire at once, and other instructions take a cycle
+            _ => {}
+        }
+        engine.pc = next;
+    }
+}
+
+/// An endless iterator over the outputs of a `Simulator` after each cycle
+pub struct Trace<'a> {
+    /// The simulator stepped
+    sim: &'a mut Simulator,
+}
+
+impl<'a> Iterator for Trace<'a> {
+    type Item = [u8; 9];
+
+    fn next(&mut self) -> Option<[u8; 9]> {
+        Some(self.sim.step())
+    }
+}
@@ -0,0 +1,279 @@
+//! Brightness slew-rate limiting
+//!
+//! With a limit set by `Lp55231::set_slew_limit` or `Lp55231::set_fader_slew_limit`, a write that
+//! would move a D line's direct PWM value or a master fader by more than the limit is cut short:
+//! the channel moves by at most the limit, and the rest of the change is kept as the channel's
+//! target. `Lp55231::slew_tick`, called periodically, moves every channel on towards its target
+//! by at most the limit per call, so abrupt host commands turn into ramps.
+//!
+//! A channel moves by at most its limit per tick, however many writes it receives: a write to a
+//! channel that has already moved since the last tick only updates its target. The limit applies
+//! to the driver's writes of the registers, including `set_rgb`, `set_color`, scenes and
+//! batches; `Lp55231::flush` writes the registers one at a time, as the limit only follows
+//! single-register writes. `set_pwm_immediate` and `set_master_fader_immediate` bypass it, for
+//! effects that want instant changes, as do the blanking of a master fader by
+//! `Lp55231::flush_blanked`, scenes activated by `ota::Updater` and `write_frame_fast`.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Brightness, Error, Fader, Lp55231, OutputMask, D};
+
+/// Number of slew-limited channels: the 9 direct PWM registers, then the 3 master faders
+pub(crate) const SLEW_CHANNELS: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// State of a slew-limited channel
+pub(crate) struct SlewChannel {
+    /// Largest change per tick
+    limit: u8,
+    /// Value last written to the register
+    current: u8,
+    /// Value the channel is moving towards
+    target: u8,
+    /// The channel has moved since the last tick
+    stepped: bool,
+}
+
+impl SlewChannel {
+    /// The value one step from the current value towards `target`
+    fn step(&self, target: u8) -> u8 {
+        if target > self.current {
+            self.current.saturating_add(self.limit).min(target)
+        } else {
+            self.current.saturating_sub(self.limit).max(target)
+        }
+    }
+}
+
+/// How a register write is changed by the slew limits
+pub(crate) enum Slewed {
+    /// Write the value as given
+    Unchanged,
+    /// Write this value instead
+    Limited(u8),
+    /// Write nothing
+    Skipped,
+}
+
+/// The state a slew-limited channel takes once a write to it goes out
+pub(crate) struct SlewStep {
+    /// Index of the channel
+    channel: usize,
+    /// State of the channel after the write
+    state: SlewChannel,
+}
+
+/// The register of a slew-limited channel
+fn register(channel: usize) -> u8 {
+    if channel < 9 {
+        reg::D_PWM_BASE + channel as u8
+    } else {
+        reg::MASTER_FADE_1 + (channel - 9) as u8
+    }
+}
+
+/// Whether writes of the register are followed through the slew limits, which only see writes of
+/// a single register
+pub(crate) fn slew_limited(register: u8) -> bool {
+    channel(register).is_some()
+}
+
+/// The slew-limited channel of a register, if any
+fn channel(register: u8) -> Option<usize> {
+    if (reg::D_PWM_BASE..reg::D_PWM_BASE + 9).contains(&register) {
+        Some(usize::from(register - reg::D_PWM_BASE))
+    } else if (reg::MASTER_FADE_1..reg::MASTER_FADE_1 + 3).contains(&register) {
+        Some(usize::from(register - reg::MASTER_FADE_1) + 9)
+    } else {
+        None
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Limit the direct PWM value of the D lines in the set to change by at most `max_delta` per
+    /// tick (see `slew_tick`), or `None` to let them change freely
+    ///
+    /// The current PWM values are read, so that the first write is limited too. A limit of 0 is
+    /// raised to 1.
+    pub fn set_slew_limit(
+        &mut self,
+        outputs: OutputMask,
+        max_delta: Option<u8>,
+    ) -> Result<(), Error<E>> {
+        for d in outputs.iter() {
+            let i = usize::from(u8::from(d));
+            self.slew[i] = match max_delta {
+                Some(limit) => {
+                    let current = self.read(register(i))?;
+                    Some(SlewChannel {
+                        limit: limit.max(1),
+                        current,
+                        target: current,
+                        stepped: false,
+                    })
+                }
+                None => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// Limit the master fader to change by at most `max_delta` per tick (see `slew_tick`), or
+    /// `None` to let it change freely
+    ///
+    /// The current fader value is read, so that the first write is limited too. A limit of 0 is
+    /// raised to 1.
+    pub fn set_fader_slew_limit(
+        &mut self,
+        f: Fader,
+        max_delta: Option<u8>,
+    ) -> Result<(), Error<E>> {
+        let i = usize::from(u8::from(f)) + 9;
+        self.slew[i] = match max_delta {
+            Some(limit) => {
+                let current = self.read(register(i))?;
+                Some(SlewChannel {
+                    limit: limit.max(1),
+                    current,
+                    target: current,
+                    stepped: false,
+                })
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Move every slew-limited channel towards its target by at most its limit, returning whether
+    /// any channel has yet to reach its target
+    pub fn slew_tick(&mut self) -> Result<bool, Error<E>> {
+        let mut moving = false;
+        for i in 0..SLEW_CHANNELS {
+            let mut channel = match self.slew[i] {
+                Some(channel) => channel,
+                None => continue,
+            };
+            channel.stepped = false;
+            if channel.current != channel.target {
+                let next = channel.step(channel.target);
+                self.send_unlimited(&[register(i), next])?;
+                channel.current = next;
+                moving |= next != channel.target;
+            }
+            self.slew[i] = Some(channel);
+        }
+        Ok(moving)
+    }
+
+    /// Set the D line to the provided PWM value, as with `set_pwm`, at once, regardless of any
+    /// slew limit
+    pub fn set_pwm_immediate<B: Into<Brightness>>(&mut self, d: D, pwm: B) -> Result<(), Error<E>> {
+        let pwm = self.output_pwm(d, pwm.into().0);
+        self.send_immediate(register(usize::from(u8::from(d))), pwm)
+    }
+
+    /// Set the master fader to the provided value, as with `set_master_fader`, at once,
+    /// regardless of any slew limit
+    pub fn set_master_fader_immediate(&mut self, f: Fader, value: u8) -> Result<(), Error<E>> {
+        let value = self.fader_curves[usize::from(u8::from(f))].apply(value);
+        let value = self.dimmed_fader(f, value);
+        self.send_immediate(register(usize::from(u8::from(f)) + 9), value)
+    }
+
+    /// Write the value to a slew-limited channel's register, making it the channel's current and
+    /// target value
+    fn send_immediate(&mut self, register: u8, value: u8) -> Result<(), Error<E>> {
+        self.send_unlimited(&[register, value])?;
+        if let Some(channel) = channel(register).and_then(|i| self.slew[i].as_mut()) {
+            channel.current = value;
+            channel.target = value;
+        }
+        Ok(())
+    }
+
+    /// Write `bytes` without applying the slew limits
+    pub(crate) fn send_unlimited(&mut self, bytes: &[u8]) -> Result<(), Error<E>> {
+        self.slew_bypass = true;
+        let res = self.send(bytes);
+        self.slew_bypass = false;
+        res
+    }
+
+    /// Follow a register write through the slew limits, returning how it must be changed and the
+    /// channel state to save with `slew_commit` once the write has gone out
+    pub(crate) fn slew_filter(&mut self, bytes: &[u8]) -> (Slewed, Option<SlewStep>) {
+        if self.slew_bypass {
+            return (Slewed::Unchanged, None);
+        }
+        let (i, value) = match bytes {
+            [register, value] => match channel(*register) {
+                Some(i) => (i, *value),
+                None => return (Slewed::Unchanged, None),
+            },
+            _ => return (Slewed::Unchanged, None),
+        };
+        let channel = match self.slew[i].as_mut() {
+            Some(channel) => channel,
+            None => return (Slewed::Unchanged, None),
+        };
+        if channel.stepped || channel.current == value {
+            channel.target = value;
+            return (Slewed::Skipped, None);
+        }
+        let next = channel.step(value);
+        let step = SlewStep {
+            channel: i,
+            state: SlewChannel {
+                current: next,
+                target: value,
+                stepped: true,
+                ..*channel
+            },
+        };
+        if next == value {
+            (Slewed::Unchanged, Some(step))
+        } else {
+            (Slewed::Limited(next), Some(step))
+        }
+    }
+
+    /// Save the channel state returned by `slew_filter`, once its write has gone out
+    pub(crate) fn slew_commit(&mut self, step: Option<SlewStep>) {
+        if let Some(step) = step {
+            self.slew[step.channel] = Some(step.state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mock;
+    use registers as reg;
+    use Fader;
+
+    #[test]
+    fn failed_writes_leave_the_channel_unchanged() {
+        let mut lp = mock::enabled();
+        lp.set_fader_slew_limit(Fader::F1, Some(10)).unwrap();
+        lp.i2c.fail_after = Some(0);
+        assert!(lp.set_master_fader(Fader::F1, 200).is_err());
+
+        // The step the chip never got is still to be made, in the same tick
+        lp.i2c.fail_after = None;
+        lp.i2c.clear();
+        lp.set_master_fader(Fader::F1, 200).unwrap();
+        assert_eq!(lp.i2c.writes, [[reg::MASTER_FADE_1, 10]]);
+        assert!(lp.slew_tick().unwrap());
+        assert_eq!(lp.i2c.writes[1], [reg::MASTER_FADE_1, 20]);
+    }
+}
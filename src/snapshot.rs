@@ -0,0 +1,208 @@
+//! Register snapshot and restore
+//!
+//! `Lp55231::snapshot` captures every writable configuration register into a `DeviceState`
+//! (`Lp55231::snapshot_with_program` the program memory as well), and `Lp55231::restore` writes
+//! it back. This lets the full device state be saved before the device is powered down and
+//! restored after it is enabled again.
+//!
+//! Engine program counters and variables, and registers that start conversions or reset the
+//! device, are not captured: restored engines start over from their programs' start addresses.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+#[cfg(feature = "engines")]
+use program::{Program, PROGRAM_MEMORY_SIZE};
+use registers as reg;
+use {Error, Lp55231};
+#[cfg(feature = "engines")]
+use Engine;
+
+/// Number of registers captured by a snapshot
+pub const SNAPSHOT_LEN: usize = 46;
+
+/// The registers captured by a snapshot, in the order they are restored
+///
+/// MISC comes first, as it selects the clock and enables auto-increment. CNTRL1 and CNTRL2, which
+/// hold the engines' modes, are kept apart in `DeviceState` and restored last, so that engines only
+/// start once everything they use is in place.
+pub const SNAPSHOT_REGISTERS: [u8; SNAPSHOT_LEN] = [
+    reg::MISC,
+    reg::RATIO_MSB,
+    reg::RATIO_LSB,
+    reg::OUTPUT_ONOFF_MSB,
+    reg::OUTPUT_ONOFF_LSB,
+    reg::D1_CTRL,
+    reg::D2_CTRL,
+    reg::D3_CTRL,
+    reg::D4_CTRL,
+    reg::D5_CTRL,
+    reg::D6_CTRL,
+    reg::D7_CTRL,
+    reg::D8_CTRL,
+    reg::D9_CTRL,
+    reg::D1_PWM,
+    reg::D2_PWM,
+    reg::D3_PWM,
+    reg::D4_PWM,
+    reg::D5_PWM,
+    reg::D6_PWM,
+    reg::D7_PWM,
+    reg::D8_PWM,
+    reg::D9_PWM,
+    reg::D1_I_CTL,
+    reg::D2_I_CTL,
+    reg::D3_I_CTL,
+    reg::D4_I_CTL,
+    reg::D5_I_CTL,
+    reg::D6_I_CTL,
+    reg::D7_I_CTL,
+    reg::D8_I_CTL,
+    reg::D9_I_CTL,
+    reg::INT_GPIO,
+    reg::MASTER_FADE_1,
+    reg::MASTER_FADE_2,
+    reg::MASTER_FADE_3,
+    reg::PROG1_START,
+    reg::PROG2_START,
+    reg::PROG3_START,
+    reg::ENG1_MAP_MSB,
+    reg::ENG1_MAP_LSB,
+    reg::ENG2_MAP_MSB,
+    reg::ENG2_MAP_LSB,
+    reg::ENG3_MAP_MSB,
+    reg::ENG3_MAP_LSB,
+    reg::GAIN_CHANGE,
+];
+
+#[derive(Copy, Clone)]
+/// The device state captured by `Lp55231::snapshot`
+pub struct DeviceState {
+    /// CNTRL1, holding CHIP_EN and each engine's execution mode
+    pub cntrl1: u8,
+    /// CNTRL2, holding each engine's operation mode
+    pub cntrl2: u8,
+    /// The value of each register in `SNAPSHOT_REGISTERS`
+    pub registers: [u8; SNAPSHOT_LEN],
+    /// The program memory, with the engines' start addresses, if captured
+    #[cfg(feature = "engines")]
+    pub program: Option<Program>,
+}
+
+impl DeviceState {
+    /// The captured value of a register, if it is captured
+    pub fn register(&self, register: u8) -> Option<u8> {
+        match register {
+            reg::CNTRL1 => Some(self.cntrl1),
+            reg::CNTRL2 => Some(self.cntrl2),
+            _ => SNAPSHOT_REGISTERS
+                .iter()
+                .position(|r| *r == register)
+                .map(|i| self.registers[i]),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Capture the device's configuration
+    pub fn snapshot(&mut self) -> Result<DeviceState, Error<E>> {
+        let cntrl1 = self.read(reg::CNTRL1)?;
+        let cntrl2 = self.read(reg::CNTRL2)?;
+        let mut registers = [0u8; SNAPSHOT_LEN];
+        for (value, register) in registers.iter_mut().zip(SNAPSHOT_REGISTERS.iter()) {
+            *value = self.read(*register)?;
+        }
+        Ok(DeviceState {
+            cntrl1,
+            cntrl2,
+            registers,
+            #[cfg(feature = "engines")]
+            program: None,
+        })
+    }
+
+    /// Capture the device's configuration and the program memory
+    ///
+    /// Reading the program memory requires an engine in load mode, so every engine is held and
+    /// disabled afterwards; `restore` sets them going again. Waiting for the engines to become
+    /// idle in load mode reads (and so clears) the STATUS_IRQ register.
+    #[cfg(feature = "engines")]
+    pub fn snapshot_with_program(&mut self) -> Result<DeviceState, Error<E>> {
+        let mut state = self.snapshot()?;
+        let start = [
+            state.register(reg::PROG1_START).unwrap_or(0),
+            state.register(reg::PROG2_START).unwrap_or(0),
+            state.register(reg::PROG3_START).unwrap_or(0),
+        ];
+        state.program = Some(self.capture_program(start)?);
+        Ok(state)
+    }
+
+    /// Write a captured state back to the device, which must be enabled
+    ///
+    /// Every engine is held and disabled while the state is written. The engines' operation and
+    /// execution modes are restored last, so running engines restart their programs. Restoring a
+    /// program waits for the engines to become idle in load mode, which reads (and so clears)
+    /// the STATUS_IRQ register.
+    pub fn restore(&mut self, state: &DeviceState) -> Result<(), Error<E>> {
+        // CNTRL1 with every engine in hold, followed by CNTRL2 with every engine disabled
+        self.send(&[
+            reg::CNTRL1,
+            reg::Cntrl1::CHIP_EN.bits(),
+            reg::Cntrl2::empty().bits(),
+        ])?;
+        for (register, value) in SNAPSHOT_REGISTERS.iter().zip(state.registers.iter()) {
+            self.send(&[*register, *value])?;
+        }
+        #[cfg(feature = "engines")]
+        {
+            if let Some(program) = state.program {
+                self.enter_load_mode(Engine::E1)?;
+                self.load_program(&program)?;
+                self.send(&[reg::CNTRL2, reg::Cntrl2::empty().bits()])?;
+            }
+        }
+        self.send(&[reg::CNTRL2, state.cntrl2])?;
+        self.send(&[reg::CNTRL1, state.cntrl1 | reg::Cntrl1::CHIP_EN.bits()])
+    }
+
+    /// Read the whole program memory, with every engine held and disabled, as a program with the
+    /// start addresses
+    #[cfg(feature = "engines")]
+    pub(crate) fn capture_program(&mut self, start: [u8; 3]) -> Result<Program, Error<E>> {
+        self.halt_all_engines(false)?;
+        let mut instructions = [0u16; PROGRAM_MEMORY_SIZE];
+        let read = self
+            .enter_load_mode(Engine::E1)
+            .and_then(|()| self.read_program(0, &mut instructions));
+        self.send(&[reg::CNTRL2, reg::Cntrl2::empty().bits()])?;
+        read?;
+        Program::new(start, &instructions).map_err(Error::from)
+    }
+}
+
+#[cfg(all(test, feature = "engines"))]
+mod tests {
+    use mock;
+
+    #[test]
+    fn program_round_trips_once_the_engines_are_idle() {
+        let mut lp = mock::enabled();
+        lp.i2c.program[..4].copy_from_slice(&[0x40, 0xff, 0xa0, 0x00]);
+        lp.i2c.load_busy_reads = 2;
+        let state = lp.snapshot_with_program().unwrap();
+
+        let mut lp = mock::enabled();
+        lp.i2c.load_busy_reads = 2;
+        lp.restore(&state).unwrap();
+        assert_eq!(&lp.i2c.program[..4], &[0x40, 0xff, 0xa0, 0x00]);
+    }
+}
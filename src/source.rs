@@ -0,0 +1,592 @@
+//! TI assembly source
+//!
+//! TI's LP5523/LP55231 evaluation software develops engine programs as assembly source, kept in
+//! `.src` files. `assemble` builds a `Program` from such source with the encoders of `asm`, so
+//! that patterns developed in TI's GUI load without first being exported as hex (see
+//! `Program::from_hex`):
+//!
+//! ```text
+//! .segment program1
+//!         mux_map_addr row1
+//! loop1:  ramp 0.5, 255       ; fade in over half a second
+//!         wait 0.2
+//!         ramp 0.5, -255
+//!         branch 0, loop1
+//! row1:   dw 0000000111111111b
+//! ```
+//!
+//! Each line holds an optional label (`name:`) followed by a directive or an instruction, and `;`
+//! starts a comment. Every instruction assembles to a single word. `.segment program1`
+//! (`program2`, `program3`) starts an engine's program at the next instruction; engines without a
+//! segment start at address 0. `dw` places a literal word, such as a mapping table row.
+//!
+//! Instructions take their operands as in TI's assembler:
+//!
+//! - `ramp time, increments` and `wait time`, with the time in seconds. The step time and
+//!   prescaler are chosen to match it as closely as they can.
+//! - `set_pwm value`, `rst`, `branch count, label`, and `end` with optional `i` (interrupt) and
+//!   `r` (reset) flags
+//! - `ld`, `add` and `sub` of a value to `ra`, `rb` or `rc`, and `add` and `sub` of two variables
+//!   into a third
+//! - `jne`, `jl`, `jge` and `je` of two variables, skipping a number of instructions or up to a
+//!   label
+//! - `trigger` with a set of engines to wait for and to send to, such as `trigger w{1|e}, s{2}`,
+//!   where `e` is the TRIG pin
+//! - `mux_map_start`, `mux_ld_end` and `mux_map_addr` of a label, `mux_map_next`, `mux_map_prev`,
+//!   `mux_sel` of a D line from 1 to 9 (0 for none), and `mux_clr`
+//!
+//! Numbers are decimal, hexadecimal with a `0x` prefix or an `h` suffix, or binary with a `b`
+//! suffix. Ramps and `set_pwm` taking their operands from variables are not supported.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use asm::{self, Condition, End, Triggers, Variable};
+use patterns::{CYCLE_TICKS, PRESCALED_CYCLE_TICKS};
+use program::{Program, ProgramError, PROGRAM_MEMORY_SIZE};
+use timing::CLOCK_HZ;
+use D;
+
+/// Largest number of labels a source can define
+pub const MAX_SOURCE_LABELS: usize = 32;
+
+/// Longest step time of a `ramp` or `wait`, in cycles
+const MAX_STEP: u32 = 31;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// An error in assembly source, and where it was found
+///
+/// Displays as e.g. `line 4: no such label`.
+pub struct SourceError {
+    /// Line number, counting from 1
+    pub line: usize,
+    /// What is wrong with the line
+    pub error: ProgramError,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl core::error::Error for SourceError {}
+
+/// Assemble TI assembly source into a program
+///
+/// Fails with the first error found, and the line it is on.
+pub fn assemble(text: &str) -> Result<Program, SourceError> {
+    let mut symbols = Symbols {
+        entries: [("", 0); MAX_SOURCE_LABELS],
+        len: 0,
+    };
+    let mut start = [0u8; 3];
+    let mut address = 0;
+    // The first pass finds the address of every label, so that branches can jump forward
+    for (n, line) in text.lines().enumerate() {
+        let at = |error| SourceError { line: n + 1, error };
+        let statement = Statement::parse(line);
+        if let Some(label) = statement.label {
+            symbols.define(label, address as u8).map_err(at)?;
+        }
+        match statement.mnemonic {
+            "" => {}
+            ".segment" => {
+                let engine = segment(statement.operands).map_err(at)?;
+                if address >= PROGRAM_MEMORY_SIZE {
+                    return Err(at(ProgramError::InvalidStart));
+                }
+                start[engine] = address as u8;
+            }
+            _ if address < PROGRAM_MEMORY_SIZE => address += 1,
+            _ => return Err(at(ProgramError::TooLarge)),
+        }
+    }
+    let mut words = [0u16; PROGRAM_MEMORY_SIZE];
+    let mut len = 0;
+    for (n, line) in text.lines().enumerate() {
+        let statement = Statement::parse(line);
+        if statement.mnemonic.is_empty() || statement.mnemonic == ".segment" {
+            continue;
+        }
+        let word = statement
+            .encode(len as u8, &symbols)
+            .map_err(|error| SourceError { line: n + 1, error })?;
+        if let Some(slot) = words.get_mut(len) {
+            *slot = word;
+            len += 1;
+        }
+    }
+    Program::new(start, &words[..len]).map_err(|error| SourceError { line: 0, error })
+}
+
+/// Labels defined by a source, with their addresses
+struct Symbols<'a> {
+    /// Name and address of each label
+    entries: [(&'a str, u8); MAX_SOURCE_LABELS],
+    /// Number of labels
+    len: usize,
+}
+
+impl<'a> Symbols<'a> {
+    /// Define a label, which must not already be defined
+    fn define(&mut self, name: &'a str, address: u8) -> Result<(), ProgramError> {
+        if name.is_empty() || self.address(name).is_some() {
+            return Err(ProgramError::InvalidText);
+        }
+        let entry = self
+            .entries
+            .get_mut(self.len)
+            .ok_or(ProgramError::TooManyLabels)?;
+        *entry = (name, address);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The address of the label with the name, if any
+    fn address(&self, name: &str) -> Option<u8> {
+        self.entries[..self.len]
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, address)| *address)
+    }
+}
+
+/// A source line, split into its label, mnemonic and operands, each of which may be empty
+struct Statement<'a> {
+    /// Label defined by the line
+    label: Option<&'a str>,
+    /// Directive or instruction
+    mnemonic: &'a str,
+    /// Comma-separated operands
+    operands: &'a str,
+}
+
+impl<'a> Statement<'a> {
+    /// Split a line, dropping its comment
+    fn parse(line: &'a str) -> Self {
+        let code = line.split(';').next().unwrap_or("").trim();
+        let (label, rest) = match code.split_once(':') {
+            Some((label, rest)) if !label.contains(char::is_whitespace) => {
+                (Some(label), rest.trim())
+            }
+            _ => (None, code),
+        };
+        let (mnemonic, operands) = match rest.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+            None => (rest, ""),
+        };
+        Statement {
+            label,
+            mnemonic,
+            operands,
+        }
+    }
+
+    /// Encode the instruction at `address`
+    fn encode(&self, address: u8, symbols: &Symbols) -> Result<u16, ProgramError> {
+        let text = self.operands;
+        match self.mnemonic {
+            "ramp" => {
+                let [time, increments] = operands(text)?;
+                let increments = signed(increments)?;
+                let steps = u32::from(increments.unsigned_abs()).max(1);
+                let (step, prescale) = step_time(seconds(time)?, steps)?;
+                asm::ramp(step, prescale, increments)
+            }
+            "wait" => {
+                let [time] = operands(text)?;
+                let (step, prescale) = step_time(seconds(time)?, 1)?;
+                asm::wait(step, prescale)
+            }
+            "set_pwm" => {
+                let [pwm] = operands(text)?;
+                Ok(asm::set_pwm(byte(pwm)?))
+            }
+            "rst" => {
+                let [] = operands(text)?;
+                Ok(asm::go_to_start())
+            }
+            "branch" => {
+                let [count, target] = operands(text)?;
+                asm::branch(byte(count)?, target_address(target, symbols)?)
+            }
+            "end" => {
+                let mut end = End::default();
+                for flag in text.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                    match flag {
+                        "i" => end.send_interrupt = true,
+                        "r" => end.reset_pc = true,
+                        _ => return Err(ProgramError::InvalidText),
+                    }
+                }
+                Ok(asm::end(end))
+            }
+            "ld" => {
+                let [var, value] = operands(text)?;
+                Ok(asm::load(variable(var)?, byte(value)?))
+            }
+            "add" | "sub" => {
+                let add = self.mnemonic == "add";
+                if let Ok([target, a, b]) = operands(text) {
+                    let (target, a, b) = (variable(target)?, variable(a)?, variable(b)?);
+                    return Ok(if add {
+                        asm::add_vars(target, a, b)
+                    } else {
+                        asm::sub_vars(target, a, b)
+                    });
+                }
+                let [var, value] = operands(text)?;
+                let (var, value) = (variable(var)?, byte(value)?);
+                Ok(if add {
+                    asm::add(var, value)
+                } else {
+                    asm::sub(var, value)
+                })
+            }
+            "jne" | "jl" | "jge" | "je" => {
+                let condition = match self.mnemonic {
+                    "jne" => Condition::NotEqual,
+                    "jl" => Condition::Less,
+                    "jge" => Condition::GreaterOrEqual,
+                    _ => Condition::Equal,
+                };
+                let [skip, a, b] = operands(text)?;
+                let skip = if skip.starts_with(|c: char| c.is_ascii_digit()) {
+                    byte(skip)?
+                } else {
+                    let target = symbols.address(skip).ok_or(ProgramError::UnknownLabel)?;
+                    target
+                        .checked_sub(address.saturating_add(1))
+                        .ok_or(ProgramError::InvalidOperand)?
+                };
+                asm::jump(condition, skip, variable(a)?, variable(b)?)
+            }
+            "trigger" => {
+                let (mut wait, mut send) = (Triggers::NONE, Triggers::NONE);
+                for set in text.split(',').map(str::trim) {
+                    let engines = |prefix| set.strip_prefix(prefix)?.strip_suffix('}');
+                    if let Some(engines) = engines("w{") {
+                        wait = triggers(engines)?;
+                    } else if let Some(engines) = engines("s{") {
+                        send = triggers(engines)?;
+                    } else {
+                        return Err(ProgramError::InvalidText);
+                    }
+                }
+                Ok(asm::trigger(wait, send))
+            }
+            "mux_map_start" | "mux_ld_end" | "mux_map_addr" => {
+                let [target] = operands(text)?;
+                let target = target_address(target, symbols)?;
+                match self.mnemonic {
+                    "mux_map_start" => asm::mux_map_start(target),
+                    "mux_ld_end" => asm::mux_ld_end(target),
+                    _ => asm::mux_map_addr(target),
+                }
+            }
+            "mux_map_next" => {
+                let [] = operands(text)?;
+                Ok(asm::mux_map_next())
+            }
+            "mux_map_prev" => {
+                let [] = operands(text)?;
+                Ok(asm::mux_map_prev())
+            }
+            "mux_sel" => {
+                let [d] = operands(text)?;
+                let d = match byte(d)? {
+                    0 => None,
+                    n => Some(D::try_from(n - 1).map_err(|_| ProgramError::InvalidOperand)?),
+                };
+                Ok(asm::mux_sel(d))
+            }
+            "mux_clr" => {
+                let [] = operands(text)?;
+                Ok(asm::mux_clr())
+            }
+            "dw" => {
+                let [word] = operands(text)?;
+                u16::try_from(number(word)?).map_err(|_| ProgramError::InvalidOperand)
+            }
+            _ => Err(ProgramError::InvalidText),
+        }
+    }
+}
+
+/// The engine whose program a `.segment` directive starts, as an index
+fn segment(name: &str) -> Result<usize, ProgramError> {
+    match name {
+        "program1" => Ok(0),
+        "program2" => Ok(1),
+        "program3" => Ok(2),
+        _ => Err(ProgramError::InvalidText),
+    }
+}
+
+/// Split comma-separated operands, of which there must be exactly `N`
+fn operands<const N: usize>(text: &str) -> Result<[&str; N], ProgramError> {
+    let mut ops = [""; N];
+    let mut count = 0;
+    if !text.is_empty() {
+        for op in text.split(',') {
+            let slot = ops.get_mut(count).ok_or(ProgramError::InvalidText)?;
+            *slot = op.trim();
+            count += 1;
+        }
+    }
+    if count != N || ops.iter().any(|op| op.is_empty()) {
+        return Err(ProgramError::InvalidText);
+    }
+    Ok(ops)
+}
+
+/// Parse an unsigned number
+fn number(text: &str) -> Result<u32, ProgramError> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(hex) = text.strip_suffix('h') {
+        (hex, 16)
+    } else if let Some(binary) = text.strip_suffix('b') {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+    u32::from_str_radix(digits, radix).map_err(|_| ProgramError::InvalidText)
+}
+
+/// Parse a number from 0 to 255
+fn byte(text: &str) -> Result<u8, ProgramError> {
+    u8::try_from(number(text)?).map_err(|_| ProgramError::InvalidOperand)
+}
+
+/// Parse a ramp's increments, from -255 to 255
+fn signed(text: &str) -> Result<i16, ProgramError> {
+    let (negative, magnitude) = match text.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let magnitude = i16::from(byte(magnitude.trim())?);
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+/// Parse a variable name
+fn variable(text: &str) -> Result<Variable, ProgramError> {
+    match text {
+        "ra" => Ok(Variable::A),
+        "rb" => Ok(Variable::B),
+        "rc" => Ok(Variable::C),
+        _ => Err(ProgramError::InvalidText),
+    }
+}
+
+/// Parse a program memory address, given as a label or a number
+fn target_address(text: &str, symbols: &Symbols) -> Result<u8, ProgramError> {
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        byte(text)
+    } else {
+        symbols.address(text).ok_or(ProgramError::UnknownLabel)
+    }
+}
+
+/// Parse a `|`-separated set of trigger sources or destinations
+fn triggers(text: &str) -> Result<Triggers, ProgramError> {
+    let mut triggers = Triggers::NONE;
+    for engine in text.split('|').map(str::trim) {
+        match engine {
+            "1" => triggers.e1 = true,
+            "2" => triggers.e2 = true,
+            "3" => triggers.e3 = true,
+            "e" => triggers.external = true,
+            _ => return Err(ProgramError::InvalidText),
+        }
+    }
+    Ok(triggers)
+}
+
+/// Parse a time in seconds, such as `0.25`, into clock ticks, to the nearest tick
+fn seconds(text: &str) -> Result<u32, ProgramError> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(ProgramError::InvalidText);
+    }
+    let whole = match whole {
+        "" => 0,
+        whole => whole.parse::<u64>().map_err(|_| ProgramError::InvalidText)?,
+    };
+    let (mut numerator, mut denominator) = (0u64, 1u64);
+    for c in fraction.chars() {
+        let digit = c.to_digit(10).ok_or(ProgramError::InvalidText)?;
+        // Digits past the nanosecond are far below a clock tick
+        if denominator < 1_000_000_000 {
+            numerator = numerator * 10 + u64::from(digit);
+            denominator *= 10;
+        }
+    }
+    let hz = u64::from(CLOCK_HZ);
+    let ticks = whole
+        .checked_mul(hz)
+        .and_then(|t| t.checked_add((numerator * hz + denominator / 2) / denominator))
+        .ok_or(ProgramError::InvalidOperand)?;
+    u32::try_from(ticks).map_err(|_| ProgramError::InvalidOperand)
+}
+
+/// The step time, in cycles, and prescaler that take `steps` steps over `ticks` clock ticks as
+/// closely as they can
+///
+/// Fails with `ProgramError::InvalidOperand` if each step would be shorter than a cycle, or
+/// longer than the longest prescaled step time.
+fn step_time(ticks: u32, steps: u32) -> Result<(u8, bool), ProgramError> {
+    let per_step = (ticks + steps / 2) / steps;
+    match (per_step + CYCLE_TICKS / 2) / CYCLE_TICKS {
+        0 => return Err(ProgramError::InvalidOperand),
+        cycles if cycles <= MAX_STEP => return Ok((cycles as u8, false)),
+        _ => {}
+    }
+    match (per_step + PRESCALED_CYCLE_TICKS / 2) / PRESCALED_CYCLE_TICKS {
+        cycles if cycles <= MAX_STEP => Ok((cycles as u8, true)),
+        _ => Err(ProgramError::InvalidOperand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_the_module_example() {
+        let program = assemble(
+            ".segment program1\n\
+             \x20       mux_map_addr row1\n\
+             loop1:  ramp 0.5, 255       ; fade in over half a second\n\
+             \x20       wait 0.2\n\
+             \x20       ramp 0.5, -255\n\
+             \x20       branch 0, loop1\n\
+             row1:   dw 0000000111111111b\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.instructions(),
+            &[
+                asm::mux_map_addr(5).unwrap(),
+                asm::ramp(4, false, 255).unwrap(),
+                asm::wait(13, true).unwrap(),
+                asm::ramp(4, false, -255).unwrap(),
+                asm::branch(0, 1).unwrap(),
+                0b0000_0001_1111_1111,
+            ][..]
+        );
+    }
+
+    #[test]
+    fn segments_set_start_addresses() {
+        let program = assemble(
+            ".segment program1\nset_pwm 255\nend\n\
+             .segment program3\nset_pwm 0x80\nend i, r\n\
+             .segment program2\nset_pwm 10h\nend r\n",
+        )
+        .unwrap();
+        assert_eq!(program.start(::Engine::E1), 0);
+        assert_eq!(program.start(::Engine::E2), 4);
+        assert_eq!(program.start(::Engine::E3), 2);
+        assert_eq!(program.instructions()[2], asm::set_pwm(0x80));
+        assert_eq!(program.instructions()[4], asm::set_pwm(0x10));
+        let end = End {
+            send_interrupt: true,
+            reset_pc: true,
+        };
+        assert_eq!(program.instructions()[3], asm::end(end));
+    }
+
+    #[test]
+    fn encodes_variables_jumps_and_triggers() {
+        let program = assemble(
+            "ld ra, 3\nadd rb, ra, rc\nsub rc, 1\n\
+             jne skip, ra, rb\ntrigger w{1|e}, s{2}\nmux_sel 9\nskip: mux_sel 0\nrst\n",
+        )
+        .unwrap();
+        let triggers = |e1, e2, external| Triggers {
+            e1,
+            e2,
+            e3: false,
+            external,
+        };
+        assert_eq!(
+            program.instructions(),
+            &[
+                asm::load(Variable::A, 3),
+                asm::add_vars(Variable::B, Variable::A, Variable::C),
+                asm::sub(Variable::C, 1),
+                asm::jump(Condition::NotEqual, 2, Variable::A, Variable::B).unwrap(),
+                asm::trigger(triggers(true, false, true), triggers(false, true, false)),
+                asm::mux_sel(Some(D::D9)),
+                asm::mux_clr(),
+                asm::go_to_start(),
+            ][..]
+        );
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        let error = |text| assemble(text).unwrap_err();
+        assert_eq!(
+            error("set_pwm 1\nbranch 0, nowhere\n"),
+            SourceError {
+                line: 2,
+                error: ProgramError::UnknownLabel,
+            }
+        );
+        assert_eq!(error("set_pwm 256").error, ProgramError::InvalidOperand);
+        assert_eq!(error("ramp 0, 255").error, ProgramError::InvalidOperand);
+        assert_eq!(error("wait 1").error, ProgramError::InvalidOperand);
+        assert_eq!(error("mux_sel 10").error, ProgramError::InvalidOperand);
+        assert_eq!(error("ramp 0.5").error, ProgramError::InvalidText);
+        assert_eq!(error("frobnicate").error, ProgramError::InvalidText);
+        assert_eq!(error("a: rst\na: rst").error, ProgramError::InvalidText);
+    }
+
+    #[test]
+    fn rejects_programs_over_the_memory_size() {
+        let mut text = [0u8; 8 * 97];
+        for line in text.chunks_mut(8) {
+            line.copy_from_slice(b"mux_clr\n");
+        }
+        let text = core::str::from_utf8(&text).unwrap();
+        assert_eq!(assemble(&text[..8 * 96]).unwrap().instructions().len(), 96);
+        assert_eq!(
+            assemble(text).unwrap_err(),
+            SourceError {
+                line: 97,
+                error: ProgramError::TooLarge,
+            }
+        );
+    }
+
+    #[test]
+    fn byte_operands_stop_at_255() {
+        assert_eq!(byte("255"), Ok(255));
+        assert_eq!(byte("0ffh"), Ok(255));
+        assert_eq!(byte("256"), Err(ProgramError::InvalidOperand));
+        assert_eq!(byte("100h"), Err(ProgramError::InvalidOperand));
+        assert_eq!(signed("-255"), Ok(-255));
+        assert_eq!(signed("+255"), Ok(255));
+        assert_eq!(signed("-256"), Err(ProgramError::InvalidOperand));
+        let error = assemble("ld ra, 256").unwrap_err().error;
+        assert_eq!(error, ProgramError::InvalidOperand);
+    }
+
+    #[test]
+    fn picks_the_closest_step_time() {
+        assert_eq!(step_time(31 * CYCLE_TICKS, 1), Ok((31, false)));
+        assert_eq!(step_time(32 * CYCLE_TICKS, 1), Ok((1, true)));
+        assert_eq!(step_time(31 * PRESCALED_CYCLE_TICKS, 1), Ok((31, true)));
+        assert_eq!(
+            step_time(32 * PRESCALED_CYCLE_TICKS, 1),
+            Err(ProgramError::InvalidOperand)
+        );
+        assert_eq!(step_time(CYCLE_TICKS / 2 - 1, 1), Err(ProgramError::InvalidOperand));
+        assert_eq!(seconds("1"), Ok(CLOCK_HZ));
+        assert_eq!(seconds(".5"), Ok(CLOCK_HZ / 2));
+        assert_eq!(seconds("x"), Err(ProgramError::InvalidText));
+    }
+}
@@ -0,0 +1,208 @@
+//! Streaming channel updates
+//!
+//! External control protocols (DMX-style frames, serial commands, BLE characteristics) deliver
+//! the 9 channel values as frames, often faster or more irregularly than is worth writing to the
+//! device. `FrameSink` is the uniform entry point for such frames, implemented by the driver
+//! itself, and `Throttle` wraps any sink to limit the rate of writes and optionally smooth the
+//! values between frames.
+//!
+//! `Lp55231::write_frame_fast` is a leaner path for frames whose timing matters more than
+//! convenience, such as audio-reactive or persistence-of-vision displays: it writes the raw PWM
+//! values straight to the bus, without the curves, trims, power-save tracking, write splitting,
+//! verification or tracing of `set_pwm`, and can skip channels unchanged since the last frame.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {Access, Error, Lp55231, Millis, Operation, D};
+
+/// Direct PWM register of each D line, in order
+const PWM_REGISTERS: [u8; 9] = [
+    reg::D_PWM_BASE,
+    reg::D_PWM_BASE + 1,
+    reg::D_PWM_BASE + 2,
+    reg::D_PWM_BASE + 3,
+    reg::D_PWM_BASE + 4,
+    reg::D_PWM_BASE + 5,
+    reg::D_PWM_BASE + 6,
+    reg::D_PWM_BASE + 7,
+    reg::D_PWM_BASE + 8,
+];
+
+/// A receiver of 9-channel frames
+pub trait FrameSink {
+    /// Error returned when a frame cannot be applied
+    type Error;
+
+    /// Apply the PWM values of D1 through D9
+    fn apply_frame(&mut self, frame: &[u8; 9]) -> Result<(), Self::Error>;
+}
+
+#[allow(deprecated)]
+impl<E, I, P> FrameSink for Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    type Error = Error<E>;
+
+    fn apply_frame(&mut self, frame: &[u8; 9]) -> Result<(), Self::Error> {
+        for (d, pwm) in D::iter().zip(frame.iter()) {
+            self.set_pwm(d, *pwm)?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Write the raw PWM values of D1 through D9 with as little overhead as possible, one I2C
+    /// transaction per channel written
+    ///
+    /// The values are written as given, without curves, trims or slew limits, and the write is
+    /// neither split, verified, traced nor seen by the power-save policy or the flicker-sensitive
+    /// mode. The device must be enabled; this is only checked in debug builds, and a disabled
+    /// device typically answers with a bus error. The shadow cache, if enabled, is kept up to
+    /// date.
+    pub fn write_frame_fast(&mut self, frame: &[u8; 9]) -> Result<(), Error<E>> {
+        debug_assert!(self.en, "write_frame_fast on a disabled device");
+        let previous = self.fast_frame.take();
+        for (i, (register, pwm)) in PWM_REGISTERS.iter().zip(frame.iter()).enumerate() {
+            if previous.is_some_and(|p| p[i] == *pwm) {
+                continue;
+            }
+            let res = self.i2c.write(self.addr, &[*register, *pwm]);
+            if let Some(shadow) = self.shadow.as_mut() {
+                shadow[usize::from(*register)] = res.as_ref().ok().map(|_| *pwm);
+            }
+            res.map_err(|e| {
+                let access = Access {
+                    device: self.addr,
+                    operation: Operation::Write,
+                    register: *register,
+                    len: 1,
+                };
+                Error::I2cError(e, access)
+            })?;
+        }
+        if self.fast_skip {
+            self.fast_frame = Some(*frame);
+        }
+        Ok(())
+    }
+
+    /// Make `write_frame_fast` skip the channels whose value is unchanged since the frame it last
+    /// wrote, or write every channel every time
+    ///
+    /// Skipping assumes nothing else writes the PWM registers in between; after any other write
+    /// to them, call this again to forget the last frame, so that the next one is written in
+    /// full. A failed write or `disable` also forgets it.
+    pub fn set_fast_skip_unchanged(&mut self, enable: bool) {
+        self.fast_skip = enable;
+        self.fast_frame = None;
+    }
+}
+
+/// Rate limiting and smoothing of the frames passed on to a `FrameSink`
+///
+/// Frames are passed to `push` as they arrive, with the current time. At most one frame is passed
+/// on per interval; a frame arriving too early is held, replacing any frame already held, and
+/// passed on by a later `push` or `poll` once the interval has elapsed. Times are in ms from an
+/// arbitrary, wrapping epoch.
+pub struct Throttle<S> {
+    /// The wrapped sink
+    sink: S,
+    /// Least time between frames passed on, in ms
+    interval: u16,
+    /// Weight, out of 256, of the previous output in each output passed on
+    smoothing: u8,
+    /// Time the last frame was passed on
+    last: Option<u32>,
+    /// Frame held until the interval elapses
+    pending: Option<[u8; 9]>,
+    /// Frame last passed on
+    output: [u8; 9],
+}
+
+impl<S: FrameSink> Throttle<S> {
+    /// Wrap a sink, passing on at most one frame every `interval` ms, without smoothing
+    pub fn new<T: Into<Millis>>(sink: S, interval: T) -> Self {
+        Throttle {
+            sink,
+            interval: interval.into().0,
+            smoothing: 0,
+            last: None,
+            pending: None,
+            output: [0; 9],
+        }
+    }
+
+    /// Smooth the frames passed on, blending each with the previous output. `smoothing` is the
+    /// weight, out of 256, of the previous output: 0 passes frames on unchanged, and larger
+    /// values follow changes more slowly. A smoothed output only reaches a steady frame after
+    /// several pushes or polls.
+    pub fn with_smoothing(mut self, smoothing: u8) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    /// The wrapped sink
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Release the wrapped sink
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
+    /// Accept a frame arriving at time `now`, passing it on if the interval has elapsed.
+    /// Returns whether a frame was passed on.
+    pub fn push(&mut self, now: u32, frame: &[u8; 9]) -> Result<bool, S::Error> {
+        self.pending = Some(*frame);
+        self.poll(now)
+    }
+
+    /// Pass on the held frame if the interval has elapsed at time `now`. With smoothing, the
+    /// last frame is held until the output reaches it. Returns whether a frame was passed on.
+    pub fn poll(&mut self, now: u32) -> Result<bool, S::Error> {
+        let frame = match self.pending {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        if let Some(last) = self.last {
+            if now.wrapping_sub(last) < u32::from(self.interval) {
+                return Ok(false);
+            }
+        }
+        let mut output = frame;
+        for (out, prev) in output.iter_mut().zip(self.output.iter()) {
+            let blended = (u32::from(*prev) * u32::from(self.smoothing)
+                + u32::from(*out) * (256 - u32::from(self.smoothing))
+                + 128)
+                / 256;
+            // Always move at least one step, so that the output reaches the frame
+            *out = match blended as u8 {
+                b if b == *prev && *out > *prev => b + 1,
+                b if b == *prev && *out < *prev => b - 1,
+                b => b,
+            };
+        }
+        self.sink.apply_frame(&output)?;
+        self.output = output;
+        self.last = Some(now);
+        if output == frame {
+            self.pending = None;
+        }
+        Ok(true)
+    }
+}
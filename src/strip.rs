@@ -0,0 +1,416 @@
+//! Multi-chip pixel strips
+//!
+//! A `Strip` presents several LP55231s as one logical strip of RGB pixels, three per chip, so that
+//! strip-oriented effect code need not know how pixels are spread over devices. Pixels are
+//! numbered chip by chip, in the order the devices are given, and within each chip in the order
+//! of the board's LEDs (see `boards::Board`).
+//!
+//! `write` follows the shape of the `smart_leds` `SmartLedsWrite::write` method, taking any
+//! iterator of colors. With the `smart-leds-trait` feature, `Strip` implements `SmartLedsWrite`
+//! itself, taking `RGB8` colors, so that `smart_leds` effect code drives it unchanged.
+//!
+//! `fill` and `apply_scene` set every device alike. The register values are worked out once and
+//! written to each device in turn, and only worked out again for a device whose curves, trims
+//! or color settings differ from the device they were worked out for.
+//!
+//! `new_checked` refuses devices sharing an I2C address, and `probe` checks that every device
+//! answers, so that a miswired address strap is reported rather than showing up as two chips
+//! mirroring each other or a silent gap in the strip.
+//!
+//! `start_all_engines` starts the engines of every device together, so that animations spread
+//! over the strip begin in step.
+
+use core::fmt::{self, Debug};
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use boards::Board;
+use color::Rgb;
+#[cfg(feature = "engines")]
+use interrupt::EngineSet;
+use registers as reg;
+use scene::Scene;
+#[cfg(feature = "smart-leds-trait")]
+use smart_leds_trait::{SmartLedsWrite, RGB8};
+use {Error, Lp55231, D};
+#[cfg(feature = "engines")]
+use {Engine, Exec, Mode};
+
+#[derive(Debug)]
+/// A problem with the devices of a strip
+///
+/// Devices are listed as masks of their positions in the strip, bit 0 for the first device;
+/// devices past the 32nd are checked but not listed.
+pub enum StripError<E> {
+    /// The devices in the mask share the I2C address
+    DuplicateAddress {
+        /// The shared address
+        address: u8,
+        /// The devices using it
+        devices: u32,
+    },
+    /// The devices in the mask did not answer, with the error from the first of them
+    Missing {
+        /// The devices not answering
+        devices: u32,
+        /// The error from the first device not answering
+        error: Error<E>,
+    },
+}
+
+impl<E: Debug> fmt::Display for StripError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StripError::DuplicateAddress { address, devices } => {
+                write!(f, "devices {:#b} share address {:#04x}", devices, address)
+            }
+            StripError::Missing { devices, error } => {
+                write!(f, "devices {:#b} not answering: {}", devices, error)
+            }
+        }
+    }
+}
+
+impl<E: Debug> core::error::Error for StripError<E> {}
+
+/// The bit of the device at `index` in a device mask
+fn device_bit(index: usize) -> u32 {
+    if index < 32 {
+        1 << index
+    } else {
+        0
+    }
+}
+
+/// Several devices driven as one strip of RGB pixels
+pub struct Strip<'a, I, P> {
+    /// The devices, in strip order
+    devices: &'a mut [Lp55231<I, P>],
+    /// The layout of the RGB LEDs on every device
+    board: Board,
+}
+
+#[allow(deprecated)]
+impl<'a, E, I, P> Strip<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Drive the devices as one strip, each with its RGB LEDs laid out as on `board`
+    pub fn new(devices: &'a mut [Lp55231<I, P>], board: Board) -> Self {
+        Strip { devices, board }
+    }
+
+    /// Drive the devices as one strip, as with `new`, after checking that no two of them share
+    /// an I2C address
+    ///
+    /// This assumes the devices share one bus; devices on separate buses may use the same
+    /// address, and are driven with `new`.
+    pub fn new_checked(
+        devices: &'a mut [Lp55231<I, P>],
+        board: Board,
+    ) -> Result<Self, StripError<E>> {
+        for (i, device) in devices.iter().enumerate() {
+            let address = device.address();
+            let mut shared = device_bit(i);
+            let mut duplicated = false;
+            for (j, other) in devices.iter().enumerate().skip(i + 1) {
+                if other.address() == address {
+                    shared |= device_bit(j);
+                    duplicated = true;
+                }
+            }
+            if duplicated {
+                return Err(StripError::DuplicateAddress {
+                    address,
+                    devices: shared,
+                });
+            }
+        }
+        Ok(Strip::new(devices, board))
+    }
+
+    /// Check that every device answers on the bus (see `Lp55231::probe`), returning
+    /// `StripError::Missing` listing those that do not
+    pub fn probe(&mut self) -> Result<(), StripError<E>> {
+        let mut missing = 0;
+        let mut first = None;
+        for (i, device) in self.devices.iter_mut().enumerate() {
+            if let Err(e) = device.probe() {
+                missing |= device_bit(i);
+                first = first.or(Some(e));
+            }
+        }
+        match first {
+            Some(error) => Err(StripError::Missing {
+                devices: missing,
+                error,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Number of pixels in the strip
+    pub fn len(&self) -> usize {
+        3 * self.devices.len()
+    }
+
+    /// Is the strip without pixels
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// The devices, in strip order
+    pub fn devices(&mut self) -> &mut [Lp55231<I, P>] {
+        self.devices
+    }
+
+    /// Set the pixels to the colors produced by `colors`, starting from the first pixel. Pixels
+    /// beyond the end of `colors` are left as they are, and colors beyond the end of the strip are
+    /// ignored.
+    pub fn write<T, C>(&mut self, colors: T) -> Result<(), Error<E>>
+    where
+        T: IntoIterator<Item = C>,
+        C: Into<Rgb>,
+    {
+        let leds = *self.board.leds();
+        let mut colors = colors.into_iter();
+        for device in self.devices.iter_mut() {
+            for led in leds.iter() {
+                match colors.next() {
+                    Some(c) => device.set_color(*led, c)?,
+                    None => return Ok(()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set every pixel to the color, as `Lp55231::set_color` does
+    pub fn fill<C: Into<Rgb>>(&mut self, color: C) -> Result<(), Error<E>> {
+        let color = color.into();
+        let leds = *self.board.leds();
+        self.replicate(|device| {
+            let mut frame = [None; 9];
+            for led in leds.iter() {
+                let c = device.rendered_color(*led, color);
+                for (d, pwm) in led.lines().iter().zip([c.r, c.g, c.b].iter()) {
+                    frame[usize::from(u8::from(*d))] = Some(*pwm);
+                }
+            }
+            frame
+        })
+    }
+
+    /// Set every D line of every device to the scene's PWM value, as `Lp55231::apply_scene` does
+    pub fn apply_scene(&mut self, scene: &Scene) -> Result<(), Error<E>> {
+        self.replicate(|device| {
+            let mut frame = [None; 9];
+            for (d, pwm) in D::iter().zip(frame.iter_mut()) {
+                *pwm = Some(device.output_pwm(d, scene.pwm(d)));
+            }
+            frame
+        })
+    }
+
+    /// Write the direct PWM values produced by `encode` to every device, calling it again only
+    /// for the devices that do not encode like the device it was last called for
+    fn replicate<F>(&mut self, encode: F) -> Result<(), Error<E>>
+    where
+        F: Fn(&Lp55231<I, P>) -> [Option<u8>; 9],
+    {
+        let mut prepared: Option<(usize, [Option<u8>; 9])> = None;
+        for i in 0..self.devices.len() {
+            let frame = match prepared {
+                Some((j, frame)) if self.devices[i].encodes_like(&self.devices[j]) => frame,
+                _ => {
+                    let frame = encode(&self.devices[i]);
+                    prepared = Some((i, frame));
+                    frame
+                }
+            };
+            let device = &mut self.devices[i];
+            for (d, pwm) in D::iter().zip(frame.iter()) {
+                if let Some(pwm) = pwm {
+                    device.send(&[reg::D_PWM_BASE + u8::from(d), *pwm])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the engines in the set on every device together, each from its program's start
+    /// address, so that animations spread over the strip begin within a frame of each other
+    ///
+    /// Every device is armed first: the engines are held, put in run mode, and their program
+    /// counters pointed at their start addresses. A read-modify-write of CNTRL1 per device then
+    /// sets them free running, back to back, so the devices start two I2C transactions apart.
+    /// Engines outside the set keep running, or stay held, as they were. Run the devices from a
+    /// shared clock (see `ClockMode`) to keep them in step afterwards.
+    #[cfg(feature = "engines")]
+    pub fn start_all_engines(&mut self, engines: EngineSet) -> Result<(), Error<E>> {
+        for device in self.devices.iter_mut() {
+            arm(device, engines)?;
+        }
+        for device in self.devices.iter_mut() {
+            run(device, engines)?;
+        }
+        Ok(())
+    }
+
+    /// Start engine `e` on every device for lockstep through the TRIG pin (see `lockstep`): the
+    /// followers first, so that they are waiting for the first trigger, and the leader, the
+    /// device at index `leader`, last
+    ///
+    /// Each engine starts from its program's start address, as with `start_all_engines`, and the
+    /// other two engines keep running, or stay held, as they were. Returns
+    /// `Error::InvalidArgument` if there is no device at `leader`.
+    #[cfg(feature = "engines")]
+    pub fn start_lockstep(&mut self, leader: usize, e: Engine) -> Result<(), Error<E>> {
+        if leader >= self.devices.len() {
+            return Err(Error::InvalidArgument);
+        }
+        let engines = EngineSet::from(e);
+        for device in self.devices.iter_mut() {
+            arm(device, engines)?;
+        }
+        for (_, device) in self.devices.iter_mut().enumerate().filter(|(i, _)| *i != leader) {
+            run(device, engines)?;
+        }
+        if let Some(device) = self.devices.get_mut(leader) {
+            run(device, engines)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "smart-leds-trait")]
+#[allow(deprecated)]
+impl<'a, E, I, P> SmartLedsWrite for Strip<'a, I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    type Error = Error<E>;
+    type Color = RGB8;
+
+    /// Set the pixels to the colors, as `Strip::write` does
+    fn write<T, C>(&mut self, colors: T) -> Result<(), Error<E>>
+    where
+        T: IntoIterator<Item = C>,
+        C: Into<RGB8>,
+    {
+        let colors = colors.into_iter().map(|c| {
+            let c = c.into();
+            Rgb::new(c.r, c.g, c.b)
+        });
+        Strip::write(self, colors)
+    }
+}
+
+/// Hold the engines, put them in run mode, and point their program counters at their start
+/// addresses
+#[cfg(feature = "engines")]
+#[allow(deprecated)]
+fn arm<E, I, P>(device: &mut Lp55231<I, P>, engines: EngineSet) -> Result<(), Error<E>>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    for e in engines.iter() {
+        device.set_exec(e, Exec::Hold)?;
+        device.set_mode(e, Mode::Run)?;
+        let start = device.read(reg::PROG1_START + u8::from(e))?;
+        device.send(&[reg::PC1 + u8::from(e), start])?;
+    }
+    Ok(())
+}
+
+/// Set the armed engines free running, leaving the exec bits of every other engine as they are
+#[cfg(feature = "engines")]
+#[allow(deprecated)]
+fn run<E, I, P>(device: &mut Lp55231<I, P>, engines: EngineSet) -> Result<(), Error<E>>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    let run = free_running(engines);
+    device.modify_register(|cntrl1: reg::Cntrl1| cntrl1 | run)
+}
+
+/// CNTRL1 bits setting the engines free running, with the chip enabled
+#[cfg(feature = "engines")]
+fn free_running(engines: EngineSet) -> reg::Cntrl1 {
+    let mut run = reg::Cntrl1::CHIP_EN;
+    for e in engines.iter() {
+        run |= match e {
+            Engine::E1 => reg::Cntrl1::ENG1_EXEC_FREE_RUN,
+            Engine::E2 => reg::Cntrl1::ENG2_EXEC_FREE_RUN,
+            Engine::E3 => reg::Cntrl1::ENG3_EXEC_FREE_RUN,
+        };
+    }
+    run
+}
+
+#[cfg(all(test, any(feature = "engines", feature = "smart-leds-trait")))]
+mod tests {
+    use super::*;
+    use boards;
+    use mock;
+    #[cfg(feature = "smart-leds-trait")]
+    use NoPin;
+
+    #[test]
+    #[cfg(feature = "engines")]
+    fn starting_engines_leaves_the_others_running() {
+        let mut devices = [mock::enabled(), mock::enabled()];
+        devices[0].set_exec(Engine::E3, Exec::FreeRun).unwrap();
+        {
+            let mut strip = Strip::new(&mut devices, boards::sparkfun());
+            strip.start_all_engines(EngineSet::from(Engine::E1)).unwrap();
+        }
+        let cntrl1 = |i: usize| devices[i].i2c.registers[usize::from(reg::CNTRL1)];
+        let e1 = reg::Cntrl1::CHIP_EN | reg::Cntrl1::ENG1_EXEC_FREE_RUN;
+        assert_eq!(cntrl1(0), (e1 | reg::Cntrl1::ENG3_EXEC_FREE_RUN).bits());
+        assert_eq!(cntrl1(1), e1.bits());
+    }
+
+    #[test]
+    #[cfg(feature = "engines")]
+    fn starting_lockstep_leaves_the_other_engines_running() {
+        let mut devices = [mock::enabled(), mock::enabled()];
+        devices[1].set_exec(Engine::E2, Exec::FreeRun).unwrap();
+        {
+            let mut strip = Strip::new(&mut devices, boards::sparkfun());
+            strip.start_lockstep(1, Engine::E1).unwrap();
+        }
+        let cntrl1 = |i: usize| devices[i].i2c.registers[usize::from(reg::CNTRL1)];
+        let e1 = reg::Cntrl1::CHIP_EN | reg::Cntrl1::ENG1_EXEC_FREE_RUN;
+        assert_eq!(cntrl1(0), e1.bits());
+        assert_eq!(cntrl1(1), (e1 | reg::Cntrl1::ENG2_EXEC_FREE_RUN).bits());
+    }
+
+    #[test]
+    #[cfg(feature = "smart-leds-trait")]
+    fn smart_leds_writes_spread_over_the_devices() {
+        let mut devices = [mock::enabled(), mock::enabled()];
+        {
+            let mut strip = Strip::new(&mut devices, boards::sparkfun());
+            let colors = [RGB8::new(1, 2, 3), RGB8::new(4, 5, 6), RGB8::new(7, 8, 9)];
+            let colors = colors.iter().cycle().take(4).cloned();
+            SmartLedsWrite::write(&mut strip, colors).unwrap();
+        }
+        let pwm = |lp: &Lp55231<mock::MockI2c, NoPin>, d: u8| {
+            lp.i2c.registers[usize::from(reg::D_PWM_BASE + d)]
+        };
+        assert_eq!([pwm(&devices[0], 6), pwm(&devices[0], 0), pwm(&devices[0], 1)], [1, 2, 3]);
+        assert_eq!([pwm(&devices[0], 8), pwm(&devices[0], 4), pwm(&devices[0], 5)], [7, 8, 9]);
+        assert_eq!([pwm(&devices[1], 6), pwm(&devices[1], 0), pwm(&devices[1], 1)], [1, 2, 3]);
+        assert_eq!(pwm(&devices[1], 7), 0);
+    }
+}
@@ -0,0 +1,61 @@
+//! Telemetry snapshot
+//!
+//! `Lp55231::telemetry` gathers the device's health and engine state in two burst reads and one
+//! temperature conversion, for periodic health logging, after checking that the device has not
+//! reset.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {DegreesC, Error, Lp55231};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A snapshot of the device's health and engine state
+pub struct Telemetry {
+    /// Die temperature
+    pub temperature: DegreesC,
+    /// Decoded STATUS_IRQ register
+    pub status: reg::StatusIrq,
+    /// Program counter of each engine
+    pub pc: [u8; 3],
+    /// Local variable of each engine
+    pub variables: [u8; 3],
+    /// The global variable shared by the engines
+    pub global_variable: u8,
+    /// Value of each master fader
+    pub master_faders: [u8; 3],
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Gather a telemetry snapshot. Note that this reads (and so clears) the STATUS_IRQ register.
+    ///
+    /// The device is first checked for an unexpected reset, as by `ping`.
+    pub fn telemetry<DL: DelayMs<u8>>(&mut self, delay: &mut DL) -> Result<Telemetry, Error<E>> {
+        self.check_reset()?;
+        // PC1, PC2, PC3, STATUS_IRQ, INT_GPIO, GLOBAL_VAR
+        let mut engines = [0u8; 6];
+        self.read_into(reg::PC1, &mut engines)?;
+        // ENGINE_A_VAR, ENGINE_B_VAR, ENGINE_C_VAR, MASTER_FADE_1, MASTER_FADE_2, MASTER_FADE_3
+        let mut vars = [0u8; 6];
+        self.read_into(reg::ENGINE_A_VAR, &mut vars)?;
+
+        Ok(Telemetry {
+            temperature: self.temperature(delay)?,
+            status: reg::StatusIrq::from_bits_truncate(engines[3]),
+            pc: [engines[0], engines[1], engines[2]],
+            variables: [vars[0], vars[1], vars[2]],
+            global_variable: engines[5],
+            master_faders: [vars[3], vars[4], vars[5]],
+        })
+    }
+}
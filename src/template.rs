@@ -0,0 +1,190 @@
+//! Parameterized program templates
+//!
+//! A template is an assembled program with named holes: operands, such as a `set_pwm` value, a
+//! `wait` step time or a `branch` loop count, that are filled in when the program is loaded
+//! rather than when it is assembled. One stored blob can then serve many brightness and speed
+//! settings. Holes are marked with `Assembler::hole` right after the instruction they belong to,
+//! and the resulting `Holes` table kept alongside the blob, e.g. as a `const`.
+//!
+//! ```ignore
+//! let mut asm = Assembler::new();
+//! asm.set_pwm(0).hole("level", HoleKind::Pwm);
+//! asm.wait(1, true).hole("speed", HoleKind::StepTime);
+//! asm.set_pwm(0).wait(1, true).hole("speed", HoleKind::StepTime);
+//! asm.branch(0, 0);
+//! let holes = *asm.holes();
+//!
+//! // at load time
+//! let program = Program::from_blob(blob)?.patched(&holes, &[("level", 128), ("speed", 8)])?;
+//! lp.start_program(&program)?;
+//! ```
+//!
+//! Several holes may share a name, and are all filled with the same value.
+
+use program::{Program, ProgramError, PROGRAM_MEMORY_SIZE};
+use Engine;
+
+/// Largest number of holes an `Assembler` records
+pub const MAX_HOLES: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// The operand a hole stands for
+pub enum HoleKind {
+    /// The value of a `set_pwm`
+    Pwm,
+    /// The step time of a `ramp` or `wait`, from 1 to 31 cycles
+    StepTime,
+    /// The loop count of a `branch`, up to `asm::MAX_LOOP_COUNT`
+    LoopCount,
+}
+
+impl HoleKind {
+    /// Whether the instruction word has this operand
+    fn fits(self, word: u16) -> bool {
+        match self {
+            HoleKind::Pwm => word & 0xff00 == 0x4000,
+            HoleKind::StepTime => word & 0x8000 == 0 && word & 0x3e00 != 0,
+            HoleKind::LoopCount => word & 0xe000 == 0xa000,
+        }
+    }
+
+    /// The instruction word with the operand set to `value`, if it is in range
+    fn patch(self, word: u16, value: u8) -> Option<u16> {
+        let value = u16::from(value);
+        match self {
+            HoleKind::Pwm => Some(word & !0x00ff | value),
+            HoleKind::StepTime if (1..=31).contains(&value) => Some(word & !0x3e00 | value << 9),
+            HoleKind::LoopCount if value <= 63 => Some(word & !0x1f80 | value << 7),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A named operand of the instruction at an address
+pub struct Hole {
+    /// Name of the hole
+    pub name: &'static str,
+    /// Address of the instruction
+    pub address: u8,
+    /// The operand the hole stands for
+    pub kind: HoleKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The holes of a template (see `Assembler::hole`)
+pub struct Holes {
+    /// Holes, in the order they were added
+    entries: [Option<Hole>; MAX_HOLES],
+    /// Number of holes
+    len: usize,
+}
+
+impl Default for Holes {
+    fn default() -> Self {
+        Holes {
+            entries: [None; MAX_HOLES],
+            len: 0,
+        }
+    }
+}
+
+impl Holes {
+    /// Build a table from a list of holes, e.g. one kept in flash. Fails with
+    /// `ProgramError::TooManyHoles` if there are more than `MAX_HOLES`.
+    pub fn new(holes: &[Hole]) -> Result<Self, ProgramError> {
+        let mut table = Holes::default();
+        for hole in holes {
+            table.push(*hole)?;
+        }
+        Ok(table)
+    }
+
+    /// Add a hole
+    pub(crate) fn push(&mut self, hole: Hole) -> Result<(), ProgramError> {
+        let entry = self
+            .entries
+            .get_mut(self.len)
+            .ok_or(ProgramError::TooManyHoles)?;
+        *entry = Some(hole);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The holes, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = Hole> + '_ {
+        self.entries.iter().flatten().cloned()
+    }
+
+    /// Number of holes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Are there no holes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Offset every hole's address by `base`, as `asm::relocate` does the instructions
+    pub fn relocate(&mut self, base: u8) {
+        for hole in self.entries.iter_mut().flatten() {
+            hole.address = hole.address.saturating_add(base);
+        }
+    }
+
+    /// Fill every hole named `name` in `instructions` with `value`
+    ///
+    /// Fails without changing the instructions with `ProgramError::UnknownHole` if there is no
+    /// such hole, and with `ProgramError::InvalidOperand` if a hole lies outside the instructions,
+    /// is not at an instruction with its operand, or cannot hold the value.
+    pub fn fill(
+        &self,
+        instructions: &mut [u16],
+        name: &str,
+        value: u8,
+    ) -> Result<(), ProgramError> {
+        let mut found = false;
+        for hole in self.iter().filter(|h| h.name == name) {
+            let word = instructions
+                .get(usize::from(hole.address))
+                .ok_or(ProgramError::InvalidOperand)?;
+            if !hole.kind.fits(*word) || hole.kind.patch(*word, value).is_none() {
+                return Err(ProgramError::InvalidOperand);
+            }
+            found = true;
+        }
+        if !found {
+            return Err(ProgramError::UnknownHole);
+        }
+        for hole in self.iter().filter(|h| h.name == name) {
+            if let Some(word) = instructions.get_mut(usize::from(hole.address)) {
+                *word = hole.kind.patch(*word, value).unwrap_or(*word);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Program {
+    /// This program with its holes filled with the named values (see `Holes::fill`)
+    pub fn patched(&self, holes: &Holes, values: &[(&str, u8)]) -> Result<Program, ProgramError> {
+        let mut instructions = [0u16; PROGRAM_MEMORY_SIZE];
+        let len = self.instructions().len();
+        let patched = instructions
+            .get_mut(..len)
+            .ok_or(ProgramError::TooLarge)?;
+        patched.copy_from_slice(self.instructions());
+        for (name, value) in values {
+            holes.fill(patched, name, *value)?;
+        }
+        let start = [
+            self.start(Engine::E1),
+            self.start(Engine::E2),
+            self.start(Engine::E3),
+        ];
+        Program::new(start, patched)
+    }
+}
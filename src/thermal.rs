@@ -0,0 +1,185 @@
+//! Automatic thermal derating
+//!
+//! The `ThermalGovernor` owns the nominal levels of the three master faders. Each call to
+//! `update` measures the die temperature and writes the faders scaled down linearly between
+//! `derate_start` and `derate_end`, bottoming out at `min_scale`. Brightness is restored as the
+//! part cools. Call `update` periodically, e.g. once a second, from the application's main loop.
+//!
+//! Only D lines mapped to a master fader (see `Lp55231::configure_channel`) are derated.
+//!
+//! For monitoring without derating, `Lp55231::start_continuous_temperature` leaves the sensor
+//! converting on its own, and `Lp55231::check_thermal` compares its latest reading against
+//! warning and critical thresholds with a single register read, cheap enough for a periodic task.
+
+use core::fmt::Debug;
+use hal::blocking::delay::DelayMs;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use registers as reg;
+use {DegreesC, Error, Fader, Lp55231};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// A temperature threshold crossed, as reported by `Lp55231::check_thermal`
+pub enum ThermalAlert {
+    /// The die temperature is above the warning threshold
+    Warning(DegreesC),
+    /// The die temperature is above the critical threshold
+    Critical(DegreesC),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Thresholds used by `Lp55231::check_thermal`
+pub struct ThermalThresholds {
+    /// Die temperature above which `ThermalAlert::Warning` is reported
+    pub warning: DegreesC,
+    /// Die temperature above which `ThermalAlert::Critical` is reported
+    pub critical: DegreesC,
+}
+
+impl Default for ThermalThresholds {
+    /// A 70°C warning, and the 85°C maximum of `Monitor`
+    fn default() -> Self {
+        ThermalThresholds {
+            warning: DegreesC(70),
+            critical: DegreesC(85),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Put the internal temperature sensor in continuous conversion mode, so that TEMP_READ
+    /// always holds a recent reading (see `latest_temperature`)
+    ///
+    /// A one-shot conversion with `temperature` or `start_temperature` ends continuous mode.
+    pub fn start_continuous_temperature(&mut self) -> Result<(), Error<E>> {
+        self.modify_register::<reg::TempCtl, _>(|ctl| {
+            (ctl - reg::TempCtl::TEMP_MEAS_BUSY)
+                | reg::TempCtl::EN_TEMP_SENSOR
+                | reg::TempCtl::CONTINUOUS_CONV
+        })
+    }
+
+    /// Stop the internal temperature sensor's continuous conversions
+    pub fn stop_continuous_temperature(&mut self) -> Result<(), Error<E>> {
+        self.modify_register::<reg::TempCtl, _>(|ctl| {
+            ctl - reg::TempCtl::TEMP_MEAS_BUSY
+                - reg::TempCtl::EN_TEMP_SENSOR
+                - reg::TempCtl::CONTINUOUS_CONV
+        })
+    }
+
+    /// Read the internal temperature sensor's latest reading, corrected by the temperature offset
+    /// (see `set_temperature_offset`), without starting a conversion or waiting for one
+    ///
+    /// Outside of continuous mode (see `start_continuous_temperature`), this is the result of the
+    /// last conversion, however old.
+    pub fn latest_temperature(&mut self) -> Result<DegreesC, Error<E>> {
+        let raw = self.read(reg::TEMP_READ)? as i8;
+        Ok(DegreesC(raw.saturating_add(self.temperature_offset)))
+    }
+
+    /// Compare the latest temperature reading against the thresholds, returning the most severe
+    /// alert, or `None` if the die is at or below the warning threshold
+    ///
+    /// This takes a single register read (see `latest_temperature`), so it is suited to being
+    /// called from a periodic task with the sensor in continuous mode.
+    pub fn check_thermal(
+        &mut self,
+        thresholds: &ThermalThresholds,
+    ) -> Result<Option<ThermalAlert>, Error<E>> {
+        let temp = self.latest_temperature()?;
+        Ok(if temp > thresholds.critical {
+            Some(ThermalAlert::Critical(temp))
+        } else if temp > thresholds.warning {
+            Some(ThermalAlert::Warning(temp))
+        } else {
+            None
+        })
+    }
+}
+
+/// Thermal derating controller for the master faders
+pub struct ThermalGovernor {
+    /// Temperature at and below which the faders run at their nominal levels
+    pub derate_start: DegreesC,
+    /// Temperature at and above which the faders are scaled by `min_scale`
+    pub derate_end: DegreesC,
+    /// Smallest scale factor applied, out of 255
+    pub min_scale: u8,
+    /// Nominal master fader levels
+    levels: [u8; 3],
+    /// Scale factor most recently applied, out of 255
+    scale: u8,
+}
+
+impl ThermalGovernor {
+    /// Create a governor derating linearly between `derate_start` and `derate_end`, with all
+    /// faders at full nominal level
+    pub fn new<T: Into<DegreesC>>(derate_start: T, derate_end: T, min_scale: u8) -> Self {
+        ThermalGovernor {
+            derate_start: derate_start.into(),
+            derate_end: derate_end.into(),
+            min_scale,
+            levels: [0xff; 3],
+            scale: 0xff,
+        }
+    }
+
+    /// Set the nominal level of a master fader. It is applied, scaled, on the next `update`.
+    pub fn set_level(&mut self, f: Fader, level: u8) {
+        self.levels[usize::from(u8::from(f))] = level;
+    }
+
+    /// The scale factor most recently applied, out of 255
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    /// Compute the scale factor, out of 255, for the given temperature
+    fn scale_for(&self, temp: DegreesC) -> u8 {
+        if temp <= self.derate_start {
+            0xff
+        } else if temp >= self.derate_end {
+            self.min_scale
+        } else {
+            let span = i32::from(self.derate_end.0) - i32::from(self.derate_start.0);
+            let over = i32::from(temp.0) - i32::from(self.derate_start.0);
+            let range = 0xff - i32::from(self.min_scale);
+            (0xff - range * over / span) as u8
+        }
+    }
+
+    /// Measure the die temperature and write the derated master fader levels
+    ///
+    /// Returns the scale factor applied, out of 255.
+    #[allow(deprecated)]
+    pub fn update<E, I, P, DL>(
+        &mut self,
+        lp: &mut Lp55231<I, P>,
+        delay: &mut DL,
+    ) -> Result<u8, Error<E>>
+    where
+        E: Debug,
+        I: Write<Error = E> + WriteRead<Error = E>,
+        P: OutputPin,
+        DL: DelayMs<u8>,
+    {
+        let temp = lp.temperature(delay)?;
+        self.scale = self.scale_for(temp);
+        for (f, level) in [Fader::F1, Fader::F2, Fader::F3].iter().zip(self.levels.iter()) {
+            let scaled = u16::from(*level) * u16::from(self.scale) / 0xff;
+            lp.set_master_fader(*f, scaled as u8)?;
+        }
+        Ok(self.scale)
+    }
+}
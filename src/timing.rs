@@ -0,0 +1,219 @@
+//! Nominal execution time of engine programs
+//!
+//! `analyze` steps through a program the way an engine would, adding up the time each instruction
+//! takes, so that an animation's length and period can be checked without timing it on hardware:
+//!
+//! - `ramp` and `wait` take their step time, in cycles of 0.49 ms (15.6 ms with prescale), for each
+//!   increment, or once for a `wait`,
+//! - every other instruction takes one cycle, and
+//! - `branch` loops run their full count.
+//!
+//! Conditional jumps are followed when both variables were set by `load` instructions seen so far,
+//! and the arithmetic on them, and are taken as not jumping otherwise, e.g. when they compare a
+//! variable written by the host.
+//! Triggers are taken as firing at once. Times are nominal, with the internal 32768 Hz clock.
+//!
+//! ```ignore
+//! let mut asm = Assembler::new();
+//! asm.forever(|asm| {
+//!     asm.label("up").ramp(16, false, 255).label("down").ramp(16, false, -255);
+//! });
+//! let timing = timing::analyze(asm.instructions()?, 0)?;
+//! assert_eq!(timing.period_us(), Some(3_984_863));
+//! assert_eq!(timing.label_us(asm.labels(), "down"), Some(1_992_187));
+//! ```
+
+use asm::Labels;
+use program::{Program, ProgramError, PROGRAM_MEMORY_SIZE};
+use Engine;
+
+/// Frequency of the engine clock, in Hz
+pub const CLOCK_HZ: u32 = 32_768;
+
+/// Clock ticks in a cycle
+const CYCLE_TICKS: u64 = 16;
+
+/// Clock ticks in a prescaled cycle
+const PRESCALED_CYCLE_TICKS: u64 = 512;
+
+/// Largest number of instructions `analyze` executes before giving up
+pub const MAX_STEPS: u32 = 1 << 20;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The nominal timing of a program, as found by `analyze`
+pub struct Timing {
+    /// Time at which each instruction first starts, in µs from the program's start
+    start: [Option<u32>; PROGRAM_MEMORY_SIZE],
+    /// Time until the program ends, or until it first starts over, in µs
+    duration: u32,
+    /// Time taken by each repetition, in µs, if the program repeats forever
+    period: Option<u32>,
+}
+
+impl Timing {
+    /// Time until the program ends, or until it first starts repeating, in µs
+    pub fn duration_us(&self) -> u32 {
+        self.duration
+    }
+
+    /// Time taken by each repetition of a program that repeats forever, in µs, or `None` if the
+    /// program ends
+    pub fn period_us(&self) -> Option<u32> {
+        self.period
+    }
+
+    /// Whether the program ends, rather than repeating forever
+    pub fn ends(&self) -> bool {
+        self.period.is_none()
+    }
+
+    /// Time at which the instruction at `address` first starts, in µs from the program's start,
+    /// or `None` if it is never executed
+    pub fn start_us(&self, address: u8) -> Option<u32> {
+        self.start.get(usize::from(address)).cloned().flatten()
+    }
+
+    /// Time at which the instruction at a label first starts, in µs from the program's start,
+    /// or `None` if there is no such label or it is never reached
+    pub fn label_us(&self, labels: &Labels, name: &str) -> Option<u32> {
+        labels.address(name).and_then(|a| self.start_us(a))
+    }
+}
+
+/// Convert clock ticks to µs, saturating
+fn ticks_to_us(ticks: u64) -> u32 {
+    let us = ticks * 1_000_000 / u64::from(CLOCK_HZ);
+    if us > u64::from(u32::MAX) {
+        u32::MAX
+    } else {
+        us as u32
+    }
+}
+
+/// Compute the nominal timing of the program starting at `start` in `instructions`, a program
+/// memory image whose branch targets are addresses in it
+///
+/// Running past the last instruction behaves as `go_to_start`, as the rest of the program memory
+/// is cleared when loading. Fails with `ProgramError::InvalidOperand` if `start` lies outside the
+/// instructions, and with `ProgramError::TooLong` if the program runs for more than `MAX_STEPS`
+/// instructions without ending or repeating.
+pub fn analyze(instructions: &[u16], start: u8) -> Result<Timing, ProgramError> {
+    if instructions.len() > PROGRAM_MEMORY_SIZE {
+        return Err(ProgramError::TooLarge);
+    }
+    if usize::from(start) >= instructions.len() {
+        return Err(ProgramError::InvalidOperand);
+    }
+    let mut first = [None; PROGRAM_MEMORY_SIZE];
+    let mut loops = [None; PROGRAM_MEMORY_SIZE];
+    let mut vars: [Option<u8>; 4] = [None; 4];
+    let mut pc = usize::from(start);
+    let mut ticks = 0u64;
+    for _ in 0..MAX_STEPS {
+        first[pc].get_or_insert(ticks);
+        let word = instructions[pc];
+        let mut next = pc + 1;
+        let mut repeat_from = None;
+        if word & 0x8000 == 0 {
+            let step = u64::from((word >> 9) & 0x1f);
+            if step == 0 {
+                // set_pwm, or go_to_start
+                ticks += CYCLE_TICKS;
+                if word == 0 {
+                    repeat_from = Some(usize::from(start));
+                }
+            } else {
+                let cycle = if word & 0x4000 != 0 {
+                    PRESCALED_CYCLE_TICKS
+                } else {
+                    CYCLE_TICKS
+                };
+                let increments = u64::from(word & 0xff).max(1);
+                ticks += increments * step * cycle;
+            }
+        } else {
+            ticks += CYCLE_TICKS;
+            match word & 0xe000 {
+                0xa000 => {
+                    let target = usize::from(word & 0x7f);
+                    let count = ((word >> 7) & 0x3f) as u8;
+                    if target >= instructions.len() {
+                        return Err(ProgramError::InvalidOperand);
+                    }
+                    if count == 0 {
+                        repeat_from = Some(target);
+                    } else {
+                        let left = loops[pc].unwrap_or(count);
+                        if left > 0 {
+                            loops[pc] = Some(left - 1);
+                            next = target;
+                        } else {
+                            loops[pc] = None;
+                        }
+                    }
+                }
+                0xc000 => {
+                    return Ok(Timing {
+                        start: first.map(|t| t.map(ticks_to_us)),
+                        duration: ticks_to_us(ticks),
+                        period: None,
+                    });
+                }
+                0x8000 if word & 0x1000 != 0 => {
+                    // load, add and sub; the mux instructions target no variable
+                    let target = usize::from((word >> 10) & 0x3);
+                    let operand = |code: u16| vars[usize::from(code & 0x3)];
+                    vars[target] = match (word >> 8) & 0x3 {
+                        0 => Some(word as u8),
+                        1 => vars[target].map(|v| v.wrapping_add(word as u8)),
+                        2 => vars[target].map(|v| v.wrapping_sub(word as u8)),
+                        _ => match (operand(word >> 2), operand(word), word & 0x10 != 0) {
+                            (Some(a), Some(b), false) => Some(a.wrapping_add(b)),
+                            (Some(a), Some(b), true) => Some(a.wrapping_sub(b)),
+                            _ => None,
+                        },
+                    };
+                }
+                0x8000 if word & 0x0800 != 0 => {
+                    // conditional jump
+                    let a = vars[usize::from((word >> 2) & 0x3)];
+                    let b = vars[usize::from(word & 0x3)];
+                    if let (Some(a), Some(b)) = (a, b) {
+                        let holds = match word & 0xfe00 {
+                            0x8800 => a != b,
+                            0x8a00 => a < b,
+                            0x8c00 => a >= b,
+                            _ => a == b,
+                        };
+                        if holds {
+                            next += usize::from((word >> 4) & 0x1f);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if repeat_from.is_none() && next >= instructions.len() {
+            repeat_from = Some(usize::from(start));
+        }
+        if let Some(target) = repeat_from {
+            if let Some(since) = first[target] {
+                return Ok(Timing {
+                    start: first.map(|t| t.map(ticks_to_us)),
+                    duration: ticks_to_us(ticks),
+                    period: Some(ticks_to_us(ticks - since)),
+                });
+            }
+            next = target;
+        }
+        pc = next;
+    }
+    Err(ProgramError::TooLong)
+}
+
+impl Program {
+    /// Compute the nominal timing of an engine's program (see `timing::analyze`)
+    pub fn timing(&self, e: Engine) -> Result<Timing, ProgramError> {
+        analyze(self.instructions(), self.start(e))
+    }
+}
@@ -0,0 +1,59 @@
+//! Register transaction logging
+//!
+//! With the `log` or `defmt` feature enabled, every register write and read is logged at trace
+//! level with the device address, the register, the bytes transferred and whether the transfer
+//! succeeded. Impossible output wiring (see `chargepump`) is logged at warning level. Without
+//! either feature these compile to nothing.
+
+use chargepump::WiringError;
+
+/// Log a register write. `bytes` starts with the register address.
+#[allow(unused_variables)]
+pub(crate) fn write(addr: u8, bytes: &[u8], ok: bool) {
+    #[cfg(feature = "log")]
+    log::trace!(
+        "lp55231@{:#04x} write {:02x?} {}",
+        addr,
+        bytes,
+        if ok { "ok" } else { "failed" }
+    );
+    #[cfg(feature = "defmt")]
+    defmt::trace!("lp55231@{=u8:#x} write {=[u8]:#x} ok={=bool}", addr, bytes, ok);
+}
+
+/// Log a read of `buf.len()` registers starting at `reg`
+#[allow(unused_variables)]
+pub(crate) fn read(addr: u8, reg: u8, buf: &[u8], ok: bool) {
+    #[cfg(feature = "log")]
+    log::trace!(
+        "lp55231@{:#04x} read {:#04x} {:02x?} {}",
+        addr,
+        reg,
+        buf,
+        if ok { "ok" } else { "failed" }
+    );
+    #[cfg(feature = "defmt")]
+    defmt::trace!(
+        "lp55231@{=u8:#x} read {=u8:#x} {=[u8]:#x} ok={=bool}",
+        addr,
+        reg,
+        buf,
+        ok
+    );
+}
+
+/// Warn of wiring that cannot drive an LED, found while choosing the charge pump mode
+#[allow(unused_variables)]
+pub(crate) fn wiring(addr: u8, e: WiringError) {
+    #[cfg(feature = "log")]
+    log::warn!("lp55231@{:#04x} impossible wiring: {:?}", addr, e);
+    #[cfg(feature = "defmt")]
+    defmt::warn!(
+        "lp55231@{=u8:#x} impossible wiring: {=str}",
+        addr,
+        match e {
+            WiringError::VddTooLow(_) => "VDD too low",
+            WiringError::VoutTooLow(_) => "VOUT too low",
+        }
+    );
+}
@@ -0,0 +1,53 @@
+//! Transaction encoding without transfer
+//!
+//! These functions build the bytes of a single I2C write transaction into a caller-provided
+//! buffer, for applications that drive the bus themselves, e.g. with DMA or from an interrupt.
+//! Each returns the encoded slice of `buf`, to be written to the device's address (see
+//! `Lp55231::address`), or `None` if `buf` is too small or the operation cannot be expressed as a
+//! single transaction.
+//!
+//! Consecutive registers may be written in one transaction when auto-increment is enabled (as it
+//! is by `Lp55231::enable`), except for the direct PWM registers, which do not support it.
+
+use registers as reg;
+use {ChannelConfig, OutputMask, D};
+
+/// Whether a burst write starting at `start` may continue on to the next register
+pub fn auto_increments(start: u8) -> bool {
+    !(reg::D1_PWM..=reg::D9_PWM).contains(&start)
+}
+
+/// Encode a write of `values` to consecutive registers starting at `start`
+pub fn write_burst<'b>(start: u8, values: &[u8], buf: &'b mut [u8]) -> Option<&'b [u8]> {
+    let len = values.len() + 1;
+    if values.is_empty() || len > buf.len() || len > 0x100 {
+        return None;
+    }
+    let last = start.checked_add((values.len() - 1) as u8)?;
+    if values.len() > 1 && (start..=last).any(|r| !auto_increments(r)) {
+        return None;
+    }
+    buf[0] = start;
+    buf[1..len].copy_from_slice(values);
+    Some(&buf[..len])
+}
+
+/// Encode a write of a single register
+pub fn write_register(register: u8, value: u8, buf: &mut [u8]) -> Option<&[u8]> {
+    write_burst(register, &[value], buf)
+}
+
+/// Encode setting the D line's direct PWM value
+pub fn set_pwm(d: D, pwm: u8, buf: &mut [u8]) -> Option<&[u8]> {
+    write_register(reg::D_PWM_BASE + u8::from(d), pwm, buf)
+}
+
+/// Encode configuring the D line's control register
+pub fn configure_channel(d: D, config: ChannelConfig, buf: &mut [u8]) -> Option<&[u8]> {
+    write_register(reg::D_CTRL_BASE + u8::from(d), u8::from(config), buf)
+}
+
+/// Encode turning the D lines in the set on, and all others off
+pub fn set_outputs(outputs: OutputMask, buf: &mut [u8]) -> Option<&[u8]> {
+    write_burst(reg::OUTPUT_ONOFF_MSB, &[outputs.msb(), outputs.lsb()], buf)
+}
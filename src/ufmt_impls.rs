@@ -0,0 +1,205 @@
+//! `ufmt` formatting
+//!
+//! With the `ufmt` feature, errors, status flags and telemetry implement `ufmt`'s `uDisplay` and
+//! `uDebug`, for logging over serial links too small for `core::fmt`. Where a type also implements
+//! `core::fmt::Display`, `uDisplay` writes the same text, and errors and status flags write it for
+//! `uDebug` too.
+
+use ufmt;
+use ufmt::{uDebug, uDisplay, uWrite, uwrite, Formatter};
+
+#[cfg(feature = "engines")]
+use program::ProgramError;
+use registers::StatusIrq;
+use telemetry::Telemetry;
+#[cfg(feature = "engines")]
+use Conflict;
+use {Access, DegreesC, Error, Operation};
+
+impl uDisplay for Access {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        let op = match self.operation {
+            Operation::Read => "reading",
+            Operation::Write => "writing",
+        };
+        if self.is_burst() {
+            uwrite!(f, "{} {} registers from {:#04x}", op, self.len, self.register)?;
+        } else {
+            uwrite!(f, "{} register {:#04x}", op, self.register)?;
+        }
+        uwrite!(f, " at {:#04x}", self.device)
+    }
+}
+
+impl<I: uDebug> uDisplay for Error<I> {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            Error::I2cError(e, access) => uwrite!(f, "I2C error {}: {:?}", access, e),
+            #[cfg(feature = "engines")]
+            Error::MappingConflict(d, Conflict::Engine(e)) => uwrite!(
+                f,
+                "D{} is already mapped to engine E{}",
+                u8::from(*d) + 1,
+                u8::from(*e) + 1
+            ),
+            #[cfg(feature = "engines")]
+            Error::MappingConflict(d, Conflict::Ratiometric) => {
+                uwrite!(f, "D{} is already under ratiometric dimming", u8::from(*d) + 1)
+            }
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl<I: uDebug> uDebug for Error<I> {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uDisplay::fmt(self, f)
+    }
+}
+
+#[cfg(feature = "engines")]
+impl uDisplay for ProgramError {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            ProgramError::UnsupportedVersion(v) => uwrite!(f, "unsupported blob version {}", v),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+#[cfg(feature = "engines")]
+impl uDebug for ProgramError {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uDisplay::fmt(self, f)
+    }
+}
+
+/// Names of the STATUS_IRQ flags, most significant first
+const STATUS_FLAGS: [(StatusIrq, &str); 8] = [
+    (StatusIrq::LEDTEST_MEAS_DONE, "LEDTEST_MEAS_DONE"),
+    (StatusIrq::MASK_BUSY, "MASK_BUSY"),
+    (StatusIrq::STARTUP_BUSY, "STARTUP_BUSY"),
+    (StatusIrq::ENGINE_BUSY, "ENGINE_BUSY"),
+    (StatusIrq::EXT_CLK_USED, "EXT_CLK_USED"),
+    (StatusIrq::ENG1_INT, "ENG1_INT"),
+    (StatusIrq::ENG2_INT, "ENG2_INT"),
+    (StatusIrq::ENG3_INT, "ENG3_INT"),
+];
+
+/// Writes the set flags separated by ` | `, or `(empty)`, as bitflags' `Debug` does
+impl uDisplay for StatusIrq {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        let mut first = true;
+        for (flag, name) in STATUS_FLAGS.iter() {
+            if self.contains(*flag) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        if first {
+            f.write_str("(empty)")?;
+        }
+        Ok(())
+    }
+}
+
+impl uDebug for StatusIrq {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uDisplay::fmt(self, f)
+    }
+}
+
+impl uDisplay for DegreesC {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uwrite!(f, "{}°C", self.0)
+    }
+}
+
+impl uDebug for DegreesC {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uwrite!(f, "DegreesC({})", self.0)
+    }
+}
+
+/// Writes e.g. `31°C ENGINE_BUSY pc [0, 16, 32] vars [0, 0, 0] global 0 faders [255, 0, 0]`
+impl uDisplay for Telemetry {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uwrite!(
+            f,
+            "{} {} pc {:?} vars {:?} global {} faders {:?}",
+            self.temperature,
+            self.status,
+            self.pc,
+            self.variables,
+            self.global_variable,
+            self.master_faders
+        )
+    }
+}
+
+impl uDebug for Telemetry {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        f.debug_struct("Telemetry")?
+            .field("temperature", &self.temperature)?
+            .field("status", &self.status)?
+            .field("pc", &self.pc)?
+            .field("variables", &self.variables)?
+            .field("global_variable", &self.global_variable)?
+            .field("master_faders", &self.master_faders)?
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use std::string::String;
+    #[cfg(feature = "engines")]
+    use D;
+
+    /// Format with `ufmt`
+    fn text<T: uDisplay>(value: &T) -> String {
+        struct Text(String);
+        impl uWrite for Text {
+            type Error = Infallible;
+            fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
+                self.0.push_str(s);
+                Ok(())
+            }
+        }
+        let mut text = Text(String::new());
+        uwrite!(text, "{}", value).unwrap();
+        text.0
+    }
+
+    #[test]
+    fn errors_read_as_with_core_fmt() {
+        let access = Access {
+            device: 0x32,
+            operation: Operation::Write,
+            register: 0x16,
+            len: 9,
+        };
+        assert_eq!(text(&access), "writing 9 registers from 0x16 at 0x32");
+        let e: Error<u8> = Error::I2cError(7, access);
+        assert_eq!(text(&e), "I2C error writing 9 registers from 0x16 at 0x32: 7");
+        #[cfg(feature = "engines")]
+        {
+            let e: Error<u8> = Error::MappingConflict(D::D4, Conflict::Ratiometric);
+            assert_eq!(text(&e), "D4 is already under ratiometric dimming");
+        }
+        let e: Error<u8> = Error::Timeout;
+        assert_eq!(text(&e), "timed out waiting for device");
+    }
+
+    #[test]
+    fn status_flags_are_listed() {
+        assert_eq!(text(&StatusIrq::empty()), "(empty)");
+        let status = StatusIrq::ENGINE_BUSY | StatusIrq::ENG1_INT;
+        assert_eq!(text(&status), "ENGINE_BUSY | ENG1_INT");
+    }
+}
@@ -0,0 +1,167 @@
+//! Stalled engine detection
+//!
+//! An engine can wedge without any error: a program waiting on a trigger that never comes sits on
+//! the same instruction forever, leaving its LEDs frozen. An `EngineWatchdog` catches this by
+//! sampling the program counter of each running engine, through `Lp55231::check_engines`, and
+//! flagging engines whose program counter has not moved for longer than their program takes to
+//! run through once (see `timing::analyze`), which is as long as any instruction may take.
+//!
+//! Samples should be taken at an interval shorter than the limits and not a multiple of a
+//! program's period, or an engine going round its loop could be seen at the same address every
+//! time. Times are in ms from an arbitrary, wrapping epoch.
+
+use core::fmt::Debug;
+use hal::blocking::i2c::{Write, WriteRead};
+#[allow(deprecated)]
+use hal::digital::OutputPin;
+
+use interrupt::EngineSet;
+use program::{Program, ProgramError};
+use registers as reg;
+use timing::Timing;
+use {Engine, Error, Exec, Lp55231};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Program counter of an engine, and when it was first seen there
+struct Sample {
+    /// The program counter
+    pc: u8,
+    /// Time it was first seen, in ms
+    since: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Stall limits and program counter samples of the engines
+pub struct EngineWatchdog {
+    /// Longest time each engine's program counter may stay put, in ms, or `None` if unwatched
+    limits: [Option<u32>; 3],
+    /// Last program counter sample of each engine
+    samples: [Option<Sample>; 3],
+    /// Restart stalled engines
+    restart: bool,
+}
+
+impl Default for EngineWatchdog {
+    fn default() -> Self {
+        EngineWatchdog::new()
+    }
+}
+
+impl EngineWatchdog {
+    /// Create a watchdog watching no engine
+    pub fn new() -> Self {
+        EngineWatchdog {
+            limits: [None; 3],
+            samples: [None; 3],
+            restart: false,
+        }
+    }
+
+    /// Create a watchdog watching every engine of the program, each with the time its program
+    /// takes to run through once as its limit
+    pub fn for_program(program: &Program) -> Result<Self, ProgramError> {
+        let mut watchdog = EngineWatchdog::new();
+        for e in [Engine::E1, Engine::E2, Engine::E3].iter() {
+            watchdog.watch(*e, &program.timing(*e)?);
+        }
+        Ok(watchdog)
+    }
+
+    /// Restart stalled engines from their program's start address when they are found
+    pub fn with_restart(mut self, restart: bool) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Watch the engine, with the time the program it runs takes to repeat, or to end, as limit
+    pub fn watch(&mut self, e: Engine, timing: &Timing) {
+        let us = timing.period_us().unwrap_or_else(|| timing.duration_us());
+        self.set_limit(e, Some(us / 1000 + 1));
+    }
+
+    /// Set the longest time, in ms, the engine's program counter may stay put, or stop watching
+    /// it with `None`
+    ///
+    /// Limits are in ms as a `u32`, rather than `Millis`, as programs may take minutes to repeat.
+    pub fn set_limit(&mut self, e: Engine, limit: Option<u32>) {
+        let i = usize::from(u8::from(e));
+        // An instruction takes at least a cycle, so a limit of zero would flag every engine
+        self.limits[i] = limit.map(|l| l.max(1));
+        self.samples[i] = None;
+    }
+
+    /// Forget the samples taken, as after restarting the engines
+    pub fn clear(&mut self) {
+        self.samples = [None; 3];
+    }
+
+    /// Record the engine's program counter at time `now`, returning whether it has stayed put
+    /// for longer than the engine's limit
+    fn sample(&mut self, e: Engine, pc: u8, now: u32) -> bool {
+        let i = usize::from(u8::from(e));
+        let limit = match self.limits[i] {
+            Some(limit) => limit,
+            None => return false,
+        };
+        match self.samples[i] {
+            Some(sample) if sample.pc == pc => now.wrapping_sub(sample.since) > limit,
+            _ => {
+                self.samples[i] = Some(Sample { pc, since: now });
+                false
+            }
+        }
+    }
+}
+
+/// Whether the engine is free running in run mode, given CNTRL1 and CNTRL2
+fn running(e: Engine, cntrl1: u8, cntrl2: u8) -> bool {
+    let shift = 4 - 2 * u8::from(e);
+    (cntrl1 >> shift) & 0b11 == 0b10 && (cntrl2 >> shift) & 0b11 == 0b10
+}
+
+#[allow(deprecated)]
+impl<E, I, P> Lp55231<I, P>
+where
+    E: Debug,
+    I: Write<Error = E> + WriteRead<Error = E>,
+    P: OutputPin,
+{
+    /// Sample the program counter of every running engine the watchdog watches at time `now`,
+    /// returning the engines stalled for longer than their limit
+    ///
+    /// Engines that are held, stepped or not in run mode, including those whose program has
+    /// ended, are not sampled, and are sampled afresh once they run again. With restarting
+    /// enabled, stalled engines are restarted from their program's start address.
+    pub fn check_engines(
+        &mut self,
+        watchdog: &mut EngineWatchdog,
+        now: u32,
+    ) -> Result<EngineSet, Error<E>> {
+        let cntrl1 = self.read(reg::CNTRL1)?;
+        let cntrl2 = self.read(reg::CNTRL2)?;
+        let mut stalled = EngineSet::none();
+        for e in [Engine::E1, Engine::E2, Engine::E3].iter() {
+            if !running(*e, cntrl1, cntrl2) {
+                watchdog.samples[usize::from(u8::from(*e))] = None;
+                continue;
+            }
+            let pc = self.program_counter(*e)?;
+            if watchdog.sample(*e, pc, now) {
+                stalled = stalled.with(*e);
+                if watchdog.restart {
+                    self.restart_engine(*e)?;
+                    watchdog.samples[usize::from(u8::from(*e))] = None;
+                }
+            }
+        }
+        Ok(stalled)
+    }
+
+    /// Restart the engine's program from its start address
+    pub(crate) fn restart_engine(&mut self, e: Engine) -> Result<(), Error<E>> {
+        self.set_exec(e, Exec::Hold)?;
+        let start = self.read(reg::PROG1_START + u8::from(e))?;
+        self.send(&[reg::PC1 + u8::from(e), start])?;
+        self.set_exec(e, Exec::FreeRun)
+    }
+}